@@ -0,0 +1,96 @@
+use anyhow::Result;
+
+use super::common::{AiBackend, ChatMessage, Role, Usage};
+use crate::audit;
+
+/// A backend whose generations are recorded in the audit log when it's on, whichever
+/// command or library call they come from
+pub struct AuditedAiBackend {
+    inner: Box<dyn AiBackend>,
+    backend: String,
+    model: Option<String>,
+}
+
+impl AuditedAiBackend {
+    pub fn new(inner: Box<dyn AiBackend>, backend: &str, model: Option<String>) -> Self {
+        Self {
+            inner,
+            backend: backend.to_string(),
+            model,
+        }
+    }
+
+    /// Sends the request and records each of its responses. The log is opened first, so a
+    /// generation that can't be recorded isn't made
+    fn audited<T>(
+        &self,
+        prompt: &str,
+        request: impl FnOnce() -> Result<T>,
+        responses: impl Fn(&T) -> Vec<&str>,
+    ) -> Result<T> {
+        let audit = audit::open()?;
+        let result = request()?;
+        if let Some(mut audit) = audit {
+            for response in responses(&result) {
+                audit.generation(&self.backend, self.model.as_deref(), prompt, response)?;
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// The prompt a conversation's responses answer, its last user message
+fn last_prompt(messages: &[ChatMessage]) -> &str {
+    messages
+        .iter()
+        .rev()
+        .find(|message| message.role == Role::User)
+        .map(|message| message.content.as_str())
+        .unwrap_or_default()
+}
+
+fn all(responses: &[String]) -> Vec<&str> {
+    responses.iter().map(String::as_str).collect()
+}
+
+impl AiBackend for AuditedAiBackend {
+    fn invoke(&self, prompt: String) -> Result<String> {
+        self.audited(
+            &prompt.clone(),
+            || self.inner.invoke(prompt),
+            |response| vec![response.as_str()],
+        )
+    }
+
+    fn invoke_n(&self, prompt: String, n: usize) -> Result<Vec<String>> {
+        self.audited(
+            &prompt.clone(),
+            || self.inner.invoke_n(prompt, n),
+            |responses| all(responses),
+        )
+    }
+
+    fn invoke_conversation(&self, messages: &[ChatMessage], n: usize) -> Result<Vec<String>> {
+        self.audited(
+            last_prompt(messages),
+            || self.inner.invoke_conversation(messages, n),
+            |responses| all(responses),
+        )
+    }
+
+    fn stream_conversation(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        self.audited(
+            last_prompt(messages),
+            || self.inner.stream_conversation(messages, on_token),
+            |response| vec![response.as_str()],
+        )
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        self.inner.usage()
+    }
+}
@@ -1,24 +1,368 @@
-use aws_config::{BehaviorVersion, Region};
+use aws_config::SdkConfig;
+use aws_sdk_bedrockruntime::error::SdkError;
+use aws_sdk_bedrockruntime::operation::converse_stream::builders::ConverseStreamFluentBuilder;
+use aws_sdk_bedrockruntime::operation::converse_stream::ConverseStreamError;
+use aws_sdk_bedrockruntime::primitives::event_stream::EventReceiver;
 use aws_sdk_bedrockruntime::types::error::ConverseStreamOutputError;
 use aws_sdk_bedrockruntime::types::{
-    ContentBlock, ConversationRole, ConverseStreamOutput, Message, SystemContentBlock,
+    CachePointBlock, CachePointType, ContentBlock, ContentBlockDelta, ContentBlockStart,
+    ConversationRole, ConverseStreamOutput, InferenceConfiguration, Message, SpecificToolChoice,
+    SystemContentBlock, Tool, ToolChoice, ToolConfiguration, ToolInputSchema, ToolResultBlock,
+    ToolResultContentBlock, ToolResultStatus, ToolSpecification, ToolUseBlock,
 };
 use aws_sdk_bedrockruntime::Client;
+use aws_smithy_types::{Document, Number};
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
-use tracing::{debug, info};
+use tracing::{debug, info, info_span, trace_span, warn, Instrument};
 
-use super::common::AiBackend;
-use crate::constants::SYSTEM_PROMPT;
+use super::common::{cutoff, deadline, report_cutoff, AiBackend, ChatMessage, Cutoff, Role, Usage};
+use super::{redacting, tools};
+use crate::aws;
+use crate::budget::{self, Spending};
+use crate::constants::{MAX_TOOL_ROUNDS, PROMPT_CACHING_MODELS};
+use crate::messages;
+use crate::redact::Redactor;
 use crate::Settings;
 
+/// How often a pending request checks for Ctrl-C and the deadline
+const CUTOFF_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The tool the model is made to call with its response when it has to match a schema
+const RESPONSE_TOOL: &str = "respond";
+
+/// A tool call streamed by the model, the input json arrives in chunks
+struct PendingToolUse {
+    id: String,
+    name: String,
+    input: String,
+}
+
+/// One assistant turn: its text and any tool calls it ended with
+#[derive(Default)]
+struct Turn {
+    text: String,
+    tool_uses: Vec<PendingToolUse>,
+}
+
 pub struct BedrockAiBackend {
     settings: Settings,
+    system_prompt: String,
+    usage: Mutex<Usage>,
+    /// Redacts the tool output sent to the model, None when redaction is off
+    redactor: Option<Arc<Redactor>>,
 }
 
 impl BedrockAiBackend {
-    pub fn new(settings: Settings) -> Self {
-        Self { settings }
+    pub fn new(settings: Settings, system_prompt: String, redactor: Option<Arc<Redactor>>) -> Self {
+        Self {
+            settings,
+            system_prompt,
+            usage: Mutex::new(Usage::default()),
+            redactor,
+        }
+    }
+
+    /// Whether the request failed because the region is out of capacity for the model,
+    /// in which case it is worth retrying in another region
+    fn is_capacity_error<R>(error: &SdkError<ConverseStreamError, R>) -> bool {
+        match error.as_service_error() {
+            Some(e) => {
+                e.is_throttling_exception()
+                    || e.is_service_unavailable_exception()
+                    || e.is_model_not_ready_exception()
+            }
+            None => false,
+        }
+    }
+
+    /// Whether to mark cache points, the models without prompt caching reject them
+    fn caches_prompt(&self) -> bool {
+        let model_id = &self.settings.aws_settings.model_id;
+        self.settings.aws_settings.prompt_caching
+            && PROMPT_CACHING_MODELS
+                .iter()
+                .any(|model| model_id.contains(model))
+    }
+
+    fn cache_point() -> Result<CachePointBlock> {
+        Ok(CachePointBlock::builder()
+            .r#type(CachePointType::Default)
+            .build()?)
+    }
+
+    /// The `bedrock_model_config` settings as Converse's inference config, None when none
+    /// are set so the model's defaults apply
+    fn inference_config(&self) -> Option<InferenceConfiguration> {
+        let config = &self.settings.bedrock_model_config;
+        if config.max_tokens.is_none()
+            && config.temperature.is_none()
+            && config.top_p.is_none()
+            && config.stop_sequences.is_empty()
+        {
+            return None;
+        }
+        Some(
+            InferenceConfiguration::builder()
+                .set_max_tokens(
+                    config
+                        .max_tokens
+                        .map(|max| i32::try_from(max).unwrap_or(i32::MAX)),
+                )
+                .set_temperature(config.temperature.map(|temperature| temperature as f32))
+                .set_top_p(config.top_p.map(|top_p| top_p as f32))
+                .set_stop_sequences(
+                    (!config.stop_sequences.is_empty()).then(|| config.stop_sequences.clone()),
+                )
+                .build(),
+        )
+    }
+
+    /// The converse stream request for the conversation so far. With `cache` the system
+    /// prompt ends in a cache point
+    fn request(
+        client: &Client,
+        model_id: &str,
+        system_prompt: &str,
+        messages: &[Message],
+        tool_config: Option<&ToolConfiguration>,
+        inference_config: Option<&InferenceConfiguration>,
+        cache: bool,
+    ) -> Result<ConverseStreamFluentBuilder> {
+        let mut system = vec![SystemContentBlock::Text(system_prompt.to_string())];
+        if cache {
+            system.push(SystemContentBlock::CachePoint(Self::cache_point()?));
+        }
+        Ok(client
+            .converse_stream()
+            .model_id(model_id)
+            .set_messages(Some(messages.to_vec()))
+            .set_system(Some(system))
+            .set_tool_config(tool_config.cloned())
+            .set_inference_config(inference_config.cloned()))
+    }
+
+    /// The conversation as Converse messages. Converse wants it to start with a user message
+    /// and alternate between user and assistant with no empty text, so blank messages are
+    /// left out and consecutive ones from the same role, e.g. a retried prompt, are joined.
+    /// With `cache` the turns before the last one end in a cache point, so a continued
+    /// conversation, including the context sent with its first prompt, is read from the cache
+    fn conversation_messages(conversation: &[ChatMessage], cache: bool) -> Result<Vec<Message>> {
+        let mut turns: Vec<(Role, String)> = Vec::new();
+        for message in conversation {
+            if message.content.trim().is_empty() {
+                continue;
+            }
+            match turns.last_mut() {
+                Some((role, content)) if *role == message.role => {
+                    content.push_str("\n\n");
+                    content.push_str(&message.content);
+                }
+                _ => turns.push((message.role, message.content.clone())),
+            }
+        }
+        match turns.first() {
+            None => anyhow::bail!("{}", messages::Message::NoBedrockPrompt),
+            Some((Role::Assistant, _)) => {
+                anyhow::bail!("{}", messages::Message::BedrockStartsWithUser)
+            }
+            Some((Role::User, _)) => {}
+        }
+        let cached_turn = turns.len().checked_sub(2).filter(|_| cache);
+        turns
+            .into_iter()
+            .enumerate()
+            .map(|(i, (role, content))| {
+                let role = match role {
+                    Role::User => ConversationRole::User,
+                    Role::Assistant => ConversationRole::Assistant,
+                };
+                let mut message = Message::builder()
+                    .role(role)
+                    .content(ContentBlock::Text(content));
+                if cached_turn == Some(i) {
+                    message = message.content(ContentBlock::CachePoint(Self::cache_point()?));
+                }
+                message
+                    .build()
+                    .map_err(|_| anyhow::anyhow!("{}", messages::Message::BuildFailed))
+            })
+            .collect()
+    }
+
+    /// Declares the built in read only tools to the model
+    fn tool_config() -> Result<ToolConfiguration> {
+        let mut config = ToolConfiguration::builder();
+        for tool in tools::builtin_tools() {
+            config = config.tools(Tool::ToolSpec(
+                ToolSpecification::builder()
+                    .name(tool.name)
+                    .description(tool.description)
+                    .input_schema(ToolInputSchema::Json(json_to_document(&tool.input_schema)))
+                    .build()?,
+            ));
+        }
+        Ok(config.build()?)
+    }
+
+    /// Makes the model respond by calling a tool whose input is the schema, so the reply
+    /// is json matching it. The built in tools aren't offered alongside
+    fn response_tool_config(schema: &serde_json::Value) -> Result<ToolConfiguration> {
+        Ok(ToolConfiguration::builder()
+            .tools(Tool::ToolSpec(
+                ToolSpecification::builder()
+                    .name(RESPONSE_TOOL)
+                    .description("Respond to the request with json matching the input schema")
+                    .input_schema(ToolInputSchema::Json(json_to_document(schema)))
+                    .build()?,
+            ))
+            .tool_choice(ToolChoice::Tool(
+                SpecificToolChoice::builder().name(RESPONSE_TOOL).build()?,
+            ))
+            .build()?)
+    }
+
+    /// Reads one assistant turn from the stream, collecting its text and tool calls
+    /// into `turn`, which keeps what arrived if the read is cancelled. `requested` is when
+    /// the conversation was sent, for the time to the first token
+    async fn read_stream(
+        mut stream: EventReceiver<ConverseStreamOutput, ConverseStreamOutputError>,
+        usage: &mut Usage,
+        turn: &mut Turn,
+        requested: Instant,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<()> {
+        info!("Starting response stream");
+        loop {
+            let token = stream.recv().instrument(trace_span!("stream_event")).await;
+            match token {
+                Ok(Some(text)) => {
+                    debug!("Received token");
+                    match &text {
+                        ConverseStreamOutput::Metadata(metadata) => {
+                            if let Some(tokens) = metadata.usage() {
+                                usage.prompt_tokens += tokens.input_tokens().max(0) as usize;
+                                usage.completion_tokens += tokens.output_tokens().max(0) as usize;
+                                usage.cache_read_tokens +=
+                                    tokens.cache_read_input_tokens().unwrap_or(0).max(0) as usize;
+                                usage.cache_write_tokens +=
+                                    tokens.cache_write_input_tokens().unwrap_or(0).max(0) as usize;
+                            }
+                        }
+                        ConverseStreamOutput::ContentBlockStart(event) => {
+                            if let Some(ContentBlockStart::ToolUse(start)) = event.start() {
+                                turn.tool_uses.push(PendingToolUse {
+                                    id: start.tool_use_id().to_string(),
+                                    name: start.name().to_string(),
+                                    input: String::new(),
+                                });
+                            }
+                        }
+                        ConverseStreamOutput::ContentBlockDelta(event) => {
+                            if let (Some(ContentBlockDelta::ToolUse(delta)), Some(tool_use)) =
+                                (event.delta(), turn.tool_uses.last_mut())
+                            {
+                                tool_use.input.push_str(delta.input());
+                            }
+                        }
+                        _ => {}
+                    }
+                    let next = BedrockAiBackend::get_converse_output_text(text);
+                    match next {
+                        Ok(text) => {
+                            debug!("{}", text);
+                            if !text.is_empty() && usage.time_to_first_token.is_none() {
+                                usage.time_to_first_token = Some(requested.elapsed());
+                            }
+                            if !text.is_empty() {
+                                on_token(&text);
+                            }
+                            turn.text.push_str(&text);
+                        }
+                        Err(e) => {
+                            let string_clone = e
+                                .meta()
+                                .message()
+                                .unwrap_or("Unable to see stream error message")
+                                .to_string();
+                            return Err(anyhow::anyhow!(string_clone));
+                        }
+                    }
+                }
+                // means the stream is complete
+                Ok(None) => break,
+                Err(e) => {
+                    if let Some(error) = e.as_service_error() {
+                        return Err(anyhow::anyhow!(error
+                            .meta()
+                            .message()
+                            .unwrap_or("Unable to open stream error message")
+                            .to_string()));
+                    }
+                    anyhow::bail!("{}", messages::Message::UnknownStreamError);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the tool calls of a turn, returning the assistant message that made them
+    /// and the user message carrying their results, redacted like the prompt
+    fn run_tools(&self, turn: Turn) -> Result<(Message, Message)> {
+        let mut assistant = Message::builder().role(ConversationRole::Assistant);
+        if !turn.text.is_empty() {
+            assistant = assistant.content(ContentBlock::Text(turn.text));
+        }
+        let mut results = Message::builder().role(ConversationRole::User);
+        for tool_use in turn.tool_uses {
+            let parsed = if tool_use.input.trim().is_empty() {
+                Ok(serde_json::Value::Object(Default::default()))
+            } else {
+                serde_json::from_str(&tool_use.input)
+            };
+            // failures go back to the model so it can try something else
+            let (input, result) = match parsed {
+                Ok(input) => {
+                    let result = tools::run_tool(&tool_use.name, &input);
+                    (input, result)
+                }
+                Err(e) => (
+                    serde_json::Value::Object(Default::default()),
+                    Err(anyhow::anyhow!(
+                        "{}",
+                        messages::Message::InvalidToolInput(&e.to_string())
+                    )),
+                ),
+            };
+            let (output, status) = match result {
+                Ok(output) => (output, ToolResultStatus::Success),
+                Err(e) => (format!("{:#}", e), ToolResultStatus::Error),
+            };
+            let output = match &self.redactor {
+                Some(redactor) => redacting::redact(redactor, "bedrock", &output)?,
+                None => output,
+            };
+            debug!("Tool {} returned {}", tool_use.name, output);
+            assistant = assistant.content(ContentBlock::ToolUse(
+                ToolUseBlock::builder()
+                    .tool_use_id(&tool_use.id)
+                    .name(tool_use.name)
+                    .input(json_to_document(&input))
+                    .build()?,
+            ));
+            results = results.content(ContentBlock::ToolResult(
+                ToolResultBlock::builder()
+                    .tool_use_id(tool_use.id)
+                    .content(ToolResultContentBlock::Text(output))
+                    .status(status)
+                    .build()?,
+            ));
+        }
+        Ok((assistant.build()?, results.build()?))
     }
 
     fn get_converse_output_text(
@@ -63,79 +407,246 @@ impl BedrockAiBackend {
 
 impl AiBackend for BedrockAiBackend {
     fn invoke(&self, prompt: String) -> Result<String> {
+        self.converse(&[ChatMessage::user(prompt)], &mut |_| {})
+    }
+
+    fn invoke_conversation(&self, messages: &[ChatMessage], n: usize) -> Result<Vec<String>> {
+        (0..n)
+            .map(|_| self.converse(messages, &mut |_| {}))
+            .collect()
+    }
+
+    fn stream_conversation(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        self.converse(messages, on_token)
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        self.usage.lock().ok().map(|usage| *usage)
+    }
+}
+
+impl BedrockAiBackend {
+    /// The bedrock-runtime client, talking to `endpoint_url` instead of the region's
+    /// public endpoint when it's set
+    fn client(&self, sdk_config: &SdkConfig) -> Client {
+        match &self.settings.aws_settings.endpoint_url {
+            Some(endpoint_url) => {
+                info!("Using the Bedrock endpoint {}", endpoint_url);
+                Client::from_conf(
+                    aws_sdk_bedrockruntime::config::Builder::from(sdk_config)
+                        .endpoint_url(endpoint_url)
+                        .build(),
+                )
+            }
+            None => Client::new(sdk_config),
+        }
+    }
+
+    /// Sends the conversation and returns the reply, running tool calls along the way and
+    /// handing each piece of text to `on_token` as it arrives
+    fn converse(
+        &self,
+        conversation: &[ChatMessage],
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
         // Clone the necessary fields to move into the async block
-        let prompt = prompt.clone();
-        let region = String::from(self.settings.aws_settings.region.as_str());
-        info!("Prompt input is: {}", prompt);
-        info!("Using region: {}", region);
+        let model_id = self.settings.aws_settings.model_id.clone();
+        // primary region first, then the fallbacks in order
+        let regions = std::iter::once(&self.settings.aws_settings.region)
+            .chain(self.settings.aws_settings.fallback_regions.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        let tool_config = match &self.settings.response_schema {
+            Some(schema) => Some(Self::response_tool_config(schema)?),
+            None if self.settings.aws_settings.tools => Some(Self::tool_config()?),
+            None => None,
+        };
+        if let Some(last) = conversation.last() {
+            info!("Prompt input is: {}", last.content);
+        }
+        let prompt = std::iter::once(self.system_prompt.as_str())
+            .chain(conversation.iter().map(|message| message.content.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        budget::check(&self.settings, &model_id, &prompt)?;
+        let timeout_secs = self.settings.request_timeout_secs;
+        let stop_at = deadline(timeout_secs);
+        let no_response = |stopped: Cutoff| match stopped {
+            Cutoff::Timeout => {
+                anyhow::anyhow!("{}", messages::Message::BedrockTimedOut(timeout_secs))
+            }
+            Cutoff::Interrupt => anyhow::anyhow!("{}", messages::Message::BedrockInterrupted),
+        };
+        info!("Using model: {}", model_id);
+        let cache = self.caches_prompt();
+        let inference_config = self.inference_config();
+        let mfa_code = aws::mfa_code(&self.settings.aws_settings)?;
 
         let result = tokio::runtime::Runtime::new()?.block_on(async {
-            let sdk_config = aws_config::defaults(BehaviorVersion::latest())
-                .region(Region::new(region))
-                .load()
-                .await;
-            info!("Creating bedrock client");
-            let client = Client::new(&sdk_config);
-            info!("Client created");
-            let response = client
-                .converse_stream()
-                .model_id("anthropic.claude-3-haiku-20240307-v1:0")
-                .messages(
-                    Message::builder()
-                        .role(ConversationRole::User)
-                        .content(ContentBlock::Text(prompt))
-                        .build()
-                        .map_err(|_| anyhow::anyhow!("failed to build message"))?,
+            let mut messages = Self::conversation_messages(conversation, cache)?;
+            let requested = Instant::now();
+            let base_config = within(
+                stop_at,
+                aws::load(&self.settings.aws_settings, mfa_code).instrument(info_span!("network")),
+            )
+            .await
+            .map_err(no_response)??;
+            let mut connection = None;
+            for (attempt, region) in regions.iter().enumerate() {
+                info!("Using region: {}", region);
+                let sdk_config = aws::in_region(&base_config, region);
+                info!("Creating bedrock client");
+                let client = self.client(&sdk_config);
+                info!("Client created");
+                let result = within(
+                    stop_at,
+                    Self::request(
+                        &client,
+                        &model_id,
+                        &self.system_prompt,
+                        &messages,
+                        tool_config.as_ref(),
+                        inference_config.as_ref(),
+                        cache,
+                    )?
+                    .send()
+                    .instrument(info_span!("network")),
                 )
-                .set_system(Some(vec![SystemContentBlock::Text(
-                    SYSTEM_PROMPT.to_string(),
-                )]))
-                .send()
                 .await
-                .map_err(|e| anyhow::anyhow!("Failed to send message: {:?}", e))?;
-            info!("Response received");
-            let mut stream = response.stream;
-
-            let mut response_text = String::new();
-            info!("Starting response stream");
-            loop {
-                let token = stream.recv().await;
-                match token {
-                    Ok(Some(text)) => {
-                        debug!("Received token");
-                        let next = BedrockAiBackend::get_converse_output_text(text);
-                        match next {
-                            Ok(text) => {
-                                debug!("{}", text);
-                                response_text.push_str(&text);
-                            }
-                            Err(e) => {
-                                let string_clone = e
-                                    .meta()
-                                    .message()
-                                    .unwrap_or("Unable to see stream error message")
-                                    .to_string();
-                                return Err(anyhow::anyhow!(string_clone));
-                            }
-                        }
+                .map_err(no_response)?;
+                match result {
+                    Ok(output) => {
+                        connection = Some((client, output));
+                        break;
+                    }
+                    Err(e) if attempt + 1 < regions.len() && Self::is_capacity_error(&e) => {
+                        warn!("{} is out of capacity, retrying in the next region", region);
+                        debug!("{:?}", e);
                     }
-                    // means the stream is complete
-                    Ok(None) => break,
                     Err(e) => {
-                        if let Some(error) = e.as_service_error() {
-                            return Err(anyhow::anyhow!(error
-                                .meta()
-                                .message()
-                                .unwrap_or("Unable to open stream error message")
-                                .to_string()));
-                        }
-                        anyhow::bail!("Unable to see stream error message");
+                        return Err(anyhow::anyhow!(
+                            "{}",
+                            messages::Message::SendFailed(&format!("{:?}", e))
+                        ))
+                    }
+                }
+            }
+            let (client, mut response) = connection
+                .ok_or_else(|| anyhow::anyhow!("{}", messages::Message::NoBedrockRegions))?;
+            let start_gen = Instant::now();
+            info!("Response received");
+            let mut usage = Usage::default();
+            let mut tool_rounds = 0;
+            let response_text = loop {
+                let mut turn = Turn::default();
+                let read = within(
+                    stop_at,
+                    Self::read_stream(response.stream, &mut usage, &mut turn, requested, on_token)
+                        .instrument(info_span!("generation")),
+                )
+                .await;
+                match read {
+                    Ok(read) => read?,
+                    Err(stopped) => {
+                        report_cutoff(stopped, timeout_secs);
+                        break turn.text;
                     }
                 }
+                if turn.tool_uses.is_empty() {
+                    break turn.text;
+                }
+                if let Some(respond) = turn.tool_uses.iter().find(|t| t.name == RESPONSE_TOOL) {
+                    break respond.input.clone();
+                }
+                tool_rounds += 1;
+                if tool_rounds > MAX_TOOL_ROUNDS {
+                    anyhow::bail!("{}", messages::Message::TooManyToolRounds(MAX_TOOL_ROUNDS));
+                }
+                let (assistant, results) = info_span!("tools").in_scope(|| self.run_tools(turn))?;
+                messages.push(assistant);
+                messages.push(results);
+                response = within(
+                    stop_at,
+                    Self::request(
+                        &client,
+                        &model_id,
+                        &self.system_prompt,
+                        &messages,
+                        tool_config.as_ref(),
+                        inference_config.as_ref(),
+                        cache,
+                    )?
+                    .send()
+                    .instrument(info_span!("network")),
+                )
+                .await
+                .map_err(no_response)?
+                .map_err(|e| {
+                    anyhow::anyhow!(
+                        "{}",
+                        messages::Message::ToolResultsFailed(&format!("{:?}", e))
+                    )
+                })?;
+            };
+            usage.duration = start_gen.elapsed();
+            usage.cost = budget::price(&self.settings, &model_id).map(|price| {
+                price.cost(usage.prompt_tokens, usage.completion_tokens)
+                    + price.cache_cost(usage.cache_read_tokens, usage.cache_write_tokens)
+            });
+            if let Some(cost) = usage.cost {
+                if let Err(e) = Spending::open().record(cost) {
+                    warn!("Unable to record spending: {:?}", e);
+                }
             }
+            self.usage
+                .lock()
+                .map_err(|_| anyhow::anyhow!("{}", messages::Message::LockPoisoned("usage")))?
+                .add(usage);
             Ok(response_text)
         })?;
 
         Ok(result)
     }
 }
+
+/// Awaits the future, giving up on Ctrl-C or at `stop_at` when there is one.
+/// Dropping the future cancels the request or closes the stream
+async fn within<F: Future>(stop_at: Option<Instant>, future: F) -> Result<F::Output, Cutoff> {
+    tokio::pin!(future);
+    loop {
+        tokio::select! {
+            output = &mut future => return Ok(output),
+            _ = tokio::time::sleep(CUTOFF_POLL_INTERVAL) => {
+                if let Some(stopped) = cutoff(stop_at) {
+                    return Err(stopped);
+                }
+            }
+        }
+    }
+}
+
+/// Converts tool input and schemas between serde_json and the smithy document model
+fn json_to_document(value: &serde_json::Value) -> Document {
+    match value {
+        serde_json::Value::Null => Document::Null,
+        serde_json::Value::Bool(b) => Document::Bool(*b),
+        serde_json::Value::Number(n) => Document::Number(match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) => Number::PosInt(u),
+            (_, Some(i)) => Number::NegInt(i),
+            _ => Number::Float(n.as_f64().unwrap_or_default()),
+        }),
+        serde_json::Value::String(s) => Document::String(s.clone()),
+        serde_json::Value::Array(items) => {
+            Document::Array(items.iter().map(json_to_document).collect())
+        }
+        serde_json::Value::Object(map) => Document::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), json_to_document(v)))
+                .collect(),
+        ),
+    }
+}
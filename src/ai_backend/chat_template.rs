@@ -0,0 +1,91 @@
+use serde::Deserialize;
+
+use super::common::{flatten_conversation, ChatMessage, Role};
+use super::local::WhichModel;
+
+/// How the turns of a conversation are laid out in a local model's prompt. Instruct models
+/// answer far better in the format they were tuned on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatTemplate {
+    /// The system prompt as a paragraph followed by the conversation as Human/Assistant
+    /// lines, for base models such as phi-2 that continue text rather than chat
+    Plain,
+    /// `<|system|>`, `<|user|>` and `<|assistant|>` turns closed by `<|end|>`, Phi-3's
+    Phi3,
+    /// `<|im_start|>role` turns closed by `<|im_end|>`, used by Qwen and others
+    ChatMl,
+}
+
+impl ChatTemplate {
+    /// The template the `chat_template` of the model's tokenizer_config.json is written
+    /// for, the built-in one of the model otherwise. Jinja templates aren't run, they're
+    /// recognized by their markers
+    pub fn detect(tokenizer_config: Option<&str>, model: WhichModel) -> Self {
+        let template = tokenizer_config
+            .and_then(|config| serde_json::from_str::<serde_json::Value>(config).ok())
+            .and_then(|config| {
+                config
+                    .get("chat_template")
+                    .map(|template| template.to_string())
+            });
+        match template {
+            Some(template) if template.contains("<|im_start|>") => ChatTemplate::ChatMl,
+            Some(template) if template.contains("<|user|>") => ChatTemplate::Phi3,
+            _ => match model {
+                WhichModel::V2 => ChatTemplate::Plain,
+                WhichModel::V3 => ChatTemplate::Phi3,
+            },
+        }
+    }
+
+    /// The prompt for the conversation, ending where the assistant's answer starts
+    pub fn format(self, system_prompt: &str, messages: &[ChatMessage]) -> String {
+        if self == ChatTemplate::Plain {
+            let conversation = flatten_conversation(messages);
+            return match system_prompt.trim() {
+                "" => conversation,
+                system_prompt => format!("{}\n\n{}", system_prompt, conversation),
+            };
+        }
+        let mut prompt = String::new();
+        if !system_prompt.trim().is_empty() {
+            prompt.push_str(&self.turn("system", system_prompt));
+        }
+        for message in messages {
+            let role = match message.role {
+                Role::User => "user",
+                Role::Assistant => "assistant",
+            };
+            prompt.push_str(&self.turn(role, &message.content));
+        }
+        prompt.push_str(&self.start("assistant"));
+        prompt
+    }
+
+    /// The token closing the assistant's turn, where its answer ends
+    pub fn end_of_turn(self) -> Option<&'static str> {
+        match self {
+            ChatTemplate::Plain => None,
+            ChatTemplate::Phi3 => Some("<|end|>"),
+            ChatTemplate::ChatMl => Some("<|im_end|>"),
+        }
+    }
+
+    fn turn(self, role: &str, content: &str) -> String {
+        let end = match self {
+            ChatTemplate::Plain => "\n",
+            ChatTemplate::Phi3 => "<|end|>\n",
+            ChatTemplate::ChatMl => "<|im_end|>\n",
+        };
+        format!("{}{}{}", self.start(role), content.trim(), end)
+    }
+
+    fn start(self, role: &str) -> String {
+        match self {
+            ChatTemplate::Plain => String::new(),
+            ChatTemplate::Phi3 => format!("<|{}|>\n", role),
+            ChatTemplate::ChatMl => format!("<|im_start|>{}\n", role),
+        }
+    }
+}
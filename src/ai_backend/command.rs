@@ -0,0 +1,167 @@
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde_json::json;
+use tracing::{debug, info, info_span, warn};
+
+use super::common::{cutoff, deadline, report_cutoff, AiBackend, ChatMessage, Cutoff};
+use crate::credentials;
+use crate::interrupt;
+use crate::messages::Message;
+use crate::settings::CommandBackendSettings;
+
+/// How often to check whether the command has exited, or has to be stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Runs an external program as the model. The request is written to its stdin as
+/// `{"system": "...", "prompt": "...", "messages": [{"role", "content"}]}` and its stdout
+/// is the completion. `messages` holds the whole conversation when continuing a session.
+pub struct CommandAiBackend {
+    settings: CommandBackendSettings,
+    system_prompt: String,
+    /// The command is killed after this many seconds, 0 for no limit
+    timeout_secs: u64,
+}
+
+impl CommandAiBackend {
+    pub fn new(settings: CommandBackendSettings, system_prompt: String, timeout_secs: u64) -> Self {
+        Self {
+            settings,
+            system_prompt,
+            timeout_secs,
+        }
+    }
+}
+
+impl AiBackend for CommandAiBackend {
+    fn invoke(&self, prompt: String) -> Result<String> {
+        self.run(&[ChatMessage::user(prompt)])
+    }
+
+    fn invoke_conversation(&self, messages: &[ChatMessage], n: usize) -> Result<Vec<String>> {
+        (0..n).map(|_| self.run(messages)).collect()
+    }
+}
+
+impl CommandAiBackend {
+    /// Runs the command once for the conversation, the last message is the prompt
+    fn run(&self, messages: &[ChatMessage]) -> Result<String> {
+        let request = json!({
+            "system": self.system_prompt,
+            "prompt": messages.last().map(|m| m.content.as_str()).unwrap_or_default(),
+            "messages": messages,
+        });
+        info!("Running command backend {}", self.settings.cmd);
+        let _generation = info_span!("generation").entered();
+        let mut command = Command::new("sh");
+        if let Some(name) = &self.settings.credential {
+            let key = credentials::get(name)?
+                .ok_or_else(|| anyhow::anyhow!("{}", Message::NoKeyringKeyAdd(name)))?;
+            command.env("AI_API_KEY", key);
+        }
+        let mut child = command
+            .arg("-c")
+            .arg(&self.settings.cmd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Unable to run command backend {}", self.settings.cmd))?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::NoStdin("the command backend")))?;
+        // read on threads so a command that's killed at the deadline still leaves its output
+        let stdout = PipeReader::spawn(child.stdout.take());
+        let stderr = PipeReader::spawn(child.stderr.take());
+        // written on a thread too, a command that answers before reading the whole request
+        // or hangs would otherwise block it before the deadline is checked. Dropping stdin
+        // closes it so the wrapper sees the end of the request
+        let request = request.to_string();
+        let writer = std::thread::spawn(move || stdin.write_all(request.as_bytes()));
+        let stop_at = deadline(self.timeout_secs);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break Ok(status);
+            }
+            if let Some(stopped) = cutoff(stop_at) {
+                warn!("Killing command backend ({:?})", stopped);
+                child.kill()?;
+                child.wait()?;
+                break Err(stopped);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        };
+        // Ctrl-C reaches the command too, it usually exits from it before it's killed
+        let status = match status {
+            Ok(status) if !status.success() && interrupt::interrupted() => Err(Cutoff::Interrupt),
+            status => status,
+        };
+        // not joined: a command that exits without reading the request closes the pipe,
+        // which isn't an error, and a grandchild holding it open mustn't block the result
+        drop(writer);
+        let stdout = stdout.finish(status.is_ok());
+        let stderr = stderr.finish(status.is_ok());
+        debug!(
+            "Command backend stderr: {}",
+            String::from_utf8_lossy(&stderr)
+        );
+        match status {
+            Err(stopped) => report_cutoff(stopped, self.timeout_secs),
+            Ok(status) if !status.success() => anyhow::bail!(
+                "{}",
+                Message::CommandBackendFailed(
+                    &self.settings.cmd,
+                    status.code().unwrap_or(-1),
+                    String::from_utf8_lossy(&stderr).trim()
+                )
+            ),
+            Ok(_) => {}
+        }
+        Ok(String::from_utf8_lossy(&stdout).trim().to_string())
+    }
+}
+
+/// A pipe read on a thread into a shared buffer, so the output so far can be taken
+/// before the pipe closes: a grandchild of a killed command may keep it open
+pub(super) struct PipeReader {
+    output: Arc<Mutex<Vec<u8>>>,
+    thread: JoinHandle<()>,
+}
+
+impl PipeReader {
+    pub(super) fn spawn(pipe: Option<impl Read + Send + 'static>) -> Self {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let buffer = output.clone();
+        let thread = std::thread::spawn(move || {
+            let Some(mut pipe) = pipe else {
+                return;
+            };
+            let mut chunk = [0; 4096];
+            while let Ok(read) = pipe.read(&mut chunk) {
+                if read == 0 {
+                    break;
+                }
+                if let Ok(mut buffer) = buffer.lock() {
+                    buffer.extend_from_slice(&chunk[..read]);
+                }
+            }
+        });
+        Self { output, thread }
+    }
+
+    /// The output read so far, waiting for the pipe to close when the command exited
+    pub(super) fn finish(self, exited: bool) -> Vec<u8> {
+        if exited {
+            let _ = self.thread.join();
+        }
+        self.output
+            .lock()
+            .map(|mut output| std::mem::take(&mut *output))
+            .unwrap_or_default()
+    }
+}
@@ -1,5 +1,213 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::interrupt;
+use crate::logging::NOTICE;
+use crate::messages::Message;
+
+/// Token counts and timing for the generations a backend has run
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    /// Time spent generating, excluding model loading
+    pub duration: Duration,
+    /// Estimated cost in USD, None for backends that are free to run
+    pub cost: Option<f64>,
+    /// Time from the request to the first generated token, of the first generation
+    pub time_to_first_token: Option<Duration>,
+    /// Prompt tokens read from Bedrock's prompt cache, not counted in `prompt_tokens`
+    pub cache_read_tokens: usize,
+    /// Prompt tokens written to Bedrock's prompt cache, not counted in `prompt_tokens`
+    pub cache_write_tokens: usize,
+    /// Seed the local model sampled the last generation with, to reproduce it with --seed
+    pub seed: Option<u64>,
+}
+
+impl Usage {
+    /// Adds another generation's usage onto this one
+    pub fn add(&mut self, other: Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.duration += other.duration;
+        self.cost = match (self.cost, other.cost) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0.) + b.unwrap_or(0.)),
+        };
+        self.time_to_first_token = self.time_to_first_token.or(other.time_to_first_token);
+        self.cache_read_tokens += other.cache_read_tokens;
+        self.cache_write_tokens += other.cache_write_tokens;
+        self.seed = other.seed.or(self.seed);
+    }
+
+    pub fn tokens_per_second(&self) -> f64 {
+        if self.duration.is_zero() {
+            0.
+        } else {
+            self.completion_tokens as f64 / self.duration.as_secs_f64()
+        }
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "prompt tokens: {}, completion tokens: {}, {:.2} tokens/s, {:.2?}",
+            self.prompt_tokens,
+            self.completion_tokens,
+            self.tokens_per_second(),
+            self.duration
+        )?;
+        if let Some(first_token) = self.time_to_first_token {
+            write!(f, ", first token after {:.2?}", first_token)?;
+        }
+        if self.cache_read_tokens > 0 || self.cache_write_tokens > 0 {
+            write!(
+                f,
+                ", cached prompt tokens: {} read, {} written",
+                self.cache_read_tokens, self.cache_write_tokens
+            )?;
+        }
+        if let Some(seed) = self.seed {
+            write!(f, ", seed: {}", seed)?;
+        }
+        if let Some(cost) = self.cost {
+            write!(f, ", estimated cost: ${:.5}", cost)?;
+        }
+        Ok(())
+    }
+}
+
+/// When a request started now has to stop, None when `timeout_secs` is 0 (no limit)
+pub fn deadline(timeout_secs: u64) -> Option<Instant> {
+    (timeout_secs > 0).then(|| Instant::now() + Duration::from_secs(timeout_secs))
+}
+
+/// Why a generation stopped before it finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cutoff {
+    /// `request_timeout_secs` ran out
+    Timeout,
+    /// Ctrl-C was pressed
+    Interrupt,
+}
+
+/// Whether the generation has to stop now, because of Ctrl-C or the deadline
+pub fn cutoff(stop_at: Option<Instant>) -> Option<Cutoff> {
+    if interrupt::interrupted() {
+        Some(Cutoff::Interrupt)
+    } else if stop_at.is_some_and(|stop_at| Instant::now() >= stop_at) {
+        Some(Cutoff::Timeout)
+    } else {
+        None
+    }
+}
+
+/// Set when a generation was cut short, so its partial output isn't cached
+static CUT_SHORT: AtomicBool = AtomicBool::new(false);
+
+/// Tells the user the generation was cut short and what follows is partial output
+pub fn report_cutoff(cutoff: Cutoff, timeout_secs: u64) {
+    CUT_SHORT.store(true, Ordering::SeqCst);
+    match cutoff {
+        Cutoff::Timeout => info!(target: NOTICE, "{}", Message::TimedOut(timeout_secs)),
+        Cutoff::Interrupt => info!(target: NOTICE, "{}", Message::Interrupted),
+    }
+}
+
+/// Whether a generation was cut short since the last call
+pub fn take_cut_short() -> bool {
+    CUT_SHORT.swap(false, Ordering::SeqCst)
+}
+
+/// Who sent a message in a conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+/// One turn of a conversation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: content.into(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.into(),
+        }
+    }
+}
+
+/// Flattens a conversation into a single prompt for backends without multi-turn support,
+/// in the same Human/Assistant format as the system prompt's example.
+/// A conversation of a single message is just that message
+pub fn flatten_conversation(messages: &[ChatMessage]) -> String {
+    if let [message] = messages {
+        return message.content.clone();
+    }
+    let mut prompt = String::new();
+    for message in messages {
+        let speaker = match message.role {
+            Role::User => "Human",
+            Role::Assistant => "Assistant",
+        };
+        prompt.push_str(&format!("{}: {}\n", speaker, message.content.trim()));
+    }
+    prompt.push_str("Assistant:");
+    prompt
+}
 
 pub trait AiBackend {
     fn invoke(&self, prompt: String) -> Result<String>;
+
+    /// Generates `n` alternative responses for the same prompt.
+    /// Backends without native n-sampling just invoke the model `n` times.
+    fn invoke_n(&self, prompt: String, n: usize) -> Result<Vec<String>> {
+        (0..n).map(|_| self.invoke(prompt.clone())).collect()
+    }
+
+    /// Generates `n` responses continuing the conversation, whose last message is the new
+    /// user turn. By default the conversation is flattened into a single prompt
+    fn invoke_conversation(&self, messages: &[ChatMessage], n: usize) -> Result<Vec<String>> {
+        self.invoke_n(flatten_conversation(messages), n)
+    }
+
+    /// Generates one response continuing the conversation, handing each piece of it to
+    /// `on_token` as it's generated. Backends that can't stream hand over the whole response
+    fn stream_conversation(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        let response = self
+            .invoke_conversation(messages, 1)?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::NoOutput))?;
+        on_token(&response);
+        Ok(response)
+    }
+
+    /// Total usage of all the invocations so far, if the backend tracks it
+    fn usage(&self) -> Option<Usage> {
+        None
+    }
 }
@@ -0,0 +1,157 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+use tracing::{debug, info};
+
+use super::common::{AiBackend, ChatMessage, Role, Usage};
+use crate::messages::Message;
+use crate::settings::HooksSettings;
+
+/// A backend whose prompts and responses are piped through the programs of the `hooks`
+/// setting, prompts before redaction and responses after the placeholders are restored
+pub struct HookedAiBackend {
+    inner: Box<dyn AiBackend>,
+    hooks: HooksSettings,
+    backend: String,
+}
+
+impl HookedAiBackend {
+    pub fn new(inner: Box<dyn AiBackend>, hooks: HooksSettings, backend: &str) -> Self {
+        Self {
+            inner,
+            hooks,
+            backend: backend.to_string(),
+        }
+    }
+
+    fn pre_prompt(&self, prompt: &str) -> Result<String> {
+        match &self.hooks.pre_prompt {
+            Some(hook) => run_hook(hook, "pre_prompt", &self.backend, prompt),
+            None => Ok(prompt.to_string()),
+        }
+    }
+
+    fn post_response(&self, response: &str) -> Result<String> {
+        match &self.hooks.post_response {
+            Some(hook) => run_hook(hook, "post_response", &self.backend, response),
+            None => Ok(response.to_string()),
+        }
+    }
+
+    fn post_responses(&self, responses: Vec<String>) -> Result<Vec<String>> {
+        responses
+            .iter()
+            .map(|response| self.post_response(response))
+            .collect()
+    }
+
+    /// The conversation with every user message through the pre_prompt hook, so earlier
+    /// turns are sent the way they were the first time
+    fn pre_messages(&self, messages: &[ChatMessage]) -> Result<Vec<ChatMessage>> {
+        messages
+            .iter()
+            .map(|message| {
+                Ok(ChatMessage {
+                    role: message.role,
+                    content: match message.role {
+                        Role::User => self.pre_prompt(&message.content)?,
+                        Role::Assistant => message.content.clone(),
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+impl AiBackend for HookedAiBackend {
+    fn invoke(&self, prompt: String) -> Result<String> {
+        let response = self.inner.invoke(self.pre_prompt(&prompt)?)?;
+        self.post_response(&response)
+    }
+
+    fn invoke_n(&self, prompt: String, n: usize) -> Result<Vec<String>> {
+        let responses = self.inner.invoke_n(self.pre_prompt(&prompt)?, n)?;
+        self.post_responses(responses)
+    }
+
+    fn invoke_conversation(&self, messages: &[ChatMessage], n: usize) -> Result<Vec<String>> {
+        let responses = self
+            .inner
+            .invoke_conversation(&self.pre_messages(messages)?, n)?;
+        self.post_responses(responses)
+    }
+
+    fn stream_conversation(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        let messages = self.pre_messages(messages)?;
+        if self.hooks.post_response.is_none() {
+            return self.inner.stream_conversation(&messages, on_token);
+        }
+        // the hook needs the whole response, so it's shown once it has run
+        let response = self
+            .inner
+            .invoke_conversation(&messages, 1)?
+            .pop()
+            .unwrap_or_default();
+        let response = self.post_response(&response)?;
+        on_token(&response);
+        Ok(response)
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        self.inner.usage()
+    }
+}
+
+/// Runs the hook with the text on its stdin and returns its stdout. It gets the hook's name
+/// in $AI_HOOK and the backend's in $AI_BACKEND, its stderr goes to the terminal
+fn run_hook(hook: &Path, name: &str, backend: &str, text: &str) -> Result<String> {
+    info!("Running the {} hook {}", name, hook.display());
+    let mut child = Command::new(hook)
+        .env("AI_HOOK", name)
+        .env("AI_BACKEND", backend)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("Unable to run the {} hook {}", name, hook.display()))?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("{}", Message::NoStdin(&hook.display().to_string())))?;
+    // written on a thread, a hook that writes before reading everything would block on a
+    // full pipe otherwise
+    let input = text.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("Unable to run the {} hook {}", name, hook.display()))?;
+    // a hook that exits without reading its input closes the pipe, which isn't an error
+    let _ = writer.join();
+    if !output.status.success() {
+        anyhow::bail!(
+            "{}",
+            Message::HookFailed(
+                name,
+                &hook.display().to_string(),
+                output.status.code().unwrap_or(-1)
+            )
+        );
+    }
+    let result = String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches(['\n', '\r'])
+        .to_string();
+    if result.trim().is_empty() {
+        anyhow::bail!(
+            "{}",
+            Message::HookPrintedNothing(name, &hook.display().to_string())
+        );
+    }
+    debug!("The {} hook returned {}", name, result);
+    Ok(result)
+}
@@ -1,24 +1,35 @@
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::task::{Context, Poll};
 use std::time::Instant;
 
 use anyhow::{Error as E, Result};
 use clap::ValueEnum;
 use serde::Deserialize;
-use tracing::info;
+use tracing::{debug, info, info_span, warn};
 
 use candle_core::{DType, Device};
 use candle_nn::VarBuilder;
-use candle_transformers::models::mixformer::Config;
 use candle_transformers::models::phi::{Config as PhiConfig, Model as Phi};
 use candle_transformers::models::phi3::{Config as Phi3Config, Model as Phi3};
-use candle_transformers::models::quantized_mixformer::MixFormerSequentialForCausalLM as QMixFormer;
-use hf_hub::api::sync::{Api, ApiRepo};
-use hf_hub::{Repo, RepoType};
 use tokenizers::Tokenizer;
 
-use super::common::AiBackend;
-use crate::text_generation::{Model, TextGeneration};
+use super::chat_template::ChatTemplate;
+use super::common::{deadline, flatten_conversation, report_cutoff, AiBackend, ChatMessage, Usage};
+use crate::context::environment::{shell_kind, ShellKind};
+use crate::grammar::{Grammar, JsonGrammar, ShellGrammar};
+use crate::logging::NOTICE;
+use crate::memory::{self, Estimate};
+use crate::messages::Message;
+use crate::network::HubRepo;
+use crate::quantized_mixformer::{Config, MixFormerSequentialForCausalLM as QMixFormer};
+use crate::settings::OutputGrammar;
+use crate::sharded;
+use crate::text_generation::{Draft, Model, TextGeneration};
 use crate::Settings;
-use crate::{device, hub_load_safetensors};
+use crate::{device, parse_device};
 
 #[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq, Deserialize)]
 pub enum WhichModel {
@@ -28,48 +39,104 @@ pub enum WhichModel {
     V3,
 }
 
+impl WhichModel {
+    /// Tokens the model attends to, the prompt and the generated tokens together
+    pub fn context_length(self) -> usize {
+        match self {
+            WhichModel::V2 => 2048,
+            WhichModel::V3 => 4096,
+        }
+    }
+
+    /// The version a config.json's `model_type` is for, None for other architectures
+    pub fn from_model_type(kind: &str) -> Option<Self> {
+        match kind {
+            "phi" => Some(WhichModel::V2),
+            "phi3" => Some(WhichModel::V3),
+            _ => None,
+        }
+    }
+}
+
+/// The `model_type` of the repo's config.json, None when it doesn't say
+pub fn model_type(repo: &HubRepo) -> Result<Option<String>> {
+    let config = std::fs::read_to_string(repo.get("config.json")?)?;
+    let config: serde_json::Value = serde_json::from_str(&config)?;
+    Ok(config
+        .get("model_type")
+        .and_then(|kind| kind.as_str())
+        .map(str::to_string))
+}
+
 pub struct LocalAiBackend {
     settings: Settings,
-
+    /// Given to models whose chat template has a place for it
+    system_prompt: String,
     start: std::time::Instant,
+    usage: Mutex<Usage>,
+    /// Seed of the next generation, moved on by each one so they sample differently, e.g.
+    /// when regenerating
+    seed: AtomicU64,
+    /// Whether the next generation ends at the end of the command line
+    stop_at_newline: AtomicBool,
+    /// The model, loaded on the first invocation and kept for the next ones
+    pipeline: Mutex<Option<TextGeneration>>,
+    /// How prompts are laid out for the model, detected on first use
+    chat_template: OnceLock<ChatTemplate>,
 }
 
 impl LocalAiBackend {
-    pub fn new(settings: Settings, start: Instant) -> Self {
-        Self { settings, start }
+    pub fn new(settings: Settings, system_prompt: String, start: Instant) -> Self {
+        Self {
+            seed: AtomicU64::new(settings.local_model_config.seed),
+            stop_at_newline: AtomicBool::new(settings.local_model_config.stop_at_newline),
+            settings,
+            system_prompt,
+            start,
+            chat_template: OnceLock::new(),
+            usage: Mutex::new(Usage::default()),
+            pipeline: Mutex::new(None),
+        }
     }
 
-    pub fn load_local_model(&self) -> Result<(Model, Tokenizer, Device)> {
-        let repo = self.get_repo_for_local_model()?;
+    /// Loads the tokenizer alone, from the `tokenizer` setting or the model's repo
+    pub fn load_tokenizer(&self) -> Result<Tokenizer> {
         let tokenizer_filename = match &self.settings.local_model_config.tokenizer {
             Some(file) => std::path::PathBuf::from(file),
             None => match self.settings.local_model_config.model {
-                WhichModel::V2 | WhichModel::V3 => repo.get("tokenizer.json")?,
+                WhichModel::V2 | WhichModel::V3 => {
+                    self.get_repo_for_local_model()?.get("tokenizer.json")?
+                }
             },
         };
+        Tokenizer::from_file(tokenizer_filename).map_err(E::msg)
+    }
+
+    pub fn load_local_model(&self) -> Result<(Model, Tokenizer, Device)> {
+        let repo = self.get_repo_for_local_model()?;
+        if !self.settings.local_model_config.quantized {
+            self.check_architecture(&repo)?;
+        }
+        // fetches the files from the hub, or finds them in its cache
+        let download = info_span!("model_download").entered();
         let filenames = match &self.settings.local_model_config.weight_file {
             Some(weight_file) => vec![std::path::PathBuf::from(weight_file)],
             None => {
                 if self.settings.local_model_config.quantized {
                     match self.settings.local_model_config.model {
                         WhichModel::V2 => vec![repo.get("model-v2-q4k.gguf")?],
-                        WhichModel::V3 => anyhow::bail!(
-                            "use the quantized or quantized-phi examples for quantized phi-v3"
-                        ),
+                        WhichModel::V3 => anyhow::bail!("{}", Message::QuantizedPhi3),
                     }
                 } else {
                     match self.settings.local_model_config.model {
-                        WhichModel::V2 => {
-                            hub_load_safetensors(&repo, "model.safetensors.index.json")?
-                        }
-                        WhichModel::V3 => {
-                            hub_load_safetensors(&repo, "model.safetensors.index.json")?
-                        }
+                        WhichModel::V2 => repo.load_safetensors("model.safetensors.index.json")?,
+                        WhichModel::V3 => repo.load_safetensors("model.safetensors.index.json")?,
                     }
                 }
             }
         };
-        let tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+        let tokenizer = self.load_tokenizer()?;
+        download.exit();
 
         let config = || match self.settings.local_model_config.model {
             WhichModel::V2 => Config::v2(),
@@ -77,7 +144,31 @@ impl LocalAiBackend {
                 panic!("use the quantized or quantized-phi examples for quantized phi-v3")
             }
         };
-        let device = device(self.settings.local_model_config.cpu)?;
+        let mut devices = self.select_devices()?;
+        if devices.len() > 1 && self.settings.local_model_config.quantized {
+            info!(target: NOTICE, "{}", Message::QuantizedOnOneDevice);
+            devices.truncate(1);
+        }
+        let device = devices[0].clone();
+        let dtype = self.dtype(&device)?;
+        let estimate = self.estimate_memory(&repo, &filenames, dtype)?;
+        info!(
+            "The model needs about {:.1} GiB, {:.1} GiB of weights and {:.1} GiB of kv cache",
+            memory::gib(estimate.total()),
+            memory::gib(estimate.weights),
+            memory::gib(estimate.kv_cache)
+        );
+        let limit = memory::limit(&devices, self.settings.local_model_config.max_memory_gb);
+        if let Some(limit) = limit.filter(|limit| estimate.total() > *limit) {
+            let (needed, limit) = (memory::gib(estimate.total()), memory::gib(limit));
+            match self.quantized_fallback() {
+                Some(fallback) => {
+                    info!(target: NOTICE, "{}", Message::QuantizedFallback(needed, limit));
+                    return fallback.load_local_model();
+                }
+                None => anyhow::bail!("{}", Message::ModelTooLarge(needed, limit)),
+            }
+        }
         let model = if self.settings.local_model_config.quantized {
             let config = config();
             let vb = candle_transformers::quantized_var_builder::VarBuilder::from_gguf(
@@ -87,21 +178,24 @@ impl LocalAiBackend {
             let model = match self.settings.local_model_config.model {
                 WhichModel::V2 => QMixFormer::new_v2(&config, vb)?,
                 WhichModel::V3 => {
-                    anyhow::bail!("Quantized Phi-3 not supported")
+                    anyhow::bail!("{}", Message::QuantizedPhi3)
                 }
             };
             Model::Quantized(model)
-        } else {
-            let dtype = match &self.settings.local_model_config.dtype {
-                Some(dtype) => dtype.parse()?,
-                None => {
-                    if self.settings.local_model_config.model == WhichModel::V3 {
-                        device.bf16_default_to_f32()
-                    } else {
-                        DType::F32
-                    }
+        } else if devices.len() > 1 {
+            let config = std::fs::read_to_string(repo.get("config.json")?)?;
+            let vbs = sharded::var_builders(&filenames, dtype, &devices)?;
+            match self.settings.local_model_config.model {
+                WhichModel::V2 => {
+                    let config = serde_json::from_str(&config)?;
+                    Model::ShardedPhi(sharded::phi::Model::new(&config, &vbs)?)
                 }
-            };
+                WhichModel::V3 => {
+                    let config = serde_json::from_str(&config)?;
+                    Model::ShardedPhi3(sharded::phi3::Model::new(&config, &vbs)?)
+                }
+            }
+        } else {
             let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, dtype, &device)? };
             match self.settings.local_model_config.model {
                 WhichModel::V2 => {
@@ -121,14 +215,173 @@ impl LocalAiBackend {
             }
         };
 
-        info!("loaded the model, device: {:?}", device);
+        info!("loaded the model, devices: {:?}", devices);
         Ok((model, tokenizer, device))
     }
 
-    fn get_repo_for_local_model(&self) -> Result<ApiRepo> {
-        info!("Loading the model, parsing model from args and settings");
-        let api = Api::new()?;
-        let model_id = match &self.settings.local_model_config.model_id {
+    /// The dtype the full precision weights are loaded as
+    fn dtype(&self, device: &Device) -> Result<DType> {
+        Ok(match &self.settings.local_model_config.dtype {
+            Some(dtype) => dtype.parse()?,
+            None => {
+                if self.settings.local_model_config.model == WhichModel::V3 {
+                    device.bf16_default_to_f32()
+                } else {
+                    DType::F32
+                }
+            }
+        })
+    }
+
+    /// The memory the model will take, from the weights' size in `dtype` (a gguf's as it
+    /// is) and the kv cache for `sample_len` tokens
+    fn estimate_memory(
+        &self,
+        repo: &HubRepo,
+        filenames: &[PathBuf],
+        dtype: DType,
+    ) -> Result<Estimate> {
+        let tokens = self.settings.local_model_config.sample_len;
+        if self.settings.local_model_config.quantized {
+            return Ok(Estimate {
+                weights: memory::file_bytes(filenames),
+                kv_cache: Config::v2().kv_cache_bytes(tokens),
+            });
+        }
+        let config = std::fs::read_to_string(repo.get("config.json")?)?;
+        let config: serde_json::Value = serde_json::from_str(&config)?;
+        let dim = |key: &str| {
+            config
+                .get(key)
+                .and_then(|value| value.as_u64())
+                .unwrap_or(0)
+        };
+        Ok(Estimate {
+            weights: memory::safetensors_bytes(filenames, dtype)?,
+            kv_cache: 2
+                * dim("num_hidden_layers")
+                * dim("hidden_size")
+                * tokens as u64
+                * dtype.size_in_bytes() as u64,
+        })
+    }
+
+    /// The quantized phi-2 in place of the full precision one, when the model is the
+    /// default phi-2 rather than one picked by `model_id` or `weight_file`
+    fn quantized_fallback(&self) -> Option<LocalAiBackend> {
+        let config = &self.settings.local_model_config;
+        let default_phi2 = config.model == WhichModel::V2
+            && config.model_id.is_none()
+            && config.weight_file.is_none();
+        if config.quantized || !default_phi2 {
+            return None;
+        }
+        let mut settings = self.settings.clone();
+        settings.local_model_config.quantized = true;
+        Some(LocalAiBackend::new(
+            settings,
+            self.system_prompt.clone(),
+            self.start,
+        ))
+    }
+
+    /// Loads the model, and the draft model if there is one, ready to generate
+    fn load_pipeline(&self) -> Result<TextGeneration> {
+        info!(
+            "avx: {}, neon: {}, simd128: {}, f16c: {}",
+            candle_core::utils::with_avx(),
+            candle_core::utils::with_neon(),
+            candle_core::utils::with_simd128(),
+            candle_core::utils::with_f16c()
+        );
+        let (model, tokenizer, device) =
+            info_span!("model_load").in_scope(|| self.load_local_model())?;
+        let stop_tokens = self.stop_tokens(&tokenizer);
+        let mut pipeline = TextGeneration::new(
+            model,
+            tokenizer,
+            self.settings.local_model_config.seed,
+            self.settings.local_model_config.temperature,
+            self.settings.local_model_config.top_p,
+            self.settings.local_model_config.repeat_penalty,
+            self.settings.local_model_config.repeat_last_n,
+            self.settings.local_model_config.verbose_prompt,
+            &device,
+        );
+        pipeline.set_banned_strings(&self.settings.local_model_config.banned_strings)?;
+        // phi-2 continues the prompt, the markup of a chat template isn't part of the answer
+        pipeline.set_echo_prompt(self.chat_template() == ChatTemplate::Plain);
+        pipeline.set_stop_tokens(stop_tokens);
+        // a response schema takes the place of the configured grammar
+        let grammar = match &self.settings.response_schema {
+            Some(schema) => Some(Box::new(JsonGrammar::new(schema)) as Box<dyn Grammar>),
+            None => self.settings.local_model_config.grammar.and_then(
+                |grammar| -> Option<Box<dyn Grammar>> {
+                    match grammar {
+                        OutputGrammar::Shell if shell_kind() != ShellKind::Posix => {
+                            warn!("The shell grammar is POSIX only, not applying it");
+                            None
+                        }
+                        OutputGrammar::Shell => Some(Box::new(ShellGrammar::default())),
+                    }
+                },
+            ),
+        };
+        pipeline.set_grammar(grammar)?;
+        let draft = info_span!("draft_load").in_scope(|| self.load_draft_model(&device))?;
+        pipeline.set_draft(draft)?;
+        Ok(pipeline)
+    }
+
+    /// Loads the draft model for speculative decoding when one is configured
+    pub fn load_draft_model(&self, device: &Device) -> Result<Option<Draft>> {
+        let Some(draft) = &self.settings.local_model_config.draft else {
+            return Ok(None);
+        };
+        let config = match draft.architecture.as_str() {
+            "1.5" => Config::v1_5(),
+            "2" => Config::v2(),
+            other => anyhow::bail!("{}", Message::UnknownDraftModel(other)),
+        };
+        let weight_file = draft.weight_file.as_deref().unwrap_or("model-q4k.gguf");
+        let filename = if std::path::Path::new(weight_file).exists() {
+            std::path::PathBuf::from(weight_file)
+        } else {
+            let model_id = draft
+                .model_id
+                .clone()
+                .unwrap_or_else(|| "lmz/candle-quantized-phi".to_string());
+            let revision = draft.revision.clone().unwrap_or_else(|| "main".to_string());
+            info!("Loading draft model {model_id} revision {revision}");
+            info_span!("model_download")
+                .in_scope(|| HubRepo::model(model_id, revision)?.get(weight_file))?
+        };
+        let vb =
+            candle_transformers::quantized_var_builder::VarBuilder::from_gguf(&filename, device)?;
+        let model = match draft.architecture.as_str() {
+            "2" => QMixFormer::new_v2(&config, vb)?,
+            _ => QMixFormer::new(&config, vb)?,
+        };
+        info!("loaded the draft model {:?}", filename);
+        Ok(Some(Draft::new(model, draft.tokens)))
+    }
+
+    /// The devices to load the model on, the configured `devices` when there are any. The
+    /// layers are split over them when there are several, the input goes to the first one
+    fn select_devices(&self) -> Result<Vec<Device>> {
+        let specs = &self.settings.local_model_config.devices;
+        if specs.is_empty() {
+            return Ok(vec![device(self.settings.local_model_config.cpu)?]);
+        }
+        Ok(specs
+            .iter()
+            .map(|spec| parse_device(spec))
+            .collect::<candle_core::Result<_>>()?)
+    }
+
+    /// The HF repo the model is pulled from
+    pub fn model_id(&self) -> String {
+        match &self.settings.local_model_config.model_id {
             Some(model_id) => model_id.to_string(),
             None => {
                 if self.settings.local_model_config.quantized {
@@ -140,7 +393,39 @@ impl LocalAiBackend {
                     }
                 }
             }
+        }
+    }
+
+    /// Fails when the repo's config.json is for another architecture than the `model`
+    /// setting, e.g. a Phi-3 repo in `model_id` while `model` is 2, which would otherwise
+    /// fail on a missing tensor
+    fn check_architecture(&self, repo: &HubRepo) -> Result<()> {
+        let Some(kind) = model_type(repo)? else {
+            return Ok(());
         };
+        let model = self.settings.local_model_config.model;
+        match WhichModel::from_model_type(&kind) {
+            Some(detected) if detected == model => Ok(()),
+            Some(_) => {
+                let model = match model {
+                    WhichModel::V2 => "2",
+                    WhichModel::V3 => "3",
+                };
+                anyhow::bail!(
+                    "{}",
+                    Message::ModelArchitecture(&self.model_id(), &kind, model)
+                )
+            }
+            None => anyhow::bail!(
+                "{}",
+                Message::UnsupportedArchitecture(&self.model_id(), &kind)
+            ),
+        }
+    }
+
+    fn get_repo_for_local_model(&self) -> Result<HubRepo> {
+        info!("Loading the model, parsing model from args and settings");
+        let model_id = self.model_id();
         let revision = match &self.settings.local_model_config.revision {
             Some(rev) => rev.to_string(),
             None => {
@@ -155,46 +440,263 @@ impl LocalAiBackend {
             }
         };
         info!("Loading model {model_id} revision {revision}");
-        Ok(api.repo(Repo::with_revision(model_id, RepoType::Model, revision)))
+        HubRepo::model(model_id, revision)
+    }
+}
+
+impl LocalAiBackend {
+    /// Generates `n` responses continuing the conversation, laid out by the model's chat
+    /// template with `system_prompt`. Each piece of output goes to `on_token` as it's
+    /// generated
+    pub fn chat(
+        &self,
+        system_prompt: &str,
+        messages: &[ChatMessage],
+        n: usize,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<Vec<String>> {
+        let template = self.chat_template();
+        let prompt = template.format(system_prompt, messages);
+        // phi-2's answer continues the conversation, without the system prompt before it
+        let echo_skip = match template {
+            ChatTemplate::Plain => prompt.len() - flatten_conversation(messages).len(),
+            _ => 0,
+        };
+        self.sample(prompt, echo_skip, n, on_token)
+    }
+
+    /// The `chat_template` setting, or the one detected from the model's
+    /// tokenizer_config.json
+    pub fn chat_template(&self) -> ChatTemplate {
+        *self.chat_template.get_or_init(|| {
+            let config = &self.settings.local_model_config;
+            if let Some(template) = config.chat_template {
+                return template;
+            }
+            let tokenizer_config = self.repo_file("tokenizer_config.json");
+            let template = ChatTemplate::detect(tokenizer_config.as_deref(), config.model);
+            info!("Using the {:?} chat template", template);
+            template
+        })
+    }
+
+    /// A json file of the model's repo, None when it has none. The default quantized phi-2's
+    /// repo only holds ggufs, it isn't looked in
+    fn repo_file(&self, name: &str) -> Option<String> {
+        let config = &self.settings.local_model_config;
+        if config.quantized && config.model_id.is_none() {
+            return None;
+        }
+        let file = || -> Result<String> {
+            let path = self.get_repo_for_local_model()?.get(name)?;
+            Ok(std::fs::read_to_string(path)?)
+        };
+        file().map_err(|e| debug!("No {}: {:#}", name, e)).ok()
+    }
+
+    /// The tokens a response ends at: the `eos_token_id`s of the model's
+    /// generation_config.json, the `eos_token` of its tokenizer_config.json, the end of turn
+    /// of its chat template and `<|endoftext|>`, those the tokenizer has
+    fn stop_tokens(&self, tokenizer: &Tokenizer) -> Vec<u32> {
+        let json = |name| {
+            self.repo_file(name)
+                .and_then(|file| serde_json::from_str::<serde_json::Value>(&file).ok())
+        };
+        let mut stop_tokens = Vec::new();
+        if let Some(eos) = json("generation_config.json")
+            .as_ref()
+            .and_then(|config| config.get("eos_token_id"))
+        {
+            // a single id or a list of them
+            let ids = eos.as_array().cloned().unwrap_or_else(|| vec![eos.clone()]);
+            stop_tokens.extend(ids.iter().filter_map(|id| id.as_u64()).map(|id| id as u32));
+        }
+        let eos_token = json("tokenizer_config.json").and_then(|config| {
+            let eos = config.get("eos_token")?;
+            // a string or an added token object
+            eos.as_str()
+                .or_else(|| eos.get("content")?.as_str())
+                .map(str::to_string)
+        });
+        let names = [
+            eos_token.as_deref(),
+            self.chat_template().end_of_turn(),
+            Some("<|endoftext|>"),
+        ];
+        stop_tokens.extend(
+            names
+                .into_iter()
+                .flatten()
+                .filter_map(|name| tokenizer.token_to_id(name)),
+        );
+        let mut seen = std::collections::HashSet::new();
+        stop_tokens.retain(|token| seen.insert(*token));
+        info!("Stopping at the tokens {:?}", stop_tokens);
+        stop_tokens
+    }
+
+    /// Samples `n` candidates, handing each piece of output to `on_token` as it's generated.
+    /// The first `echo_skip` bytes of the prompt aren't echoed in front of the output
+    fn sample(
+        &self,
+        prompt: String,
+        echo_skip: usize,
+        n: usize,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<Vec<String>> {
+        let mut pipeline = self
+            .pipeline
+            .lock()
+            .map_err(|_| anyhow::anyhow!("{}", Message::LockPoisoned("model")))?;
+        let pipeline = match &mut *pipeline {
+            Some(pipeline) => pipeline,
+            None => pipeline.insert(self.load_pipeline()?),
+        };
+        let seed = self.seed.fetch_add(n as u64, Ordering::Relaxed);
+        // the model load doesn't count towards the timeout, only the token loop
+        let timeout_secs = self.settings.request_timeout_secs;
+        pipeline.set_deadline(deadline(timeout_secs));
+        pipeline.set_stop_at_newline(self.stop_at_newline.load(Ordering::Relaxed));
+        pipeline.set_echo_skip(echo_skip);
+        let runtime = tokio::runtime::Runtime::new()?;
+        let mut candidates = Vec::with_capacity(n);
+        let _generation = info_span!("generation").entered();
+        for i in 0..n {
+            let seed = seed.wrapping_add(i as u64);
+            info!("Sampling with seed {}", seed);
+            pipeline.reset(seed);
+            let mut sink = TokenSink {
+                output: Vec::new(),
+                pending: 0,
+                on_token: &mut *on_token,
+            };
+            // Use tokio runtime to run the async method
+            let (mut usage, stopped) = runtime.block_on(async {
+                pipeline
+                    .run(
+                        &prompt,
+                        self.settings.local_model_config.sample_len,
+                        &mut sink,
+                    )
+                    .await
+            })?;
+            usage.seed = Some(seed);
+            self.usage
+                .lock()
+                .map_err(|_| anyhow::anyhow!("{}", Message::LockPoisoned("usage")))?
+                .add(usage);
+            info!("generated output {} in {:?}", i + 1, self.start.elapsed());
+            candidates.push(String::from_utf8(sink.output)?);
+            if let Some(stopped) = stopped {
+                report_cutoff(stopped, timeout_secs);
+                break;
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Drops the model, freeing its memory, the next generation loads it again. Returns
+    /// whether it was loaded, a generation holding it keeps it
+    pub fn unload(&self) -> bool {
+        match self.pipeline.try_lock() {
+            Ok(mut pipeline) => pipeline.take().is_some(),
+            Err(_) => false,
+        }
+    }
+
+    /// Samples the next generation with this seed, for the daemon to use each client's
+    pub fn reseed(&self, seed: u64) {
+        self.seed.store(seed, Ordering::Relaxed);
+    }
+
+    /// Sets whether the next generation ends at the end of the command line, for the daemon
+    /// to follow each client's `stop_at_newline`
+    pub fn set_stop_at_newline(&self, stop_at_newline: bool) {
+        self.stop_at_newline
+            .store(stop_at_newline, Ordering::Relaxed);
+    }
+
+    /// The usage since the last call, for the daemon to report each request's own
+    pub fn take_usage(&self) -> Usage {
+        self.usage
+            .lock()
+            .map(|mut usage| std::mem::take(&mut *usage))
+            .unwrap_or_default()
+    }
+
+    /// Whether the model is in memory. A generation holding it counts as loaded
+    pub fn is_loaded(&self) -> bool {
+        match self.pipeline.try_lock() {
+            Ok(pipeline) => pipeline.is_some(),
+            Err(_) => true,
+        }
+    }
+}
+
+/// Collects the generated text, passing on each complete piece of UTF-8 as it's written
+struct TokenSink<'a> {
+    output: Vec<u8>,
+    /// Start of the bytes not passed on yet, the beginning of a split character
+    pending: usize,
+    on_token: &'a mut dyn FnMut(&str),
+}
+
+impl tokio::io::AsyncWrite for TokenSink<'_> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let sink = &mut *self;
+        sink.output.extend_from_slice(buf);
+        let pending = &sink.output[sink.pending..];
+        let valid = match std::str::from_utf8(pending) {
+            Ok(text) => text,
+            Err(e) => std::str::from_utf8(&pending[..e.valid_up_to()]).unwrap_or_default(),
+        };
+        if !valid.is_empty() {
+            (sink.on_token)(valid);
+            sink.pending += valid.len();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
     }
 }
 
 impl AiBackend for LocalAiBackend {
     fn invoke(&self, prompt: String) -> Result<String> {
-        info!(
-            "avx: {}, neon: {}, simd128: {}, f16c: {}",
-            candle_core::utils::with_avx(),
-            candle_core::utils::with_neon(),
-            candle_core::utils::with_simd128(),
-            candle_core::utils::with_f16c()
-        );
-        let (model, tokenizer, device) = self.load_local_model()?;
-        info!("loaded the model in {:?}", self.start.elapsed());
+        self.invoke_n(prompt, 1)?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::NoOutput))
+    }
 
-        let mut pipeline = TextGeneration::new(
-            model,
-            tokenizer,
-            self.settings.local_model_config.seed,
-            self.settings.local_model_config.temperature,
-            self.settings.local_model_config.top_p,
-            self.settings.local_model_config.repeat_penalty,
-            self.settings.local_model_config.repeat_last_n,
-            self.settings.local_model_config.verbose_prompt,
-            &device,
-        );
-        let mut string_buffer = std::io::Cursor::new(Vec::new());
-        // Use tokio runtime to run the async method
-        tokio::runtime::Runtime::new()?.block_on(async {
-            // pass in string buffer stream into run function
-            pipeline
-                .run(
-                    &prompt,
-                    self.settings.local_model_config.sample_len,
-                    &mut string_buffer,
-                )
-                .await
-        })?;
-        info!("generated the output in {:?}", self.start.elapsed());
-        Ok(String::from_utf8(string_buffer.into_inner())?)
+    /// Loads the model on the first call and samples each candidate with a different seed
+    fn invoke_n(&self, prompt: String, n: usize) -> Result<Vec<String>> {
+        self.invoke_conversation(&[ChatMessage::user(prompt)], n)
+    }
+
+    fn invoke_conversation(&self, messages: &[ChatMessage], n: usize) -> Result<Vec<String>> {
+        self.chat(&self.system_prompt, messages, n, &mut |_| {})
+    }
+
+    fn stream_conversation(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        self.chat(&self.system_prompt, messages, 1, on_token)?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::NoOutput))
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        self.usage.lock().ok().map(|usage| *usage)
     }
 }
@@ -0,0 +1,109 @@
+use anyhow::Result;
+use tracing::warn;
+
+use super::common::{AiBackend, ChatMessage, Usage};
+use crate::budget;
+use crate::quota::{self, UsageLog};
+use crate::settings::QuotaSettings;
+
+/// A backend whose requests are checked against the quotas and counted in the usage log
+pub struct MeteredAiBackend {
+    inner: Box<dyn AiBackend>,
+    quotas: QuotaSettings,
+    backend: String,
+}
+
+impl MeteredAiBackend {
+    pub fn new(inner: Box<dyn AiBackend>, quotas: QuotaSettings, backend: &str) -> Self {
+        Self {
+            inner,
+            quotas,
+            backend: backend.to_string(),
+        }
+    }
+
+    /// Sends the request when the quotas allow it and records it with its tokens: what the
+    /// backend's usage grew by, estimated from the text for backends that don't report it
+    fn metered<T>(
+        &self,
+        prompt: &str,
+        request: impl FnOnce() -> Result<T>,
+        output: impl Fn(&T) -> usize,
+    ) -> Result<T> {
+        quota::check(&self.quotas, &self.backend, prompt)?;
+        let before = self.inner.usage().map(|usage| tokens(&usage));
+        let result = request()?;
+        let used = match (before, self.inner.usage()) {
+            (Some(before), Some(after)) => tokens(&after).saturating_sub(before),
+            _ => (budget::estimate_tokens(prompt) + output(&result)) as u64,
+        };
+        if let Err(e) = UsageLog::open().record(&self.backend, used) {
+            warn!("Unable to record usage: {:?}", e);
+        }
+        Ok(result)
+    }
+}
+
+fn tokens(usage: &Usage) -> u64 {
+    (usage.prompt_tokens
+        + usage.completion_tokens
+        + usage.cache_read_tokens
+        + usage.cache_write_tokens) as u64
+}
+
+fn estimate_all(responses: &[String]) -> usize {
+    responses
+        .iter()
+        .map(|response| budget::estimate_tokens(response))
+        .sum()
+}
+
+fn conversation_text(messages: &[ChatMessage]) -> String {
+    messages
+        .iter()
+        .map(|message| message.content.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl AiBackend for MeteredAiBackend {
+    fn invoke(&self, prompt: String) -> Result<String> {
+        self.metered(
+            &prompt.clone(),
+            || self.inner.invoke(prompt),
+            |response| budget::estimate_tokens(response),
+        )
+    }
+
+    fn invoke_n(&self, prompt: String, n: usize) -> Result<Vec<String>> {
+        self.metered(
+            &prompt.clone(),
+            || self.inner.invoke_n(prompt, n),
+            |responses| estimate_all(responses),
+        )
+    }
+
+    fn invoke_conversation(&self, messages: &[ChatMessage], n: usize) -> Result<Vec<String>> {
+        self.metered(
+            &conversation_text(messages),
+            || self.inner.invoke_conversation(messages, n),
+            |responses| estimate_all(responses),
+        )
+    }
+
+    fn stream_conversation(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        self.metered(
+            &conversation_text(messages),
+            || self.inner.stream_conversation(messages, on_token),
+            |response| budget::estimate_tokens(response),
+        )
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        self.inner.usage()
+    }
+}
@@ -0,0 +1,120 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing::{info, info_span};
+
+use super::common::{cutoff, deadline, report_cutoff, AiBackend, ChatMessage, Usage};
+use crate::messages::Message;
+use crate::settings::MockBackendSettings;
+
+/// Response used without a fixture file
+const DEFAULT_RESPONSE: &str = "echo \"mock response\"";
+
+/// One canned response, used when `prompt` is part of the last message
+#[derive(Debug, Clone, Deserialize)]
+struct Fixture {
+    /// Text the prompt has to contain, entries without one match any prompt
+    prompt: Option<String>,
+    response: String,
+}
+
+/// Returns canned responses from a fixture file without any network or model, for
+/// demos and for testing the CLI. The response is streamed word by word with an
+/// optional delay so the spinner, timeouts and Ctrl-C behave like a real generation
+pub struct MockAiBackend {
+    fixtures: Vec<Fixture>,
+    delay: Duration,
+    timeout_secs: u64,
+    usage: Mutex<Usage>,
+}
+
+impl MockAiBackend {
+    /// Loads the fixture file, a json array of `{"prompt": "...", "response": "..."}`
+    pub fn new(settings: MockBackendSettings, timeout_secs: u64) -> Result<Self> {
+        let fixtures = match &settings.fixture {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .with_context(|| format!("Unable to read mock fixture {:?}", path))?;
+                serde_json::from_str(&contents)
+                    .with_context(|| format!("Invalid mock fixture {:?}", path))?
+            }
+            None => vec![Fixture {
+                prompt: None,
+                response: DEFAULT_RESPONSE.to_string(),
+            }],
+        };
+        Ok(Self {
+            fixtures,
+            delay: Duration::from_millis(settings.delay_ms),
+            timeout_secs,
+            usage: Mutex::new(Usage::default()),
+        })
+    }
+
+    /// The first fixture matching the prompt
+    fn response_for(&self, prompt: &str) -> Result<&str> {
+        self.fixtures
+            .iter()
+            .find(|fixture| {
+                fixture
+                    .prompt
+                    .as_ref()
+                    .is_none_or(|pattern| prompt.contains(pattern.as_str()))
+            })
+            .map(|fixture| fixture.response.as_str())
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::NoMockResponse(prompt)))
+    }
+
+    fn respond(&self, messages: &[ChatMessage]) -> Result<String> {
+        let prompt = messages
+            .last()
+            .map(|m| m.content.as_str())
+            .unwrap_or_default();
+        let response = self.response_for(prompt)?;
+        info!("Mock backend responding with {:?}", response);
+        let _generation = info_span!("generation").entered();
+        let start = Instant::now();
+        let stop_at = deadline(self.timeout_secs);
+        let mut output = String::with_capacity(response.len());
+        let mut completion_tokens = 0;
+        let mut time_to_first_token = None;
+        for word in response.split_inclusive(char::is_whitespace) {
+            if let Some(stopped) = cutoff(stop_at) {
+                report_cutoff(stopped, self.timeout_secs);
+                break;
+            }
+            std::thread::sleep(self.delay);
+            output.push_str(word);
+            completion_tokens += 1;
+            time_to_first_token = time_to_first_token.or_else(|| Some(start.elapsed()));
+        }
+        self.usage
+            .lock()
+            .map_err(|_| anyhow::anyhow!("{}", Message::LockPoisoned("usage")))?
+            .add(Usage {
+                prompt_tokens: prompt.split_whitespace().count(),
+                completion_tokens,
+                duration: start.elapsed(),
+                cost: None,
+                time_to_first_token,
+                ..Default::default()
+            });
+        Ok(output)
+    }
+}
+
+impl AiBackend for MockAiBackend {
+    fn invoke(&self, prompt: String) -> Result<String> {
+        self.respond(&[ChatMessage::user(prompt)])
+    }
+
+    fn invoke_conversation(&self, messages: &[ChatMessage], n: usize) -> Result<Vec<String>> {
+        (0..n).map(|_| self.respond(messages)).collect()
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        self.usage.lock().ok().map(|usage| *usage)
+    }
+}
@@ -1,7 +1,135 @@
+pub mod audited;
 pub mod bedrock;
+pub mod chat_template;
+pub mod command;
 pub mod common;
+pub mod hooked;
 pub mod local;
+pub mod metered;
+pub mod mock;
+pub mod plugin;
+pub mod redacting;
+pub mod tools;
 
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{Error as E, Result};
+use tracing::info;
+
+pub use audited::AuditedAiBackend;
 pub use bedrock::BedrockAiBackend;
-pub use common::AiBackend;
+pub use command::CommandAiBackend;
+pub use common::{AiBackend, ChatMessage, Usage};
+pub use hooked::HookedAiBackend;
 pub use local::LocalAiBackend;
+pub use metered::MeteredAiBackend;
+pub use mock::MockAiBackend;
+pub use plugin::PluginAiBackend;
+pub use redacting::RedactingAiBackend;
+
+use crate::daemon;
+use crate::history::GenerationParams;
+use crate::messages::Message;
+use crate::redact::Redactor;
+use crate::Settings;
+
+/// Creates the backend with the given name, "bedrock", "local", "command", "mock" or a
+/// plugin's. The local models get the system prompt when their chat template has a place
+/// for it, and are run by `ai daemon` when it's running with the same model settings.
+/// Prompts to the others are redacted unless the `[redaction]` settings turn it off, and
+/// every generation is recorded in the audit log when `[audit]` is on
+pub fn create_backend(
+    name: &str,
+    settings: Settings,
+    system_prompt: String,
+    start: Instant,
+) -> Result<Box<dyn AiBackend>> {
+    if settings.local_only && !redacting::is_local(name) {
+        anyhow::bail!("{}", Message::RemoteBackendLocalOnly(name));
+    }
+    let redactor = (settings.redaction.enabled && !redacting::is_local(name))
+        .then(|| Redactor::new(&settings.redaction).map(Arc::new))
+        .transpose()?;
+    let quotas = settings.quotas.clone();
+    let hooks = settings.hooks.clone();
+    let model = GenerationParams::from_settings(&settings, name).model_id;
+    let backend = open_backend(name, settings, system_prompt, start, redactor.clone())?;
+    let mut backend: Box<dyn AiBackend> = match redactor {
+        Some(redactor) => Box::new(RedactingAiBackend::new(backend, redactor, name)),
+        None => backend,
+    };
+    if hooks.pre_prompt.is_some() || hooks.post_response.is_some() {
+        backend = Box::new(HookedAiBackend::new(backend, hooks, name));
+    }
+    let backend = Box::new(MeteredAiBackend::new(backend, quotas, name));
+    Ok(Box::new(AuditedAiBackend::new(backend, name, model)))
+}
+
+/// The backend itself. Bedrock gets the redactor for the output of the tools it runs,
+/// which goes to the model without passing the redacting backend
+fn open_backend(
+    name: &str,
+    settings: Settings,
+    system_prompt: String,
+    start: Instant,
+    redactor: Option<Arc<Redactor>>,
+) -> Result<Box<dyn AiBackend>> {
+    match name {
+        "bedrock" => {
+            info!("Using Bedrock AI backend");
+            Ok(Box::new(BedrockAiBackend::new(
+                settings,
+                system_prompt,
+                redactor,
+            )))
+        }
+        "local" => match daemon::DaemonAiBackend::connect(&settings, &system_prompt) {
+            Some(backend) => {
+                info!("Using Local AI backend through the daemon");
+                Ok(Box::new(backend))
+            }
+            None => {
+                info!("Using Local AI backend");
+                Ok(Box::new(LocalAiBackend::new(
+                    settings,
+                    system_prompt,
+                    start,
+                )))
+            }
+        },
+        "command" => {
+            info!("Using command AI backend");
+            let command = settings
+                .command_backend
+                .ok_or_else(|| E::msg(Message::NoCommandBackend.to_string()))?;
+            Ok(Box::new(CommandAiBackend::new(
+                command,
+                system_prompt,
+                settings.request_timeout_secs,
+            )))
+        }
+        "mock" => {
+            info!("Using mock AI backend");
+            Ok(Box::new(MockAiBackend::new(
+                settings.mock_backend.unwrap_or_default(),
+                settings.request_timeout_secs,
+            )?))
+        }
+        // only generating routes, the other commands need a backend named
+        "auto" => Err(E::msg(Message::AutoNeedsBackend.to_string())),
+        _ => match plugin::find(&settings, name) {
+            Some(plugin) => {
+                info!("Using the {} plugin backend", name);
+                Ok(Box::new(PluginAiBackend::new(
+                    plugin,
+                    system_prompt,
+                    settings.request_timeout_secs,
+                )))
+            }
+            None => Err(E::msg(
+                Message::UnknownBackend(name, plugin::PLUGIN_PREFIX).to_string(),
+            )),
+        },
+    }
+}
@@ -0,0 +1,345 @@
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info, info_span, warn};
+
+use super::command::PipeReader;
+use super::common::{cutoff, deadline, report_cutoff, AiBackend, ChatMessage, Usage};
+use crate::credentials;
+use crate::messages::Message;
+use crate::settings::Settings;
+
+/// Executables on PATH named with this prefix are backends, `ai-backend-ollama` is `-b ollama`
+pub const PLUGIN_PREFIX: &str = "ai-backend-";
+
+/// Version of the plugin protocol, sent with every request. Changes that would break
+/// existing plugins get a new version
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Backends built in, a plugin can't take their names
+pub const BUILTIN_BACKENDS: &[&str] = &["local", "bedrock", "command", "mock", "auto"];
+
+/// How often to check for Ctrl-C and the deadline while waiting for output
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long a plugin has to exit once it has sent its response
+const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+/// Where a plugin was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluginSource {
+    /// A `[plugins.<name>]` table
+    Settings,
+    /// An `ai-backend-<name>` executable on PATH
+    Path,
+}
+
+/// A backend provided by another program
+#[derive(Debug, Clone)]
+pub struct Plugin {
+    pub name: String,
+    pub path: PathBuf,
+    pub args: Vec<String>,
+    pub credential: Option<String>,
+    pub source: PluginSource,
+}
+
+/// Every plugin that can be used with -b, by name. The `[plugins]` settings come first and
+/// win over executables on PATH of the same name, as do earlier PATH directories
+pub fn discover(settings: &Settings) -> Vec<Plugin> {
+    let mut plugins = settings
+        .plugins
+        .iter()
+        .map(|(name, plugin)| Plugin {
+            name: name.clone(),
+            path: plugin.path.clone(),
+            args: plugin.args.clone(),
+            credential: plugin.credential.clone(),
+            source: PluginSource::Settings,
+        })
+        .collect::<Vec<_>>();
+    let dirs = std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).collect::<Vec<_>>())
+        .unwrap_or_default();
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut found = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_executable(path))
+            .filter_map(|path| Some((plugin_name(&path)?, path)))
+            .collect::<Vec<_>>();
+        found.sort();
+        for (name, path) in found {
+            if plugins.iter().all(|plugin| plugin.name != name) {
+                plugins.push(Plugin {
+                    name,
+                    path,
+                    args: Vec::new(),
+                    credential: None,
+                    source: PluginSource::Path,
+                });
+            }
+        }
+    }
+    plugins.retain(|plugin| {
+        let builtin = BUILTIN_BACKENDS.contains(&plugin.name.as_str());
+        if builtin {
+            warn!(
+                "Ignoring the plugin {}, {} is a built-in backend",
+                plugin.path.display(),
+                plugin.name
+            );
+        }
+        !builtin
+    });
+    plugins.sort_by(|a, b| a.name.cmp(&b.name));
+    plugins
+}
+
+/// The plugin for the backend name, None when there is none
+pub fn find(settings: &Settings, name: &str) -> Option<Plugin> {
+    discover(settings)
+        .into_iter()
+        .find(|plugin| plugin.name == name)
+}
+
+/// `ollama` for `ai-backend-ollama`, and for `ai-backend-ollama.exe` on Windows
+fn plugin_name(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    let file_name = if cfg!(windows) {
+        file_name.strip_suffix(".exe").unwrap_or(file_name)
+    } else {
+        file_name
+    };
+    file_name
+        .strip_prefix(PLUGIN_PREFIX)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+/// Waits for the plugin to exit after its response, killing it if it doesn't within
+/// `EXIT_GRACE`. None when it had to be killed
+fn wait_briefly(child: &mut Child) -> Result<Option<ExitStatus>> {
+    let give_up = Instant::now() + EXIT_GRACE;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= give_up {
+            warn!("The plugin didn't exit after its response, killing it");
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// A line the plugin prints on stdout
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Event {
+    /// The next piece of the response
+    Token { text: String },
+    /// Token counts of the request, the last one sent counts
+    Usage {
+        #[serde(default)]
+        prompt_tokens: usize,
+        #[serde(default)]
+        completion_tokens: usize,
+    },
+    /// The request failed
+    Error { message: String },
+    /// The response is complete, the same as closing stdout
+    Done,
+}
+
+/// Runs a plugin for each request. It gets one line of json on stdin,
+/// `{"protocol": 1, "system": "...", "prompt": "...", "messages": [{"role", "content"}]}`,
+/// and streams json lines back on stdout: `{"type": "token", "text": "..."}` for each piece
+/// of the response, then optionally `{"type": "usage", "prompt_tokens": n,
+/// "completion_tokens": n}`, and `{"type": "done"}` or `{"type": "error", "message": "..."}`
+pub struct PluginAiBackend {
+    plugin: Plugin,
+    system_prompt: String,
+    /// The plugin is killed after this many seconds, 0 for no limit
+    timeout_secs: u64,
+    usage: Mutex<Usage>,
+}
+
+impl PluginAiBackend {
+    pub fn new(plugin: Plugin, system_prompt: String, timeout_secs: u64) -> Self {
+        Self {
+            plugin,
+            system_prompt,
+            timeout_secs,
+            usage: Mutex::new(Usage::default()),
+        }
+    }
+
+    /// Runs the plugin once for the conversation, the last message is the prompt, handing
+    /// each token to `on_token`. Tokens received before Ctrl-C or the timeout are kept
+    fn run(&self, messages: &[ChatMessage], on_token: &mut dyn FnMut(&str)) -> Result<String> {
+        let request = json!({
+            "protocol": PROTOCOL_VERSION,
+            "system": self.system_prompt,
+            "prompt": messages.last().map(|m| m.content.as_str()).unwrap_or_default(),
+            "messages": messages,
+        });
+        info!(
+            "Running the {} plugin {}",
+            self.plugin.name,
+            self.plugin.path.display()
+        );
+        let _generation = info_span!("generation").entered();
+        let mut command = Command::new(&self.plugin.path);
+        if let Some(name) = &self.plugin.credential {
+            let key = credentials::get(name)?
+                .ok_or_else(|| anyhow::anyhow!("{}", Message::NoKeyringKeyAdd(name)))?;
+            command.env("AI_API_KEY", key);
+        }
+        let start = Instant::now();
+        let mut child = command
+            .args(&self.plugin.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Unable to run plugin {}", self.plugin.path.display()))?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::NoStdin(&self.plugin.name)))?;
+        let stderr = PipeReader::spawn(child.stderr.take());
+        let (sender, lines) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            std::thread::spawn(move || {
+                for line in BufReader::new(stdout).lines() {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        // written on a thread once the output is read, a plugin that answers before reading
+        // a long conversation would block both sides on full pipes otherwise. Dropping stdin
+        // closes it so the plugin sees the end of the request
+        let request = format!("{}\n", request);
+        std::thread::spawn(move || stdin.write_all(request.as_bytes()));
+
+        let stop_at = deadline(self.timeout_secs);
+        let mut response = String::new();
+        let mut usage = Usage::default();
+        let mut failure = None;
+        loop {
+            if let Some(stopped) = cutoff(stop_at) {
+                warn!("Killing the {} plugin ({:?})", self.plugin.name, stopped);
+                child.kill()?;
+                report_cutoff(stopped, self.timeout_secs);
+                break;
+            }
+            let line = match lines.recv_timeout(POLL_INTERVAL) {
+                Ok(line) => line?,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = serde_json::from_str::<Event>(&line).with_context(|| {
+                format!(
+                    "The {} plugin printed a line that isn't a protocol message: {}",
+                    self.plugin.name, line
+                )
+            })?;
+            match event {
+                Event::Token { text } => {
+                    usage.time_to_first_token =
+                        usage.time_to_first_token.or_else(|| Some(start.elapsed()));
+                    usage.completion_tokens += 1;
+                    on_token(&text);
+                    response.push_str(&text);
+                }
+                Event::Usage {
+                    prompt_tokens,
+                    completion_tokens,
+                } => {
+                    usage.prompt_tokens = prompt_tokens;
+                    usage.completion_tokens = completion_tokens;
+                }
+                Event::Error { message } => failure = Some(message),
+                Event::Done => break,
+            }
+            if failure.is_some() {
+                break;
+            }
+        }
+        let status = wait_briefly(&mut child)?;
+        let stderr = String::from_utf8_lossy(&stderr.finish(status.is_some())).to_string();
+        debug!("The {} plugin's stderr: {}", self.plugin.name, stderr);
+        if let Some(message) = failure {
+            anyhow::bail!("{}", Message::PluginFailed(&self.plugin.name, &message));
+        }
+        // output from a plugin that then crashed is still worth showing
+        if let Some(status) = status.filter(|status| !status.success() && response.is_empty()) {
+            anyhow::bail!(
+                "{}",
+                Message::PluginStatus(
+                    &self.plugin.name,
+                    status.code().unwrap_or(-1),
+                    stderr.trim()
+                )
+            );
+        }
+        usage.duration = start.elapsed();
+        self.usage
+            .lock()
+            .map_err(|_| anyhow::anyhow!("{}", Message::LockPoisoned("usage")))?
+            .add(usage);
+        Ok(response.trim().to_string())
+    }
+}
+
+impl AiBackend for PluginAiBackend {
+    fn invoke(&self, prompt: String) -> Result<String> {
+        self.run(&[ChatMessage::user(prompt)], &mut |_| {})
+    }
+
+    fn invoke_conversation(&self, messages: &[ChatMessage], n: usize) -> Result<Vec<String>> {
+        (0..n).map(|_| self.run(messages, &mut |_| {})).collect()
+    }
+
+    fn stream_conversation(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        self.run(messages, on_token)
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        self.usage.lock().ok().map(|usage| *usage)
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use tracing::warn;
+
+use super::common::{AiBackend, ChatMessage, Usage};
+use crate::logging::NOTICE;
+use crate::messages::Message;
+use crate::redact::Redactor;
+
+/// Backends the prompt stays on this machine with, the others get it redacted. The
+/// command backend and plugins may send it anywhere
+pub fn is_local(name: &str) -> bool {
+    matches!(name, "local" | "mock")
+}
+
+/// The text redacted for the backend, warning about the values redacted for the first time
+pub fn redact(redactor: &Redactor, backend: &str, text: &str) -> Result<String> {
+    let (redacted, found) = redactor.redact(text)?;
+    if !found.is_empty() {
+        warn!(
+            target: NOTICE,
+            "{}",
+            Message::Redacted(backend, &found.join(", "))
+        );
+    }
+    Ok(redacted)
+}
+
+/// A remote backend whose prompts have credentials and personal data swapped for
+/// placeholders, which are swapped back in its responses
+pub struct RedactingAiBackend {
+    inner: Box<dyn AiBackend>,
+    redactor: Arc<Redactor>,
+    backend: String,
+}
+
+impl RedactingAiBackend {
+    pub fn new(inner: Box<dyn AiBackend>, redactor: Arc<Redactor>, backend: &str) -> Self {
+        Self {
+            inner,
+            redactor,
+            backend: backend.to_string(),
+        }
+    }
+
+    fn redact(&self, text: &str) -> Result<String> {
+        redact(&self.redactor, &self.backend, text)
+    }
+
+    fn redact_messages(&self, messages: &[ChatMessage]) -> Result<Vec<ChatMessage>> {
+        messages
+            .iter()
+            .map(|message| {
+                Ok(ChatMessage {
+                    role: message.role,
+                    content: self.redact(&message.content)?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl AiBackend for RedactingAiBackend {
+    fn invoke(&self, prompt: String) -> Result<String> {
+        let response = self.inner.invoke(self.redact(&prompt)?)?;
+        Ok(self.redactor.restore(&response))
+    }
+
+    fn invoke_n(&self, prompt: String, n: usize) -> Result<Vec<String>> {
+        let responses = self.inner.invoke_n(self.redact(&prompt)?, n)?;
+        Ok(responses
+            .iter()
+            .map(|response| self.redactor.restore(response))
+            .collect())
+    }
+
+    fn invoke_conversation(&self, messages: &[ChatMessage], n: usize) -> Result<Vec<String>> {
+        let responses = self
+            .inner
+            .invoke_conversation(&self.redact_messages(messages)?, n)?;
+        Ok(responses
+            .iter()
+            .map(|response| self.redactor.restore(response))
+            .collect())
+    }
+
+    fn stream_conversation(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        // a placeholder split over two pieces is only restored in the returned response
+        let response = self
+            .inner
+            .stream_conversation(&self.redact_messages(messages)?, &mut |text| {
+                on_token(&self.redactor.restore(text))
+            })?;
+        Ok(self.redactor.restore(&response))
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        self.inner.usage()
+    }
+}
@@ -0,0 +1,275 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use tracing::info;
+
+use super::command::PipeReader;
+use crate::audit;
+use crate::context::directory::{directory_listing, MAX_DIRECTORY_ENTRIES};
+use crate::messages::Message;
+
+/// Longest tool output sent back to the model, in characters
+const MAX_TOOL_OUTPUT: usize = 8000;
+
+/// How long a tool command may run before it is killed
+const TOOL_COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Programs run_command may run and the arguments they accept, None for any. Everything
+/// else only runs with the version flag. `hostname` takes none as with one it renames the host
+const READ_ONLY_COMMANDS: &[(&str, Option<&[&str]>)] = &[
+    ("which", None),
+    (
+        "uname",
+        Some(&["-a", "-s", "-n", "-r", "-v", "-m", "-p", "-i", "-o"]),
+    ),
+    ("whoami", Some(&[])),
+    ("hostname", Some(&[])),
+    ("pwd", Some(&[])),
+];
+
+/// Flag that only prints a version, accepted for any program found on $PATH. Single dash
+/// spellings aren't, to some programs (e.g. `find -version`) they mean something else
+const VERSION_FLAG: &str = "--version";
+
+/// Directories and files the file tools don't open in the working tree: keys, credentials
+/// and their stores
+const SECRET_NAMES: &[&str] = &[
+    ".ssh",
+    ".aws",
+    ".gnupg",
+    ".docker",
+    ".kube",
+    ".netrc",
+    ".npmrc",
+    ".pypirc",
+    ".git-credentials",
+    "credentials",
+];
+
+/// Starts of the names of secret files: dotenv files and ssh keys
+const SECRET_PREFIXES: &[&str] = &[".env", "id_rsa", "id_dsa", "id_ecdsa", "id_ed25519"];
+
+/// Extensions of key and certificate store files
+const SECRET_EXTENSIONS: &[&str] = &["pem", "key", "p12", "pfx", "jks"];
+
+/// A tool the model can call to look at the machine before answering
+pub struct ToolSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// JSON schema of the tool's input
+    pub input_schema: Value,
+}
+
+/// The built in, read only tools
+pub fn builtin_tools() -> Vec<ToolSpec> {
+    vec![
+        ToolSpec {
+            name: "read_file",
+            description: "Read the contents of a text file in the current directory, truncated when long",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "Path of the file to read"}
+                },
+                "required": ["path"]
+            }),
+        },
+        ToolSpec {
+            name: "list_directory",
+            description: "List the entries of a directory, directories end with /",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "Directory to list, defaults to the current directory"}
+                }
+            }),
+        },
+        ToolSpec {
+            name: "run_command",
+            description: "Run a read only command to inspect the environment: which, uname, whoami, hostname, pwd, or any program with --version",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "command": {"type": "string", "description": "The command, e.g. `which rg` or `sed --version`"}
+                },
+                "required": ["command"]
+            }),
+        },
+    ]
+}
+
+/// Runs a tool call from the model and returns the output to send back
+pub fn run_tool(name: &str, input: &Value) -> Result<String> {
+    let arg = |key: &str| input.get(key).and_then(Value::as_str);
+    info!("Running tool {} with {}", name, input);
+    let output = match name {
+        "read_file" => {
+            let path = arg("path")
+                .ok_or_else(|| anyhow::anyhow!("{}", Message::ToolNeeds("read_file", "path")))?;
+            let bytes = std::fs::read(in_working_tree(path)?)
+                .with_context(|| format!("Unable to read {}", path))?;
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        "list_directory" => directory_listing(
+            &in_working_tree(arg("path").unwrap_or("."))?,
+            MAX_DIRECTORY_ENTRIES,
+        ),
+        "run_command" => {
+            let command = arg("command").ok_or_else(|| {
+                anyhow::anyhow!("{}", Message::ToolNeeds("run_command", "command"))
+            })?;
+            run_read_only(command)?
+        }
+        _ => anyhow::bail!("{}", Message::UnknownTool(name)),
+    };
+    Ok(truncate(output))
+}
+
+/// The path resolved, links included, when it's in the current directory and isn't a secret
+/// file or in a secret directory. The model only sees the project it's asked about
+fn in_working_tree(path: &str) -> Result<PathBuf> {
+    let root = std::env::current_dir()?.canonicalize()?;
+    let resolved = root
+        .join(path)
+        .canonicalize()
+        .with_context(|| format!("Unable to find {}", path))?;
+    let Ok(relative) = resolved.strip_prefix(&root) else {
+        anyhow::bail!("{}", Message::OutsideWorkingTree(path));
+    };
+    if is_secret(relative) {
+        anyhow::bail!("{}", Message::SecretFile(path));
+    }
+    Ok(resolved)
+}
+
+/// Whether the path, relative to the working tree, is or is in a secret file or directory
+fn is_secret(path: &Path) -> bool {
+    path.iter().any(|name| {
+        let name = name.to_string_lossy();
+        SECRET_NAMES.contains(&name.as_ref())
+            || SECRET_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+    }) || path
+        .extension()
+        .is_some_and(|extension| SECRET_EXTENSIONS.contains(&extension.to_string_lossy().as_ref()))
+}
+
+/// Runs an allowed command without a shell, so pipes and redirections can't sneak in, and
+/// records it in the audit log when that's on
+fn run_read_only(command: &str) -> Result<String> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let Some((program, args)) = words.split_first() else {
+        anyhow::bail!("{}", Message::EmptyCommand);
+    };
+    if !is_read_only(program, args) {
+        anyhow::bail!("{}", Message::NotReadOnly(command));
+    }
+    let mut audit = audit::open()?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Unable to run {}", program))?;
+    // read on threads, a command printing more than a pipe holds would never exit otherwise
+    let stdout = PipeReader::spawn(child.stdout.take());
+    let stderr = PipeReader::spawn(child.stderr.take());
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() > TOOL_COMMAND_TIMEOUT {
+            child.kill()?;
+            child.wait()?;
+            if let Some(audit) = &mut audit {
+                audit.run(command, -1)?;
+            }
+            anyhow::bail!("{}", Message::CommandTimedOut(command));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    if let Some(audit) = &mut audit {
+        audit.run(command, status.code().unwrap_or(-1))?;
+    }
+    // version banners are printed to stderr by some programs
+    Ok(format!(
+        "exit status: {}\n{}{}",
+        status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&stdout.finish(true)),
+        String::from_utf8_lossy(&stderr.finish(true))
+    ))
+}
+
+/// Whether run_command may run the program with the arguments
+fn is_read_only(program: &str, args: &[&str]) -> bool {
+    // a path could be any script, only programs from $PATH are run
+    if program.contains(['/', '\\']) {
+        return false;
+    }
+    let allowed = READ_ONLY_COMMANDS
+        .iter()
+        .find(|(name, _)| *name == program)
+        .map(|(_, allowed)| match allowed {
+            Some(allowed) => args.iter().all(|arg| allowed.contains(arg)),
+            None => true,
+        });
+    allowed.unwrap_or(false) || args == [VERSION_FLAG]
+}
+
+fn truncate(mut output: String) -> String {
+    if let Some((index, _)) = output.char_indices().nth(MAX_TOOL_OUTPUT) {
+        output.truncate(index);
+        output.push_str("\n[truncated]");
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only_commands_take_only_their_arguments() {
+        assert!(is_read_only("hostname", &[]));
+        assert!(!is_read_only("hostname", &["evil"]));
+        assert!(is_read_only("uname", &["-a"]));
+        assert!(!is_read_only("uname", &["--help"]));
+        assert!(is_read_only("which", &["-a", "rg"]));
+        assert!(!is_read_only("pwd", &["-P", "x"]));
+    }
+
+    #[test]
+    fn keys_credentials_and_dotenv_files_are_secret() {
+        assert!(is_secret(Path::new(".env")));
+        assert!(is_secret(Path::new("app/.env.production")));
+        assert!(is_secret(Path::new("home/.ssh/config")));
+        assert!(is_secret(Path::new(".aws/credentials")));
+        assert!(is_secret(Path::new("deploy/id_ed25519")));
+        assert!(is_secret(Path::new("certs/server.pem")));
+        assert!(!is_secret(Path::new("src/main.rs")));
+        assert!(!is_secret(Path::new("src/id_generator.rs")));
+        assert!(!is_secret(Path::new("docs/environment.md")));
+    }
+
+    #[test]
+    fn files_outside_the_current_directory_are_refused() {
+        assert!(in_working_tree("Cargo.toml").is_ok());
+        assert!(in_working_tree("/etc/hostname").is_err());
+        assert!(in_working_tree("../").is_err());
+    }
+
+    #[test]
+    fn other_programs_only_print_their_version() {
+        assert!(is_read_only("sed", &["--version"]));
+        assert!(!is_read_only("find", &["-version"]));
+        assert!(!is_read_only("rm", &["--version", "-rf"]));
+        assert!(!is_read_only("./script", &["--version"]));
+        assert!(!is_read_only("/tmp/script", &["--version"]));
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::messages::Message;
+use crate::settings::config_dir;
+use crate::shell::Shell;
+
+/// Generated commands saved under a name, ~/.config/ai/aliases.json
+pub struct AliasStore {
+    path: PathBuf,
+}
+
+impl AliasStore {
+    pub fn open() -> Self {
+        Self::new(config_dir().join("aliases.json"))
+    }
+
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// All aliases by name
+    pub fn list(&self) -> Result<BTreeMap<String, String>> {
+        if !self.path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let contents = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Unable to read aliases {:?}", self.path))?;
+        serde_json::from_str(&contents).with_context(|| format!("Invalid aliases {:?}", self.path))
+    }
+
+    fn write(&self, aliases: &BTreeMap<String, String>) -> Result<()> {
+        std::fs::write(&self.path, serde_json::to_string_pretty(aliases)?)
+            .with_context(|| format!("Unable to write aliases {:?}", self.path))
+    }
+
+    /// Saves a command, replacing any alias with the same name. Names have to be valid
+    /// shell alias names, so they can be exported
+    pub fn save(&self, name: &str, command: &str) -> Result<()> {
+        if name.is_empty()
+            || name.starts_with('-')
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!("{}", Message::InvalidAliasName(name));
+        }
+        let mut aliases = self.list()?;
+        aliases.insert(name.to_string(), command.trim().to_string());
+        self.write(&aliases)
+    }
+
+    pub fn get(&self, name: &str) -> Result<String> {
+        self.list()?
+            .remove(name)
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::NoAlias(name)))
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let mut aliases = self.list()?;
+        if aliases.remove(name).is_none() {
+            anyhow::bail!("{}", Message::NoAlias(name));
+        }
+        self.write(&aliases)
+    }
+
+    /// Alias definitions for the shell's startup file
+    pub fn export(&self, shell: Shell) -> Result<String> {
+        let mut definitions = String::new();
+        for (name, command) in self.list()? {
+            let definition = match shell {
+                Shell::Bash | Shell::Zsh => {
+                    format!("alias {}='{}'\n", name, command.replace('\'', r"'\''"))
+                }
+                Shell::Fish => format!(
+                    "alias {} '{}'\n",
+                    name,
+                    command.replace('\\', r"\\").replace('\'', r"\'")
+                ),
+            };
+            definitions.push_str(&definition);
+        }
+        Ok(definitions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(dir: &tempfile::TempDir) -> AliasStore {
+        AliasStore::new(dir.path().join("aliases.json"))
+    }
+
+    #[test]
+    fn exports_quote_the_commands_for_each_shell() {
+        let dir = tempfile::tempdir().unwrap();
+        let aliases = store(&dir);
+        aliases.save("greet", r"echo 'hi' \n").unwrap();
+        assert_eq!(
+            aliases.export(Shell::Bash).unwrap(),
+            "alias greet='echo '\\''hi'\\'' \\n'\n"
+        );
+        assert_eq!(
+            aliases.export(Shell::Zsh).unwrap(),
+            aliases.export(Shell::Bash).unwrap()
+        );
+        assert_eq!(
+            aliases.export(Shell::Fish).unwrap(),
+            "alias greet 'echo \\'hi\\' \\\\n'\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_bash_export_runs_the_command_as_it_was_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let aliases = store(&dir);
+        aliases
+            .save("quoted", r#"printf '%s\n' "it's" | tr -d "'""#)
+            .unwrap();
+        // aliases are expanded from the line after the one defining them
+        let script = format!("{}quoted\n", aliases.export(Shell::Bash).unwrap());
+        let Ok(output) = std::process::Command::new("bash")
+            .args(["-O", "expand_aliases", "-c", &script])
+            .output()
+        else {
+            return;
+        };
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "its\n");
+    }
+
+    #[test]
+    fn names_have_to_be_valid_alias_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let aliases = store(&dir);
+        for name in ["", "-x", "a b", "a;b", "a'b"] {
+            assert!(aliases.save(name, "ls").is_err(), "{:?} was saved", name);
+        }
+        aliases.save("list_all-2", " ls -la ").unwrap();
+        assert_eq!(aliases.get("list_all-2").unwrap(), "ls -la");
+    }
+}
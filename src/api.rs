@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use anyhow::Result;
+use tracing::info;
+
+use crate::ai_backend::create_backend;
+use crate::messages::Message;
+use crate::network;
+use crate::prompts::{self, Prompt, Task};
+use crate::schema;
+use crate::settings::Settings;
+
+/// Options for [`generate`], the defaults match running `ai <prompt>` without flags
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+    /// Backend to use, "local", "bedrock" or "command". Defaults to the `ai_backend` setting
+    pub backend: Option<String>,
+    /// Replaces the system prompt, `{{default}}`, `{{shell}}` and `{{os}}` are expanded.
+    /// Defaults to the `system_prompt` setting, then the built-in prompt
+    pub system_prompt: Option<String>,
+    /// Describe the OS, shell and installed tools in the system prompt, unless the
+    /// `context.environment` setting turns it off
+    pub environment: bool,
+    /// Number of alternative commands to generate
+    pub candidates: usize,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            backend: None,
+            system_prompt: None,
+            environment: true,
+            candidates: 1,
+        }
+    }
+}
+
+/// Generates commands for `prompt` with the configured backend and returns them, one per
+/// candidate. Nothing is printed, history isn't recorded and the clipboard is left alone.
+/// With `settings.response_schema` set every response is json checked against it.
+/// `local_only` and `ca_bundle` apply from the first call on, for the rest of the process.
+///
+/// ```no_run
+/// let settings = ai::Settings::new()?;
+/// let commands = ai::generate(settings, "list files by size", &ai::GenerateOptions::default())?;
+/// println!("{}", commands[0]);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn generate(
+    settings: Settings,
+    prompt: &str,
+    options: &GenerateOptions,
+) -> Result<Vec<String>> {
+    if prompt.trim().is_empty() {
+        anyhow::bail!("{}", Message::PromptEmpty);
+    }
+    network::init(&settings)?;
+    let prompts = Prompt::load(Task::Generate)?;
+    let system_prompt = prompts::system_prompt(
+        options.system_prompt.as_deref(),
+        &settings,
+        &prompts.system,
+        options.environment && settings.context.environment,
+    )?;
+    let prompt = prompts.render(&HashMap::from([("prompt".to_string(), prompt.to_string())]))?;
+    let response_schema = settings.response_schema.clone();
+    let backend = options
+        .backend
+        .clone()
+        .unwrap_or_else(|| settings.ai_backend.clone());
+    info!(
+        "Generating {} candidates with {}",
+        options.candidates, backend
+    );
+    let responses = create_backend(&backend, settings, system_prompt, Instant::now())?
+        .invoke_n(prompt, options.candidates.max(1))?;
+    match response_schema {
+        Some(response_schema) => responses
+            .iter()
+            .map(|response| schema::check(&response_schema, response))
+            .collect(),
+        None => Ok(responses),
+    }
+}
@@ -0,0 +1,251 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::budget;
+use crate::logging;
+use crate::messages::Message;
+use crate::settings::AuditSettings;
+
+/// File the audit log is written to when `audit.path` isn't set, in the state directory
+const DEFAULT_AUDIT_FILE: &str = "audit.jsonl";
+
+/// What the audit log keeps of prompts, responses and commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditContent {
+    /// Their SHA-256, enough to check a given text against the log without keeping it
+    #[default]
+    Hash,
+    /// The text itself
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditEvent {
+    /// A model generated a response
+    Generate,
+    /// A generated or aliased command was run
+    Run,
+}
+
+/// A line of the audit log
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub user: String,
+    pub host: String,
+    /// Working directory the command was run in
+    pub cwd: String,
+    pub event: AuditEvent,
+    /// Whether `prompt`, `response` and `command` are hashes or the texts
+    pub content: AuditContent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+}
+
+static SETTINGS: OnceLock<AuditSettings> = OnceLock::new();
+
+/// Sets whether and where generations and runs are recorded from now on
+pub fn init(settings: &AuditSettings) {
+    let _ = SETTINGS.set(settings.clone());
+}
+
+/// The audit log's file, relative paths are in the state directory
+pub fn path() -> PathBuf {
+    let configured = SETTINGS.get().and_then(|settings| settings.path.clone());
+    logging::state_dir().join(configured.unwrap_or_else(|| PathBuf::from(DEFAULT_AUDIT_FILE)))
+}
+
+/// The audit log opened for appending, None when it's off. Entries are only ever added to
+/// the end, and what can't be recorded fails rather than going unrecorded
+pub fn open() -> Result<Option<AuditLog>> {
+    let Some(settings) = SETTINGS.get().filter(|settings| settings.enabled) else {
+        return Ok(None);
+    };
+    let path = path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create the audit log directory {:?}", parent))?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Unable to open the audit log {:?}", path))?;
+    Ok(Some(AuditLog {
+        file,
+        content: settings.content,
+    }))
+}
+
+/// Every entry of the audit log, oldest first
+pub fn entries() -> Result<Vec<AuditEntry>> {
+    let path = path();
+    if !path.exists() {
+        anyhow::bail!("{}", Message::NoAuditLog(&path.display().to_string()));
+    }
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("Unable to read the audit log {:?}", path))?;
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Line {} of the audit log {:?} is damaged", i + 1, path))
+        })
+        .collect()
+}
+
+/// The audit log, open for appending
+pub struct AuditLog {
+    file: File,
+    content: AuditContent,
+}
+
+impl AuditLog {
+    /// Records a response generated for the prompt
+    pub fn generation(
+        &mut self,
+        backend: &str,
+        model: Option<&str>,
+        prompt: &str,
+        response: &str,
+    ) -> Result<()> {
+        let entry = AuditEntry {
+            backend: Some(backend.to_string()),
+            model: model.map(str::to_string),
+            prompt: Some(self.text(prompt)),
+            response: Some(self.text(response)),
+            ..self.entry(AuditEvent::Generate)?
+        };
+        self.append(&entry)
+    }
+
+    /// Records the command having been run and how it exited
+    pub fn run(&mut self, command: &str, exit_code: i32) -> Result<()> {
+        let entry = AuditEntry {
+            command: Some(self.text(command)),
+            exit_code: Some(exit_code),
+            ..self.entry(AuditEvent::Run)?
+        };
+        self.append(&entry)
+    }
+
+    /// An entry of who did what, where and when, without its details
+    fn entry(&self, event: AuditEvent) -> Result<AuditEntry> {
+        Ok(AuditEntry {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            user: user(),
+            host: host(),
+            cwd: std::env::current_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default(),
+            event,
+            content: self.content,
+            backend: None,
+            model: None,
+            prompt: None,
+            response: None,
+            command: None,
+            exit_code: None,
+        })
+    }
+
+    fn text(&self, text: &str) -> String {
+        match self.content {
+            AuditContent::Hash => format!("{:x}", Sha256::digest(text.as_bytes())),
+            AuditContent::Full => text.to_string(),
+        }
+    }
+
+    /// Writes the entry as one line, in a single write so concurrent runs don't interleave
+    fn append(&mut self, entry: &AuditEntry) -> Result<()> {
+        let line = format!("{}\n", serde_json::to_string(entry)?);
+        self.file
+            .write_all(line.as_bytes())
+            .context("Unable to write to the audit log")
+    }
+}
+
+impl std::fmt::Display for AuditEntry {
+    /// A line for `ai audit show`: time, user@host, event, then what it was about
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}@{} {}",
+            budget::utc_time(self.timestamp),
+            self.user,
+            self.host,
+            match self.event {
+                AuditEvent::Generate => "generate",
+                AuditEvent::Run => "run",
+            }
+        )?;
+        if let Some(backend) = &self.backend {
+            write!(f, " {}", backend)?;
+            if let Some(model) = &self.model {
+                write!(f, " ({})", model)?;
+            }
+        }
+        if let Some(exit_code) = self.exit_code {
+            write!(f, " exit {}", exit_code)?;
+        }
+        write!(f, " in {}", self.cwd)?;
+        for (name, text) in [
+            ("prompt", &self.prompt),
+            ("response", &self.response),
+            ("command", &self.command),
+        ] {
+            if let Some(text) = text {
+                let text = match self.content {
+                    AuditContent::Hash => format!("sha256:{}", text),
+                    AuditContent::Full => text.trim().to_string(),
+                };
+                write!(f, "\n    {}: {}", name, text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The login name, from the environment
+fn user() -> String {
+    ["USER", "LOGNAME", "USERNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|user| !user.is_empty()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(unix)]
+fn host() -> String {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return "unknown".to_string();
+    }
+    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..end]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn host() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "unknown".to_string())
+}
@@ -0,0 +1,154 @@
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use aws_config::{BehaviorVersion, Region, SdkConfig};
+use aws_sdk_sts::config::{Credentials, ProvideCredentials, SharedCredentialsProvider};
+use aws_sdk_sts::error::DisplayErrorContext;
+use dialoguer::Input;
+use tracing::{debug, info};
+
+use crate::messages::Message;
+use crate::network;
+use crate::settings::AwsSettings;
+
+/// Session name of the assumed role, shows up in CloudTrail
+const ROLE_SESSION_NAME: &str = "ai-cli";
+
+/// Asks for the MFA code when the role in `role_arn` needs one. Done before the request
+/// starts so the time typing doesn't count towards its timeout
+pub fn mfa_code(settings: &AwsSettings) -> Result<Option<String>> {
+    let (Some(_), Some(serial)) = (&settings.role_arn, &settings.mfa_serial) else {
+        return Ok(None);
+    };
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("{}", Message::MfaNeedsTerminal(serial));
+    }
+    let code = Input::<String>::new()
+        .with_prompt(format!("MFA code for {}", serial))
+        .interact_text()?;
+    Ok(Some(code.trim().to_string()))
+}
+
+/// Loads the AWS config for the primary region with the credentials resolved up front:
+/// the `profile`'s or the default chain's, then the `role_arn` assumed with them. Other
+/// regions reuse it with `in_region`. An expired SSO session fails here with the command
+/// to renew it, rather than later as an error from the request
+pub async fn load(settings: &AwsSettings, mfa_code: Option<String>) -> Result<SdkConfig> {
+    let mut loader = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(settings.region.clone()))
+        .http_client(network::aws_http_client());
+    if let Some(profile) = &settings.profile {
+        loader = loader.profile_name(profile);
+    }
+    let config = loader.load().await;
+    let provider = config
+        .credentials_provider()
+        .ok_or_else(|| anyhow::anyhow!("{}", Message::NoCredentialsProvider))?;
+    let credentials = provider
+        .provide_credentials()
+        .await
+        .map_err(|e| credentials_error(settings, &DisplayErrorContext(&e).to_string()))?;
+    let credentials = match &settings.role_arn {
+        Some(role_arn) => {
+            let config = with_credentials(&config, credentials);
+            assume_role(&config, role_arn, settings.mfa_serial.as_deref(), mfa_code).await?
+        }
+        None => credentials,
+    };
+    Ok(with_credentials(&config, credentials))
+}
+
+/// The config for another region, keeping the credentials
+pub fn in_region(config: &SdkConfig, region: &str) -> SdkConfig {
+    config
+        .to_builder()
+        .region(Region::new(region.to_string()))
+        .build()
+}
+
+fn with_credentials(config: &SdkConfig, credentials: Credentials) -> SdkConfig {
+    config
+        .to_builder()
+        .credentials_provider(SharedCredentialsProvider::new(credentials))
+        .build()
+}
+
+/// STS AssumeRole, with the MFA device's serial and code when it needs them
+async fn assume_role(
+    config: &SdkConfig,
+    role_arn: &str,
+    mfa_serial: Option<&str>,
+    mfa_code: Option<String>,
+) -> Result<Credentials> {
+    info!("Assuming the role {}", role_arn);
+    let response = aws_sdk_sts::Client::new(config)
+        .assume_role()
+        .role_arn(role_arn)
+        .role_session_name(ROLE_SESSION_NAME)
+        .set_serial_number(mfa_serial.map(str::to_string))
+        .set_token_code(mfa_code)
+        .send()
+        .await
+        .map_err(|e| anyhow::anyhow!("{}", DisplayErrorContext(&e)))
+        .with_context(|| format!("Unable to assume the role {}", role_arn))?;
+    let credentials = response
+        .credentials()
+        .ok_or_else(|| anyhow::anyhow!("{}", Message::NoStsCredentials(role_arn)))?;
+    Ok(Credentials::new(
+        credentials.access_key_id(),
+        credentials.secret_access_key(),
+        Some(credentials.session_token().to_string()),
+        SystemTime::try_from(*credentials.expiration()).ok(),
+        "AssumeRole",
+    ))
+}
+
+/// The profile the credentials come from: the `profile` setting, $AWS_PROFILE or default
+fn profile_name(settings: &AwsSettings) -> String {
+    settings
+        .profile
+        .clone()
+        .or_else(|| std::env::var("AWS_PROFILE").ok())
+        .unwrap_or_else(|| "default".to_string())
+}
+
+/// Explains a failure to resolve credentials. The SDK's error for an expired or missing
+/// SSO token doesn't say how to get a new one
+fn credentials_error(settings: &AwsSettings, error: &str) -> anyhow::Error {
+    let profile = profile_name(settings);
+    debug!("Unable to load AWS credentials: {}", error);
+    if uses_sso(&profile) {
+        anyhow::anyhow!("{}", Message::SsoExpired(&profile))
+    } else {
+        anyhow::anyhow!("{}", Message::NoAwsCredentials(error))
+    }
+}
+
+/// Whether the profile in the AWS config file signs in with SSO
+fn uses_sso(profile: &str) -> bool {
+    let path = std::env::var_os("AWS_CONFIG_FILE")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".aws").join("config")));
+    let Some(config) = path.and_then(|path| std::fs::read_to_string(path).ok()) else {
+        return false;
+    };
+    // the default profile's section is [default], the others' [profile name]
+    let section = match profile {
+        "default" => "default".to_string(),
+        profile => format!("profile {}", profile),
+    };
+    let mut in_profile = false;
+    for line in config.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_profile = name.trim() == section;
+        } else if in_profile {
+            let key = line.split('=').next().unwrap_or_default().trim();
+            if key == "sso_session" || key == "sso_start_url" {
+                return true;
+            }
+        }
+    }
+    false
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::ai_backend::{AiBackend, ChatMessage};
+use crate::interrupt;
+use crate::prompts::Prompt;
+
+/// One prompt of a batch file
+#[derive(Debug, Deserialize)]
+pub struct BatchPrompt {
+    /// Identifies the prompt in the results, the line number unless the record has one
+    #[serde(default)]
+    pub id: Option<String>,
+    pub prompt: String,
+}
+
+/// One line of the results file
+#[derive(Debug, Serialize)]
+pub struct BatchResult<'a> {
+    pub id: &'a str,
+    pub prompt: &'a str,
+    pub response: Option<String>,
+    /// Why the prompt failed, the batch carries on with the next one
+    pub error: Option<String>,
+    pub duration_ms: u128,
+}
+
+/// Reads a batch file: one prompt per line, or json records `{"id": "...", "prompt": "..."}`
+/// for prompts spanning several lines. Blank lines and lines starting with # are skipped
+pub fn read_prompts(path: &Path) -> Result<Vec<BatchPrompt>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read batch file {:?}", path))?;
+    let mut prompts = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut prompt = if line.starts_with('{') {
+            serde_json::from_str(line)
+                .with_context(|| format!("Invalid record on line {} of {:?}", index + 1, path))?
+        } else {
+            BatchPrompt {
+                id: None,
+                prompt: line.to_string(),
+            }
+        };
+        prompt.id.get_or_insert_with(|| (index + 1).to_string());
+        prompts.push(prompt);
+    }
+    Ok(prompts)
+}
+
+/// Generates a response for every prompt with the same backend, so a local model is only
+/// loaded once, writing a json line per prompt as soon as it's done. Ctrl-C stops after
+/// the current prompt. Returns how many prompts failed
+pub fn run(
+    backend: &dyn AiBackend,
+    template: &Prompt,
+    prompts: &[BatchPrompt],
+    out: &mut dyn Write,
+) -> Result<usize> {
+    interrupt::install();
+    let mut failed = 0;
+    for (index, prompt) in prompts.iter().enumerate() {
+        let id = prompt.id.as_deref().unwrap_or_default();
+        info!("Generating {} of {} ({})", index + 1, prompts.len(), id);
+        let start = Instant::now();
+        let vars = HashMap::from([("prompt".to_string(), prompt.prompt.clone())]);
+        let response = template.render(&vars).and_then(|rendered| {
+            interrupt::generating(|| backend.invoke_conversation(&[ChatMessage::user(rendered)], 1))
+        });
+        let (response, error) = match response.map(|mut responses| responses.pop()) {
+            Ok(Some(response)) => (Some(response), None),
+            Ok(None) => (None, Some("No output was generated".to_string())),
+            Err(e) => {
+                warn!("Prompt {} failed: {:?}", id, e);
+                (None, Some(format!("{:#}", e)))
+            }
+        };
+        failed += usize::from(error.is_some());
+        let result = BatchResult {
+            id,
+            prompt: &prompt.prompt,
+            response,
+            error,
+            duration_ms: start.elapsed().as_millis(),
+        };
+        writeln!(out, "{}", serde_json::to_string(&result)?)?;
+        out.flush()?;
+        if interrupt::interrupted() {
+            warn!(
+                "Interrupted, stopping the batch after {} prompts",
+                index + 1
+            );
+            break;
+        }
+    }
+    Ok(failed)
+}
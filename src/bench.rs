@@ -0,0 +1,208 @@
+use std::io::Read;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::ai_backend::{create_backend, Usage};
+use crate::messages::Message;
+use crate::prompts::{self, Prompt, Task};
+use crate::settings::Settings;
+
+/// Prompt benchmarked when none is given, short so runs are comparable across backends
+pub const DEFAULT_BENCH_PROMPT: &str = "find all files larger than 100MB in the home directory";
+
+/// Set for the child process a run happens in, which prints its measurements as json
+const SINGLE_RUN_ENV: &str = "AI_BENCH_RUN";
+
+/// Measurements of one run
+#[derive(Serialize, Deserialize)]
+struct Run {
+    total: Duration,
+    usage: Usage,
+    /// Peak resident memory of the run's process, measured by the parent
+    #[serde(skip)]
+    peak_memory: Option<u64>,
+}
+
+impl Run {
+    /// Everything before generation started: model load on the local backend,
+    /// connection setup on remote ones
+    fn load(&self) -> Duration {
+        self.total.saturating_sub(self.usage.duration)
+    }
+}
+
+/// Runs the prompt `runs` times, each in a process of its own so every run pays the model
+/// load and has its own peak memory, and prints load time, time to first token, tokens per
+/// second and peak memory
+pub fn bench(backend: &str, runs: usize, prompt: &str) -> Result<()> {
+    if std::env::var_os(SINGLE_RUN_ENV).is_some() {
+        let run = run_once(backend, prompt)?;
+        println!("{}", serde_json::to_string(&run)?);
+        return Ok(());
+    }
+    let mut results = Vec::with_capacity(runs);
+    for run in 1..=runs {
+        let result = run_in_child()?;
+        info!("Run {} took {:?}: {}", run, result.total, result.usage);
+        results.push(result);
+    }
+
+    println!("Benchmark of the {} backend, {} runs", backend, runs);
+    println!(
+        "  {:>3} {:>10} {:>10} {:>12} {:>8} {:>10} {:>12}",
+        "run", "total", "load", "first token", "tokens", "tokens/s", "peak memory"
+    );
+    for (run, result) in results.iter().enumerate() {
+        println!(
+            "  {:>3} {:>10.2?} {:>10.2?} {:>12} {:>8} {:>10.2} {:>12}",
+            run + 1,
+            result.total,
+            result.load(),
+            first_token(&result.usage),
+            result.usage.completion_tokens,
+            result.usage.tokens_per_second(),
+            megabytes(result.peak_memory)
+        );
+    }
+    if runs > 1 {
+        let mean = |duration: fn(&Run) -> Duration| {
+            results.iter().map(duration).sum::<Duration>() / runs as u32
+        };
+        let tokens_per_second = results
+            .iter()
+            .map(|r| r.usage.tokens_per_second())
+            .sum::<f64>()
+            / runs as f64;
+        println!(
+            "  {:>3} {:>10.2?} {:>10.2?} {:>12} {:>8} {:>10.2}",
+            "avg",
+            mean(|r| r.total),
+            mean(Run::load),
+            "",
+            "",
+            tokens_per_second
+        );
+    }
+    if results.iter().all(|result| result.peak_memory.is_none()) {
+        println!("Peak memory is unavailable on this platform");
+    }
+    Ok(())
+}
+
+/// Loads a fresh backend and runs the prompt once
+fn run_once(backend: &str, prompt: &str) -> Result<Run> {
+    let prompts = Prompt::load(Task::Generate)?;
+    let settings = Settings::new()?;
+    let system_prompt = prompts::system_prompt(
+        None,
+        &settings,
+        &prompts.system,
+        settings.context.environment,
+    )?;
+    let start = Instant::now();
+    let model = create_backend(backend, settings, system_prompt, start)?;
+    model.invoke(prompt.to_string())?;
+    Ok(Run {
+        total: start.elapsed(),
+        usage: model.usage().unwrap_or_default(),
+        peak_memory: None,
+    })
+}
+
+/// Runs this same invocation again with SINGLE_RUN_ENV set and reads back its measurements,
+/// with the peak memory of that process alone
+fn run_in_child() -> Result<Run> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .env(SINGLE_RUN_ENV, "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .context("Unable to start the benchmark run")?;
+    let mut stdout = String::new();
+    if let Some(mut pipe) = child.stdout.take() {
+        pipe.read_to_string(&mut stdout)?;
+    }
+    let (status, peak_memory) = wait(child)?;
+    if !status.success() {
+        anyhow::bail!("{}", Message::BenchmarkFailed(status.code().unwrap_or(-1)));
+    }
+    // logs may come before the measurements, they're the last line
+    let mut run = stdout
+        .lines()
+        .rev()
+        .find_map(|line| serde_json::from_str::<Run>(line).ok())
+        .ok_or_else(|| anyhow::anyhow!("{}", Message::NoMeasurements))?;
+    run.peak_memory = peak_memory;
+    Ok(run)
+}
+
+/// Waits for the child, with its peak resident memory from the resource usage wait4 reports
+#[cfg(unix)]
+fn wait(child: Child) -> Result<(ExitStatus, Option<u64>)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    loop {
+        let pid = unsafe {
+            libc::wait4(
+                child.id() as libc::pid_t,
+                &mut status,
+                0,
+                usage.as_mut_ptr(),
+            )
+        };
+        if pid >= 0 {
+            break;
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(error).context("Unable to wait for the benchmark run");
+        }
+    }
+    let usage = unsafe { usage.assume_init() };
+    // kilobytes on linux, bytes on macOS
+    let unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    Ok((
+        ExitStatus::from_raw(status),
+        Some(usage.ru_maxrss as u64 * unit),
+    ))
+}
+
+#[cfg(not(unix))]
+fn wait(mut child: Child) -> Result<(ExitStatus, Option<u64>)> {
+    Ok((child.wait()?, None))
+}
+
+fn megabytes(bytes: Option<u64>) -> String {
+    bytes
+        .map(|bytes| format!("{:.1} MiB", bytes as f64 / 1024. / 1024.))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn first_token(usage: &Usage) -> String {
+    usage
+        .time_to_first_token
+        .map(|duration| format!("{:.2?}", duration))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Resident memory of this process, from /proc on linux
+pub fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kib = status.lines().find_map(|line| {
+        line.strip_prefix("VmRSS:")?
+            .trim()
+            .strip_suffix("kB")?
+            .trim()
+            .parse::<u64>()
+            .ok()
+    })?;
+    Some(kib * 1024)
+}
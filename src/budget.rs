@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use tracing::{info, warn};
+
+use crate::constants::BEDROCK_PRICES;
+use crate::messages::Message;
+use crate::settings::{config_dir, ModelPrice, Settings};
+
+impl ModelPrice {
+    pub fn cost(&self, prompt_tokens: usize, completion_tokens: usize) -> f64 {
+        prompt_tokens as f64 / 1000. * self.input_per_1k
+            + completion_tokens as f64 / 1000. * self.output_per_1k
+    }
+
+    /// Cost of the prompt tokens read from and written to the prompt cache, which are
+    /// priced at a tenth and 1.25 times the input price
+    pub fn cache_cost(&self, read_tokens: usize, write_tokens: usize) -> f64 {
+        (read_tokens as f64 * 0.1 + write_tokens as f64 * 1.25) / 1000. * self.input_per_1k
+    }
+}
+
+/// The model's price from the `prices` setting, then the built-in table
+pub fn price(settings: &Settings, model_id: &str) -> Option<ModelPrice> {
+    settings.prices.get(model_id).copied().or_else(|| {
+        BEDROCK_PRICES
+            .iter()
+            .find(|(model, _, _)| model_id.contains(model))
+            .map(|&(_, input_per_1k, output_per_1k)| ModelPrice {
+                input_per_1k,
+                output_per_1k,
+            })
+    })
+}
+
+/// Rough token count of text for a model whose tokenizer isn't available, about four
+/// characters per token for English
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Money spent per calendar month, ~/.config/ai/spending.json
+pub struct Spending {
+    path: PathBuf,
+}
+
+impl Spending {
+    pub fn open() -> Self {
+        Self {
+            path: config_dir().join("spending.json"),
+        }
+    }
+
+    /// USD spent by month, e.g. "2024-11"
+    fn months(&self) -> Result<BTreeMap<String, f64>> {
+        if !self.path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let contents = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Unable to read {:?}", self.path))?;
+        serde_json::from_str(&contents).with_context(|| format!("Invalid {:?}", self.path))
+    }
+
+    pub fn this_month(&self) -> Result<f64> {
+        Ok(self
+            .months()?
+            .get(&current_month())
+            .copied()
+            .unwrap_or_default())
+    }
+
+    /// Adds the cost of a request to this month's spending
+    pub fn record(&self, cost: f64) -> Result<()> {
+        let mut months = self.months()?;
+        *months.entry(current_month()).or_default() += cost;
+        std::fs::write(&self.path, serde_json::to_string_pretty(&months)?)
+            .with_context(|| format!("Unable to write {:?}", self.path))
+    }
+}
+
+/// Refuses a request whose estimated cost would take this month's spending over the
+/// `monthly_budget_usd` setting, unless --force was given
+pub fn check(settings: &Settings, model_id: &str, prompt: &str) -> Result<()> {
+    let Some(budget) = settings.monthly_budget_usd else {
+        return Ok(());
+    };
+    let Some(price) = price(settings, model_id) else {
+        warn!(
+            "No price known for {}, add it to the prices setting for the budget to apply",
+            model_id
+        );
+        return Ok(());
+    };
+    let estimate = price.cost(estimate_tokens(prompt), 0);
+    let spent = Spending::open().this_month()?;
+    info!(
+        "Estimated prompt cost ${:.5}, ${:.4} of ${:.2} spent this month",
+        estimate, spent, budget
+    );
+    if spent + estimate > budget {
+        if settings.ignore_budget {
+            warn!("Over the monthly budget, sending anyway because of --force");
+        } else {
+            anyhow::bail!("{}", Message::OverBudget(spent, estimate, budget));
+        }
+    }
+    Ok(())
+}
+
+/// The current month in UTC as "YYYY-MM"
+pub fn current_month() -> String {
+    let (year, month, _) = today();
+    format!("{:04}-{:02}", year, month)
+}
+
+/// The current day in UTC as "YYYY-MM-DD"
+pub fn current_day() -> String {
+    let (year, month, day) = today();
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn today() -> (i64, i64, i64) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    utc_date(secs)
+}
+
+/// The UTC year, month and day of a unix timestamp
+pub fn utc_date(secs: u64) -> (i64, i64, i64) {
+    let days = (secs / 86400) as i64;
+    // days since the epoch to a civil date, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// A unix timestamp as "YYYY-MM-DD HH:MM:SS" in UTC
+pub fn utc_time(secs: u64) -> String {
+    let (year, month, day) = utc_date(secs);
+    let seconds = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_of_timestamps_around_leap_days() {
+        assert_eq!(utc_date(0), (1970, 1, 1));
+        assert_eq!(utc_date(946598400), (1999, 12, 31));
+        assert_eq!(utc_date(951782400), (2000, 2, 29));
+        assert_eq!(utc_date(1709164800 + 86399), (2024, 2, 29));
+        assert_eq!(utc_date(4107542400), (2100, 3, 1));
+        assert_eq!(utc_date(4107542400 - 1), (2100, 2, 28));
+    }
+
+    #[test]
+    fn times_are_formatted_in_utc() {
+        assert_eq!(utc_time(0), "1970-01-01 00:00:00");
+        assert_eq!(utc_time(1709164800 + 3723), "2024-02-29 01:02:03");
+    }
+
+    #[test]
+    fn the_current_month_is_the_start_of_the_current_day() {
+        let month = current_month();
+        assert_eq!(month.len(), "YYYY-MM".len());
+        assert!(current_day().starts_with(&month), "{}", current_day());
+        let number = month[5..].parse::<u32>().unwrap();
+        assert!((1..=12).contains(&number));
+    }
+
+    #[test]
+    fn tokens_are_estimated_from_characters() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens("éééé"), 1);
+    }
+}
@@ -0,0 +1,223 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::ai_backend::ChatMessage;
+use crate::history::GenerationParams;
+use crate::install::cache_dir;
+use crate::settings::Settings;
+
+/// Generated responses keyed by everything that went into the request, so asking the same
+/// question again doesn't rerun a slow local generation or pay for another Bedrock call.
+/// Each entry is a json file under ~/.cache/ai/responses named after the request's hash
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedResponse {
+    /// Seconds since the unix epoch
+    created: u64,
+    backend: String,
+    responses: Vec<String>,
+    /// Times the entry was used instead of generating
+    #[serde(default)]
+    hits: u64,
+}
+
+/// Everything a response depends on, hashed into the cache key
+#[derive(Serialize)]
+struct Request<'a> {
+    backend: &'a str,
+    model: String,
+    params: GenerationParams,
+    system_prompt: &'a str,
+    messages: &'a [ChatMessage],
+    candidates: usize,
+    /// Left out without --schema so existing keys stay the same
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schema: Option<&'a serde_json::Value>,
+}
+
+/// Totals shown by `ai cache stats`
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    pub entries: usize,
+    /// Entries older than the ttl, removed on their next lookup or by `ai cache clear`
+    pub expired: usize,
+    pub hits: u64,
+    pub bytes: u64,
+}
+
+impl ResponseCache {
+    pub fn new(ttl_secs: u64) -> Self {
+        Self {
+            dir: cache_dir().join("responses"),
+            ttl: Duration::from_secs(ttl_secs),
+        }
+    }
+
+    /// The cache key of a request: backend, model, sampling settings with the seed, system
+    /// prompt and the conversation
+    pub fn key(
+        settings: &Settings,
+        backend: &str,
+        system_prompt: &str,
+        messages: &[ChatMessage],
+        candidates: usize,
+    ) -> Result<String> {
+        let params = GenerationParams::from_settings(settings, backend);
+        let request = Request {
+            backend,
+            model: model(settings, backend),
+            params,
+            system_prompt,
+            messages,
+            candidates,
+            schema: settings.response_schema.as_ref(),
+        };
+        let hash = Sha256::digest(serde_json::to_vec(&request)?);
+        Ok(format!("{:x}", hash))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key).with_extension("json")
+    }
+
+    /// The cached responses for the key, None when there are none or they expired.
+    /// A broken entry is treated as missing
+    pub fn get(&self, key: &str) -> Option<Vec<String>> {
+        let path = self.path(key);
+        let contents = std::fs::read_to_string(&path).ok()?;
+        let mut cached: CachedResponse = match serde_json::from_str(&contents) {
+            Ok(cached) => cached,
+            Err(e) => {
+                warn!("Ignoring invalid cache entry {:?}: {:?}", path, e);
+                return None;
+            }
+        };
+        if now().saturating_sub(cached.created) > self.ttl.as_secs() {
+            info!("Cache entry {} expired", key);
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        cached.hits += 1;
+        if let Err(e) = self.write(key, &cached) {
+            warn!("Unable to update cache entry: {:?}", e);
+        }
+        Some(cached.responses)
+    }
+
+    /// Stores the responses for the key, replacing an older entry
+    pub fn put(&self, key: &str, backend: &str, responses: &[String]) -> Result<()> {
+        self.write(
+            key,
+            &CachedResponse {
+                created: now(),
+                backend: backend.to_string(),
+                responses: responses.to_vec(),
+                hits: 0,
+            },
+        )
+    }
+
+    fn write(&self, key: &str, cached: &CachedResponse) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Unable to create cache directory {:?}", self.dir))?;
+        let path = self.path(key);
+        std::fs::write(&path, serde_json::to_string(cached)?)
+            .with_context(|| format!("Unable to write cache entry {:?}", path))
+    }
+
+    /// The cache entries on disk, with their size
+    fn entries(&self) -> Result<Vec<(PathBuf, u64)>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)
+            .with_context(|| format!("Unable to read cache directory {:?}", self.dir))?
+        {
+            let entry = entry?;
+            let path = entry.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                entries.push((path, entry.metadata()?.len()));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Deletes every cached response, returns how many there were
+    pub fn clear(&self) -> Result<usize> {
+        let entries = self.entries()?;
+        for (path, _) in entries.iter() {
+            std::fs::remove_file(path).with_context(|| format!("Unable to remove {:?}", path))?;
+        }
+        Ok(entries.len())
+    }
+
+    pub fn stats(&self) -> Result<CacheStats> {
+        let mut stats = CacheStats::default();
+        for (path, bytes) in self.entries()? {
+            stats.entries += 1;
+            stats.bytes += bytes;
+            let Some(cached) = std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| serde_json::from_str::<CachedResponse>(&contents).ok())
+            else {
+                continue;
+            };
+            stats.hits += cached.hits;
+            if now().saturating_sub(cached.created) > self.ttl.as_secs() {
+                stats.expired += 1;
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// What identifies the model beyond the params: the local weights, the command run or
+/// the mock fixture
+fn model(settings: &Settings, backend: &str) -> String {
+    match backend {
+        "local" => {
+            let local = &settings.local_model_config;
+            format!(
+                "{:?} quantized={} weights={:?} dtype={:?} banned={:?} grammar={:?}",
+                local.model,
+                local.quantized,
+                local.weight_file,
+                local.dtype,
+                local.banned_strings,
+                local.grammar
+            )
+        }
+        "command" => settings
+            .command_backend
+            .as_ref()
+            .map(|command| command.cmd.clone())
+            .unwrap_or_default(),
+        "mock" => settings
+            .mock_backend
+            .as_ref()
+            .and_then(|mock| mock.fixture.as_ref())
+            .map(|fixture| fixture.display().to_string())
+            .unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
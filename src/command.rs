@@ -1,21 +1,491 @@
-use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::Instant;
 
-use crate::ai_backend::AiBackend;
-use crate::ai_backend::{BedrockAiBackend, LocalAiBackend};
-use anyhow::{Error as E, Result};
+use crate::ai_backend::{create_backend, ChatMessage};
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use clap_verbosity_flag::Level;
-use indicatif::{ProgressBar, ProgressStyle};
+use clap_verbosity_flag::{Level, LogLevel};
+use dialoguer::{Confirm, Password, Select};
 
-use crate::settings::{ConfigLogLevel, Settings};
-use tracing::info;
+use crate::ai_backend::common::{take_cut_short, Role};
+use crate::ai_backend::plugin::{self, PluginSource};
+use crate::alias::AliasStore;
+use crate::audit;
+use crate::batch;
+use crate::bench;
+use crate::cache::ResponseCache;
+use crate::commit::{self, StagedDiff};
+use crate::compare;
+use crate::complete;
+use crate::constants::{COMMIT_DIFF_MAX_TOKENS, REGENERATE_WAIT};
+use crate::context::{GitContext, PromptBuilder};
+use crate::credentials;
+use crate::daemon::{self, DaemonClient};
+use crate::delivery;
+use crate::doctor;
+use crate::encryption;
+use crate::explain::ErrorOutput;
+use crate::fix::{self, FailedCommand};
+use crate::history::{self, ExportFormat, Feedback, GenerationParams, History};
+use crate::input;
+use crate::install;
+use crate::interact::{self, Action};
+use crate::interrupt;
+use crate::logging::NOTICE;
+use crate::messages::{self, Message};
+use crate::model_alias;
+use crate::modes;
+use crate::network;
+use crate::output::{self, ColorChoice};
+use crate::pipe;
+use crate::pipeline::{self, Pipeline};
+use crate::profile;
+use crate::prompts::{self, Prompt, Task};
+use crate::quantize;
+use crate::quota;
+use crate::rag;
+use crate::render;
+use crate::routing;
+use crate::schedule::{self, Delivery};
+use crate::schema;
+use crate::session::Session;
+use crate::settings::{config_file, ConfigLogLevel, Settings};
+use crate::shell::{self, Shell};
+use crate::telemetry;
+use crate::template::{self, TemplateStore};
+use crate::tokens;
+use crate::tui::Tui;
+use tracing::{info, info_span, warn};
 
 #[derive(Clone, Debug, Subcommand)]
 pub enum AiCliCommands {
-    /// Prints the Settings, arguments, and the log verbosity
-    Config,
+    /// Prints the Settings, with secrets such as webhook urls redacted, arguments, and the log verbosity
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigCommands>,
+    },
     /// Generate a bash one liner based off of the prompt
     Generate,
+    /// Manage and run saved prompt templates
+    Template {
+        #[command(subcommand)]
+        action: TemplateCommands,
+    },
+    /// Save generated commands under a name, run them and export them as shell aliases
+    Alias {
+        #[command(subcommand)]
+        action: AliasCommands,
+    },
+    /// List, rate, export and search previous generations. Without a subcommand, and as
+    /// `ai h`, opens the fuzzy finder like `ai history search`
+    #[command(visible_alias = "h")]
+    History {
+        #[command(subcommand)]
+        action: Option<HistoryCommands>,
+    },
+    /// Show the audit log of generations and the commands run, see [audit] in the config
+    Audit {
+        #[command(subcommand)]
+        action: AuditCommands,
+    },
+    /// Run multi step pipelines chaining prompts and shell commands
+    Pipeline {
+        #[command(subcommand)]
+        action: PipelineCommands,
+    },
+    /// Run prompts on a schedule via cron and deliver the output
+    Schedule {
+        #[command(subcommand)]
+        action: ScheduleCommands,
+    },
+    /// Manage local model artifacts
+    Models {
+        #[command(subcommand)]
+        action: ModelCommands,
+    },
+    /// Backends provided by other programs: `ai-backend-<name>` executables on PATH and the
+    /// `[plugins]` in the config, used with `-b <name>`
+    Plugins {
+        #[command(subcommand)]
+        action: PluginCommands,
+    },
+    /// Keep the local model loaded between commands in a background process, which the
+    /// local backend then uses over a Unix socket rather than loading the model itself
+    Daemon {
+        #[command(subcommand)]
+        action: DaemonCommands,
+    },
+    /// Create the config directory, default config and cache directory, for package post-install scripts
+    InitSystem,
+    /// Suggest a corrected version of a failed command. Uses the last command recorded by the
+    /// shell integration unless --command is given, error output can be piped in:
+    /// `make 2>&1 | ai fix --command make`
+    Fix {
+        /// The failed command
+        #[arg(long)]
+        command: Option<String>,
+        /// Exit status of the failed command
+        #[arg(long)]
+        status: Option<i32>,
+    },
+    /// Suggest the command reversing what a command did, e.g. `mv` the file back or
+    /// `git revert`, or say why it can't be undone. Uses the last command recorded by the
+    /// shell integration unless one is given: `ai undo 'git commit -am wip'`
+    Undo {
+        /// The command to undo
+        command: Vec<String>,
+    },
+    /// Diagnose an error message or stack trace piped in and suggest a fix:
+    /// `cargo build 2>&1 | ai explain-error`. Long output keeps its start and end
+    ExplainError {
+        /// The command that printed the error
+        #[arg(long)]
+        command: Option<String>,
+    },
+    /// Write a commit message for the staged changes and commit with it after confirmation
+    Commit {
+        /// Commit without asking for confirmation
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+    /// Change the last answer, e.g. `ai refine make it recursive and ignore hidden files`.
+    /// The previous prompt and answer are resent with the refinement as a conversation
+    Refine {
+        /// How to change the previous command
+        refinement: Vec<String>,
+    },
+    /// Finish a partly typed command and print only the rest of it, e.g. `ai complete 'tar -c'`.
+    /// Used by the Alt-G binding of `ai init`
+    Complete {
+        /// The start of the command, as typed
+        fragment: Vec<String>,
+    },
+    /// Apply an instruction to text piped in, e.g. `cat app.log | ai pipe summarize the errors`.
+    /// Input too long for the model is split into chunks whose results are merged
+    Pipe {
+        /// What to do with the input
+        instruction: Vec<String>,
+    },
+    /// Generate a git command for the repository's actual state, e.g. `ai git squash my last
+    /// 3 commits and rebase onto main`. The branch, recent log and status are sent with it
+    Git {
+        /// What to do in the repository
+        request: Vec<String>,
+    },
+    /// Write a regular expression, e.g. `ai regex match ISO dates`. It's compiled before
+    /// it's printed
+    Regex {
+        /// What the regex should match
+        description: Vec<String>,
+    },
+    /// Write a jq filter, e.g. `ai jq names of the items older than 30`. It's compiled with
+    /// jq, when installed, before it's printed
+    Jq {
+        /// What the filter should do
+        description: Vec<String>,
+    },
+    /// Write an awk command, e.g. `ai awk sum the third column of a csv`
+    Awk {
+        /// What the command should do
+        description: Vec<String>,
+    },
+    /// Write a cron expression, e.g. `ai cron every weekday at 9am`. Its fields are checked
+    /// before it's printed
+    Cron {
+        /// The schedule
+        description: Vec<String>,
+    },
+    /// Summarize a large input piped in, e.g. `journalctl -b | ai summarize`. It's split into
+    /// chunks that fit the model, each is summarized and then the summaries are, until one
+    /// is left. Words after it say what to focus on: `ai summarize the failed logins`
+    Summarize {
+        /// What the summary should focus on
+        focus: Vec<String>,
+    },
+    /// Embed the text files under directories with a local model, e.g. `ai index ~/notes`.
+    /// `--context index` then adds the parts closest to the prompt. Unchanged files are
+    /// skipped when run again
+    Index {
+        /// Directories to index
+        #[arg(required_unless_present = "clear")]
+        dirs: Vec<PathBuf>,
+        /// Delete the index
+        #[arg(long, conflicts_with = "dirs")]
+        clear: bool,
+    },
+    /// Full-screen mode: type prompts, see the responses and browse the history. Enter
+    /// sends, Ctrl-Y copies, Ctrl-R runs, Ctrl-G regenerates and Esc quits
+    Tui,
+    /// Print the shell integration snippet, e.g. `eval "$(ai shell-init bash)"` in ~/.bashrc
+    ShellInit {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print a snippet binding Ctrl-G to replace the command line with the command
+    /// generated from it, e.g. `eval "$(ai init zsh)"` in ~/.zshrc
+    Init {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Generate a response for every line of a file, loading the model once, and write
+    /// them as json lines: `ai batch prompts.txt --out results.jsonl`
+    Batch {
+        /// One prompt per line, or json records with a prompt and an optional id
+        file: PathBuf,
+        /// File to write the results to, stdout by default
+        #[arg(long, value_name = "PATH")]
+        out: Option<PathBuf>,
+    },
+    /// Run a prompt several times and report model load time, time to first token,
+    /// tokens per second and peak memory
+    Bench {
+        /// Number of runs
+        #[arg(long, short = 'n', default_value_t = 3)]
+        runs: usize,
+        /// Prompt to run, defaults to a short example
+        prompt: Vec<String>,
+    },
+    /// Send the same prompt to several backends at once and print their responses side by
+    /// side with each one's latency, e.g. `ai compare -b local -b bedrock list open ports`
+    Compare {
+        /// Backend to compare, repeat for each one
+        #[arg(long = "backend", short = 'b', value_name = "BACKEND", required = true)]
+        backends: Vec<String>,
+        /// The prompt, --prompt-file adds a file's contents after it
+        prompt: Vec<String>,
+    },
+    /// Check the GPU support, model cache, AWS credentials, clipboard and config, printing
+    /// how to fix what's wrong
+    Doctor,
+    /// Run a prompt and report where the time went: config, context, model load, network, generation
+    ProfileStartup {
+        /// Prompt to profile, defaults to a short example
+        prompt: Vec<String>,
+    },
+    /// Store API keys in the OS keyring rather than in the config file
+    Auth {
+        #[command(subcommand)]
+        action: AuthCommands,
+    },
+    /// Count the prompt's tokens with the local model's tokenizer, without loading the
+    /// model, to check it fits in the context with `sample_len`
+    Tokens {
+        /// List every token with its id, like `verbose_prompt`
+        #[arg(long)]
+        breakdown: bool,
+        /// The text to count, --prompt-file adds a file's contents after it
+        prompt: Vec<String>,
+    },
+    /// Show the requests made today and the tokens used this month by backend, with the
+    /// quotas set under [quotas]
+    Usage,
+    /// Manage the cache of generated responses
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommands,
+    },
+    /// Remove scheduled prompts, for package pre-remove scripts
+    Uninstall {
+        /// Also delete the config, templates, history and caches
+        #[arg(long)]
+        purge: bool,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum AliasCommands {
+    /// Save the last generated command under a name
+    Save { name: String },
+    /// Run a saved command in the current shell
+    Run { name: String },
+    /// List the saved commands
+    List,
+    /// Delete a saved command
+    Delete { name: String },
+    /// Print the saved commands as alias definitions, e.g.
+    /// `ai alias export --format bash >> ~/.bashrc`
+    Export {
+        #[arg(long, value_enum)]
+        format: Shell,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum AuditCommands {
+    /// Show the most recent entries, oldest first
+    Show {
+        /// Number of entries to show
+        #[arg(long, short, default_value_t = 20)]
+        limit: usize,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum AuthCommands {
+    /// Store an API key, read from a hidden prompt or from stdin when piped
+    Set {
+        /// Name the key is stored under, e.g. openai
+        name: String,
+    },
+    /// Delete a stored API key
+    Remove { name: String },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum CacheCommands {
+    /// Delete every cached response
+    Clear,
+    /// Show the number of cached responses, how often they were reused and their size
+    Stats,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ConfigCommands {
+    /// Print the config file's location, set with --config, $AI_CONFIG_DIR or $XDG_CONFIG_HOME
+    Path,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum HistoryCommands {
+    /// Show the most recent generations
+    List {
+        /// Number of entries to show
+        #[arg(long, short, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Give a generation a thumbs up or down
+    Rate {
+        /// Id of the history entry
+        id: u64,
+        /// up or down
+        #[arg(value_enum)]
+        feedback: Feedback,
+    },
+    /// Write the history to stdout. The finetune format emits prompt/completion
+    /// pairs of thumbs-up generations for instruction tuning, csv and markdown a table
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+        format: ExportFormat,
+    },
+    /// Add the entries of a jsonl export to the history, skipping ones already in it
+    Import {
+        /// File written by `ai history export`
+        file: PathBuf,
+    },
+    /// Fuzzy find a past generation by its prompt or command, then copy, edit, run or
+    /// regenerate it. Prints the matches when not run in a terminal
+    Search {
+        /// Text to start the search with
+        query: Vec<String>,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum PipelineCommands {
+    /// Run a pipeline file (yaml, toml or json)
+    Run {
+        /// Path to the pipeline file
+        file: PathBuf,
+        /// Set a pipeline variable, overriding the file's vars
+        #[arg(long = "var", value_name = "KEY=VALUE")]
+        vars: Vec<String>,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ScheduleCommands {
+    /// Register a prompt to run non-interactively, output goes to ~/.config/ai/schedule.log
+    /// unless a delivery option is given
+    Add {
+        /// When to run: hourly, "daily 9am", "weekdays 8:30am", "weekly mon 9am",
+        /// "every 15m" or a cron expression
+        schedule: String,
+        /// The prompt to run
+        prompt: String,
+        /// Append the output to this file
+        #[arg(long, group = "delivery")]
+        output: Option<String>,
+        /// Pipe the output into this shell command, e.g. "mail -s report me@example.com"
+        #[arg(long, group = "delivery")]
+        command: Option<String>,
+        /// Show the output as a desktop notification
+        #[arg(long, group = "delivery")]
+        notify: bool,
+        /// Post the output to a webhook from the `webhooks` settings
+        #[arg(long, group = "delivery")]
+        webhook: Option<String>,
+    },
+    /// List the scheduled prompts
+    List,
+    /// Remove a scheduled prompt
+    Remove {
+        /// Id shown by `ai schedule list`
+        id: u64,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum PluginCommands {
+    /// List the plugins found, with their executables and where they were found
+    List,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum DaemonCommands {
+    /// Run the daemon in the foreground, e.g. `ai daemon start &` or from a systemd user
+    /// unit. The model is loaded by the first request
+    Start,
+    /// Show whether the daemon is running, its model and how many requests it served
+    Status,
+    /// Stop the daemon once its current generation is done
+    Stop,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ModelCommands {
+    /// Convert a model's safetensors weights into a quantized gguf file in the cache.
+    /// Point `weight_file` in the config at the printed path to use it
+    Quantize {
+        /// Hugging Face model id, e.g. microsoft/phi-2
+        model: String,
+        /// Bits per weight, one of 4, 5, 6 or 8
+        #[arg(long, default_value_t = 4)]
+        bits: u8,
+        /// Model revision (git branch) to download
+        #[arg(long)]
+        revision: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum TemplateCommands {
+    /// Save a prompt template, variables are written as {{name}}
+    Add {
+        /// Name to save the template under
+        name: String,
+        /// The template text, e.g. "Write a Dockerfile for {{lang}} app in {{dir}}".
+        /// Read from --prompt-file when not given
+        template: Option<String>,
+    },
+    /// Fill in a template with --name value pairs and generate from it,
+    /// --prompt-file adds the file's contents after the filled in template and
+    /// --edit opens the result in the editor
+    Run {
+        /// Name of the template to run
+        name: String,
+        /// Template variables as --name value or --name=value
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        vars: Vec<String>,
+    },
+    /// List the saved templates
+    List,
+    /// Delete a saved template
+    Delete {
+        /// Name of the template to delete
+        name: String,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -26,21 +496,162 @@ pub struct AiCliArgs {
     #[arg(long, short)]
     pub tracing: bool,
 
+    /// Read this config file instead of ~/.config/ai/config.toml, .ai.toml and ./config.toml.
+    /// It isn't created when missing
+    #[arg(long, value_name = "PATH", global = true)]
+    pub config: Option<PathBuf>,
+
     /// Specify which AI backend to use for processing requests:
     /// - "bedrock": Use Amazon Bedrock managed AI service
     /// - "local": Use local LLM model (Phi 2 or 3) pulled from Hugging face
-    /// 
+    /// - "command": Run the program set in `command_backend.cmd` as the model
+    /// - "mock": Answer from the `mock_backend.fixture` file, for demos and tests
+    /// - "auto": Short prompts go to the local model, long ones or with several files to
+    ///   Bedrock, thresholds in `[routing]`
+    ///
     /// If not specified, the backend will be read from config file, defaulting to "local"
     #[arg(long, short = 'b')]
     pub ai_backend: Option<String>,
 
+    /// Read the prompt from a file, `-` for stdin. Words given on the command line go first.
+    /// Text piped in alongside a prompt file is added as the input to work on
+    #[arg(long, short = 'f', value_name = "PATH", global = true)]
+    pub prompt_file: Option<PathBuf>,
+
+    /// Attach a file to the prompt under a header with its name, can be repeated.
+    /// Files too long for the `[context.providers.files] max_tokens` budget are cut down
+    /// to their first and last lines
+    #[arg(long = "file", value_name = "PATH", global = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Write the prompt in $VISUAL or $EDITOR, starting from the words given, the
+    /// --prompt-file contents or the filled in template
+    #[arg(long, short = 'e', global = true)]
+    pub edit: bool,
+
+    /// Number of candidate commands to generate. When more than one is generated
+    /// an interactive picker is shown and the selected command is printed
+    #[arg(long = "candidates", short = 'n', default_value_t = 1)]
+    pub candidates: usize,
+
+    /// Don't copy the generated command to the clipboard, overrides the clipboard setting
+    #[arg(long)]
+    pub no_clipboard: bool,
+
+    /// Deliver the response to a webhook from the `webhooks` settings, can be repeated
+    #[arg(long, value_name = "WEBHOOK")]
+    pub deliver: Vec<String>,
+
+    /// Also write the generated command to this file as an executable script, with a
+    /// shebang for the current shell
+    #[arg(long, value_name = "PATH")]
+    pub out: Option<PathBuf>,
+
+    /// Include a listing of the current directory in the prompt, same as --context directory
+    #[arg(long)]
+    pub dir_context: bool,
+
+    /// Add a context provider to the prompt for this run: system, git, directory, tools, docs
+    /// or index.
+    /// Can be repeated, providers can also be enabled in the [context] settings
+    #[arg(long, value_name = "PROVIDER")]
+    pub context: Vec<String>,
+
+    /// Include the man page (or --help output of common tools) of the tools the prompt
+    /// mentions so flags aren't made up, same as --context docs. The budget is [context.providers.docs] max_tokens
+    #[arg(long)]
+    pub with_docs: bool,
+
+    /// Send the prompt without any context: no environment details in the system prompt
+    /// and no context providers, even the ones enabled in the settings
+    #[arg(long, conflicts_with_all = ["context", "dir_context", "with_docs"])]
+    pub no_context: bool,
+
+    /// System prompt for this run, replaces the `system_prompt` setting.
+    /// `{{default}}` expands to the built-in prompt, `{{shell}}` and `{{os}}` to the environment
+    #[arg(long, value_name = "PROMPT")]
+    pub system: Option<String>,
+
+    /// After generating, offer to copy, edit and run, run or regenerate the command.
+    /// Can be turned on for every run with the `interactive` setting
+    #[arg(long, short = 'i', conflicts_with = "no_interactive")]
+    pub interactive: bool,
+
+    /// Print the command and exit, without the action menu or waiting for `r` to regenerate
+    #[arg(long)]
+    pub no_interactive: bool,
+
+    /// Generate even if the same request has a cached response, the new response replaces it
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// Print token usage, generation speed and estimated cost to stderr after generating
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Seed for the local model's sampling, to reproduce a generation exactly. `--stats`
+    /// and the history show the seed each generation used
+    #[arg(long, global = true)]
+    pub seed: Option<u64>,
+
+    /// Model to use, a name from `[model_aliases]` such as `fast`, `hf:<repo>` for the local
+    /// backend or a Bedrock model id. Picks the backend that runs it
+    #[arg(long = "model", value_name = "NAME", global = true)]
+    pub model_name: Option<String>,
+
+    /// Replay a history entry with the backend and settings it was generated with.
+    /// The prompt, if given, is an instruction refining the previous command
+    #[arg(long, value_name = "HISTORY_ID", conflicts_with = "continue_session")]
+    pub seed_from: Option<u64>,
+
+    /// Continue the last conversation with the same backend and model, the prompt is the next turn
+    #[arg(long = "continue")]
+    pub continue_session: bool,
+
+    /// Print the response as is, without markdown rendering or syntax highlighting.
+    /// Output is also plain when stdout isn't a terminal or colors are turned off
+    #[arg(long)]
+    pub plain: bool,
+
+    /// When to use colors: auto colors what goes to a terminal unless $NO_COLOR is set.
+    /// Applies to the response, spinner, menus, logs and the other commands' output
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t, global = true)]
+    pub color: ColorChoice,
+
+    /// Respond with json matching the JSON schema in this file instead of a command.
+    /// The local models can only generate matching json, Bedrock is made to call a tool
+    /// taking the schema as input, and any response that doesn't match is an error
+    #[arg(long, value_name = "PATH")]
+    pub schema: Option<PathBuf>,
+
+    /// Let the local model's response go on past the end of the command line, see the
+    /// `stop_at_newline` setting
+    #[arg(long)]
+    pub multiline: bool,
+
+    /// Send to Bedrock even when the prompt would go over the `monthly_budget_usd` setting
+    #[arg(long, global = true)]
+    pub force: bool,
+
+    /// Forbid any network access for this run: remote backends are rejected, models are
+    /// only loaded from the cache and nothing is delivered or exported
+    #[arg(long, global = true)]
+    pub local_only: bool,
+
+    /// Send the prompt to a remote backend as is, without redacting credentials, emails
+    /// and IP addresses
+    #[arg(long, global = true)]
+    pub no_redact: bool,
+
     /// Control log output verbosity level:
     /// - v: warnings
     /// - vv: info
     /// - vvv: debug
     /// - vvvv: trace
-    /// 
-    /// Default level is error if not specified, overrides the config setting
+    ///
+    /// Default level is error if not specified, overrides the config setting.
+    /// -q/--quiet prints exactly the command on stdout for scripts, `eval "$(ai -q ...)"`:
+    /// no spinner, colors, markdown or menus, logs and errors go to stderr
     #[command(flatten)]
     pub verbose: clap_verbosity_flag::Verbosity<ConfigLogLevel>,
 
@@ -56,6 +667,13 @@ pub struct AiCliArgs {
     pub other_args: Vec<String>,
 }
 
+impl AiCliArgs {
+    /// Whether -q/--quiet was given, it lowers the log level below the configured one
+    pub fn quiet(&self) -> bool {
+        self.verbose.is_present() && self.verbose.log_level() < ConfigLogLevel::default()
+    }
+}
+
 pub struct AiCli {
     pub settings: Settings,
     pub args: AiCliArgs,
@@ -80,83 +698,1055 @@ impl AiCli {
             prompt,
         }
     }
-    pub fn exec(self) -> Result<()> {
-        match self.args.command {
-            Some(AiCliCommands::Config) => {
+    pub fn exec(mut self) -> Result<()> {
+        messages::init(self.settings.language.as_deref());
+        if self.args.local_only {
+            self.settings.local_only = true;
+        }
+        if self.settings.local_only && self.settings.telemetry.enabled {
+            warn!("Not exporting telemetry, nothing leaves the machine in local-only mode");
+            self.settings.telemetry.enabled = false;
+        }
+        telemetry::init(&self.settings.telemetry);
+        encryption::init(
+            self.settings.history_encryption,
+            self.settings.history_passphrase_cache_secs,
+        );
+        audit::init(&self.settings.audit);
+        if let Err(e) = network::init(&self.settings) {
+            warn!("{:#}", e);
+        }
+        self.settings.ignore_budget = self.args.force;
+        if self.args.no_redact {
+            self.settings.redaction.enabled = false;
+        }
+        self.apply_overrides()?;
+        // never signal anything with color alone in accessibility mode, and keep the
+        // command as is in quiet mode, rendering falls back to plain text
+        output::init(
+            self.args.color,
+            self.settings.accessibility || self.args.quiet(),
+        );
+        match self.args.command.clone() {
+            Some(AiCliCommands::Config {
+                action: Some(ConfigCommands::Path),
+            }) => {
+                println!("{}", config_file().display());
+                Ok(())
+            }
+            Some(AiCliCommands::Config { action: None }) => {
                 // pretty println settings, args and log level
                 println!("Settings: {:#?}", self.settings);
                 println!("Args: {:#?}", self.args);
                 println!("Log level: {:#?}", self.log_level);
                 Ok(())
             }
-            Some(_) | None => {
-                // check prompt is not empty
-                if self.prompt.is_empty() {
-                    return Err(anyhow::anyhow!("Prompt is empty"));
+            Some(AiCliCommands::Template { action }) => {
+                let store = TemplateStore::open()?;
+                match action {
+                    TemplateCommands::Add { name, template } => {
+                        let template = match (template, &self.args.prompt_file) {
+                            (Some(template), _) => template,
+                            (None, Some(path)) => input::read_prompt_file(path)?,
+                            (None, None) => anyhow::bail!("{}", Message::TemplateMissing),
+                        };
+                        store.add(&name, &template)?;
+                        println!("{}", Message::SavedTemplate(&name));
+                        Ok(())
+                    }
+                    TemplateCommands::Run { name, vars } => {
+                        let prompt =
+                            template::render(&store.get(&name)?, &template::parse_vars(&vars)?)?;
+                        let prompt = self.complete_prompt(prompt)?;
+                        info!("Rendered template {} into prompt {}", name, prompt);
+                        self.generate(Task::Generate, prompt, HashMap::new())?;
+                        Ok(())
+                    }
+                    TemplateCommands::List => {
+                        for (name, template) in store.list()? {
+                            println!("{}: {}", name, template);
+                        }
+                        Ok(())
+                    }
+                    TemplateCommands::Delete { name } => {
+                        store.delete(&name)?;
+                        println!("{}", Message::DeletedTemplate(&name));
+                        Ok(())
+                    }
                 }
-                info!(
-                    "temp: {:.2} repeat-penalty: {:.2} repeat-last-n: {}",
-                    self.settings.local_model_config.temperature.unwrap_or(0.),
-                    self.settings.local_model_config.repeat_penalty,
-                    self.settings.local_model_config.repeat_last_n
+            }
+            Some(AiCliCommands::Alias { action }) => {
+                let store = AliasStore::open();
+                match action {
+                    AliasCommands::Save { name } => {
+                        let command = Session::load()
+                            .ok()
+                            .and_then(|session| {
+                                session
+                                    .messages
+                                    .into_iter()
+                                    .rev()
+                                    .find(|message| message.role == Role::Assistant)
+                            })
+                            .ok_or_else(|| anyhow::anyhow!("{}", Message::NothingToAlias))?;
+                        store.save(&name, &render::strip_code_fence(&command.content))?;
+                        println!("{}", Message::SavedAlias(&name));
+                    }
+                    AliasCommands::Run { name } => {
+                        let command = store.get(&name)?;
+                        eprintln!("{}", command);
+                        eprintln!("{}", Message::ExitStatus(interact::run(&command)?));
+                    }
+                    AliasCommands::List => {
+                        for (name, command) in store.list()? {
+                            println!("{}: {}", name, command);
+                        }
+                    }
+                    AliasCommands::Delete { name } => {
+                        store.delete(&name)?;
+                        println!("{}", Message::DeletedAlias(&name));
+                    }
+                    AliasCommands::Export { format } => print!("{}", store.export(format)?),
+                }
+                Ok(())
+            }
+            Some(AiCliCommands::Audit { action }) => match action {
+                AuditCommands::Show { limit } => {
+                    let entries = audit::entries()?;
+                    for entry in entries.iter().skip(entries.len().saturating_sub(limit)) {
+                        println!("{}", entry);
+                    }
+                    Ok(())
+                }
+            },
+            Some(AiCliCommands::History { action }) => {
+                let history = History::open()?;
+                match action.unwrap_or(HistoryCommands::Search { query: Vec::new() }) {
+                    HistoryCommands::List { limit } => {
+                        let entries = history.entries()?;
+                        for entry in entries.iter().skip(entries.len().saturating_sub(limit)) {
+                            let feedback = match entry.feedback {
+                                Some(Feedback::Up) => " [+]",
+                                Some(Feedback::Down) => " [-]",
+                                None => "",
+                            };
+                            println!("{}{}: {}", entry.id, feedback, entry.prompt);
+                            println!("    {}", entry.response.trim());
+                        }
+                        Ok(())
+                    }
+                    HistoryCommands::Rate { id, feedback } => history.rate(id, feedback),
+                    HistoryCommands::Export { format } => {
+                        history.export(format, &mut std::io::stdout().lock())
+                    }
+                    HistoryCommands::Import { file } => {
+                        let (imported, skipped) = history.import(&file)?;
+                        println!("{}", Message::ImportedHistory(imported, skipped));
+                        Ok(())
+                    }
+                    HistoryCommands::Search { query } => {
+                        let entries = history.entries()?;
+                        let query = query.join(" ");
+                        if !interact::available() {
+                            for entry in history::search(&entries, &query).iter().take(20) {
+                                println!("{}: {}", entry.id, entry.prompt);
+                                println!("    {}", entry.response.trim());
+                            }
+                            return Ok(());
+                        }
+                        let Some(entry) = interact::find_history(&entries, &query)? else {
+                            return Ok(());
+                        };
+                        println!("{}", entry.response.trim());
+                        match interact::choose_action()? {
+                            Action::Copy => match interact::copy(&entry.response) {
+                                Ok(()) => eprintln!("{}", Message::Copied),
+                                Err(e) => eprintln!("{}", e),
+                            },
+                            Action::Edit => {
+                                let command = interact::edit(&entry.response)?;
+                                eprintln!("{}", command);
+                                eprintln!("{}", Message::ExitStatus(interact::run(&command)?));
+                            }
+                            Action::Run => {
+                                eprintln!(
+                                    "{}",
+                                    Message::ExitStatus(interact::run(&entry.response)?)
+                                )
+                            }
+                            Action::Regenerate => {
+                                // replays the prompt with the backend and settings it had
+                                self.args.seed_from = Some(entry.id);
+                                self.generate(Task::Generate, String::new(), HashMap::new())?;
+                            }
+                            Action::Quit => {}
+                        }
+                        Ok(())
+                    }
+                }
+            }
+            Some(AiCliCommands::Pipeline { action }) => match action {
+                PipelineCommands::Run { file, vars } => {
+                    let pipeline = Pipeline::load(&file)?;
+                    let overrides = pipeline::parse_vars(&vars)?;
+                    let backend_name = self.backend_name();
+                    let system_prompt =
+                        self.system_prompt(&Prompt::load(Task::Generate)?.system)?;
+                    let backend =
+                        create_backend(&backend_name, self.settings, system_prompt, self.start)?;
+                    pipeline.run(backend.as_ref(), overrides)?;
+                    Ok(())
+                }
+            },
+            Some(AiCliCommands::Schedule { action }) => match action {
+                ScheduleCommands::Add {
+                    schedule,
+                    prompt,
+                    output,
+                    command,
+                    notify,
+                    webhook,
+                } => {
+                    let delivery = match (output, command, notify, webhook) {
+                        (Some(path), _, _, _) => Some(Delivery::File(path)),
+                        (_, Some(cmd), _, _) => Some(Delivery::Command(cmd)),
+                        (_, _, true, _) => Some(Delivery::Notify),
+                        (_, _, _, Some(name)) => {
+                            if !self.settings.webhooks.contains_key(&name) {
+                                anyhow::bail!("{}", Message::UnknownWebhook(&name));
+                            }
+                            Some(Delivery::Webhook(name))
+                        }
+                        _ => None,
+                    };
+                    let id = schedule::add(&schedule, &prompt, delivery)?;
+                    println!("{}", Message::ScheduledPrompt(id));
+                    Ok(())
+                }
+                ScheduleCommands::List => {
+                    for scheduled in schedule::list()? {
+                        println!("{}: {}", scheduled.id, scheduled.line);
+                    }
+                    Ok(())
+                }
+                ScheduleCommands::Remove { id } => {
+                    schedule::remove(id)?;
+                    println!("{}", Message::RemovedScheduledPrompt(id));
+                    Ok(())
+                }
+            },
+            Some(AiCliCommands::Models { action }) => match action {
+                ModelCommands::Quantize {
+                    model,
+                    bits,
+                    revision,
+                } => {
+                    let path = quantize::quantize_model(&model, revision.as_deref(), bits)?;
+                    println!("{}", path.display());
+                    Ok(())
+                }
+            },
+            Some(AiCliCommands::Plugins { action }) => match action {
+                PluginCommands::List => {
+                    let plugins = plugin::discover(&self.settings);
+                    if plugins.is_empty() {
+                        println!(
+                            "No plugins, put an {}<name> executable on PATH or add a [plugins.<name>] table to the config",
+                            plugin::PLUGIN_PREFIX
+                        );
+                    }
+                    for plugin in plugins {
+                        let source = match plugin.source {
+                            PluginSource::Settings => "config",
+                            PluginSource::Path => "PATH",
+                        };
+                        println!("{}\t{}\t{}", plugin.name, plugin.path.display(), source);
+                    }
+                    Ok(())
+                }
+            },
+            Some(AiCliCommands::Daemon { action }) => {
+                match action {
+                    DaemonCommands::Start => daemon::server::run(self.settings, self.start),
+                    DaemonCommands::Status => {
+                        let status = DaemonClient::connect()
+                            .ok_or_else(|| anyhow::anyhow!("{}", Message::DaemonNotRunning))?
+                            .status()?;
+                        println!("pid: {}", status.pid);
+                        println!("version: {}", status.version);
+                        println!("uptime: {}s", status.uptime_secs);
+                        println!("requests: {}", status.requests);
+                        println!("model: {}", status.model_name);
+                        match (status.loaded, status.keep_alive_secs) {
+                            (true, Some(keep_alive)) => println!(
+                                "loaded: yes, unloaded after {}s idle ({}s so far)",
+                                keep_alive, status.idle_secs
+                            ),
+                            (true, None) => println!("loaded: yes"),
+                            (false, _) => println!("loaded: no, the next request loads it"),
+                        }
+                        if let Some(bytes) = status.memory_bytes {
+                            println!("memory: {:.1} MiB", bytes as f64 / 1024. / 1024.);
+                        }
+                        if status.model != daemon::model_fingerprint(&self.settings) {
+                            println!("The current settings differ, the local backend loads the model itself");
+                        }
+                        Ok(())
+                    }
+                    DaemonCommands::Stop => {
+                        DaemonClient::connect()
+                            .ok_or_else(|| anyhow::anyhow!("{}", Message::DaemonNotRunning))?
+                            .shutdown()?;
+                        println!("Stopping the daemon");
+                        Ok(())
+                    }
+                }
+            }
+            Some(AiCliCommands::Fix { command, status }) => {
+                let failed = FailedCommand::gather(command, status)?;
+                let prompt = failed.command.clone();
+                self.generate(Task::Fix, prompt, failed.vars())?;
+                Ok(())
+            }
+            Some(AiCliCommands::Undo { command }) => {
+                let command = fix::last_command(Some(command.join(" ")))
+                    .ok_or_else(|| anyhow::anyhow!("{}", Message::NoCommandToUndo))?;
+                let vars = HashMap::from([("command".to_string(), command.clone())]);
+                self.generate(Task::Undo, command, vars)?;
+                Ok(())
+            }
+            Some(AiCliCommands::ExplainError { command }) => {
+                let error = ErrorOutput::read(command)?;
+                self.generate(Task::ExplainError, error.summary(), error.vars())?;
+                Ok(())
+            }
+            Some(AiCliCommands::Commit { yes }) => {
+                let staged = StagedDiff::collect(COMMIT_DIFF_MAX_TOKENS)?;
+                let accessible = self.settings.accessibility;
+                let message = self.generate(Task::Commit, staged.summary(), staged.vars())?;
+                let message = render::strip_code_fence(&message);
+                let confirmed = yes
+                    || (std::io::stdin().is_terminal()
+                        && std::io::stderr().is_terminal()
+                        && Confirm::with_theme(output::theme(accessible).as_ref())
+                            .with_prompt(Message::ConfirmCommit.to_string())
+                            .default(true)
+                            .interact()?);
+                if confirmed {
+                    commit::commit(&message)?;
+                }
+                Ok(())
+            }
+            Some(AiCliCommands::Refine { refinement }) => {
+                let refinement = self.complete_prompt(refinement.join(" "))?;
+                self.generate(Task::Refine, refinement, HashMap::new())?;
+                Ok(())
+            }
+            Some(AiCliCommands::Complete { fragment }) => {
+                self.generate(Task::Complete, fragment.join(" "), HashMap::new())?;
+                Ok(())
+            }
+            Some(AiCliCommands::Git { request }) => {
+                if !GitContext::in_repository() {
+                    anyhow::bail!("{}", Message::NotAGitRepository);
+                }
+                let prompt = self.complete_prompt(request.join(" "))?;
+                self.generate(Task::Git, prompt, HashMap::new())?;
+                Ok(())
+            }
+            Some(AiCliCommands::Regex { description }) => {
+                let prompt = self.complete_prompt(description.join(" "))?;
+                self.generate(Task::Regex, prompt, HashMap::new())?;
+                Ok(())
+            }
+            Some(AiCliCommands::Jq { description }) => {
+                let prompt = self.complete_prompt(description.join(" "))?;
+                self.generate(Task::Jq, prompt, HashMap::new())?;
+                Ok(())
+            }
+            Some(AiCliCommands::Awk { description }) => {
+                let prompt = self.complete_prompt(description.join(" "))?;
+                self.generate(Task::Awk, prompt, HashMap::new())?;
+                Ok(())
+            }
+            Some(AiCliCommands::Cron { description }) => {
+                let prompt = self.complete_prompt(description.join(" "))?;
+                self.generate(Task::Cron, prompt, HashMap::new())?;
+                Ok(())
+            }
+            Some(AiCliCommands::Pipe { instruction }) => {
+                let instruction = instruction.join(" ");
+                if instruction.trim().is_empty() {
+                    anyhow::bail!("{}", Message::PromptEmpty);
+                }
+                self.pipe(Task::Pipe, &instruction)
+            }
+            Some(AiCliCommands::Summarize { focus }) => {
+                let instruction = match focus.join(" ") {
+                    focus if focus.trim().is_empty() => "Summarize the input".to_string(),
+                    focus => format!("Summarize the input, focusing on {}", focus),
+                };
+                self.pipe(Task::Summarize, &instruction)
+            }
+            Some(AiCliCommands::Tui) => {
+                let prompts = Prompt::load(Task::Generate)?;
+                let mut builder =
+                    PromptBuilder::new(self.settings.context.clone()).attach(&self.args.files);
+                for name in self.args.context.iter() {
+                    builder = builder.enable(name);
+                }
+                let backend_name = self.backend_name();
+                let params = GenerationParams::from_settings(&self.settings, &backend_name);
+                let save_history = self.settings.history;
+                let system_prompt = self.system_prompt(&prompts.system)?;
+                let backend =
+                    create_backend(&backend_name, self.settings, system_prompt, self.start)?;
+                Tui::new(
+                    backend.as_ref(),
+                    backend_name,
+                    prompts,
+                    builder,
+                    params,
+                    save_history,
+                )
+                .run()
+            }
+            Some(AiCliCommands::Index { dirs, clear }) => {
+                if clear {
+                    let path = rag::Index::path();
+                    if path.exists() {
+                        std::fs::remove_file(&path)
+                            .with_context(|| format!("Unable to delete {:?}", path))?;
+                    }
+                    eprintln!("{}", Message::IndexCleared);
+                    return Ok(());
+                }
+                let indexed = rag::index(&self.settings.embedding_model, &dirs)?;
+                eprintln!(
+                    "{}",
+                    Message::Indexed(indexed.files, indexed.embedded, indexed.chunks)
                 );
-                // get from args, fallback to settings obj
-                let backend = match self.args.ai_backend {
-                    Some(ref backend) => backend,
-                    None => &self.settings.ai_backend,
+                Ok(())
+            }
+            Some(AiCliCommands::ShellInit { shell }) => {
+                print!("{}", shell::init_snippet(shell));
+                Ok(())
+            }
+            Some(AiCliCommands::Init { shell }) => {
+                print!("{}", shell::widget_snippet(shell));
+                Ok(())
+            }
+            Some(AiCliCommands::Batch { file, out }) => {
+                let prompts = batch::read_prompts(&file)?;
+                let template = Prompt::load(Task::Generate)?;
+                let system_prompt = self.system_prompt(&template.system)?;
+                let backend = create_backend(
+                    &self.backend_name(),
+                    self.settings,
+                    system_prompt,
+                    self.start,
+                )?;
+                let mut writer: Box<dyn std::io::Write> = match &out {
+                    Some(path) => Box::new(std::io::BufWriter::new(
+                        std::fs::File::create(path)
+                            .with_context(|| format!("Unable to create {:?}", path))?,
+                    )),
+                    None => Box::new(std::io::stdout()),
                 };
-
-                let local_model: Box<dyn AiBackend> = match backend.as_str() {
-                    "bedrock" => {
-                        info!("Using Bedrock AI backend");
-                        Box::new(BedrockAiBackend::new(self.settings))
+                let failed = batch::run(backend.as_ref(), &template, &prompts, &mut writer)?;
+                eprintln!("{}", Message::BatchFinished(prompts.len(), failed));
+                if self.args.stats {
+                    if let Some(usage) = backend.usage() {
+                        eprintln!("{}", usage);
                     }
-                    "local" => {
-                        info!("Using Local AI backend");
-                        Box::new(LocalAiBackend::new(self.settings, self.start))
+                }
+                Ok(())
+            }
+            Some(AiCliCommands::Bench { runs, prompt }) => {
+                let prompt = if prompt.is_empty() {
+                    bench::DEFAULT_BENCH_PROMPT.to_string()
+                } else {
+                    prompt.join(" ")
+                };
+                bench::bench(&self.backend_name(), runs.max(1), &prompt)
+            }
+            Some(AiCliCommands::Compare { backends, prompt }) => {
+                let prompt = self.complete_prompt(prompt.join(" "))?;
+                if prompt.is_empty() {
+                    anyhow::bail!("{}", Message::PromptEmpty);
+                }
+                let prompts = Prompt::load(Task::Generate)?;
+                let mut builder =
+                    PromptBuilder::new(self.settings.context.clone()).attach(&self.args.files);
+                for name in self.args.context.iter() {
+                    builder = builder.enable(name);
+                }
+                let vars = HashMap::from([("prompt".to_string(), prompt)]);
+                let prompt = builder.build(&prompts.render(&vars)?)?;
+                let system_prompt = self.system_prompt(&prompts.system)?;
+                let answers = compare::run(&backends, &self.settings, &system_prompt, &prompt);
+                compare::print(&answers);
+                Ok(())
+            }
+            Some(AiCliCommands::Doctor) => doctor::doctor(),
+            Some(AiCliCommands::Tokens { breakdown, prompt }) => {
+                let prompt = self.complete_prompt(prompt.join(" "))?;
+                if prompt.is_empty() {
+                    anyhow::bail!("{}", Message::PromptEmpty);
+                }
+                tokens::count(self.settings, &prompt, breakdown)
+            }
+            Some(AiCliCommands::Usage) => quota::show(&self.settings.quotas),
+            Some(AiCliCommands::ProfileStartup { prompt }) => {
+                let prompt = if prompt.is_empty() {
+                    profile::DEFAULT_PROFILE_PROMPT.to_string()
+                } else {
+                    prompt.join(" ")
+                };
+                profile::profile_startup(
+                    self.settings,
+                    self.args,
+                    self.start,
+                    self.log_level,
+                    prompt,
+                )
+            }
+            Some(AiCliCommands::InitSystem) => {
+                for path in install::init_system()? {
+                    println!("{}", Message::Created(&path.display().to_string()));
+                }
+                Ok(())
+            }
+            Some(AiCliCommands::Auth { action }) => match action {
+                AuthCommands::Set { name } => {
+                    let secret = if std::io::stdin().is_terminal() {
+                        Password::new()
+                            .with_prompt(Message::EnterApiKey(&name).to_string())
+                            .interact()?
+                    } else {
+                        let mut secret = String::new();
+                        std::io::stdin().read_line(&mut secret)?;
+                        secret.trim().to_string()
+                    };
+                    if secret.is_empty() {
+                        anyhow::bail!("{}", Message::EmptyApiKey);
                     }
-                    _ => {
-                        return Err(E::msg(format!("Unknown backend: {}", backend)));
+                    credentials::set(&name, &secret)?;
+                    println!("{}", Message::SavedApiKey(&name));
+                    Ok(())
+                }
+                AuthCommands::Remove { name } => {
+                    credentials::remove(&name)?;
+                    println!("{}", Message::RemovedApiKey(&name));
+                    Ok(())
+                }
+            },
+            Some(AiCliCommands::Cache { action }) => {
+                let cache = ResponseCache::new(self.settings.cache_ttl_secs);
+                match action {
+                    CacheCommands::Clear => {
+                        println!("{}", Message::ClearedCache(cache.clear()?));
+                        Ok(())
                     }
-                };
-                info!("Beginning inference");
-                let mut bar: Option<ProgressBar> = None;
-                // if match verbosity is info or below
-                if self.log_level < Level::Info {
-                    let temp_bar = ProgressBar::new_spinner();
-                    temp_bar.set_style(
-                        ProgressStyle::with_template("{spinner:.green} {msg}")
-                            .unwrap()
-                            .tick_strings(&[
-                                "⣷", "⣯", "⣟", "⡿", "⢿", "⣻", "⣽", "⣾", // full block
-                                "⣿", // "▹▹▹▹▹",
-                                     //                 "▸▹▹▹▹",
-                                     //                 "▹▸▹▹▹",
-                                     //                 "▹▹▸▹▹",
-                                     //                 "▹▹▹▸▹",
-                                     //                 "▹▹▹▹▸",
-                                     //                 "▪▪▪▪▪",
-                            ]),
+                    CacheCommands::Stats => {
+                        let stats = cache.stats()?;
+                        println!("{}", Message::CacheEntries(stats.entries, stats.expired));
+                        println!("{}", Message::CacheHits(stats.hits));
+                        println!("{}", Message::CacheSize(stats.bytes as f64 / 1024.));
+                        match self.settings.cache_ttl_secs {
+                            0 => println!("{}", Message::CacheOff),
+                            ttl => println!("{}", Message::CacheTtl(ttl)),
+                        }
+                        Ok(())
+                    }
+                }
+            }
+            Some(AiCliCommands::Uninstall { purge }) => {
+                let removed = install::uninstall(purge)?;
+                if removed.scheduled_prompts > 0 {
+                    println!(
+                        "{}",
+                        Message::RemovedScheduledPrompts(removed.scheduled_prompts)
                     );
-                    temp_bar.tick();
-                    temp_bar.enable_steady_tick(Duration::from_millis(100));
-                    temp_bar.set_message("Thinking...");
-                    bar = Some(temp_bar);
                 }
-                let result = local_model.invoke(self.prompt)?; //print result
-                if let Some(bar) = bar {
-                    bar.finish_with_message("Done");
+                for path in removed.paths.iter() {
+                    println!("{}", Message::Removed(&path.display().to_string()));
+                }
+                Ok(())
+            }
+            Some(AiCliCommands::Generate) | None => {
+                let prompt = self.complete_prompt(self.prompt.clone())?;
+                self.generate(Task::Generate, prompt, HashMap::new())?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Applies --seed and --model to the settings
+    fn apply_overrides(&mut self) -> Result<()> {
+        if let Some(seed) = self.args.seed {
+            self.settings.local_model_config.seed = seed;
+        }
+        if let Some(model) = &self.args.model_name {
+            let backend = model_alias::apply(&mut self.settings, model)?;
+            match &self.args.ai_backend {
+                Some(chosen) if chosen != backend => {
+                    anyhow::bail!("{}", Message::ModelOnOtherBackend(model, backend, chosen))
+                }
+                _ => self.settings.ai_backend = backend.to_string(),
+            }
+        }
+        Ok(())
+    }
+
+    /// The backend to use, from args with a fallback to the settings
+    fn backend_name(&self) -> String {
+        match self.args.ai_backend {
+            Some(ref backend) => backend.clone(),
+            None => self.settings.ai_backend.clone(),
+        }
+    }
+
+    /// The prompt followed by the contents of --prompt-file, if given, then opened in
+    /// the editor with --edit
+    fn complete_prompt(&self, prompt: String) -> Result<String> {
+        let prompt = match &self.args.prompt_file {
+            Some(path) => input::join(&prompt, &input::read_prompt_file(path)?),
+            None => prompt,
+        };
+        if self.args.edit {
+            input::edit(&prompt, "prompt.md")
+        } else {
+            Ok(prompt)
+        }
+    }
+
+    /// The system prompt, `--system` or the settings' custom one, or the task's default.
+    /// Describes the environment unless context is turned off
+    fn system_prompt(&self, default: &str) -> Result<String> {
+        prompts::system_prompt(
+            self.args.system.as_deref(),
+            &self.settings,
+            default,
+            !self.args.no_context && self.settings.context.environment,
+        )
+    }
+
+    /// Applies the instruction to the text piped in with the task's prompts, in chunks
+    /// merged until one result is left when it's too long for a single request
+    fn pipe(mut self, task: Task, instruction: &str) -> Result<()> {
+        let input = pipe::read_input()?;
+        let template = Prompt::load(task)?;
+        let backend_name = self.backend_name();
+        let max_tokens = pipe::chunk_tokens(&self.settings, &backend_name);
+        // the output isn't a shell command and the environment doesn't matter
+        self.settings.local_model_config.grammar = None;
+        self.settings.local_model_config.stop_at_newline = false;
+        let system_prompt = prompts::system_prompt(
+            self.args.system.as_deref(),
+            &self.settings,
+            &template.system,
+            false,
+        )?;
+        let backend = create_backend(&backend_name, self.settings, system_prompt, self.start)?;
+        let result = pipe::run(backend.as_ref(), &template, instruction, &input, max_tokens)?;
+        println!("{}", result);
+        if self.args.stats {
+            if let Some(usage) = backend.usage() {
+                eprintln!("{}", usage);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the prompt through the configured backend, prints the generated command and returns it.
+    /// The prompt is sent using the task's template, filled in with `vars`
+    fn generate(
+        mut self,
+        task: Task,
+        prompt: String,
+        mut vars: HashMap<String, String>,
+    ) -> Result<String> {
+        let prompts = Prompt::load(task)?;
+        let prompt = match self.args.seed_from {
+            Some(id) => {
+                let entry = History::open()?.get(id)?;
+                if self.args.ai_backend.is_none() {
+                    self.settings.ai_backend = entry.backend.clone();
+                }
+                let backend = self.backend_name();
+                if let Some(params) = &entry.params {
+                    params.apply(&mut self.settings, &backend);
                 }
+                info!("Replaying history entry {}", id);
+                history::follow_up(&entry, &prompt)
+            }
+            None => prompt,
+        };
+        let session = if self.args.continue_session || task == Task::Refine {
+            let session = Session::load()?;
+            if self.args.ai_backend.is_none() {
+                self.settings.ai_backend = session.backend.clone();
+            }
+            let backend = self.backend_name();
+            session.params.apply(&mut self.settings, &backend);
+            info!(
+                "Continuing a conversation of {} messages",
+                session.messages.len()
+            );
+            Some(session)
+        } else {
+            None
+        };
+        // an explicit --seed or --model wins over the replayed or continued one
+        self.apply_overrides()?;
+        // a commit message or regex isn't a shell command, nor is the rest of one
+        if !task.is_command() || task == Task::Complete {
+            self.settings.local_model_config.grammar = None;
+        }
+        if task == Task::Commit || task == Task::ExplainError || self.args.multiline {
+            self.settings.local_model_config.stop_at_newline = false;
+        }
+        if let Some(path) = &self.args.schema {
+            self.settings.response_schema = Some(schema::load(path)?);
+        }
+        let response_schema = self.settings.response_schema.clone();
+        // check prompt is not empty
+        if prompt.is_empty() {
+            return Err(anyhow::anyhow!("{}", Message::PromptEmpty));
+        }
+        info!(
+            "temp: {:.2} repeat-penalty: {:.2} repeat-last-n: {}",
+            self.settings.local_model_config.temperature.unwrap_or(0.),
+            self.settings.local_model_config.repeat_penalty,
+            self.settings.local_model_config.repeat_last_n
+        );
+        let mut builder =
+            PromptBuilder::new(self.settings.context.clone()).attach(&self.args.files);
+        if self.args.dir_context {
+            builder = builder.enable("directory");
+        }
+        if self.args.with_docs {
+            builder = builder.enable("docs");
+        }
+        if task == Task::Git {
+            builder = builder.enable("git");
+        }
+        for name in self.args.context.iter() {
+            builder = builder.enable(name);
+        }
+        // history records what the user asked, the backend also sees the context
+        let (full_prompt, system_prompt) = info_span!("context").in_scope(|| -> Result<_> {
+            vars.insert("prompt".to_string(), prompt.clone());
+            let asked = prompts.render(&vars)?;
+            // a refinement continues a conversation whose first turn already has the context,
+            // attached files are sent either way
+            let full_prompt = if self.args.no_context || task == Task::Refine {
+                builder.build_attachments(&asked)?
+            } else {
+                builder.build(&asked)?
+            };
+            Ok((full_prompt, self.system_prompt(&prompts.system)?))
+        })?;
+        if self.backend_name() == routing::AUTO {
+            let routed = if self.settings.local_only {
+                info!(
+                    "Local only, routing to {}",
+                    self.settings.routing.local_backend
+                );
+                &self.settings.routing.local_backend
+            } else {
+                routing::route(&self.settings.routing, &full_prompt, self.args.files.len())
+            };
+            self.args.ai_backend = Some(routed.to_string());
+        }
+        #[cfg(feature = "clipboard")]
+        let copy_to_clipboard = !self.args.no_clipboard
+            && match self.settings.clipboard {
+                crate::settings::ClipboardMode::Always => true,
+                crate::settings::ClipboardMode::Never => false,
+                crate::settings::ClipboardMode::Auto => std::io::stdout().is_terminal(),
+            };
+        let save_history = self.settings.history;
+        let mut params = GenerationParams::from_settings(&self.settings, &self.backend_name());
+        let webhooks = self
+            .args
+            .deliver
+            .iter()
+            .map(|name| {
+                self.settings
+                    .webhooks
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("{}", Message::UnknownWebhook(name)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // fails before generating rather than after
+        if !webhooks.is_empty() {
+            network::ensure_online("Delivering to a webhook")?;
+        }
+        let webhook_timeout_secs = self.settings.request_timeout_secs;
+        let backend = self.backend_name();
+        let mut session = session.unwrap_or_else(|| Session::new(&backend, params.clone()));
+        session.messages.push(ChatMessage::user(full_prompt));
 
-                info!("response time: {:?}", self.start.elapsed());
-                info!("{:?}", result);
+        let accessible = self.settings.accessibility;
+        let quiet = self.args.quiet();
+        // the actions are for commands, not json
+        let interactive = !quiet
+            && response_schema.is_none()
+            && task.is_command()
+            && task != Task::Complete
+            && !self.args.no_interactive
+            && (self.args.interactive || self.settings.interactive);
+        // without the menu, `r` pressed right after the command is printed regenerates it.
+        // The wait takes keys typed ahead for the shell, `quick_regenerate = false` skips it
+        let offer_regenerate = self.settings.quick_regenerate
+            && !quiet
+            && response_schema.is_none()
+            && task != Task::Complete
+            && !self.args.no_interactive
+            && std::io::stdout().is_terminal()
+            && interact::available();
+        let candidates = self.args.candidates.max(1);
+        // the context is part of the key, so a changed directory or git state misses. So
+        // is the seed, a random one samples anew rather than reusing an earlier sample
+        let cache = (self.settings.cache_ttl_secs > 0)
+            .then(|| ResponseCache::new(self.settings.cache_ttl_secs));
+        let cache_key = match &cache {
+            Some(_) => Some(ResponseCache::key(
+                &self.settings,
+                &backend,
+                &system_prompt,
+                &session.messages,
+                candidates,
+            )?),
+            None => None,
+        };
+        let cached = match (&cache, &cache_key) {
+            (Some(cache), Some(key)) if !self.args.no_cache => cache.get(key),
+            _ => None,
+        };
+        let local_model = info_span!("backend_init", backend = %backend)
+            .in_scope(|| create_backend(&backend, self.settings, system_prompt, self.start))?;
+        info!("Beginning inference");
+        let mut bar = None;
+        // if match verbosity is info or below
+        if quiet {
+            info!("Quiet, not showing progress");
+        } else if self.log_level < Level::Info && accessible {
+            // a plain message instead of the spinner, which screen readers read out every tick
+            eprintln!("{}", Message::Generating);
+        } else if self.log_level < Level::Info {
+            bar = output::spinner(Message::Thinking.to_string());
+        }
+        // Ctrl-C stops the generation and keeps what was generated so far
+        interrupt::install();
+        let from_cache = cached.is_some();
+        let mut results = match cached {
+            Some(results) => {
+                info!("Using the cached response");
+                results
+            }
+            None => {
+                take_cut_short();
+                let started = Instant::now();
+                let results = interrupt::generating(|| {
+                    local_model.invoke_conversation(&session.messages, candidates)
+                })?;
+                telemetry::record_generation(
+                    &backend,
+                    params.model_id.as_deref(),
+                    started.elapsed(),
+                    local_model.usage(),
+                );
+                if let (Some(cache), Some(key)) = (&cache, &cache_key) {
+                    // partial output isn't worth keeping
+                    if take_cut_short() {
+                        info!("Not caching the partial response");
+                    } else if let Err(e) = cache.put(key, &backend, &results) {
+                        warn!("Unable to cache the response: {:?}", e);
+                    }
+                }
+                results
+            }
+        };
+        if let Some(bar) = bar {
+            bar.finish_with_message(Message::Done.to_string());
+        } else if self.log_level < Level::Info && accessible && !quiet {
+            eprintln!("{}", Message::Done);
+        }
+        let count = results.len();
+        let (picked, result) = if count > 1 {
+            pick_candidate(results, accessible)?
+        } else {
+            let result = results
+                .pop()
+                .ok_or_else(|| anyhow::anyhow!("{}", Message::NoOutput))?;
+            (0, result)
+        };
+        // candidates are sampled with consecutive seeds, the usage has the last one
+        let mut seed = local_model
+            .usage()
+            .and_then(|usage| usage.seed)
+            .map(|last| last.wrapping_sub((count - 1 - picked) as u64));
+        let _post_processing = info_span!("post_processing").entered();
+        let result = match &response_schema {
+            Some(response_schema) => schema::check(response_schema, &result)?,
+            None => result,
+        };
+        let result = match task {
+            Task::Complete => complete::continuation(&prompt, &result),
+            _ => modes::check(task, &result)?,
+        };
+        // values the request left out are asked for before the command is printed or copied
+        let ask_placeholders = task.is_command()
+            && task != Task::Complete
+            && response_schema.is_none()
+            && interact::available();
+        let fill = |result: String| -> Result<String> {
+            match ask_placeholders {
+                true => interact::fill_placeholders(&result, accessible),
+                false => Ok(result),
+            }
+        };
+        let result = fill(result)?;
+
+        info!("response time: {:?}", self.start.elapsed());
+        if self.args.stats && from_cache {
+            eprintln!("{}", Message::CachedResponse);
+        } else if self.args.stats {
+            match local_model.usage() {
+                Some(usage) => eprintln!("{}", usage),
+                None => eprintln!("{}", Message::UsageUnavailable(&backend)),
+            }
+        }
+        info!("{:?}", result);
+        let plain = self.args.plain || response_schema.is_some() || !output::styled_stdout();
+        let print = |result: &str| {
+            if task == Task::Complete {
+                // the leading space of a continuation matters
                 println!("{}", result);
-                #[cfg(feature = "clipboard")]{
-                    let mut clipboard = arboard::Clipboard::new()?;
-                    clipboard.set_text(result)?;
+            } else if quiet {
+                // only the command, so the output can be run as is
+                println!("{}", render::strip_code_fence(result));
+            } else if plain {
+                println!("{}", result);
+            } else {
+                println!("{}", render::render_markdown(result));
+            }
+        };
+        print(&result);
+        let mut result = result;
+        if interactive && interact::available() {
+            loop {
+                match interact::choose_action()? {
+                    Action::Copy => {
+                        match interact::copy(&result) {
+                            Ok(()) => eprintln!("{}", Message::Copied),
+                            Err(e) => eprintln!("{}", e),
+                        }
+                        break;
+                    }
+                    Action::Edit => {
+                        result = interact::edit(&result)?;
+                        eprintln!("{}", result);
+                        eprintln!("{}", Message::ExitStatus(interact::run(&result)?));
+                        break;
+                    }
+                    Action::Run => {
+                        eprintln!("{}", Message::ExitStatus(interact::run(&result)?));
+                        break;
+                    }
+                    Action::Regenerate => {
+                        eprintln!("{}", Message::Generating);
+                        result = interrupt::generating(|| {
+                            local_model.invoke_conversation(&session.messages, 1)
+                        })?
+                        .pop()
+                        .ok_or_else(|| anyhow::anyhow!("{}", Message::NoOutput))?;
+                        result = fill(modes::check(task, &result)?)?;
+                        seed = local_model.usage().and_then(|usage| usage.seed);
+                        print(&result);
+                    }
+                    Action::Quit => break,
                 }
-                Ok(())
             }
+        } else if offer_regenerate {
+            let term = console::Term::stderr();
+            loop {
+                eprint!(
+                    "{}",
+                    console::style(Message::RegenerateHint).for_stderr().dim()
+                );
+                let key = interact::key_within(REGENERATE_WAIT)?;
+                term.clear_line()?;
+                if key != Some('r') {
+                    break;
+                }
+                eprintln!("{}", Message::Generating);
+                result = interrupt::generating(|| {
+                    local_model.invoke_conversation(&session.messages, 1)
+                })?
+                .pop()
+                .ok_or_else(|| anyhow::anyhow!("{}", Message::NoOutput))?;
+                result = fill(modes::check(task, &result)?)?;
+                seed = local_model.usage().and_then(|usage| usage.seed);
+                print(&result);
+            }
+        }
+        session
+            .messages
+            .push(ChatMessage::assistant(result.clone()));
+        if let Err(e) = session.save() {
+            warn!("Unable to save the session: {:?}", e);
         }
+        if seed.is_some() {
+            params.seed = seed;
+        }
+        if save_history {
+            match History::open().and_then(|h| h.record(&backend, &prompt, &result, params)) {
+                Ok(id) => info!("Saved to history as {}", id),
+                Err(e) => warn!("Unable to save history: {:?}", e),
+            }
+        }
+        // the response is still written and copied when a webhook is down
+        for webhook in webhooks.iter() {
+            let delivered =
+                delivery::deliver(webhook, &backend, &prompt, &result, webhook_timeout_secs);
+            if let Err(e) = delivered {
+                warn!(target: NOTICE, "{}", Message::DeliveryFailed(&format!("{:#}", e)));
+            }
+        }
+        if let Some(path) = &self.args.out {
+            delivery::write_script(path, &result)?;
+            eprintln!("{}", Message::WroteScript(&path.display().to_string()));
+        }
+        #[cfg(feature = "clipboard")]
+        if copy_to_clipboard {
+            let mut clipboard = arboard::Clipboard::new()?;
+            clipboard.set_text(result.clone())?;
+        }
+        Ok(result)
+    }
+}
+
+/// Shows an interactive picker over the candidates and returns the selected one and its index.
+/// Falls back to the first candidate when stderr isn't a terminal.
+/// In accessibility mode the picker uses plain text markers instead of colors.
+fn pick_candidate(mut candidates: Vec<String>, accessible: bool) -> Result<(usize, String)> {
+    if !std::io::stderr().is_terminal() {
+        warn!("Not running in a terminal, using the first candidate");
+        return Ok((0, candidates.swap_remove(0)));
+    }
+    let items: Vec<&str> = candidates.iter().map(|c| c.trim()).collect();
+    let selection = Select::with_theme(output::theme(accessible).as_ref())
+        .with_prompt(Message::PickCommand.to_string())
+        .items(&items)
+        .default(0)
+        .interact_opt()?;
+    match selection {
+        Some(index) => Ok((index, candidates.swap_remove(index))),
+        None => Err(anyhow::anyhow!("{}", Message::NoCommandSelected)),
     }
 }
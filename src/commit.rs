@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+use crate::audit;
+use crate::context::CHARS_PER_TOKEN;
+use crate::interrupt;
+use crate::messages::Message;
+use crate::schedule::shell_quote;
+
+/// The staged changes `ai commit` writes a message for
+#[derive(Debug)]
+pub struct StagedDiff {
+    /// `git diff --cached --stat`, always sent in full so the model sees every file
+    pub stat: String,
+    /// `git diff --cached`, trimmed to the token budget
+    pub diff: String,
+}
+
+impl StagedDiff {
+    /// Collects the staged diff, fitting it into roughly `max_tokens`
+    pub fn collect(max_tokens: usize) -> Result<Self> {
+        let stat = git(&["diff", "--cached", "--stat"])?;
+        if stat.trim().is_empty() {
+            anyhow::bail!("{}", Message::NothingStaged);
+        }
+        let diff = git(&["diff", "--cached", "--no-color", "--no-ext-diff"])?;
+        let budget = (max_tokens * CHARS_PER_TOKEN).saturating_sub(stat.len());
+        let diff = fit_diff(&diff, budget);
+        info!("Staged diff is {} characters after trimming", diff.len());
+        Ok(Self { stat, diff })
+    }
+
+    /// The last line of the stat, e.g. "2 files changed, 10 insertions(+)"
+    pub fn summary(&self) -> String {
+        self.stat
+            .lines()
+            .last()
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+    }
+
+    /// Variables for the commit prompt template: stat and diff
+    pub fn vars(&self) -> HashMap<String, String> {
+        HashMap::from([
+            ("stat".to_string(), self.stat.trim_end().to_string()),
+            ("diff".to_string(), self.diff.trim_end().to_string()),
+        ])
+    }
+}
+
+/// Commits the staged changes with the message, recorded in the audit log when that's on
+pub fn commit(message: &str) -> Result<()> {
+    let audit = audit::open()?;
+    // hooks run by git commit may prompt
+    let status =
+        interrupt::foreground(|| Command::new("git").args(["commit", "-m", message]).status())
+            .context("Unable to run git commit")?;
+    if let Some(mut audit) = audit {
+        let command = format!("git commit -m {}", shell_quote(message));
+        audit.run(&command, status.code().unwrap_or(-1))?;
+    }
+    if !status.success() {
+        anyhow::bail!("{}", Message::GitCommitFailed(&status.to_string()));
+    }
+    Ok(())
+}
+
+fn git(args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .context("Unable to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{}",
+            Message::GitFailed(
+                &args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Trims the diff to `budget` characters by sharing the budget between files, so one
+/// large file (a lockfile, generated code) can't crowd out the others. Small files are
+/// kept whole and what they don't use goes to the larger ones
+fn fit_diff(diff: &str, budget: usize) -> String {
+    if diff.len() <= budget {
+        return diff.to_string();
+    }
+    let mut files: Vec<&str> = Vec::new();
+    let mut start = 0;
+    for (newline, _) in diff.match_indices("\ndiff --git ") {
+        files.push(&diff[start..newline + 1]);
+        start = newline + 1;
+    }
+    files.push(&diff[start..]);
+
+    let mut order: Vec<usize> = (0..files.len()).collect();
+    order.sort_by_key(|&i| files[i].len());
+    let mut shares = vec![0; files.len()];
+    let mut remaining = budget;
+    for (done, &i) in order.iter().enumerate() {
+        let share = remaining / (files.len() - done);
+        shares[i] = files[i].len().min(share);
+        remaining -= shares[i];
+    }
+    files
+        .iter()
+        .zip(shares)
+        .map(|(file, share)| {
+            if share >= file.len() {
+                return file.to_string();
+            }
+            let mut end = share;
+            while !file.is_char_boundary(end) {
+                end -= 1;
+            }
+            // keep the `diff --git` header so the file is still named
+            let header = file.lines().next().unwrap_or_default();
+            if end <= header.len() {
+                format!("{}\n... (diff omitted)\n", header)
+            } else {
+                format!("{}\n... (truncated)\n", &file[..end].trim_end())
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,117 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use console::{pad_str, style, Alignment, Term};
+use tracing::info;
+
+use crate::ai_backend::create_backend;
+use crate::messages::Message;
+use crate::render;
+use crate::settings::Settings;
+
+/// Columns between two backends' outputs
+const GUTTER: &str = " │ ";
+
+/// One backend's response to the compared prompt
+pub struct Answer {
+    pub backend: String,
+    pub elapsed: Duration,
+    pub response: Result<String>,
+}
+
+impl Answer {
+    fn label(&self) -> String {
+        format!("{} ({:.2?})", self.backend, self.elapsed)
+    }
+
+    /// The response without its code fence, or the error
+    fn text(&self) -> String {
+        match &self.response {
+            Ok(response) => render::strip_code_fence(response),
+            Err(e) => format!("error: {:#}", e),
+        }
+    }
+}
+
+/// Sends the prompt to every backend at once, each on its own thread with its own copy
+/// of the settings. The latency includes loading the local model
+pub fn run(
+    backends: &[String],
+    settings: &Settings,
+    system_prompt: &str,
+    prompt: &str,
+) -> Vec<Answer> {
+    std::thread::scope(|scope| {
+        let handles = backends
+            .iter()
+            .map(|backend| {
+                scope.spawn(move || {
+                    let start = Instant::now();
+                    let response =
+                        create_backend(backend, settings.clone(), system_prompt.to_string(), start)
+                            .and_then(|model| model.invoke(prompt.to_string()));
+                    let elapsed = start.elapsed();
+                    info!("The {} backend answered in {:?}", backend, elapsed);
+                    Answer {
+                        backend: backend.clone(),
+                        elapsed,
+                        response,
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .zip(backends)
+            .map(|(handle, backend)| {
+                handle.join().unwrap_or_else(|_| Answer {
+                    backend: backend.clone(),
+                    elapsed: Duration::ZERO,
+                    response: Err(anyhow::anyhow!("{}", Message::BackendPanicked(backend))),
+                })
+            })
+            .collect()
+    })
+}
+
+/// Prints the answers side by side in labeled columns on a terminal, one after the other
+/// when piped or when the terminal is too narrow
+pub fn print(answers: &[Answer]) {
+    let term = Term::stdout();
+    let columns = answers.len().max(1);
+    let width = (term.size().1 as usize).saturating_sub(GUTTER.len() * (columns - 1)) / columns;
+    if !term.is_term() || columns == 1 || width < 20 {
+        for answer in answers {
+            println!("== {} ==", answer.label());
+            println!("{}\n", answer.text().trim_end());
+        }
+        return;
+    }
+    let header = answers
+        .iter()
+        .map(|answer| {
+            let label = answer.label();
+            style(pad_str(&label, width, Alignment::Left, Some("…")))
+                .bold()
+                .to_string()
+        })
+        .collect::<Vec<_>>();
+    println!("{}", header.join(GUTTER));
+    let rule = "─".repeat(width);
+    println!("{}", vec![rule; columns].join("─┼─"));
+    let wrapped = answers
+        .iter()
+        .map(|answer| render::wrap(answer.text().trim_end(), width))
+        .collect::<Vec<_>>();
+    let rows = wrapped.iter().map(Vec::len).max().unwrap_or(0);
+    for row in 0..rows {
+        let line = wrapped
+            .iter()
+            .map(|lines| {
+                let line = lines.get(row).map_or("", |line| line.as_str());
+                pad_str(line, width, Alignment::Left, None).to_string()
+            })
+            .collect::<Vec<_>>();
+        println!("{}", line.join(GUTTER).trim_end());
+    }
+}
@@ -0,0 +1,27 @@
+use crate::render;
+
+/// What to append to `fragment` for the command in `reply`. The model is asked for the
+/// whole command, when it answers with only the rest its overlap with the end of the
+/// fragment is dropped instead, e.g. `tar -c` and `-czf out.tgz dir` give `zf out.tgz dir`
+pub fn continuation(fragment: &str, reply: &str) -> String {
+    let reply = render::strip_code_fence(reply);
+    if let Some(rest) = reply.strip_prefix(fragment) {
+        return rest.to_string();
+    }
+    let trimmed = fragment.trim_end();
+    if let Some(rest) = reply.strip_prefix(trimmed) {
+        // the fragment's trailing space is already on the command line
+        return rest.trim_start().to_string();
+    }
+    // the longest run of whole words at the end of the fragment the reply starts with
+    let overlap = trimmed
+        .char_indices()
+        .filter(|(i, _)| *i == 0 || trimmed[..*i].ends_with(char::is_whitespace))
+        .map(|(i, _)| &trimmed[i..])
+        .find(|suffix| reply.starts_with(suffix));
+    match overlap {
+        Some(suffix) if fragment.len() == trimmed.len() => reply[suffix.len()..].to_string(),
+        _ if fragment.is_empty() || fragment.ends_with(char::is_whitespace) => reply,
+        _ => format!(" {}", reply),
+    }
+}
@@ -0,0 +1,431 @@
+use config::{Value, ValueKind};
+
+/// What a setting accepts
+enum Kind {
+    Bool,
+    /// An integer of at least `min`
+    Int {
+        min: i64,
+    },
+    /// A number from `min` to `max`
+    Float {
+        min: f64,
+        max: f64,
+    },
+    Str,
+    /// One of a fixed set of strings
+    OneOf(&'static [&'static str]),
+    List(&'static Kind),
+    Table(&'static [Field]),
+    /// A table with any keys, all holding the same kind of value
+    Map(&'static Kind),
+}
+
+struct Field {
+    key: &'static str,
+    kind: Kind,
+}
+
+const fn field(key: &'static str, kind: Kind) -> Field {
+    Field { key, kind }
+}
+
+/// Plugins on PATH can be named too, `ai doctor` checks the backend exists
+const BACKEND: Kind = Kind::Str;
+const UNSIGNED: Kind = Kind::Int { min: 0 };
+const POSITIVE: Kind = Kind::Int { min: 1 };
+const PRICE: Kind = Kind::Float {
+    min: 0.0,
+    max: f64::MAX,
+};
+
+/// Every key the settings read, mirroring the structs in settings.rs
+const SCHEMA: &[Field] = &[
+    field("config_version", UNSIGNED),
+    field(
+        "verbosity",
+        Kind::OneOf(&["error", "warn", "info", "debug", "trace"]),
+    ),
+    field("log_file", Kind::Str),
+    field("log_max_size_mb", POSITIVE),
+    field("log_max_files", UNSIGNED),
+    field("ai_backend", BACKEND),
+    field("local_model_config", Kind::Table(LOCAL_MODEL_CONFIG)),
+    field("aws_settings", Kind::Table(AWS_SETTINGS)),
+    field(
+        "bedrock_model_config",
+        Kind::Table(&[
+            field("max_tokens", POSITIVE),
+            field("temperature", Kind::Float { min: 0.0, max: 1.0 }),
+            field("top_p", Kind::Float { min: 0.0, max: 1.0 }),
+            field("stop_sequences", Kind::List(&Kind::Str)),
+        ]),
+    ),
+    field("clipboard", Kind::OneOf(&["auto", "always", "never"])),
+    field("history", Kind::Bool),
+    field(
+        "history_encryption",
+        Kind::OneOf(&["off", "passphrase", "keyring"]),
+    ),
+    field("history_passphrase_cache_secs", UNSIGNED),
+    field("local_only", Kind::Bool),
+    field("request_timeout_secs", UNSIGNED),
+    field("ca_bundle", Kind::Str),
+    field("cache_ttl_secs", UNSIGNED),
+    field("interactive", Kind::Bool),
+    field("quick_regenerate", Kind::Bool),
+    field("accessibility", Kind::Bool),
+    field("language", Kind::Str),
+    field("system_prompt", Kind::Str),
+    field("system_prompt_file", Kind::Str),
+    field("system_prompt_append", Kind::Str),
+    field("embedding_model", Kind::Str),
+    field("pipe_chunk_tokens", POSITIVE),
+    field("daemon", Kind::Table(&[field("keep_alive", Kind::Str)])),
+    field(
+        "redaction",
+        Kind::Table(&[
+            field("enabled", Kind::Bool),
+            field(
+                "kinds",
+                Kind::List(&Kind::OneOf(&["aws_key", "token", "email", "ip"])),
+            ),
+            field("patterns", Kind::Map(&Kind::Str)),
+        ]),
+    ),
+    field(
+        "audit",
+        Kind::Table(&[
+            field("enabled", Kind::Bool),
+            field("content", Kind::OneOf(&["hash", "full"])),
+            field("path", Kind::Str),
+        ]),
+    ),
+    field(
+        "hooks",
+        Kind::Table(&[
+            field("pre_prompt", Kind::Str),
+            field("post_response", Kind::Str),
+        ]),
+    ),
+    field("context", Kind::Table(CONTEXT)),
+    field(
+        "routing",
+        Kind::Table(&[
+            field("max_local_tokens", UNSIGNED),
+            field("max_local_files", UNSIGNED),
+            field("local_backend", BACKEND),
+            field("remote_backend", BACKEND),
+        ]),
+    ),
+    field(
+        "telemetry",
+        Kind::Table(&[
+            field("enabled", Kind::Bool),
+            field("endpoint", Kind::Str),
+            field("service_name", Kind::Str),
+            field("headers", Kind::Map(&Kind::Str)),
+            field("export_interval_secs", UNSIGNED),
+        ]),
+    ),
+    field(
+        "webhooks",
+        Kind::Map(&Kind::Table(&[
+            field("url", Kind::Str),
+            field("kind", Kind::OneOf(&["generic", "slack"])),
+        ])),
+    ),
+    field(
+        "command_backend",
+        Kind::Table(&[field("cmd", Kind::Str), field("credential", Kind::Str)]),
+    ),
+    field(
+        "mock_backend",
+        Kind::Table(&[field("fixture", Kind::Str), field("delay_ms", UNSIGNED)]),
+    ),
+    field(
+        "plugins",
+        Kind::Map(&Kind::Table(&[
+            field("path", Kind::Str),
+            field("args", Kind::List(&Kind::Str)),
+            field("credential", Kind::Str),
+        ])),
+    ),
+    field("monthly_budget_usd", PRICE),
+    field(
+        "prices",
+        Kind::Map(&Kind::Table(&[
+            field("input_per_1k", PRICE),
+            field("output_per_1k", PRICE),
+        ])),
+    ),
+    field("model_aliases", Kind::Map(&Kind::Str)),
+    field(
+        "quotas",
+        Kind::Table(&[
+            field("max_requests_per_day", UNSIGNED),
+            field("max_tokens_per_month", UNSIGNED),
+            field("backends", Kind::Map(&Kind::Table(QUOTA))),
+        ]),
+    ),
+];
+
+const QUOTA: &[Field] = &[
+    field("max_requests_per_day", UNSIGNED),
+    field("max_tokens_per_month", UNSIGNED),
+];
+
+const LOCAL_MODEL_CONFIG: &[Field] = &[
+    field("cpu", Kind::Bool),
+    field("devices", Kind::List(&Kind::Str)),
+    field("model", Kind::OneOf(&["V2", "V3"])),
+    field("quantized", Kind::Bool),
+    field("verbose_prompt", Kind::Bool),
+    field("temperature", Kind::Float { min: 0.0, max: 2.0 }),
+    field("top_p", Kind::Float { min: 0.0, max: 1.0 }),
+    field("seed", UNSIGNED),
+    field("sample_len", POSITIVE),
+    field("model_id", Kind::Str),
+    field("revision", Kind::Str),
+    field("weight_file", Kind::Str),
+    field("tokenizer", Kind::Str),
+    field(
+        "repeat_penalty",
+        Kind::Float {
+            min: 0.0,
+            max: 10.0,
+        },
+    ),
+    field("repeat_last_n", UNSIGNED),
+    field("dtype", Kind::OneOf(&["f32", "f16", "bf16"])),
+    field("chat_template", Kind::OneOf(&["plain", "phi3", "chatml"])),
+    field(
+        "max_memory_gb",
+        Kind::Float {
+            min: 0.0,
+            max: f64::MAX,
+        },
+    ),
+    field("banned_strings", Kind::List(&Kind::Str)),
+    field("stop_at_newline", Kind::Bool),
+    field("grammar", Kind::OneOf(&["shell"])),
+    field(
+        "draft",
+        Kind::Table(&[
+            field("model_id", Kind::Str),
+            field("revision", Kind::Str),
+            field("weight_file", Kind::Str),
+            field("architecture", Kind::OneOf(&["1.5", "2"])),
+            field("tokens", POSITIVE),
+        ]),
+    ),
+];
+
+const AWS_SETTINGS: &[Field] = &[
+    field("profile", Kind::Str),
+    field("role_arn", Kind::Str),
+    field("mfa_serial", Kind::Str),
+    field("region", Kind::Str),
+    field("endpoint_url", Kind::Str),
+    field("model_id", Kind::Str),
+    field("fallback_regions", Kind::List(&Kind::Str)),
+    field("tools", Kind::Bool),
+    field("prompt_caching", Kind::Bool),
+];
+
+const CONTEXT: &[Field] = &[
+    field("timeout_ms", UNSIGNED),
+    field("environment", Kind::Bool),
+    field(
+        "providers",
+        Kind::Map(&Kind::Table(&[
+            field("enabled", Kind::Bool),
+            field("priority", Kind::Int { min: i64::MIN }),
+            field("max_tokens", POSITIVE),
+        ])),
+    ),
+];
+
+/// Problems found in the merged config
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Values of the wrong type or out of range, the config can't be used
+    pub errors: Vec<String>,
+    /// Keys nothing reads, most likely typos or sections from an older version
+    pub warnings: Vec<String>,
+}
+
+/// Checks every key of the merged config against the settings the code reads. Messages
+/// name the file a value came from, the full key and what was expected
+pub fn validate(root: &Value) -> Report {
+    let mut report = Report::default();
+    check_table(root, SCHEMA, "", &mut report);
+    // tables iterate in no particular order
+    report.errors.sort();
+    report.warnings.sort();
+    report
+}
+
+fn check_table(value: &Value, fields: &[Field], path: &str, report: &mut Report) {
+    let ValueKind::Table(table) = &value.kind else {
+        return;
+    };
+    for (key, value) in table.iter() {
+        let full_key = join(path, key);
+        match fields.iter().find(|field| field.key == key) {
+            Some(field) => check(value, &field.kind, &full_key, report),
+            // an empty section does no harm, e.g. a header left above commented out keys
+            None if is_empty_table(value) => {}
+            None => {
+                let hint = closest(key, fields)
+                    .map(|known| format!(", did you mean {}?", join(path, known)))
+                    .unwrap_or_default();
+                report.warnings.push(format!(
+                    "{}: unknown key {} is ignored{}",
+                    origin(value),
+                    full_key,
+                    hint
+                ));
+            }
+        }
+    }
+}
+
+fn check(value: &Value, kind: &Kind, path: &str, report: &mut Report) {
+    // unset, the default applies
+    if matches!(value.kind, ValueKind::Nil) {
+        return;
+    }
+    let valid = match (kind, &value.kind) {
+        (Kind::Table(fields), ValueKind::Table(_)) => {
+            check_table(value, fields, path, report);
+            true
+        }
+        (Kind::Map(kind), ValueKind::Table(table)) => {
+            for (key, value) in table.iter() {
+                check(value, kind, &join(path, key), report);
+            }
+            true
+        }
+        (Kind::List(kind), ValueKind::Array(items)) => {
+            for (index, item) in items.iter().enumerate() {
+                check(item, kind, &format!("{}[{}]", path, index), report);
+            }
+            true
+        }
+        (Kind::Bool, ValueKind::Boolean(_)) => true,
+        (Kind::Bool, ValueKind::String(s)) => s.parse::<bool>().is_ok(),
+        (Kind::Int { min }, _) => integer(value).is_some_and(|n| n >= i128::from(*min)),
+        (Kind::Float { min, max }, _) => number(value).is_some_and(|n| n >= *min && n <= *max),
+        (Kind::Str, ValueKind::String(_)) => true,
+        (Kind::OneOf(choices), ValueKind::String(s)) => choices.contains(&s.as_str()),
+        _ => false,
+    };
+    if !valid {
+        report.errors.push(format!(
+            "{}: {} must be {}, found {}",
+            origin(value),
+            path,
+            expected(kind),
+            found(value)
+        ));
+    }
+}
+
+fn integer(value: &Value) -> Option<i128> {
+    match &value.kind {
+        ValueKind::I64(n) => Some(i128::from(*n)),
+        ValueKind::I128(n) => Some(*n),
+        ValueKind::U64(n) => Some(i128::from(*n)),
+        ValueKind::U128(n) => i128::try_from(*n).ok(),
+        ValueKind::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match &value.kind {
+        ValueKind::Float(n) => Some(*n),
+        ValueKind::String(s) => s.trim().parse().ok(),
+        _ => integer(value).map(|n| n as f64),
+    }
+}
+
+fn expected(kind: &Kind) -> String {
+    match kind {
+        Kind::Bool => "true or false".to_string(),
+        Kind::Int { min: i64::MIN } => "a whole number".to_string(),
+        Kind::Int { min } => format!("a whole number of at least {}", min),
+        Kind::Float { min, max } if *max == f64::MAX => format!("a number of at least {}", min),
+        Kind::Float { min, max } => format!("a number from {} to {}", min, max),
+        Kind::Str => "a string".to_string(),
+        Kind::OneOf(choices) => format!("one of \"{}\"", choices.join("\", \"")),
+        Kind::List(kind) => format!("a list of {}", expected(kind)),
+        Kind::Table(_) | Kind::Map(_) => "a table".to_string(),
+    }
+}
+
+fn found(value: &Value) -> String {
+    match &value.kind {
+        ValueKind::Table(_) => "a table".to_string(),
+        ValueKind::Array(_) => "a list".to_string(),
+        ValueKind::String(s) => format!("\"{}\"", s),
+        _ => value.to_string(),
+    }
+}
+
+/// The file a value came from. Tables don't record one, their first value does
+fn origin(value: &Value) -> &str {
+    match (value.origin(), &value.kind) {
+        (Some(origin), _) => origin,
+        (None, ValueKind::Table(table)) => table
+            .values()
+            .map(origin)
+            .find(|origin| *origin != "config")
+            .unwrap_or("config"),
+        (None, _) => "config",
+    }
+}
+
+fn is_empty_table(value: &Value) -> bool {
+    matches!(&value.kind, ValueKind::Table(table) if table.values().all(is_empty_table))
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// A known key the unknown one is probably meant to be: one containing it, such as
+/// local_model_config for model_config, or one a couple of typos away
+fn closest<'a>(key: &str, fields: &'a [Field]) -> Option<&'a str> {
+    fields
+        .iter()
+        .map(|field| field.key)
+        .find(|known| key.len() > 3 && (known.contains(key) || key.contains(known)))
+        .or_else(|| {
+            fields
+                .iter()
+                .map(|field| (field.key, edit_distance(key, field.key)))
+                .filter(|(_, distance)| *distance <= 2)
+                .min_by_key(|(_, distance)| *distance)
+                .map(|(known, _)| known)
+        })
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
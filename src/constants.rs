@@ -1,53 +1,336 @@
 // constants file
-pub const SYSTEM_PROMPT: &str = "You are a command-line interface expert focused on generating bash one-liners. Your role is to create concise, efficient, and safe bash commands that solve the user's specified task in a single line.
-
-Key responsibilities:
-1. Generate ONLY the bash command, without explanation unless asked
-2. Always use proper shell escaping and quoting
-3. Prefer portable POSIX-compliant solutions when possible
-4. Use common Unix tools (grep, sed, awk, find, etc.) appropriately
-5. Consider error handling and edge cases
-6. Never include dangerous operations (rm -rf, etc.) without warning
-7. Add comments only if they fit in the one-liner using #
-
-Guidelines for command generation:
-- Parse the user's intent carefully
-- Choose the most efficient approach for the task
-- Use pipes (|) to chain commands when needed
-- Leverage command substitution $() where appropriate
-- Consider environment variables if relevant
-- Use appropriate file globbing patterns when needed
-
-Security and safety:
-- Always escape special characters in filenames
-- Use quotes around variables and paths
-- Avoid commands that could cause data loss
-- Include error checking where critical
-- Never generate commands that could harm the system
-
-Example format:
-Human: Find all PDF files modified in the last 24 hours
-Assistant: find . -type f -name \"*.pdf\" -mtime -1";
+
+/// Default Bedrock model
+pub const BEDROCK_MODEL_ID: &str = "anthropic.claude-3-haiku-20240307-v1:0";
+
+/// On demand Bedrock prices in USD per 1000 input and output tokens, by a part of the
+/// model id so cross-region profiles match too. The `prices` setting adds to these
+pub const BEDROCK_PRICES: &[(&str, f64, f64)] = &[
+    ("claude-3-haiku", 0.00025, 0.00125),
+    ("claude-3-5-haiku", 0.0008, 0.004),
+    ("claude-3-sonnet", 0.003, 0.015),
+    ("claude-3-5-sonnet", 0.003, 0.015),
+    ("claude-3-7-sonnet", 0.003, 0.015),
+    ("claude-3-opus", 0.015, 0.075),
+    ("titan-text-express", 0.0002, 0.0006),
+    ("titan-text-lite", 0.00015, 0.0002),
+    ("llama3-8b-instruct", 0.0003, 0.0006),
+    ("llama3-70b-instruct", 0.00265, 0.0035),
+    ("mistral-7b-instruct", 0.00015, 0.0002),
+    ("mixtral-8x7b-instruct", 0.00045, 0.0007),
+];
+/// Parts of the ids of the Bedrock models that support prompt caching
+pub const PROMPT_CACHING_MODELS: &[&str] = &[
+    "claude-3-5-haiku",
+    "claude-3-7-sonnet",
+    "claude-sonnet-4",
+    "claude-opus-4",
+    "claude-haiku-4",
+];
+/// How long `ai` waits for `r` to regenerate after printing a command in a terminal
+pub const REGENERATE_WAIT: std::time::Duration = std::time::Duration::from_millis(1500);
+/// Most tool calling round trips before the Bedrock backend gives up on a final answer
+pub const MAX_TOOL_ROUNDS: usize = 5;
+/// Approximate token budget for the staged diff sent by `ai commit`
+pub const COMMIT_DIFF_MAX_TOKENS: usize = 3000;
+/// Approximate tokens of stdin `ai pipe` sends per request to backends other than local
+pub const PIPE_CHUNK_TOKENS: usize = 24000;
 
 pub const DEFAULT_CONFIG_CONTENT: &str = r#"# AI CLI Configuration
 
+# Format version of this file, ai migrates older files and keeps a backup
+config_version = 1
+
 # Optional verbosity setting
 # verbosity = "info"
 
-# AI backend to use (default: "local")
+# Write the logs to this file instead of stdout, so the command stays alone on stdout even
+# with -vvv. Errors are still shown on stderr. Relative paths are in ~/.local/state/ai
+# log_file = "ai.log"
+# Rotate the log file when ai starts and it's over this many MiB, keeping log_max_files
+# old files as ai.log.1, ai.log.2, ... (defaults: 10, 3)
+# log_max_size_mb = 10
+# log_max_files = 3
+
+# AI backend to use, "local", "bedrock", "command", "mock" or a plugin's name (default: "local").
+# "auto" sends each prompt to the local or the remote backend by its size, see [routing]
 # ai_backend = "local"
 
+# When to copy the generated command to the clipboard, requires the clipboard feature
+# auto only copies when output isn't piped (default: "auto")
+# clipboard = "auto" # "always" | "never"
+
+# Forbid any network access, also --local-only: remote backends are refused, models are
+# only loaded from the Hugging Face cache, webhooks and telemetry are off (default: false)
+# local_only = false
+
+# Seconds a request may take before it's cancelled. Bedrock and command backend requests
+# are stopped, local generation stops after the tokens so far (model loading isn't counted).
+# Whatever was generated is printed. 0 for no limit (default: 120)
+# request_timeout_secs = 120
+
+# Requests go through the proxy in HTTPS_PROXY, HTTP_PROXY or ALL_PROXY, except to the
+# hosts in NO_PROXY. A proxy that intercepts TLS needs its CA certificates trusted, from a
+# PEM file given here. Model downloads only use the proxy, not NO_PROXY or this file
+# ca_bundle = "/etc/ssl/certs/corporate-ca.pem"
+
+# Seconds a response is reused when the same prompt is sent again with the same backend,
+# model, settings and context. Stored in ~/.cache/ai/responses, --no-cache skips the
+# lookup and `ai cache clear` empties it. The local seed is part of the request, set
+# local_model_config.seed too or every run samples anew. 0 turns the cache off (default: 0)
+# cache_ttl_secs = 86400
+
+# After generating, show a menu to [c]opy, [e]dit in $EDITOR then run, [r]un,
+# regenerate [x] or [q]uit, same as --interactive (default: false)
+# interactive = false
+
+# Without the menu, wait 1.5s after printing the command for `r` to regenerate it. Keys
+# typed meanwhile are read by ai rather than the shell (default: true)
+# quick_regenerate = true
+
+# Screen reader friendly output: replaces the spinner with plain "Generating…"/"Done"
+# messages and turns off colors (default: false)
+# accessibility = false
+
+# Language for the CLI's own messages, the model output language is up to the prompt.
+# Defaults to the system locale (LC_ALL, LC_MESSAGES, LANG), supported: en, es, de, fr
+# language = "de"
+
+# Replace the built-in system prompt, e.g. to enforce a house style. {{default}} expands to
+# the built-in prompt, {{shell}} to the shell name and {{os}} to the OS and distro.
+# Overridden for a single run with --system
+# system_prompt = "{{default}}\nAlways use long flags, e.g. --recursive rather than -r."
+
+# Or read it from a file, used when system_prompt isn't set
+# system_prompt_file = "/home/me/.config/ai/system_prompt.txt"
+
+# Added to the end of the system prompt, whichever one is used. Handy in a project's .ai.toml
+# system_prompt_append = "This repo uses pnpm, never npm."
+
+# The built-in prompts of each task (generate, fix, commit, refine, complete, pipe) can also be replaced in
+# ~/.config/ai/prompts/<task>.toml with `system` and `template` keys, see the README
+
+# Hugging Face BERT model `ai index` embeds files with, e.g. "thenlper/gte-small". Changing
+# it rebuilds the index (default: "sentence-transformers/all-MiniLM-L6-v2")
+# embedding_model = "sentence-transformers/all-MiniLM-L6-v2"
+
+# Approximate tokens of input `ai pipe` sends per request, longer input is split into
+# chunks whose results are then merged. Defaults to what fits in the local model's
+# context, or 24000 for the other backends
+# pipe_chunk_tokens = 8000
+
+# Most to spend on Bedrock per calendar month in USD. Requests whose estimated prompt cost
+# would go over it are refused unless --force is given. Spending is recorded in
+# ~/.config/ai/spending.json (default: no budget)
+# monthly_budget_usd = 5.0
+
+# Prices in USD per 1000 tokens for Bedrock models the built-in table doesn't know,
+# or to replace its price, keyed by the exact model id
+# [prices."anthropic.claude-sonnet-4-20250514-v1:0"]
+# input_per_1k = 0.003
+# output_per_1k = 0.015
+
+# Short names for models, picked with --model <name>, which also switches to the backend
+# that runs it. hf:<repo> runs on the local backend, anything else is a Bedrock model id
+# [model_aliases]
+# fast = "anthropic.claude-3-haiku-20240307-v1:0"
+# smart = "us.anthropic.claude-3-5-sonnet-20241022-v2:0"
+# tiny = "hf:microsoft/phi-2"
+
+# Save prompts and generated commands to ~/.config/ai/history.jsonl (default: true)
+# history = true
+
+# Encrypt the history and the last conversation (session.json) on disk. "passphrase" asks
+# for it, or reads $AI_HISTORY_PASSPHRASE; "keyring" keeps a random key in the login
+# Keychain, Credential Manager or Secret Service. Files are rewritten in the new mode the
+# next time they're saved (default: "off")
+# history_encryption = "off" # "passphrase" | "keyring"
+
+# Seconds the key derived from the passphrase is kept in $XDG_RUNTIME_DIR/ai, which only
+# you can read and is cleared at logout, so it isn't asked for on every run. 0 asks every
+# run, as do systems without a runtime directory such as macOS (default: 900)
+# history_passphrase_cache_secs = 900
+
+# Quotas on requests per day and tokens per month, days and months in UTC. The top-level
+# keys limit every backend together, [quotas.backends.<name>] one backend. Requests over
+# a quota are refused with an error, `ai usage` shows what was used. Usage is recorded in
+# ~/.config/ai/usage.json (default: no quotas)
+# [quotas]
+# max_requests_per_day = 200
+# max_tokens_per_month = 2000000
+# [quotas.backends.bedrock]
+# max_tokens_per_month = 500000
+
+# Webhooks output can be delivered to with --deliver <name>
+# kind is "generic" (json with prompt, response and backend) or "slack"
+# [webhooks.team]
+# url = "https://hooks.slack.com/services/..."
+# kind = "slack"
+
+[routing]
+# With ai_backend = "auto", prompts of up to max_local_tokens estimated tokens, context
+# included, and at most max_local_files files attached with --file go to local_backend,
+# the others to remote_backend (defaults: 400, 1, "local", "bedrock")
+# max_local_tokens = 400
+# max_local_files = 1
+# local_backend = "local"
+# remote_backend = "bedrock"
+
+[telemetry]
+# Export traces (settings load, context, model load, generation, network) and metrics
+# (latency and token counts by backend and model) to an OpenTelemetry collector over
+# OTLP/HTTP, sent every export_interval_secs and when each command finishes (default: false)
+# enabled = true
+# endpoint = "http://localhost:4318"
+# service_name = "ai"
+# export_interval_secs = 30
+# [telemetry.headers]
+# x-honeycomb-team = "your-api-key"
+
+[redaction]
+# Prompts sent to a backend other than local or mock, context included, have credentials
+# and personal data swapped for placeholders like REDACTED_EMAIL_1, swapped back in the
+# response. --no-redact skips it for a run (defaults: true, all kinds)
+# enabled = true
+# kinds = ["aws_key", "token", "email", "ip"]
+# [redaction.patterns]
+# host = '\b[a-z0-9-]+\.corp\.example\.com\b'
+
+[audit]
+# Append a line to an audit log for every generation and every command run from ai, with
+# the time, user, host, working directory, backend and model, for traceability on shared
+# systems. content = "hash" logs the SHA-256 of prompts, responses and commands, "full" the
+# texts. Relative paths are in ~/.local/state/ai. `ai audit show` lists the entries
+# (defaults: false, "hash", "audit.jsonl")
+# enabled = true
+# content = "hash"
+# path = "/var/log/ai/audit.jsonl"
+
+[hooks]
+# Executables the prompt and the response are piped through, stdin to stdout, for custom
+# redaction, translation or logging. pre_prompt gets every prompt, context included,
+# before it's sent, post_response every response before it's shown. They get the hook's
+# name in $AI_HOOK and the backend's in $AI_BACKEND, a non-zero exit stops ai
+# (default: none)
+# pre_prompt = "/usr/local/bin/ai-pre-prompt"
+# post_response = "/usr/local/bin/ai-post-response"
+
+[daemon]
+# How long `ai daemon` keeps the local model loaded after the last request before freeing
+# its memory, e.g. "90s", "10m", "2h" or "forever". The next request loads it again
+# (default: "10m")
+# keep_alive = "10m"
+
+[context]
+# Time budget in milliseconds for gathering prompt context,
+# slower context providers are skipped (default: 300)
+# timeout_ms = 300
+
+# Describe the OS, distro, shell, coreutils flavor (GNU/BSD) and installed tools such as
+# rg, fd and jq in the system prompt, skipped for a single run with --no-context (default: true)
+# environment = true
+
+# Context providers: system, git, directory, tools, docs and index. All are off unless enabled here
+# or for a single run with --context <name>
+# [context.providers.git]
+# enabled = true
+# priority = 20
+# max_tokens = 256
+
+# docs adds the man page of the tools the prompt mentions (--help output for common tools without one), also --with-docs.
+# Rendering man pages can take longer than the default timeout_ms
+# [context.providers.docs]
+# max_tokens = 1024
+
+# index adds the parts of the files indexed with `ai index <dir>` closest to the prompt,
+# also --context index. Loading the embedding model takes a moment on every prompt
+# [context.providers.index]
+# enabled = true
+# max_tokens = 1024
+
+# files holds the files attached with --file, sent even with --no-context. Longer files
+# keep their first and last lines within the budget shared between them
+# [context.providers.files]
+# max_tokens = 4096
+
+# Any program can act as the model with ai_backend = "command". It is run with sh -c,
+# gets {"system": "...", "prompt": "...", "messages": [{"role", "content"}]} as json on stdin,
+# messages holds the whole conversation with --continue, and prints the completion
+# [command_backend]
+# cmd = "my-llm-wrapper --model mistral"
+# API key stored in the OS keyring with `ai auth set <name>`, passed to cmd as $AI_API_KEY
+# credential = "openai"
+
+# The mock backend answers from a fixture file without a model or network, for demos
+# and tests. The fixture is a json array of {"prompt": "...", "response": "..."}, the first
+# entry whose prompt is part of the request wins, leave prompt out to match anything
+# [mock_backend]
+# fixture = "/home/me/.config/ai/mock.json"
+# delay_ms = 30
+
+# Executables named ai-backend-<name> on PATH are plugin backends, used with -b <name>.
+# Others are registered here. The protocol is in the README, `ai plugins list` shows them
+# [plugins.ollama]
+# path = "/opt/ollama-plugin/run"
+# args = ["--model", "mistral"]
+# credential = "ollama"
+
 [aws_settings]
-# Optional AWS profile name
+# Optional AWS profile name, SSO profiles need a current `aws sso login --profile <name>`
 # profile = "default"
 
+# Role to assume with the profile's credentials, and the MFA device it requires, whose
+# code is asked for on every request
+# role_arn = "arn:aws:iam::123456789012:role/bedrock-user"
+# mfa_serial = "arn:aws:iam::123456789012:mfa/me"
+
 # AWS region (default: "us-east-1")
 # region = "us-east-1"
 
-[model_config]
+# Send Bedrock requests to this endpoint instead of the region's public one, e.g. a VPC
+# interface endpoint or an emulator. Credentials and STS are unaffected
+# endpoint_url = "https://vpce-0123456789abcdef-abcdefgh.bedrock-runtime.us-east-1.vpce.amazonaws.com"
+
+# Bedrock model id, also accepts cross-region inference profiles such as
+# "us.anthropic.claude-3-5-sonnet-20241022-v2:0" or an application inference profile ARN
+# (default: "anthropic.claude-3-haiku-20240307-v1:0")
+# model_id = "anthropic.claude-3-haiku-20240307-v1:0"
+
+# Regions to retry in when the primary region is throttled or out of capacity
+# fallback_regions = ["us-west-2", "us-east-2"]
+
+# Let the model inspect the machine with read only tools before answering: read a file or
+# list a directory under the current one, secrets excepted, and run `which`, `uname` or `<program> --version` (default: false)
+# tools = false
+
+# Mark the system prompt and the earlier turns of a conversation as cache points, so
+# repeated requests read them from Bedrock's prompt cache at a tenth of the input price.
+# Only for the Claude models that support it, 3.5 Haiku, 3.7 Sonnet and later (default: true)
+# prompt_caching = true
+
+[bedrock_model_config]
+# Sampling settings sent with every Bedrock request, the model's own defaults are used for
+# the ones left unset. Most tokens to generate
+# max_tokens = 512
+
+# Randomness of the output, 0.0-1.0
+# temperature = 0.5
+
+# Top-p sampling threshold, 0.0-1.0. Anthropic recommends changing it or temperature, not both
+# top_p = 0.9
+
+# The response ends where the model generates one of these
+# stop_sequences = ["\n\n"]
+
+[local_model_config]
 # Whether to run on the cpu by default or not (default: false)
 # cpu = false
 
+# Devices to place the model on: cpu, cuda:N or metal:N, overrides cpu when set
+# (default: the first GPU, else the cpu). With several, the full precision model's
+# layers are split over them, for models too large for one GPU
+# devices = ["cuda:0", "cuda:1"]
+
 # Which Phi model to use. V2 or V3
 # model = "V2"
 
@@ -90,4 +373,41 @@ pub const DEFAULT_CONFIG_CONTENT: &str = r#"# AI CLI Configuration
 # Data type for model operations (default: "f32")
 # dtype = "f32"
 
+# How conversations are laid out in the prompt: "plain" Human/Assistant lines without the
+# system prompt for base models like phi-2, "phi3" (<|user|> ... <|end|>) or "chatml"
+# (<|im_start|>user ... <|im_end|>). Detected from the chat_template in the model's
+# tokenizer_config.json, or phi3 for Phi-3 and plain for phi-2 (default: detected)
+# chat_template = "phi3"
+
+# Before loading, the memory the model needs (its weights in dtype plus the kv cache for
+# sample_len tokens) is estimated and compared to the free RAM, or VRAM on a CUDA GPU,
+# and this cap. Over it the full precision phi-2 switches to the quantized one, other
+# models fail with the estimate rather than being killed mid-load (default: no cap)
+# max_memory_gb = 8
+
+# End the output at the first newline once the command has started, unless a backslash
+# continues the line, rather than generating an explanation after the one-liner that's
+# then cut. Not applied to commit messages, `ai pipe`, `ai explain-error` or with
+# --multiline (default: true)
+# stop_at_newline = true
+
+# Constrain sampling so the output is always a complete, syntactically valid shell
+# command: quotes and parentheses closed, no dangling pipes, if/fi and do/done matched.
+# The output stops at the end of the command line (default: off)
+# grammar = "shell"
+
+# Strings the local model is never allowed to generate, suppressed token by token while
+# sampling (default: none)
+# banned_strings = ["sudo", "rm -rf /", "`"]
+
+# Speculative decoding: a small draft model proposes a few tokens at a time and the model
+# checks them in one pass, which is faster on CPU when most proposals are accepted. The
+# output is the same as without it. Needs the quantized phi-2 model, and the draft has to
+# share its tokenizer, so the phi-1.5 and phi-2 ggufs work but a Phi-3 does not
+# [local_model_config.draft]
+# model_id = "lmz/candle-quantized-phi"
+# weight_file = "model-q4k.gguf"
+# architecture = "1.5"
+# tokens = 4
+
 "#;
@@ -0,0 +1,19 @@
+/// A source of context that is added to the prompt, such as the directory listing or git status.
+/// Providers run on their own thread under the context time budget.
+pub trait ContextProvider: Send {
+    /// Name used in the `[context.providers.<name>]` settings, --context and logs
+    fn name(&self) -> &'static str;
+
+    /// Providers with a higher priority are placed first in the prompt
+    fn default_priority(&self) -> i32 {
+        0
+    }
+
+    /// Approximate number of tokens the provider may add before being truncated
+    fn default_max_tokens(&self) -> usize {
+        256
+    }
+
+    /// Collects the context, None when there's nothing to add
+    fn collect(&self) -> Option<String>;
+}
@@ -0,0 +1,180 @@
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use super::common::ContextProvider;
+use super::display_path;
+
+/// Paths longer than this are skipped rather than sent to the model
+pub const MAX_CONTEXT_PATH_LEN: usize = 255;
+
+/// Maximum number of directory entries included in the prompt
+pub const MAX_DIRECTORY_ENTRIES: usize = 50;
+
+/// Lists the files in the working directory
+pub struct DirectoryContext {
+    dir: PathBuf,
+}
+
+impl DirectoryContext {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+impl ContextProvider for DirectoryContext {
+    fn name(&self) -> &'static str {
+        "directory"
+    }
+
+    fn default_priority(&self) -> i32 {
+        10
+    }
+
+    fn default_max_tokens(&self) -> usize {
+        400
+    }
+
+    fn collect(&self) -> Option<String> {
+        Some(directory_listing(&self.dir, MAX_DIRECTORY_ENTRIES))
+    }
+}
+
+/// Lists the entries of `dir` for the prompt, directories get a trailing `/`.
+/// Non UTF-8 names are shown lossily, entries whose full path is overly long and
+/// unreadable entries are skipped with a note so odd filesystems never fail the request.
+pub fn directory_listing(dir: &Path, max_entries: usize) -> String {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return format!("Unable to list {}: {}", display_path(dir), e),
+    };
+    let mut names = Vec::new();
+    let mut lossy = 0;
+    let mut too_long = 0;
+    let mut unreadable = 0;
+    for entry in entries {
+        let Ok(entry) = entry else {
+            unreadable += 1;
+            continue;
+        };
+        let file_name = entry.file_name();
+        let path = entry.path();
+        if path.as_os_str().len() > MAX_CONTEXT_PATH_LEN {
+            debug!("Skipping long path {:?}", path);
+            too_long += 1;
+            continue;
+        }
+        let mut name = match file_name.to_str() {
+            Some(name) => name.to_string(),
+            None => {
+                lossy += 1;
+                file_name.to_string_lossy().into_owned()
+            }
+        };
+        // file_type doesn't follow symlinks, so a broken link is still listed
+        if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+            name.push('/');
+        }
+        names.push(name);
+    }
+    names.sort();
+    let total = names.len();
+    names.truncate(max_entries);
+
+    let mut listing = format!("Files in {}:\n", display_path(dir));
+    for name in names.iter() {
+        listing.push_str(name);
+        listing.push('\n');
+    }
+    if total > max_entries {
+        listing.push_str(&format!("... and {} more\n", total - max_entries));
+    }
+    if lossy > 0 {
+        listing.push_str(&format!(
+            "({} names are not valid UTF-8 and are shown with \u{FFFD})\n",
+            lossy
+        ));
+    }
+    if too_long > 0 || unreadable > 0 {
+        listing.push_str(&format!(
+            "({} entries skipped: {} paths too long, {} unreadable)\n",
+            too_long + unreadable,
+            too_long,
+            unreadable
+        ));
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_names_with_a_slash_after_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("b.txt"), "").unwrap();
+        std::fs::create_dir(dir.path().join("a")).unwrap();
+        let listing = directory_listing(dir.path(), 10);
+        assert!(listing.ends_with(":\na/\nb.txt\n"), "{}", listing);
+    }
+
+    #[test]
+    fn skips_entries_whose_full_path_is_too_long() {
+        let dir = tempfile::tempdir().unwrap();
+        // short enough for the filesystem, too long once joined to the directory
+        let name = "x".repeat(MAX_CONTEXT_PATH_LEN - 1);
+        std::fs::write(dir.path().join(&name), "").unwrap();
+        std::fs::write(dir.path().join("short"), "").unwrap();
+        let listing = directory_listing(dir.path(), 10);
+        assert!(!listing.contains(&name));
+        assert!(listing.contains("short\n"));
+        assert!(listing.contains("(1 entries skipped: 1 paths too long, 0 unreadable)"));
+    }
+
+    #[test]
+    fn truncates_to_the_maximum_number_of_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a", "b", "c"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        let listing = directory_listing(dir.path(), 2);
+        assert!(listing.contains("a\nb\n... and 1 more\n"), "{}", listing);
+    }
+
+    #[test]
+    fn a_missing_directory_is_reported_instead_of_failing() {
+        let dir = tempfile::tempdir().unwrap();
+        let listing = directory_listing(&dir.path().join("gone"), 10);
+        assert!(listing.starts_with("Unable to list "), "{}", listing);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shows_non_utf8_names_lossily() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let name = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+        std::fs::write(dir.path().join(name), "").unwrap();
+        let listing = directory_listing(dir.path(), 10);
+        assert!(listing.contains("caf\u{FFFD}.txt\n"), "{}", listing);
+        assert!(listing.contains("(1 names are not valid UTF-8"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lists_broken_symlinks_and_unreadable_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(dir.path().join("nowhere"), dir.path().join("broken")).unwrap();
+        let locked = dir.path().join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        let listing = directory_listing(dir.path(), 10);
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(listing.contains("broken\n"), "{}", listing);
+        assert!(listing.contains("locked/\n"), "{}", listing);
+    }
+}
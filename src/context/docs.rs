@@ -0,0 +1,193 @@
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use super::common::ContextProvider;
+use super::tools::{installed, KNOWN_TOOLS};
+use super::CHARS_PER_TOKEN;
+
+/// Default token budget shared by the documentation of all mentioned tools
+pub const DOCS_MAX_TOKENS: usize = 1024;
+
+/// Most tools documented for one prompt
+const MAX_DOC_TOOLS: usize = 2;
+
+/// Words that are also commands but usually just English in a prompt
+const COMMON_WORDS: &[&str] = &[
+    "find", "sort", "time", "test", "yes", "true", "false", "which", "file", "less", "more",
+    "head", "tail", "cut", "join", "split", "kill", "top", "watch", "touch", "install", "link",
+    "date", "make", "list", "echo", "read", "type", "wait", "print", "size", "free", "last", "who",
+    "id", "w", "at", "as", "ls", "cat",
+];
+
+/// How often to check whether man or --help has exited
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Man pages of the tools the prompt mentions so flags aren't made up. Tools without one
+/// are only run with --help when they're among the known tools, as an unknown program may
+/// not treat the flag as harmless
+pub struct DocsContext {
+    tools: Vec<String>,
+    max_tokens: usize,
+    /// man or --help is killed after this long, it would outlive the context budget otherwise
+    timeout: Duration,
+}
+
+impl DocsContext {
+    /// Picks the installed tools mentioned in the prompt, in order of mention.
+    /// `max_tokens` is split between them
+    pub fn for_prompt(prompt: &str, max_tokens: usize, timeout: Duration) -> Self {
+        let mut mentioned: Vec<&str> = Vec::new();
+        for word in prompt.split(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_')) {
+            if word.is_empty()
+                || COMMON_WORDS.contains(&word)
+                || mentioned.contains(&word)
+                || word.starts_with('-')
+            {
+                continue;
+            }
+            mentioned.push(word);
+        }
+        let tools = installed(&mentioned)
+            .into_iter()
+            .take(MAX_DOC_TOOLS)
+            .map(str::to_string)
+            .collect();
+        Self {
+            tools,
+            max_tokens,
+            timeout,
+        }
+    }
+}
+
+impl ContextProvider for DocsContext {
+    fn name(&self) -> &'static str {
+        "docs"
+    }
+
+    fn default_priority(&self) -> i32 {
+        -10
+    }
+
+    fn default_max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+
+    fn collect(&self) -> Option<String> {
+        if self.tools.is_empty() {
+            return None;
+        }
+        let max_len = self.max_tokens * CHARS_PER_TOKEN / self.tools.len();
+        let docs = self
+            .tools
+            .iter()
+            .filter_map(|tool| {
+                let docs =
+                    man_page(tool, self.timeout).or_else(|| help_text(tool, self.timeout))?;
+                let docs = squeeze_blank_lines(&docs);
+                let docs = match docs.char_indices().nth(max_len) {
+                    Some((end, _)) => format!("{}\n...(truncated)", &docs[..end]),
+                    None => docs,
+                };
+                Some(format!("Documentation for {}:\n{}\n", tool, docs))
+            })
+            .collect::<Vec<_>>();
+        (!docs.is_empty()).then(|| docs.join("\n"))
+    }
+}
+
+/// The man page as plain text, `man <tool> | col -b`
+fn man_page(tool: &str, timeout: Duration) -> Option<String> {
+    let mut command = Command::new("man");
+    command
+        .arg(tool)
+        .env("MANPAGER", "cat")
+        .env("MANWIDTH", "100");
+    let (success, stdout, _) = output_within(command, timeout)?;
+    if !success || stdout.is_empty() {
+        return None;
+    }
+    Some(strip_overstrikes(&String::from_utf8_lossy(&stdout)))
+}
+
+/// `<tool> --help`, for the known tools only
+fn help_text(tool: &str, timeout: Duration) -> Option<String> {
+    if !KNOWN_TOOLS.contains(&tool) {
+        return None;
+    }
+    let mut command = Command::new(tool);
+    command.arg("--help");
+    let (_, stdout, stderr) = output_within(command, timeout)?;
+    // plenty of tools print their help to stderr
+    let text = if stdout.is_empty() { stderr } else { stdout };
+    (!text.is_empty()).then(|| String::from_utf8_lossy(&text).into_owned())
+}
+
+/// Runs the command without input and returns whether it succeeded with its stdout and
+/// stderr, or None when it can't be run or is still running after `timeout`, killing it
+fn output_within(mut command: Command, timeout: Duration) -> Option<(bool, Vec<u8>, Vec<u8>)> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+    // read on threads, a man page doesn't fit in the pipe
+    let read = |pipe: Option<Box<dyn Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut output = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut output);
+            }
+            output
+        })
+    };
+    let stdout = read(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+    let stderr = read(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait().ok()? {
+            Some(status) => break status,
+            None if Instant::now() >= deadline => {
+                warn!("Killing {:?}, it took longer than {:?}", command, timeout);
+                let _ = child.kill();
+                let _ = child.wait();
+                // the readers are left, a grandchild may still hold the pipes
+                return None;
+            }
+            None => std::thread::sleep(POLL_INTERVAL),
+        }
+    };
+    Some((status.success(), stdout.join().ok()?, stderr.join().ok()?))
+}
+
+/// Removes the backspace overstrikes man uses for bold and underline, like `col -b`
+fn strip_overstrikes(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '\u{8}' {
+            plain.pop();
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+fn squeeze_blank_lines(text: &str) -> String {
+    let mut squeezed = String::with_capacity(text.len());
+    let mut blank = false;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() && blank {
+            continue;
+        }
+        blank = line.is_empty();
+        squeezed.push_str(line);
+        squeezed.push('\n');
+    }
+    squeezed
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+
+use super::common::ContextProvider;
+use super::tools::installed;
+
+/// Binaries whose presence changes how a one-liner should be written
+const ALTERNATIVE_TOOLS: &[&str] = &["rg", "fd", "fdfind", "jq", "gsed", "gawk", "gfind"];
+
+/// The syntax family of the user's shell, which decides the kind of command generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellKind {
+    /// bash, zsh, fish, sh and the like
+    Posix,
+    /// Windows PowerShell or PowerShell 7 (pwsh)
+    PowerShell,
+    /// The Windows command prompt, cmd.exe
+    Cmd,
+}
+
+/// A compact description of the machine appended to the system prompt, so generated
+/// commands use the right flags: OS, distro, architecture, shell, coreutils flavor
+/// and which of the common alternative tools are installed
+pub fn environment_block() -> String {
+    let shell = shell_name();
+    let mut block = format!(
+        "\n\nThe command will run on {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    if let Some(distro) = distro() {
+        block.push_str(&format!(" ({})", distro));
+    }
+    match shell_kind() {
+        ShellKind::Posix => block.push_str(&format!(
+            " with the {} shell and {} coreutils.",
+            shell,
+            coreutils_flavor()
+        )),
+        ShellKind::PowerShell | ShellKind::Cmd => {
+            block.push_str(&format!(" with the {} shell.", shell))
+        }
+    }
+    let tools = installed(ALTERNATIVE_TOOLS);
+    if !tools.is_empty() {
+        block.push_str(&format!(" Installed: {}.", tools.join(", ")));
+    }
+    block
+}
+
+/// The environment block as a provider, describing the machine runs `ls` and `sw_vers`,
+/// so it's held to the context time budget like the other providers
+pub struct EnvironmentContext;
+
+impl ContextProvider for EnvironmentContext {
+    fn name(&self) -> &'static str {
+        "environment"
+    }
+
+    fn collect(&self) -> Option<String> {
+        Some(environment_block())
+    }
+}
+
+/// Values for the placeholders of a custom system prompt: `{{default}}` is the built-in
+/// prompt, `{{shell}}` the shell name and `{{os}}` the OS, with the distro when known
+pub fn placeholders(default: &str) -> HashMap<String, String> {
+    let os = match distro() {
+        Some(distro) => format!("{} ({})", std::env::consts::OS, distro),
+        None => std::env::consts::OS.to_string(),
+    };
+    HashMap::from([
+        ("default".to_string(), default.to_string()),
+        ("shell".to_string(), shell_name()),
+        ("os".to_string(), os),
+    ])
+}
+
+/// The name of the user's shell, from $SHELL. Windows doesn't set it outside of Git Bash
+/// or MSYS, there it is cmd when cmd.exe's $PROMPT is set and PowerShell otherwise
+pub fn shell_name() -> String {
+    let shell = std::env::var("SHELL").ok().and_then(|shell| {
+        shell
+            .rsplit(['/', '\\'])
+            .next()
+            .map(|name| name.trim_end_matches(".exe").to_string())
+    });
+    match shell {
+        Some(shell) => shell,
+        None if cfg!(windows) && std::env::var_os("PROMPT").is_some() => "cmd".to_string(),
+        None if cfg!(windows) => "powershell".to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Whether the user's shell takes POSIX, PowerShell or cmd syntax
+pub fn shell_kind() -> ShellKind {
+    match shell_name().to_lowercase().as_str() {
+        "powershell" | "pwsh" => ShellKind::PowerShell,
+        "cmd" => ShellKind::Cmd,
+        _ => ShellKind::Posix,
+    }
+}
+
+/// The distribution name from /etc/os-release on linux or sw_vers on macOS
+fn distro() -> Option<String> {
+    if cfg!(target_os = "macos") {
+        let output = Command::new("sw_vers")
+            .arg("-productVersion")
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        return (!version.is_empty()).then(|| format!("macOS {}", version));
+    }
+    let os_release = std::fs::read_to_string("/etc/os-release").ok()?;
+    os_release.lines().find_map(|line| {
+        line.strip_prefix("PRETTY_NAME=")
+            .map(|name| name.trim_matches('"').to_string())
+    })
+}
+
+/// GNU coreutils accept --version, the BSD ones shipped with macOS don't
+fn coreutils_flavor() -> &'static str {
+    let gnu = Command::new("ls")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if gnu {
+        "GNU"
+    } else {
+        "BSD"
+    }
+}
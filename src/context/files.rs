@@ -0,0 +1,113 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use super::common::ContextProvider;
+use super::{display_path, CHARS_PER_TOKEN};
+
+use crate::messages::Message;
+
+/// Default token budget shared by the files attached with --file
+pub const FILES_MAX_TOKENS: usize = 4096;
+
+/// Characters of a file's budget kept for its header, code fence and omission note
+const EXCERPT_OVERHEAD: usize = 64;
+
+/// Files attached with --file, each under a header with its name
+pub struct FilesContext {
+    files: Vec<(String, String)>,
+    max_tokens: usize,
+}
+
+impl FilesContext {
+    /// Reads the files up front, a missing or binary file is an error rather than context
+    /// that's quietly left out. `max_tokens` is split between them
+    pub fn read(paths: &[PathBuf], max_tokens: usize) -> Result<Self> {
+        let files = paths
+            .iter()
+            .map(|path| {
+                let bytes = std::fs::read(path)
+                    .with_context(|| format!("Unable to read attached file {:?}", path))?;
+                let contents = String::from_utf8(bytes)
+                    .map_err(|_| anyhow::anyhow!("{}", Message::NotText(&format!("{:?}", path))))?;
+                Ok((display_path(path), contents))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { files, max_tokens })
+    }
+}
+
+impl ContextProvider for FilesContext {
+    fn name(&self) -> &'static str {
+        "files"
+    }
+
+    fn default_priority(&self) -> i32 {
+        20
+    }
+
+    fn default_max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+
+    fn collect(&self) -> Option<String> {
+        if self.files.is_empty() {
+            return None;
+        }
+        let max_len = self.max_tokens * CHARS_PER_TOKEN / self.files.len();
+        let files = self
+            .files
+            .iter()
+            .map(|(name, contents)| {
+                // room for the header, fence and note so the budget isn't overrun
+                let max_len = max_len.saturating_sub(name.len() + EXCERPT_OVERHEAD);
+                format!(
+                    "File {}:\n```\n{}\n```\n",
+                    name,
+                    excerpt(contents.trim_end(), max_len)
+                )
+            })
+            .collect::<Vec<_>>();
+        Some(files.join("\n"))
+    }
+}
+
+/// The whole file when it fits in `max_len` characters, otherwise its first and last
+/// lines with a note of how many lines were left out in between
+fn excerpt(contents: &str, max_len: usize) -> String {
+    if contents.chars().count() <= max_len {
+        return contents.to_string();
+    }
+    let lines = contents.lines().collect::<Vec<_>>();
+    // two thirds of the budget for the start, where the structure usually is
+    let mut head_len = 0;
+    let head = lines
+        .iter()
+        .take_while(|line| {
+            head_len += line.chars().count() + 1;
+            head_len <= max_len * 2 / 3
+        })
+        .count();
+    let mut tail_len = 0;
+    let tail = lines[head..]
+        .iter()
+        .rev()
+        .take_while(|line| {
+            tail_len += line.chars().count() + 1;
+            tail_len <= max_len / 3
+        })
+        .count();
+    if head == 0 && tail == 0 {
+        // one very long line
+        let cut = contents.chars().take(max_len).collect::<String>();
+        return format!("{}\n...(truncated)", cut);
+    }
+    let omitted = lines.len() - head - tail;
+    format!(
+        "{}\n... ({} of {} lines left out)\n{}",
+        lines[..head].join("\n"),
+        omitted,
+        lines.len(),
+        lines[lines.len() - tail..].join("\n")
+    )
+}
@@ -0,0 +1,55 @@
+use std::process::Command;
+
+use super::common::ContextProvider;
+
+/// Commits of the recent log included
+const LOG_ENTRIES: &str = "10";
+
+/// Branch, recent commits and short status of the git repository in the working directory
+pub struct GitContext;
+
+impl GitContext {
+    /// Whether the working directory is inside a git work tree
+    pub fn in_repository() -> bool {
+        git(&["rev-parse", "--is-inside-work-tree"]).is_some_and(|inside| inside.trim() == "true")
+    }
+}
+
+impl ContextProvider for GitContext {
+    fn name(&self) -> &'static str {
+        "git"
+    }
+
+    fn default_priority(&self) -> i32 {
+        20
+    }
+
+    fn default_max_tokens(&self) -> usize {
+        512
+    }
+
+    fn collect(&self) -> Option<String> {
+        // not a repository, or git isn't installed
+        let status = git(&["status", "--short", "--branch"])?;
+        let (branch, changes) = status.split_once('\n').unwrap_or((&status, ""));
+        let mut context = format!("Git branch: {}\n", branch.trim_start_matches("## "));
+        // a new repository has no commits yet
+        if let Some(log) = git(&["log", "--oneline", "--decorate", "-n", LOG_ENTRIES]) {
+            context.push_str(&format!("Recent commits:\n{}", log));
+        }
+        // last, as a long list of changes is what gets truncated
+        if !changes.trim().is_empty() {
+            context.push_str(&format!("Git status:\n{}", changes));
+        }
+        Some(context)
+    }
+}
+
+/// The output of git, None when it fails
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
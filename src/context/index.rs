@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+use tracing::warn;
+
+use super::common::ContextProvider;
+use crate::rag;
+
+/// Chunks of the index retrieved per prompt, the token budget may cut the last ones
+pub const INDEX_TOP_K: usize = 5;
+
+/// Chunks of the files indexed with `ai index` that are closest to the prompt, from the
+/// project the current directory is in
+pub struct IndexContext {
+    prompt: String,
+    scope: PathBuf,
+}
+
+impl IndexContext {
+    pub fn for_prompt(prompt: &str) -> Self {
+        let scope = std::env::current_dir()
+            .map(|dir| rag::project_root(&dir))
+            .unwrap_or_default();
+        Self {
+            prompt: prompt.to_string(),
+            scope,
+        }
+    }
+}
+
+impl ContextProvider for IndexContext {
+    fn name(&self) -> &'static str {
+        "index"
+    }
+
+    fn default_priority(&self) -> i32 {
+        5
+    }
+
+    fn default_max_tokens(&self) -> usize {
+        1024
+    }
+
+    /// Searches the index within the context time budget, the embedding model is loaded
+    /// once per process. Nothing is added when the search fails
+    fn collect(&self) -> Option<String> {
+        let hits = rag::search(&self.prompt, INDEX_TOP_K, &self.scope)
+            .map_err(|e| warn!("Unable to search the index: {:?}", e))
+            .ok()?;
+        if hits.is_empty() {
+            return None;
+        }
+        let excerpts = hits
+            .iter()
+            .map(|hit| format!("{}:{}:\n{}", hit.path, hit.line, hit.text.trim_end()))
+            .collect::<Vec<_>>();
+        Some(format!(
+            "Excerpts of the user's files that may be relevant:\n{}\n",
+            excerpts.join("\n\n")
+        ))
+    }
+}
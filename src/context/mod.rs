@@ -0,0 +1,281 @@
+pub mod common;
+pub mod directory;
+pub mod docs;
+pub mod environment;
+pub mod files;
+pub mod git;
+pub mod index;
+pub mod system;
+pub mod tools;
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tracing::{debug, info, warn};
+
+pub use common::ContextProvider;
+pub use directory::DirectoryContext;
+pub use docs::DocsContext;
+pub use files::FilesContext;
+pub use git::GitContext;
+pub use index::IndexContext;
+pub use system::SystemContext;
+pub use tools::ToolsContext;
+
+use crate::logging::NOTICE;
+use crate::messages::Message;
+use crate::settings::ContextSettings;
+
+/// Rough characters per token used to enforce the provider token budgets
+pub(crate) const CHARS_PER_TOKEN: usize = 4;
+
+/// Displays a path for the prompt. Names that aren't valid UTF-8 are converted lossily,
+/// replacing the invalid bytes with U+FFFD, instead of failing.
+pub fn display_path(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Every provider that can be enabled, in no particular order
+pub fn available_providers() -> Result<Vec<Box<dyn ContextProvider>>> {
+    Ok(vec![
+        Box::new(SystemContext),
+        Box::new(GitContext),
+        Box::new(DirectoryContext::new(std::env::current_dir()?)),
+        Box::new(ToolsContext),
+    ])
+}
+
+/// Assembles the prompt from the user's request and the enabled context providers
+pub struct PromptBuilder {
+    settings: ContextSettings,
+    /// Providers enabled for this run on top of the settings
+    enabled: Vec<String>,
+    /// Files attached with --file
+    attachments: Vec<PathBuf>,
+}
+
+impl PromptBuilder {
+    pub fn new(settings: ContextSettings) -> Self {
+        Self {
+            settings,
+            enabled: Vec::new(),
+            attachments: Vec::new(),
+        }
+    }
+
+    /// Enables a provider for this run regardless of the settings
+    pub fn enable(mut self, name: &str) -> Self {
+        self.enabled.push(name.to_string());
+        self
+    }
+
+    /// Attaches files to the prompt, enabling the files provider
+    pub fn attach(mut self, paths: &[PathBuf]) -> Self {
+        if !paths.is_empty() {
+            self.attachments.extend_from_slice(paths);
+            self.enabled.push("files".to_string());
+        }
+        self
+    }
+
+    /// Prepends only the attached files to the prompt, for when context is turned off
+    /// but files were attached explicitly
+    pub fn build_attachments(&self, prompt: &str) -> Result<String> {
+        let files = self.files()?;
+        let max_tokens = self.max_tokens(&files);
+        let context = files
+            .collect()
+            .map(|c| truncate_to_tokens(c, max_tokens))
+            .into_iter()
+            .collect::<Vec<_>>();
+        Ok(with_context(&context, prompt))
+    }
+
+    fn files(&self) -> Result<FilesContext> {
+        let max_tokens = self
+            .settings
+            .providers
+            .get("files")
+            .and_then(|s| s.max_tokens)
+            .unwrap_or(files::FILES_MAX_TOKENS);
+        FilesContext::read(&self.attachments, max_tokens)
+    }
+
+    /// Gathers the enabled providers' context, highest priority first, and prepends it to the prompt
+    pub fn build(&self, prompt: &str) -> Result<String> {
+        let mut providers = available_providers()?;
+        let timeout = Duration::from_millis(self.settings.timeout_ms);
+        // only available per prompt, it documents the tools the prompt mentions. Looking
+        // them up searches $PATH, so only when it's asked for
+        if self.is_enabled("docs") {
+            let docs_max_tokens = self
+                .settings
+                .providers
+                .get("docs")
+                .and_then(|s| s.max_tokens)
+                .unwrap_or(docs::DOCS_MAX_TOKENS);
+            providers.push(Box::new(DocsContext::for_prompt(
+                prompt,
+                docs_max_tokens,
+                timeout,
+            )));
+        }
+        providers.push(Box::new(self.files()?));
+        // loads the embedding model, so only when it's asked for
+        if self.is_enabled("index") {
+            providers.push(Box::new(IndexContext::for_prompt(prompt)));
+        }
+        for name in self.enabled.iter() {
+            if !providers.iter().any(|p| p.name() == name) {
+                let names = providers.iter().map(|p| p.name()).collect::<Vec<_>>();
+                anyhow::bail!(
+                    "{}",
+                    Message::UnknownContextProvider(name, &names.join(", "))
+                );
+            }
+        }
+        let mut providers = providers
+            .into_iter()
+            .filter(|p| self.is_enabled(p.name()))
+            .collect::<Vec<_>>();
+        providers.sort_by_key(|p| std::cmp::Reverse(self.priority(p.as_ref())));
+        let budgets = providers
+            .iter()
+            .map(|p| self.max_tokens(p.as_ref()))
+            .collect::<Vec<_>>();
+
+        let gathered = gather(providers, timeout);
+        let context = gathered
+            .into_iter()
+            .zip(budgets)
+            .filter_map(|(context, max_tokens)| context.map(|c| truncate_to_tokens(c, max_tokens)))
+            .collect::<Vec<_>>();
+        Ok(with_context(&context, prompt))
+    }
+
+    /// Enabled for this run or in the settings
+    fn is_enabled(&self, name: &str) -> bool {
+        self.enabled.iter().any(|enabled| enabled == name)
+            || self
+                .settings
+                .providers
+                .get(name)
+                .and_then(|s| s.enabled)
+                .unwrap_or(false)
+    }
+
+    fn priority(&self, provider: &dyn ContextProvider) -> i32 {
+        self.settings
+            .providers
+            .get(provider.name())
+            .and_then(|s| s.priority)
+            .unwrap_or_else(|| provider.default_priority())
+    }
+
+    fn max_tokens(&self, provider: &dyn ContextProvider) -> usize {
+        self.settings
+            .providers
+            .get(provider.name())
+            .and_then(|s| s.max_tokens)
+            .unwrap_or_else(|| provider.default_max_tokens())
+    }
+}
+
+/// Cuts the context down to roughly `max_tokens` tokens
+fn truncate_to_tokens(mut context: String, max_tokens: usize) -> String {
+    let max_len = max_tokens * CHARS_PER_TOKEN;
+    if context.len() > max_len {
+        let mut end = max_len;
+        while !context.is_char_boundary(end) {
+            end -= 1;
+        }
+        context.truncate(end);
+        context.push_str("\n...(truncated)\n");
+    }
+    context
+}
+
+/// Runs the providers in parallel under a total time budget.
+/// Providers that haven't finished when the budget runs out are skipped and the user is
+/// told, so slow providers never hold up the request. Results keep the providers' order.
+pub fn gather(providers: Vec<Box<dyn ContextProvider>>, budget: Duration) -> Vec<Option<String>> {
+    let start = Instant::now();
+    let deadline = start + budget;
+    let (sender, receiver) = mpsc::channel();
+    let names = providers.iter().map(|p| p.name()).collect::<Vec<_>>();
+    for (index, provider) in providers.into_iter().enumerate() {
+        let sender = sender.clone();
+        // left detached if it overruns, the process exits once generation is done
+        std::thread::spawn(move || {
+            let _ = sender.send((index, provider.collect()));
+        });
+    }
+    drop(sender);
+
+    let mut results = vec![None; names.len()];
+    let mut finished = vec![false; names.len()];
+    while finished.iter().any(|f| !f) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(remaining) {
+            Ok((index, result)) => {
+                debug!("{} context finished in {:?}", names[index], start.elapsed());
+                finished[index] = true;
+                results[index] = result;
+            }
+            Err(_) => break,
+        }
+    }
+    for (name, done) in names.iter().zip(finished.iter()) {
+        if !done {
+            info!(
+                "Skipped the {} context, it took longer than {:?}",
+                name, budget
+            );
+            warn!(
+                target: NOTICE,
+                "{}",
+                Message::ContextSkipped(name, budget.as_millis())
+            );
+        }
+    }
+    info!("Gathered context in {:?}", start.elapsed());
+    results
+}
+
+/// Prepends the gathered context to the user's prompt
+pub fn with_context(context: &[String], prompt: &str) -> String {
+    if context.is_empty() {
+        return prompt.to_string();
+    }
+    format!("{}\n{}", context.join("\n"), prompt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(&'static str, Duration);
+
+    impl ContextProvider for Fixed {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn collect(&self) -> Option<String> {
+            std::thread::sleep(self.1);
+            Some(self.0.to_string())
+        }
+    }
+
+    #[test]
+    fn providers_over_the_budget_are_left_out() {
+        let providers: Vec<Box<dyn ContextProvider>> = vec![
+            Box::new(Fixed("slow", Duration::from_secs(5))),
+            Box::new(Fixed("fast", Duration::ZERO)),
+        ];
+        let results = gather(providers, Duration::from_millis(200));
+        assert_eq!(results, vec![None, Some("fast".to_string())]);
+    }
+}
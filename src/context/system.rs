@@ -0,0 +1,34 @@
+use super::common::ContextProvider;
+use super::display_path;
+use super::environment;
+
+/// Operating system, architecture, shell and working directory
+pub struct SystemContext;
+
+impl ContextProvider for SystemContext {
+    fn name(&self) -> &'static str {
+        "system"
+    }
+
+    fn default_priority(&self) -> i32 {
+        30
+    }
+
+    fn default_max_tokens(&self) -> usize {
+        64
+    }
+
+    fn collect(&self) -> Option<String> {
+        let shell = environment::shell_name();
+        let cwd = std::env::current_dir()
+            .map(|dir| display_path(&dir))
+            .unwrap_or_else(|_| "unknown".to_string());
+        Some(format!(
+            "OS: {} ({})\nShell: {}\nWorking directory: {}\n",
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            shell,
+            cwd
+        ))
+    }
+}
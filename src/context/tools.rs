@@ -0,0 +1,60 @@
+use super::common::ContextProvider;
+
+/// Tools that change which one-liner is best when they are installed
+pub(super) const KNOWN_TOOLS: &[&str] = &[
+    "awk",
+    "sed",
+    "jq",
+    "yq",
+    "rg",
+    "fd",
+    "fzf",
+    "curl",
+    "wget",
+    "git",
+    "docker",
+    "podman",
+    "kubectl",
+    "helm",
+    "aws",
+    "gh",
+    "python3",
+    "node",
+    "brew",
+    "apt",
+    "dnf",
+    "systemctl",
+];
+
+/// Which of the commonly used command line tools are on the PATH
+pub struct ToolsContext;
+
+impl ContextProvider for ToolsContext {
+    fn name(&self) -> &'static str {
+        "tools"
+    }
+
+    fn collect(&self) -> Option<String> {
+        let installed = installed(KNOWN_TOOLS);
+        if installed.is_empty() {
+            return None;
+        }
+        Some(format!("Installed tools: {}\n", installed.join(", ")))
+    }
+}
+
+/// The tools that are found on the PATH, as `<tool>.exe` on Windows
+pub fn installed<'a>(tools: &[&'a str]) -> Vec<&'a str> {
+    let Some(path) = std::env::var_os("PATH") else {
+        return Vec::new();
+    };
+    let dirs = std::env::split_paths(&path).collect::<Vec<_>>();
+    tools
+        .iter()
+        .filter(|tool| {
+            let file = format!("{}{}", tool, std::env::consts::EXE_SUFFIX);
+            dirs.iter().any(|dir| dir.join(&file).is_file())
+        })
+        .copied()
+        .collect()
+}
@@ -0,0 +1,45 @@
+use anyhow::{Context, Result};
+use keyring::Entry;
+use tracing::info;
+
+use crate::messages::Message;
+
+/// Service name the keys are stored under in the keyring
+const SERVICE: &str = "ai-cli";
+
+/// The keyring entry holding the key for `name`
+fn entry(name: &str) -> Result<Entry> {
+    Entry::new(SERVICE, name).with_context(|| format!("Unable to open the {} key", name))
+}
+
+/// Stores the API key for `name` (e.g. "openai") in the platform keyring: the login
+/// Keychain on macOS, the Credential Manager on Windows, the Secret Service (GNOME Keyring,
+/// KWallet) elsewhere. The secret is passed through the platform's API, never on a command line
+pub fn set(name: &str, secret: &str) -> Result<()> {
+    info!("Storing the {} key in the keyring", name);
+    entry(name)?
+        .set_password(secret)
+        .with_context(|| format!("Unable to store the {} key in the keyring", name))
+}
+
+/// The stored API key for `name`, None when there isn't one
+pub fn get(name: &str) -> Result<Option<String>> {
+    match entry(name)?.get_password() {
+        Ok(secret) => Ok((!secret.is_empty()).then_some(secret)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => {
+            Err(e).with_context(|| format!("Unable to read the {} key from the keyring", name))
+        }
+    }
+}
+
+/// Deletes the stored API key for `name`
+pub fn remove(name: &str) -> Result<()> {
+    match entry(name)?.delete_credential() {
+        Ok(()) => Ok(()),
+        Err(keyring::Error::NoEntry) => anyhow::bail!("{}", Message::NoKeyringKey(name)),
+        Err(e) => {
+            Err(e).with_context(|| format!("Unable to delete the {} key from the keyring", name))
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use tracing::{debug, info, warn};
+
+use super::protocol::{read_frame, write_frame, Request, Response, Status, PROTOCOL_VERSION};
+use super::{model_fingerprint, socket_path};
+use crate::ai_backend::common::{cutoff, deadline, report_cutoff, Cutoff};
+use crate::ai_backend::{AiBackend, ChatMessage, Usage};
+use crate::messages::Message;
+use crate::settings::Settings;
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+/// How often a pending request checks for Ctrl-C and the deadline
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A connection to the daemon
+pub struct DaemonClient {
+    #[cfg(unix)]
+    stream: UnixStream,
+}
+
+impl DaemonClient {
+    /// Connects to the daemon, None when none is running
+    pub fn connect() -> Option<Self> {
+        #[cfg(unix)]
+        {
+            let path = socket_path();
+            match UnixStream::connect(&path) {
+                Ok(stream) => Some(Self { stream }),
+                Err(e) => {
+                    debug!("No daemon at {}: {}", path.display(), e);
+                    None
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            None
+        }
+    }
+
+    /// Sends a request answered with a single frame
+    fn call(&mut self, request: &Request) -> Result<Response> {
+        #[cfg(unix)]
+        {
+            write_frame(&mut self.stream, request)?;
+            match read_frame(&mut self.stream)? {
+                Some(Response::Error { message, .. }) => Err(anyhow::anyhow!(message)),
+                Some(response) => Ok(response),
+                None => anyhow::bail!("{}", Message::DaemonClosed),
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = request;
+            anyhow::bail!("{}", Message::DaemonNeedsUnix)
+        }
+    }
+
+    pub fn status(&mut self) -> Result<Status> {
+        match self.call(&Request::Status)? {
+            Response::Status(status) => Ok(status),
+            other => anyhow::bail!("{}", Message::UnexpectedAnswer(&format!("{:?}", other))),
+        }
+    }
+
+    /// Asks the daemon to exit once the generation it's running is done
+    pub fn shutdown(&mut self) -> Result<()> {
+        match self.call(&Request::Shutdown)? {
+            Response::ShuttingDown => Ok(()),
+            other => anyhow::bail!("{}", Message::UnexpectedAnswer(&format!("{:?}", other))),
+        }
+    }
+
+    /// Runs a generation, streamed when `on_token` is given. Ctrl-C and the deadline cancel
+    /// it, keeping what the daemon generated until then
+    fn generate(
+        self,
+        request: Request,
+        stop_at: Option<std::time::Instant>,
+        timeout_secs: u64,
+        mut on_token: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<(Vec<String>, Usage)> {
+        #[cfg(unix)]
+        {
+            let id = match &request {
+                Request::Generate { id, .. } | Request::Stream { id, .. } => *id,
+                _ => anyhow::bail!("{}", Message::NotAGeneration(&format!("{:?}", request))),
+            };
+            let mut stream = self.stream;
+            write_frame(&mut stream, &request)?;
+            let mut reader = stream.try_clone()?;
+            let (sender, frames) = mpsc::channel();
+            std::thread::spawn(move || loop {
+                let frame = read_frame::<Response>(&mut reader);
+                let last = !matches!(frame, Ok(Some(Response::Token { .. })));
+                if sender.send(frame).is_err() || last {
+                    break;
+                }
+            });
+            let mut cancelled = None;
+            loop {
+                if cancelled.is_none() {
+                    if let Some(stopped) = cutoff(stop_at) {
+                        info!("Cancelling the daemon's generation ({:?})", stopped);
+                        write_frame(&mut stream, &Request::Cancel { id })?;
+                        cancelled = Some(stopped);
+                    }
+                }
+                let frame = match frames.recv_timeout(POLL_INTERVAL) {
+                    Ok(frame) => frame?,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => None,
+                };
+                match frame {
+                    Some(Response::Token { text, .. }) => {
+                        if let Some(on_token) = on_token.as_mut() {
+                            on_token(&text);
+                        }
+                    }
+                    Some(Response::Done {
+                        responses,
+                        usage,
+                        cut_short,
+                        ..
+                    }) => {
+                        if cut_short {
+                            report_cutoff(cancelled.unwrap_or(Cutoff::Interrupt), timeout_secs);
+                        }
+                        return Ok((responses, usage));
+                    }
+                    Some(Response::Error { message, .. }) => {
+                        anyhow::bail!("{}", Message::DaemonFailed(&message))
+                    }
+                    Some(other) => {
+                        anyhow::bail!("{}", Message::UnexpectedAnswer(&format!("{:?}", other)))
+                    }
+                    None => anyhow::bail!("{}", Message::DaemonClosed),
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (request, stop_at, timeout_secs, on_token);
+            anyhow::bail!("{}", Message::DaemonNeedsUnix)
+        }
+    }
+}
+
+/// The local backend served by the daemon, which keeps the model loaded between commands.
+/// Used in place of loading the model when a daemon with the same model settings is running
+pub struct DaemonAiBackend {
+    model: String,
+    system_prompt: String,
+    timeout_secs: u64,
+    /// Seed of the next generation, moved on like the local backend's
+    seed: AtomicU64,
+    stop_at_newline: bool,
+    next_id: AtomicU64,
+    usage: Mutex<Usage>,
+}
+
+impl DaemonAiBackend {
+    /// The daemon's backend, None when no daemon is running or it runs another model
+    pub fn connect(settings: &Settings, system_prompt: &str) -> Option<Self> {
+        let status = DaemonClient::connect()?
+            .status()
+            .map_err(|e| warn!("Not using the daemon: {:#}", e))
+            .ok()?;
+        let model = model_fingerprint(settings);
+        if status.protocol != PROTOCOL_VERSION {
+            warn!(
+                "Not using the daemon, it speaks protocol {} rather than {}, restart it",
+                status.protocol, PROTOCOL_VERSION
+            );
+            return None;
+        }
+        if status.model != model {
+            info!("Not using the daemon, it runs the local model with other settings");
+            return None;
+        }
+        Some(Self {
+            model,
+            system_prompt: system_prompt.to_string(),
+            timeout_secs: settings.request_timeout_secs,
+            seed: AtomicU64::new(settings.local_model_config.seed),
+            stop_at_newline: settings.local_model_config.stop_at_newline,
+            next_id: AtomicU64::new(1),
+            usage: Mutex::new(Usage::default()),
+        })
+    }
+
+    fn run(
+        &self,
+        messages: &[ChatMessage],
+        n: usize,
+        on_token: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<Vec<String>> {
+        let client =
+            DaemonClient::connect().ok_or_else(|| anyhow::anyhow!("{}", Message::DaemonStopped))?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let seed = self.seed.fetch_add(n as u64, Ordering::Relaxed);
+        let messages = messages.to_vec();
+        let request = match on_token {
+            Some(_) => Request::Stream {
+                id,
+                model: self.model.clone(),
+                seed,
+                stop_at_newline: self.stop_at_newline,
+                system: self.system_prompt.clone(),
+                messages,
+            },
+            None => Request::Generate {
+                id,
+                model: self.model.clone(),
+                seed,
+                stop_at_newline: self.stop_at_newline,
+                system: self.system_prompt.clone(),
+                messages,
+                n,
+            },
+        };
+        let (responses, usage) = client.generate(
+            request,
+            deadline(self.timeout_secs),
+            self.timeout_secs,
+            on_token,
+        )?;
+        self.usage
+            .lock()
+            .map_err(|_| anyhow::anyhow!("{}", Message::LockPoisoned("usage")))?
+            .add(usage);
+        Ok(responses)
+    }
+}
+
+impl AiBackend for DaemonAiBackend {
+    fn invoke(&self, prompt: String) -> Result<String> {
+        self.invoke_n(prompt, 1)?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::NoOutput))
+    }
+
+    fn invoke_n(&self, prompt: String, n: usize) -> Result<Vec<String>> {
+        self.run(&[ChatMessage::user(prompt)], n, None)
+    }
+
+    fn invoke_conversation(&self, messages: &[ChatMessage], n: usize) -> Result<Vec<String>> {
+        self.run(messages, n, None)
+    }
+
+    fn stream_conversation(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        self.run(messages, 1, Some(on_token))?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::NoOutput))
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        self.usage.lock().ok().map(|usage| *usage)
+    }
+}
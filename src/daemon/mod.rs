@@ -0,0 +1,61 @@
+pub mod client;
+pub mod protocol;
+pub mod server;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use sha2::{Digest, Sha256};
+
+pub use client::{DaemonAiBackend, DaemonClient};
+
+use crate::logging;
+use crate::messages::Message;
+use crate::settings::{LocalModelConfig, Settings};
+
+/// The daemon's socket, in $XDG_RUNTIME_DIR/ai where there is one, readable only by the user
+pub fn socket_path() -> PathBuf {
+    dirs::runtime_dir()
+        .map(|dir| dir.join("ai"))
+        .unwrap_or_else(logging::state_dir)
+        .join("daemon.sock")
+}
+
+/// Identifies the local model and how it samples, requests go to the daemon only when its
+/// model was started with the same settings. The seed differs on every run and
+/// `stop_at_newline` by command, they're sent with each request instead
+pub fn model_fingerprint(settings: &Settings) -> String {
+    let config = LocalModelConfig {
+        seed: 0,
+        stop_at_newline: false,
+        ..settings.local_model_config.clone()
+    };
+    let settings = format!("{:?} {:?}", config, settings.response_schema);
+    format!("{:x}", Sha256::digest(settings.as_bytes()))
+}
+
+/// Parses `keep_alive`: a number of seconds, minutes or hours such as "90s", "10m" or "2h",
+/// or "forever" for None
+pub fn parse_keep_alive(keep_alive: &str) -> Result<Option<Duration>> {
+    let keep_alive = keep_alive.trim();
+    if keep_alive == "forever" {
+        return Ok(None);
+    }
+    let (number, unit) = keep_alive.split_at(
+        keep_alive
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(keep_alive.len()),
+    );
+    let secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(number) if secs > 0 => Ok(Some(Duration::from_secs(number * secs))),
+        _ => anyhow::bail!("{}", Message::InvalidKeepAlive(keep_alive)),
+    }
+}
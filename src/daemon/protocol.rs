@@ -0,0 +1,135 @@
+use std::io::{ErrorKind, Read, Write};
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::ai_backend::{ChatMessage, Usage};
+use crate::messages::Message;
+
+/// Version of the protocol, the client and the daemon have to agree on it
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Largest frame either side accepts, long conversations fit comfortably
+const MAX_FRAME_BYTES: u32 = 64 * 1024 * 1024;
+
+/// What a client asks the daemon. Every frame is a big endian u32 length followed by that
+/// many bytes of json
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Generates `n` responses continuing the conversation, answered with `Done`
+    Generate {
+        id: u64,
+        /// `model_fingerprint` of the client's settings, the daemon refuses other models
+        model: String,
+        /// Seed of the first response, the others count up from it
+        seed: u64,
+        /// Whether the responses end at the end of the command line
+        #[serde(default)]
+        stop_at_newline: bool,
+        /// Goes in the chat template of models that take one
+        #[serde(default)]
+        system: String,
+        messages: Vec<ChatMessage>,
+        n: usize,
+    },
+    /// Generates one response, sending `Token`s as it goes and then `Done`
+    Stream {
+        id: u64,
+        model: String,
+        seed: u64,
+        #[serde(default)]
+        stop_at_newline: bool,
+        #[serde(default)]
+        system: String,
+        messages: Vec<ChatMessage>,
+    },
+    /// Stops the generation with the id, its `Done` then has what was generated so far
+    Cancel { id: u64 },
+    /// Answered with `Status`
+    Status,
+    /// Stops the daemon once the current generation is done, answered with `ShuttingDown`
+    Shutdown,
+}
+
+/// What the daemon sends back
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    /// The next piece of a streamed response
+    Token {
+        id: u64,
+        text: String,
+    },
+    /// The request's responses. `cut_short` when it was cancelled or timed out
+    Done {
+        id: u64,
+        responses: Vec<String>,
+        usage: Usage,
+        cut_short: bool,
+    },
+    /// The request failed, `id` is None when the frame couldn't be read
+    Error {
+        id: Option<u64>,
+        message: String,
+    },
+    Status(Status),
+    ShuttingDown,
+}
+
+/// The daemon's state, for `ai daemon status`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    pub protocol: u32,
+    pub version: String,
+    pub pid: u32,
+    pub uptime_secs: u64,
+    /// Generations served since it started
+    pub requests: u64,
+    /// `model_fingerprint` of the settings it was started with
+    pub model: String,
+    /// Human readable name of the model
+    pub model_name: String,
+    /// Whether the model is in memory, it's loaded by the first request
+    pub loaded: bool,
+    /// Seconds since the last generation finished
+    #[serde(default)]
+    pub idle_secs: u64,
+    /// Seconds the model stays loaded when idle, None to keep it
+    #[serde(default)]
+    pub keep_alive_secs: Option<u64>,
+    /// Resident memory of the daemon, where the platform reports it
+    #[serde(default)]
+    pub memory_bytes: Option<u64>,
+}
+
+pub fn write_frame<T: Serialize>(writer: &mut impl Write, frame: &T) -> Result<()> {
+    let body = serde_json::to_vec(frame)?;
+    let length = u32::try_from(body.len())
+        .ok()
+        .filter(|length| *length <= MAX_FRAME_BYTES)
+        .ok_or_else(|| anyhow::anyhow!("{}", Message::FrameTooLarge(body.len())))?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(&body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// The next frame, None when the other side closed the connection between frames
+pub fn read_frame<T: DeserializeOwned>(reader: &mut impl Read) -> Result<Option<T>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let length = u32::from_be_bytes(length);
+    if length > MAX_FRAME_BYTES {
+        anyhow::bail!("{}", Message::FrameTooLarge(length as usize));
+    }
+    let mut body = vec![0; length as usize];
+    reader.read_exact(&mut body)?;
+    let frame = serde_json::from_slice(&body).context("Invalid frame")?;
+    Ok(Some(frame))
+}
@@ -0,0 +1,361 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use tracing::{debug, info, warn};
+
+use super::protocol::{read_frame, write_frame, Request, Response, Status, PROTOCOL_VERSION};
+use super::{model_fingerprint, parse_keep_alive, socket_path, DaemonClient};
+use crate::ai_backend::common::take_cut_short;
+use crate::ai_backend::LocalAiBackend;
+use crate::bench;
+use crate::interrupt;
+use crate::messages::Message;
+use crate::settings::Settings;
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// How often the daemon checks whether the model has been idle for `keep_alive`
+const UNLOAD_POLL: Duration = Duration::from_secs(1);
+
+/// A generation, by connection and the id the client gave it
+type GenerationId = (u64, u64);
+
+/// The daemon's state, shared by the connections
+struct Daemon {
+    backend: LocalAiBackend,
+    model: String,
+    model_name: String,
+    started: Instant,
+    /// How long the model stays loaded when idle, None to keep it
+    keep_alive: Option<Duration>,
+    /// When the last generation finished
+    last_used: Mutex<Instant>,
+    requests: AtomicU64,
+    next_connection: AtomicU64,
+    /// Held while generating, the model runs one generation at a time
+    generation: Mutex<()>,
+    /// The generation running, cancelling it interrupts the model
+    active: Mutex<Option<GenerationId>>,
+    /// Generations cancelled while waiting for the model
+    cancelled: Mutex<HashSet<GenerationId>>,
+    shutting_down: AtomicBool,
+}
+
+/// Runs the daemon in the foreground until `ai daemon stop`. It serves the local model
+/// over a Unix socket, loading it on the first request and keeping it loaded
+#[cfg(unix)]
+pub fn run(settings: Settings, start: Instant) -> Result<()> {
+    let keep_alive = parse_keep_alive(&settings.daemon.keep_alive)?;
+    let path = socket_path();
+    if DaemonClient::connect().is_some() {
+        anyhow::bail!(
+            "{}",
+            Message::DaemonAlreadyRunning(&path.display().to_string())
+        );
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Unable to create {}", dir.display()))?;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    // left behind by a daemon that didn't stop cleanly
+    if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Unable to listen on {}", path.display()))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    let backend = LocalAiBackend::new(settings.clone(), String::new(), start);
+    let daemon = Arc::new(Daemon {
+        model: model_fingerprint(&settings),
+        model_name: backend.model_id(),
+        backend,
+        started: Instant::now(),
+        keep_alive,
+        last_used: Mutex::new(Instant::now()),
+        requests: AtomicU64::new(0),
+        next_connection: AtomicU64::new(0),
+        generation: Mutex::new(()),
+        active: Mutex::new(None),
+        cancelled: Mutex::new(HashSet::new()),
+        shutting_down: AtomicBool::new(false),
+    });
+    eprintln!(
+        "Serving {} on {}, stop it with `ai daemon stop`",
+        daemon.model_name,
+        path.display()
+    );
+    if let Some(keep_alive) = keep_alive {
+        let daemon = daemon.clone();
+        std::thread::spawn(move || daemon.unload_when_idle(keep_alive));
+    }
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let daemon = daemon.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = daemon.serve(stream) {
+                        warn!("Connection failed: {:#}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Unable to accept a connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn run(_settings: Settings, _start: Instant) -> Result<()> {
+    anyhow::bail!("{}", Message::DaemonNeedsUnix)
+}
+
+#[cfg(unix)]
+impl Daemon {
+    /// Answers a connection's requests until it closes. Generations run on their own
+    /// thread so a cancel on the same connection is read while they run
+    fn serve(self: Arc<Self>, stream: UnixStream) -> Result<()> {
+        let connection = self.next_connection.fetch_add(1, Ordering::Relaxed);
+        let mut reader = stream.try_clone()?;
+        let writer = Arc::new(Mutex::new(stream));
+        loop {
+            let request = match read_frame::<Request>(&mut reader) {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(e) => {
+                    send(
+                        &writer,
+                        &Response::Error {
+                            id: None,
+                            message: format!("{:#}", e),
+                        },
+                    );
+                    break;
+                }
+            };
+            debug!("Request on connection {}: {:?}", connection, request);
+            match request {
+                request @ (Request::Generate { id, .. } | Request::Stream { id, .. }) => {
+                    self.spawn_generation((connection, id), request, &writer)
+                }
+                Request::Cancel { id } => self.cancel((connection, id)),
+                Request::Status => send(&writer, &Response::Status(self.status())),
+                Request::Shutdown => {
+                    info!("Shutting down");
+                    self.shutting_down.store(true, Ordering::SeqCst);
+                    send(&writer, &Response::ShuttingDown);
+                    // lets the running generation finish
+                    let _generation = self.generation.lock();
+                    let _ = std::fs::remove_file(socket_path());
+                    std::process::exit(0);
+                }
+            }
+        }
+        // nobody is left to read the output of the connection's generation
+        self.cancel_connection(connection);
+        Ok(())
+    }
+
+    fn spawn_generation(
+        self: &Arc<Self>,
+        generation: GenerationId,
+        request: Request,
+        writer: &Arc<Mutex<UnixStream>>,
+    ) {
+        let daemon = self.clone();
+        let writer = writer.clone();
+        std::thread::spawn(move || {
+            let (_, id) = generation;
+            let response = daemon
+                .generate(generation, request, &writer)
+                .unwrap_or_else(|e| Response::Error {
+                    id: Some(id),
+                    message: format!("{:#}", e),
+                });
+            send(&writer, &response);
+        });
+    }
+
+    /// Runs a generate or stream request once the model is free
+    fn generate(
+        &self,
+        generation: GenerationId,
+        request: Request,
+        writer: &Mutex<UnixStream>,
+    ) -> Result<Response> {
+        let (model, seed, stop_at_newline, system, messages, n) = match request {
+            Request::Generate {
+                model,
+                seed,
+                stop_at_newline,
+                system,
+                messages,
+                n,
+                ..
+            } => (model, seed, stop_at_newline, system, messages, Some(n)),
+            Request::Stream {
+                model,
+                seed,
+                stop_at_newline,
+                system,
+                messages,
+                ..
+            } => (model, seed, stop_at_newline, system, messages, None),
+            request => anyhow::bail!("{}", Message::NotAGeneration(&format!("{:?}", request))),
+        };
+        if model != self.model {
+            anyhow::bail!("{}", Message::DaemonOtherModel);
+        }
+        let _generation = self
+            .generation
+            .lock()
+            .map_err(|_| anyhow::anyhow!("{}", Message::LockPoisoned("generation")))?;
+        if self.shutting_down.load(Ordering::SeqCst) {
+            anyhow::bail!("{}", Message::DaemonShuttingDown);
+        }
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.backend.reseed(seed);
+        self.backend.set_stop_at_newline(stop_at_newline);
+        let (_, id) = generation;
+        interrupt::generating(|| {
+            if !self.start(generation)? {
+                return Ok(Response::Done {
+                    id,
+                    responses: Vec::new(),
+                    usage: Default::default(),
+                    cut_short: true,
+                });
+            }
+            let responses = self
+                .backend
+                .chat(&system, &messages, n.unwrap_or(1), &mut |text| {
+                    // only streams send the tokens
+                    if n.is_none() {
+                        send(
+                            writer,
+                            &Response::Token {
+                                id,
+                                text: text.to_string(),
+                            },
+                        )
+                    }
+                });
+            self.finish();
+            Ok(Response::Done {
+                id,
+                responses: responses?,
+                usage: self.backend.take_usage(),
+                cut_short: take_cut_short(),
+            })
+        })
+    }
+
+    /// Marks the generation as running, false when it was cancelled while waiting
+    fn start(&self, generation: GenerationId) -> Result<bool> {
+        let mut active = self
+            .active
+            .lock()
+            .map_err(|_| anyhow::anyhow!("{}", Message::LockPoisoned("generation")))?;
+        let mut cancelled = self
+            .cancelled
+            .lock()
+            .map_err(|_| anyhow::anyhow!("{}", Message::LockPoisoned("generation")))?;
+        if cancelled.remove(&generation) {
+            return Ok(false);
+        }
+        *active = Some(generation);
+        Ok(true)
+    }
+
+    fn finish(&self) {
+        if let Ok(mut active) = self.active.lock() {
+            *active = None;
+        }
+        if let Ok(mut last_used) = self.last_used.lock() {
+            *last_used = Instant::now();
+        }
+    }
+
+    fn cancel(&self, generation: GenerationId) {
+        let Ok(active) = self.active.lock() else {
+            return;
+        };
+        if *active == Some(generation) {
+            info!("Cancelling generation {:?}", generation);
+            interrupt::cancel();
+        } else if let Ok(mut cancelled) = self.cancelled.lock() {
+            cancelled.insert(generation);
+        }
+    }
+
+    fn cancel_connection(&self, connection: u64) {
+        let Ok(active) = self.active.lock() else {
+            return;
+        };
+        if active.is_some_and(|(active, _)| active == connection) {
+            info!(
+                "Connection {} closed, cancelling its generation",
+                connection
+            );
+            interrupt::cancel();
+        }
+    }
+
+    fn idle(&self) -> Duration {
+        self.last_used
+            .lock()
+            .map(|last_used| last_used.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Drops the model once no generation has run for `keep_alive`, freeing its RAM or
+    /// VRAM until the next request loads it again
+    fn unload_when_idle(&self, keep_alive: Duration) {
+        loop {
+            std::thread::sleep(UNLOAD_POLL);
+            if self.idle() < keep_alive {
+                continue;
+            }
+            // a generation waiting for the model will use it
+            let Ok(_generation) = self.generation.try_lock() else {
+                continue;
+            };
+            if self.backend.unload() {
+                info!("Unloaded the model after {:?} idle", keep_alive);
+            }
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            protocol: PROTOCOL_VERSION,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            pid: std::process::id(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            requests: self.requests.load(Ordering::Relaxed),
+            model: self.model.clone(),
+            model_name: self.model_name.clone(),
+            loaded: self.backend.is_loaded(),
+            idle_secs: self.idle().as_secs(),
+            keep_alive_secs: self.keep_alive.map(|keep_alive| keep_alive.as_secs()),
+            memory_bytes: bench::resident_memory(),
+        }
+    }
+}
+
+/// Sends a frame, a client that went away is only logged
+#[cfg(unix)]
+fn send(writer: &Mutex<UnixStream>, response: &Response) {
+    let Ok(mut stream) = writer.lock() else {
+        return;
+    };
+    if let Err(e) = write_frame(&mut *stream, response) {
+        debug!("Unable to answer: {:#}", e);
+    }
+}
@@ -0,0 +1,98 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
+
+use crate::context::environment::{self, ShellKind};
+use crate::network;
+use crate::redact::Secret;
+use crate::render;
+
+/// Payload format a webhook expects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookKind {
+    /// POSTs {"prompt", "response", "backend"} as json
+    Generic,
+    /// Slack incoming webhook, POSTs {"text"} formatted with mrkdwn
+    Slack,
+}
+
+/// A named webhook from the `[webhooks.<name>]` settings tables
+#[derive(Debug, Clone, Deserialize)]
+pub struct Webhook {
+    pub url: Secret,
+    #[serde(default = "default_kind")]
+    pub kind: WebhookKind,
+}
+
+fn default_kind() -> WebhookKind {
+    WebhookKind::Generic
+}
+
+/// Posts a generated response to the webhook, giving up after `timeout_secs`, 0 for no
+/// limit
+pub fn deliver(
+    webhook: &Webhook,
+    backend: &str,
+    prompt: &str,
+    response: &str,
+    timeout_secs: u64,
+) -> Result<()> {
+    let body = match webhook.kind {
+        WebhookKind::Generic => json!({
+            "prompt": prompt,
+            "response": response,
+            "backend": backend,
+        }),
+        WebhookKind::Slack => json!({
+            "text": format!("*{}*\n```{}```", prompt, response.trim()),
+        }),
+    };
+    network::ensure_online("Delivering to a webhook")?;
+    info!("Delivering response to {:?} webhook", webhook.kind);
+    let url = webhook.url.expose();
+    let mut agent = network::agent(url);
+    if timeout_secs > 0 {
+        agent = agent.timeout(Duration::from_secs(timeout_secs));
+    }
+    agent
+        .build()
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&body.to_string())
+        // the url itself is a secret for slack, so keep it out of the error
+        .context("Unable to deliver response to webhook")?;
+    Ok(())
+}
+
+/// Writes the generated command to `path` as an executable script, with a shebang for the
+/// user's shell unless the response already starts with one. PowerShell and cmd scripts
+/// are run by their extension, .ps1 or .cmd, so they get none
+pub fn write_script(path: &Path, command: &str) -> Result<()> {
+    let command = render::strip_code_fence(command);
+    let script = if command.starts_with("#!") || environment::shell_kind() != ShellKind::Posix {
+        format!("{}\n", command)
+    } else {
+        let interpreter = match environment::shell_name().as_str() {
+            "unknown" => "/bin/sh".to_string(),
+            shell => format!("/usr/bin/env {}", shell),
+        };
+        format!("#!{}\n{}\n", interpreter, command)
+    };
+    info!("Writing script to {:?}", path);
+    std::fs::write(path, script).with_context(|| format!("Unable to write script {:?}", path))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = std::fs::metadata(path)?.permissions();
+        // executable by whoever can read it
+        permissions.set_mode(permissions.mode() | ((permissions.mode() & 0o444) >> 2));
+        std::fs::set_permissions(path, permissions)
+            .with_context(|| format!("Unable to make {:?} executable", path))?;
+    }
+    Ok(())
+}
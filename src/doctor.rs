@@ -0,0 +1,484 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::Result;
+use aws_sdk_sts::error::DisplayErrorContext;
+use candle_core::utils::{cuda_is_available, has_accelerate, has_mkl, metal_is_available};
+use tracing::info;
+
+use crate::ai_backend::{plugin, redacting};
+use crate::aws;
+use crate::memory;
+use crate::messages::Message;
+use crate::network;
+use crate::routing;
+use crate::settings::{config_file, ClipboardMode, Settings};
+
+/// Free space below which the model cache is reported, the full precision phi weights
+/// take about 5.5 GB
+const MIN_CACHE_FREE_BYTES: u64 = 6 * 1024 * 1024 * 1024;
+
+/// How long the network checks may take before they're reported as unreachable
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// One line of the report, with what to do about it when it isn't ok
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Checks the config, the GPU support of this build, the model cache, AWS credentials and
+/// the clipboard, and prints what to fix. Fails when any check failed.
+/// Loads the settings itself so it can report a config that doesn't parse
+pub fn doctor() -> Result<()> {
+    let (settings, mut checks) = match Settings::new() {
+        Ok(settings) => {
+            let checks = config(&settings);
+            (Some(settings), checks)
+        }
+        Err(e) => (
+            None,
+            vec![Check::fail(
+                "config",
+                format!("{}", e),
+                format!(
+                    "Fix the value in {}, or move the file away to get the defaults back",
+                    config_file().display()
+                ),
+            )],
+        ),
+    };
+    checks.extend(config_overrides());
+    checks.push(accelerator(settings.as_ref()));
+    checks.extend(memory(settings.as_ref()));
+    checks.extend(network(settings.as_ref()));
+    checks.extend(model_cache());
+    match &settings {
+        Some(_) if network::local_only() => info!("Skipping the AWS check, local only"),
+        Some(settings) if !uses_bedrock(settings) => {
+            info!("Skipping the AWS check, Bedrock isn't used")
+        }
+        Some(settings) => checks.push(aws(settings)),
+        None => info!("Skipping the AWS check, the config didn't load"),
+    }
+    checks.push(clipboard(settings.as_ref()));
+
+    for check in checks.iter() {
+        let marker = match check.status {
+            Status::Ok => console::style("[ok]  ").green(),
+            Status::Warn => console::style("[warn]").yellow(),
+            Status::Fail => console::style("[fail]").red(),
+        };
+        println!("{} {}: {}", marker, check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            println!("       fix: {}", fix);
+        }
+    }
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{}", Message::ChecksFailed(failed, checks.len()));
+    }
+    Ok(())
+}
+
+/// Settings that parse but can't work, such as an unknown backend
+fn config(settings: &Settings) -> Vec<Check> {
+    let path = config_file();
+    let mut checks = vec![Check::ok("config", path.display().to_string())];
+    if let Some(project) = &settings.project_config {
+        checks.push(Check::ok(
+            "config",
+            format!("{} is merged over the user config", project.display()),
+        ));
+    }
+    checks.extend(settings.unknown_keys.iter().map(|warning| {
+        Check::warn(
+            "config",
+            warning.clone(),
+            "Fix the key's name or remove it, the setting isn't used",
+        )
+    }));
+    match settings.ai_backend.as_str() {
+        "command" if settings.command_backend.is_none() => checks.push(Check::fail(
+            "backend",
+            "ai_backend is \"command\" but there is no [command_backend] table",
+            "Add [command_backend] with cmd set to the program to run",
+        )),
+        "mock" => match settings
+            .mock_backend
+            .as_ref()
+            .and_then(|mock| mock.fixture.as_ref())
+        {
+            Some(fixture) if !fixture.exists() => checks.push(Check::fail(
+                "backend",
+                format!("The mock fixture {} doesn't exist", fixture.display()),
+                "Point mock_backend.fixture at a json file or remove it",
+            )),
+            _ => {}
+        },
+        "local" | "bedrock" | "command" | "auto" => {}
+        other => match plugin::find(settings, other) {
+            Some(plugin) => checks.push(Check::ok(
+                "backend",
+                format!("{} is the plugin {}", other, plugin.path.display()),
+            )),
+            None => checks.push(Check::fail(
+                "backend",
+                format!("Unknown ai_backend \"{}\"", other),
+                format!(
+                    "Set ai_backend to local, bedrock, command, mock or auto, or install a {}{} plugin",
+                    plugin::PLUGIN_PREFIX,
+                    other
+                ),
+            )),
+        },
+    }
+    let backend = settings.ai_backend.as_str();
+    if (settings.local_only || network::local_only())
+        && backend != routing::AUTO
+        && !redacting::is_local(backend)
+    {
+        checks.push(Check::fail(
+            "backend",
+            format!("{} is a remote backend, local only rejects it", backend),
+            "Set ai_backend to local, or turn local_only off",
+        ));
+    }
+    if let Some(file) = &settings.system_prompt_file {
+        if settings.system_prompt.is_none() && !file.exists() {
+            checks.push(Check::fail(
+                "system prompt",
+                format!("system_prompt_file {} doesn't exist", file.display()),
+                "Create the file or remove the system_prompt_file setting",
+            ));
+        }
+    }
+    checks
+}
+
+/// A config file in the current directory is merged over the one in ~/.config/ai,
+/// which is easy to forget about
+fn config_overrides() -> Vec<Check> {
+    ["toml", "json", "yaml", "yml", "ini"]
+        .iter()
+        .map(|extension| PathBuf::from("config").with_extension(extension))
+        .filter(|path| path.is_file())
+        .map(|path| {
+            Check::warn(
+                "config",
+                format!(
+                    "{} in the current directory overrides the user config",
+                    path.display()
+                ),
+                "Run ai from another directory or rename the file if that isn't intended",
+            )
+        })
+        .collect()
+}
+
+/// Whether the local model can use the GPU, comparing the hardware to the compiled features
+fn accelerator(settings: Option<&Settings>) -> Check {
+    let name = "gpu";
+    let cpu_forced = settings.is_some_and(|s| s.local_model_config.cpu);
+    let blas = if has_mkl() {
+        ", with MKL"
+    } else if has_accelerate() {
+        ", with Accelerate"
+    } else {
+        ""
+    };
+    if cuda_is_available() {
+        return Check::ok(name, "CUDA is available");
+    }
+    if metal_is_available() {
+        return Check::ok(name, "Metal is available");
+    }
+    if cfg!(feature = "metal") {
+        return Check::fail(
+            name,
+            "Built with the metal feature but no Metal device was found",
+            "Rebuild without --features metal, or set local_model_config.cpu = true",
+        );
+    }
+    if cpu_forced {
+        return Check::ok(
+            name,
+            format!("local_model_config.cpu is set, running on CPU{}", blas),
+        );
+    }
+    if cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+        return Check::warn(
+            name,
+            format!(
+                "This Mac has a GPU but the build has no Metal support{}",
+                blas
+            ),
+            "Rebuild with `cargo install --path . --features metal`",
+        );
+    }
+    if nvidia_gpu() {
+        return Check::warn(
+            name,
+            format!(
+                "An NVIDIA GPU was found but the build has no CUDA support{}",
+                blas
+            ),
+            "Rebuild with candle's cuda feature enabled to run the local model on the GPU",
+        );
+    }
+    Check::ok(name, format!("No GPU found, running on CPU{}", blas))
+}
+
+/// The RAM free for the local model and the `max_memory_gb` cap, the model is checked
+/// against them before it's loaded
+fn memory(settings: Option<&Settings>) -> Option<Check> {
+    let available = memory::available_ram().map(|bytes| format!("{} of RAM available", gib(bytes)));
+    let cap = settings
+        .and_then(|settings| settings.local_model_config.max_memory_gb)
+        .map(|gb| {
+            format!(
+                "the local model is capped at {:.1} GiB by max_memory_gb",
+                gb
+            )
+        });
+    let detail = match (available, cap) {
+        (Some(available), Some(cap)) => format!("{}, {}", available, cap),
+        (available, cap) => available.or(cap)?,
+    };
+    Some(Check::ok("memory", detail))
+}
+
+fn nvidia_gpu() -> bool {
+    Path::new("/proc/driver/nvidia/version").exists()
+        || Command::new("nvidia-smi")
+            .arg("-L")
+            .output()
+            .is_ok_and(|output| output.status.success())
+}
+
+/// The Hugging Face cache the local models are downloaded to: writable, enough free
+/// space, and the hub reachable for the first download
+fn model_cache() -> Vec<Check> {
+    let name = "model cache";
+    let path = hf_hub::Cache::default().path().clone();
+    let mut checks = Vec::new();
+    let probe = path.join(format!(".ai-doctor-{}", std::process::id()));
+    let writable = std::fs::create_dir_all(&path)
+        .and_then(|_| std::fs::write(&probe, b""))
+        .and_then(|_| std::fs::remove_file(&probe));
+    match writable {
+        Ok(()) => checks.push(Check::ok(name, path.display().to_string())),
+        Err(e) => {
+            checks.push(Check::fail(
+                name,
+                format!("{} isn't writable: {}", path.display(), e),
+                "Fix the permissions or point HF_HOME at a writable directory",
+            ));
+            return checks;
+        }
+    }
+    match free_space(&path) {
+        Some(free) if free < MIN_CACHE_FREE_BYTES => checks.push(Check::warn(
+            "disk space",
+            format!("{} free for the model cache", gib(free)),
+            "Free up space or point HF_HOME at a larger disk, the models need several GB",
+        )),
+        Some(free) => checks.push(Check::ok("disk space", format!("{} free", gib(free)))),
+        None => info!("Unable to read the free space of {:?}", path),
+    }
+    if network::local_only() {
+        checks.push(Check::ok(
+            "hugging face",
+            "not checked, local only: models have to be in the cache",
+        ));
+        return checks;
+    }
+    let endpoint =
+        std::env::var("HF_ENDPOINT").unwrap_or_else(|_| "https://huggingface.co".to_string());
+    match ureq::head(&endpoint).timeout(NETWORK_TIMEOUT).call() {
+        Ok(_) | Err(ureq::Error::Status(..)) => checks.push(Check::ok(
+            "hugging face",
+            format!("{} is reachable", endpoint),
+        )),
+        Err(e) => checks.push(Check::warn(
+            "hugging face",
+            format!("{} isn't reachable: {}", endpoint, e),
+            "Models that aren't cached yet can't be downloaded, check the network or proxy",
+        )),
+    }
+    checks
+}
+
+/// Bytes available on the filesystem holding `path`, from `df` as it works on linux and macOS
+fn free_space(path: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(path).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Filesystem 1024-blocks Used Available Capacity Mounted-on
+    let kib = stdout
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+fn gib(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / 1024. / 1024. / 1024.)
+}
+
+/// The proxy the requests go through, and whether the `ca_bundle` can be used
+fn network(settings: Option<&Settings>) -> Vec<Check> {
+    let mut checks = Vec::new();
+    match network::proxy_for("https://huggingface.co") {
+        Some(proxy) => checks.push(Check::ok("proxy", format!("HTTPS goes through {}", proxy))),
+        None => info!("No proxy is set"),
+    }
+    if let Some(path) = settings.and_then(|settings| settings.ca_bundle.as_ref()) {
+        checks.push(match network::read_bundle(path) {
+            Ok(certificates) => Check::ok(
+                "ca bundle",
+                format!("{} certificates in {}", certificates.len(), path.display()),
+            ),
+            Err(e) => Check::fail(
+                "ca bundle",
+                format!("{:#}", e),
+                "Point ca_bundle at a PEM file of certificates, or remove it",
+            ),
+        });
+    }
+    checks
+}
+
+/// Resolves AWS credentials, assuming `role_arn` when set, and checks them with STS
+/// GetCallerIdentity, which needs no permissions and costs nothing
+/// Whether prompts may go to Bedrock: it's the backend, or `auto` routes some there
+fn uses_bedrock(settings: &Settings) -> bool {
+    match settings.ai_backend.as_str() {
+        "bedrock" => true,
+        "auto" => [
+            &settings.routing.local_backend,
+            &settings.routing.remote_backend,
+        ]
+        .iter()
+        .any(|backend| backend.as_str() == "bedrock"),
+        _ => false,
+    }
+}
+
+fn aws(settings: &Settings) -> Check {
+    let name = "aws";
+    let region = settings.aws_settings.region.clone();
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => return Check::fail(name, format!("{}", e), "Report this as a bug"),
+    };
+    let mfa_code = match aws::mfa_code(&settings.aws_settings) {
+        Ok(code) => code,
+        Err(e) => return Check::fail(name, format!("{:#}", e), "Run ai doctor in a terminal"),
+    };
+    runtime.block_on(async {
+        let config = match aws::load(&settings.aws_settings, mfa_code).await {
+            Ok(config) => config,
+            Err(e) => return Check::fail(
+                name,
+                format!("{:#}", e),
+                "Run `aws configure` or `aws sso login`, set AWS_PROFILE to a configured profile, or check aws_settings.role_arn",
+            ),
+        };
+        let client = aws_sdk_sts::Client::new(&config);
+        let identity =
+            tokio::time::timeout(NETWORK_TIMEOUT, client.get_caller_identity().send()).await;
+        match identity {
+            Ok(Ok(identity)) => Check::ok(
+                name,
+                format!(
+                    "{} in {}",
+                    identity.arn().unwrap_or("unknown identity"),
+                    region
+                ),
+            ),
+            Ok(Err(e)) => Check::fail(
+                name,
+                format!("STS rejected the credentials in {}: {}", region, DisplayErrorContext(&e)),
+                "Check aws_settings.region is a valid region, and refresh expired credentials with `aws sso login`",
+            ),
+            Err(_) => Check::fail(
+                name,
+                format!("STS in {} didn't answer within {:?}", region, NETWORK_TIMEOUT),
+                "Check aws_settings.region and the network or proxy settings",
+            ),
+        }
+    })
+}
+
+fn clipboard(settings: Option<&Settings>) -> Check {
+    let name = "clipboard";
+    let wanted = settings.is_none_or(|s| s.clipboard != ClipboardMode::Never);
+    if !wanted {
+        return Check::ok(name, "Turned off by the clipboard setting");
+    }
+    #[cfg(feature = "clipboard")]
+    {
+        match arboard::Clipboard::new() {
+            Ok(_) => Check::ok(name, "Available"),
+            Err(e) => Check::warn(
+                name,
+                format!("Unavailable: {}", e),
+                "Run ai in a graphical session (X11 or Wayland), or set clipboard = \"never\"",
+            ),
+        }
+    }
+    #[cfg(not(feature = "clipboard"))]
+    {
+        Check::warn(
+            name,
+            "This build has no clipboard support",
+            "Rebuild with --features clipboard, or set clipboard = \"never\"",
+        )
+    }
+}
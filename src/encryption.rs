@@ -0,0 +1,574 @@
+use std::io::{BufRead, Write};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use console::Term;
+use dialoguer::Password;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use tracing::{debug, info, warn};
+
+use crate::credentials;
+use crate::interact;
+use crate::messages::Message;
+
+/// Start of the first line of an encrypted file, followed by where the key comes from and
+/// for a passphrase the salt. Every following line is a record encrypted on its own, so
+/// text can be appended without rewriting the file, and the contents are the records' texts
+/// one after the other
+const MAGIC: &str = "ai-encrypted-v2";
+
+/// File in the runtime directory the key derived from the passphrase is kept in for
+/// `history_passphrase_cache_secs`, so every run doesn't ask for it and derive it again
+const KEY_CACHE_FILE: &str = "history-key";
+
+/// Keyring account the random key of the keyring mode is stored under
+const KEYRING_ACCOUNT: &str = "history-encryption";
+
+/// Variable the passphrase is read from before asking for it
+const PASSPHRASE_VAR: &str = "AI_HISTORY_PASSPHRASE";
+
+/// PBKDF2-HMAC-SHA256 rounds turning the passphrase into a key, OWASP's recommendation
+const PBKDF2_ITERATIONS: u32 = 600_000;
+
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+/// How the history and the session are stored on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    /// Plain text
+    #[default]
+    Off,
+    /// Encrypted with a key derived from a passphrase, from $AI_HISTORY_PASSPHRASE or asked for
+    Passphrase,
+    /// Encrypted with a random key kept in the platform keyring
+    Keyring,
+}
+
+static MODE: OnceLock<(Encryption, Duration)> = OnceLock::new();
+
+/// The passphrase once entered, it's only asked for once per run
+static PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Keys derived from the passphrase with their salts. Deriving is slow on purpose, so
+/// files read and written in the same run reuse them
+static DERIVED: Mutex<Vec<Derived>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Copy)]
+struct Derived {
+    salt: [u8; SALT_LEN],
+    key: [u8; KEY_LEN],
+    /// Whether it decrypted a file, or was confirmed for a new one. Only such keys encrypt
+    /// files and go in the key cache
+    verified: bool,
+}
+
+/// The key read from the keyring, looking it up is a round trip to the keyring service
+static KEYRING: Mutex<Option<[u8; KEY_LEN]>> = Mutex::new(None);
+
+/// Sets how files are written from now on, and how long a key derived from the passphrase
+/// is kept for the next runs. Encrypted files are read whatever the mode, so turning
+/// encryption off rewrites them as plain text the next time they're saved
+pub fn init(mode: Encryption, key_cache_secs: u64) {
+    let _ = MODE.set((mode, Duration::from_secs(key_cache_secs)));
+}
+
+fn mode() -> Encryption {
+    MODE.get().map(|(mode, _)| *mode).unwrap_or_default()
+}
+
+fn key_cache_duration() -> Duration {
+    MODE.get()
+        .map(|(_, duration)| *duration)
+        .unwrap_or_default()
+}
+
+/// The contents of the file, decrypted when it's encrypted
+pub fn read(path: &Path) -> Result<String> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+    let Some(header) = Header::parse(&contents) else {
+        return Ok(contents);
+    };
+    let key = header.key()?;
+    let plain = decrypt_records(&key, &contents);
+    if plain.is_ok() {
+        header.decrypted_with(key)?;
+    }
+    plain.with_context(|| {
+        format!(
+            "Unable to decrypt {}, the passphrase or key is wrong or the file is damaged",
+            path.display()
+        )
+    })
+}
+
+/// Writes the file, encrypted unless encryption is off. It's written next to the old one
+/// and renamed over it, so a crash or a full disk doesn't leave it cut short
+pub fn write(path: &Path, contents: &str) -> Result<()> {
+    let contents = match Header::for_writing(path)? {
+        None => contents.to_string(),
+        Some((header, key)) => format!("{}{}\n", header, record(&key, contents)?),
+    };
+    write_atomic(path, &contents)
+}
+
+/// Adds the text to the end of the file. An encrypted file gets a record of its own, so
+/// neither kind is rewritten, unless it's encrypted otherwise than the setting says: it's
+/// then rewritten the way `write` would
+pub fn append(path: &Path, text: &str) -> Result<()> {
+    let mode = mode();
+    if !path.exists() {
+        return write(path, text);
+    }
+    let mut first_line = String::new();
+    std::fs::File::open(path)
+        .map(std::io::BufReader::new)
+        .and_then(|mut file| file.read_line(&mut first_line))
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+    let line = match Header::parse(&first_line) {
+        None if mode == Encryption::Off => text.to_string(),
+        Some(header) if header.mode == mode => {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("Unable to read {}", path.display()))?;
+            let key = header.key()?;
+            // a mistyped passphrase derives another key, records appended with it couldn't
+            // be read back along with the others
+            decrypt_records(&key, &contents).with_context(|| {
+                format!(
+                    "Unable to decrypt {}, the passphrase or key is wrong or the file is damaged",
+                    path.display()
+                )
+            })?;
+            header.decrypted_with(key)?;
+            format!("{}\n", record(&key, text)?)
+        }
+        _ => {
+            debug!("Rewriting {} to change its encryption", path.display());
+            return write(path, &format!("{}{}", read(path)?, text));
+        }
+    };
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Unable to open {}", path.display()))?;
+    file.write_all(line.as_bytes())
+        .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// The first line of an encrypted file
+struct Header {
+    mode: Encryption,
+    salt: Option<[u8; SALT_LEN]>,
+}
+
+impl Header {
+    /// The header of the file written now with its key, None when encryption is off
+    fn for_writing(path: &Path) -> Result<Option<(String, [u8; KEY_LEN])>> {
+        Ok(match mode() {
+            Encryption::Off => None,
+            Encryption::Passphrase => {
+                // the passphrase is checked against the file being replaced, a mistyped one
+                // would lock away what's written with it
+                if is_passphrase_encrypted(path)? {
+                    read(path)?;
+                }
+                let verified = lock(&DERIVED)?.iter().find(|d| d.verified).copied();
+                let (salt, key) = match verified {
+                    Some(derived) => (derived.salt, derived.key),
+                    None => {
+                        let mut salt = [0; SALT_LEN];
+                        fill_random(&mut salt)?;
+                        // nothing was decrypted with it yet, so a typo would lock the file
+                        let key = passphrase_key(salt, true)?;
+                        remember(salt, key)?;
+                        (salt, key)
+                    }
+                };
+                let header = format!("{} passphrase {}\n", MAGIC, BASE64.encode(salt));
+                Some((header, key))
+            }
+            Encryption::Keyring => Some((format!("{} keyring\n", MAGIC), keyring_key(true)?)),
+        })
+    }
+
+    fn parse(contents: &str) -> Option<Self> {
+        let line = contents.lines().next()?;
+        let mut words = line.strip_prefix(MAGIC)?.split_whitespace();
+        match (words.next()?, words.next()) {
+            ("passphrase", Some(salt)) => Some(Self {
+                mode: Encryption::Passphrase,
+                salt: BASE64.decode(salt).ok()?.try_into().ok(),
+            }),
+            ("keyring", None) => Some(Self {
+                mode: Encryption::Keyring,
+                salt: None,
+            }),
+            _ => None,
+        }
+    }
+
+    fn key(&self) -> Result<[u8; KEY_LEN]> {
+        match (self.mode, self.salt) {
+            (Encryption::Passphrase, Some(salt)) => passphrase_key(salt, false),
+            (Encryption::Keyring, _) => keyring_key(false),
+            _ => anyhow::bail!("{}", Message::UnknownEncryption),
+        }
+    }
+
+    /// Keeps a key derived from the passphrase for the next runs once it's known to be right
+    fn decrypted_with(&self, key: [u8; KEY_LEN]) -> Result<()> {
+        match self.salt {
+            Some(salt) => remember(salt, key),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether the file exists and is encrypted with the passphrase
+fn is_passphrase_encrypted(path: &Path) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let mut first_line = String::new();
+    std::fs::File::open(path)
+        .map(std::io::BufReader::new)
+        .and_then(|mut file| file.read_line(&mut first_line))
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+    Ok(Header::parse(&first_line).is_some_and(|header| header.mode == Encryption::Passphrase))
+}
+
+/// One line of an encrypted file holding the text
+fn record(key: &[u8; KEY_LEN], text: &str) -> Result<String> {
+    Ok(BASE64.encode(encrypt(key, text.as_bytes())?))
+}
+
+/// The texts of the records after the header, joined
+fn decrypt_records(key: &[u8; KEY_LEN], contents: &str) -> Result<String> {
+    let mut plain = String::new();
+    for line in contents
+        .lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+    {
+        let data = BASE64.decode(line.trim())?;
+        plain.push_str(&String::from_utf8(decrypt(key, &data)?)?);
+    }
+    Ok(plain)
+}
+
+/// Replaces the file with a temporary one written in the same directory
+fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Unable to write {}", path.display()))?;
+    file.write_all(contents.as_bytes())
+        .and_then(|_| file.as_file().sync_all())
+        .with_context(|| format!("Unable to write {}", path.display()))?;
+    file.persist(path)
+        .with_context(|| format!("Unable to write {}", path.display()))?;
+    Ok(())
+}
+
+/// The nonce followed by the ciphertext and its tag
+fn encrypt(key: &[u8; KEY_LEN], plain: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0; NONCE_LEN];
+    fill_random(&mut nonce)?;
+    let mut data = plain.to_vec();
+    cipher(key)?
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+        .map_err(|_| anyhow::anyhow!("{}", Message::EncryptFailed))?;
+    Ok([&nonce[..], &data].concat())
+}
+
+fn decrypt(key: &[u8; KEY_LEN], data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        anyhow::bail!("{}", Message::TooShortToDecrypt);
+    }
+    let (nonce, data) = data.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce)
+        .map_err(|_| anyhow::anyhow!("{}", Message::InvalidNonce))?;
+    let mut data = data.to_vec();
+    let plain = cipher(key)?
+        .open_in_place(nonce, Aad::empty(), &mut data)
+        .map_err(|_| anyhow::anyhow!("{}", Message::AuthenticationFailed))?;
+    Ok(plain.to_vec())
+}
+
+fn cipher(key: &[u8; KEY_LEN]) -> Result<LessSafeKey> {
+    let key = UnboundKey::new(&AES_256_GCM, key)
+        .map_err(|_| anyhow::anyhow!("{}", Message::InvalidKey))?;
+    Ok(LessSafeKey::new(key))
+}
+
+fn fill_random(bytes: &mut [u8]) -> Result<()> {
+    SystemRandom::new()
+        .fill(bytes)
+        .map_err(|_| anyhow::anyhow!("{}", Message::NoRandomBytes))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<std::sync::MutexGuard<'_, T>> {
+    mutex
+        .lock()
+        .map_err(|_| anyhow::anyhow!("{}", Message::LockPoisoned("encryption")))
+}
+
+/// The key for the salt, derived from the passphrase unless the key cache has it. `new`
+/// asks for the passphrase twice
+fn passphrase_key(salt: [u8; SALT_LEN], new: bool) -> Result<[u8; KEY_LEN]> {
+    if let Some(derived) = lock(&DERIVED)?.iter().find(|d| d.salt == salt) {
+        return Ok(derived.key);
+    }
+    if let Some(key) = cached_key(salt) {
+        lock(&DERIVED)?.push(Derived {
+            salt,
+            key,
+            verified: true,
+        });
+        return Ok(key);
+    }
+    let passphrase = passphrase(new)?;
+    let mut key = [0; KEY_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).expect("iterations are not zero"),
+        &salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    lock(&DERIVED)?.push(Derived {
+        salt,
+        key,
+        verified: false,
+    });
+    Ok(key)
+}
+
+/// The key cache, in the runtime directory only as that's the user's alone and cleared at
+/// logout. None without one or when `history_passphrase_cache_secs` is 0
+fn key_cache_path() -> Option<PathBuf> {
+    if key_cache_duration().is_zero() {
+        return None;
+    }
+    dirs::runtime_dir().map(|dir| dir.join("ai").join(KEY_CACHE_FILE))
+}
+
+/// The keys in the key cache by salt, none once it's older than
+/// `history_passphrase_cache_secs`
+fn cached_keys() -> Vec<(String, String)> {
+    let Some(path) = key_cache_path() else {
+        return Vec::new();
+    };
+    let age = std::fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+    if age.is_none_or(|age| age > key_cache_duration()) {
+        let _ = std::fs::remove_file(&path);
+        return Vec::new();
+    }
+    std::fs::read_to_string(&path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(salt, key)| (salt.to_string(), key.to_string()))
+        .collect()
+}
+
+/// The cached key for the salt
+fn cached_key(salt: [u8; SALT_LEN]) -> Option<[u8; KEY_LEN]> {
+    let salt = BASE64.encode(salt);
+    let (_, key) = cached_keys()
+        .into_iter()
+        .find(|(cached, _)| *cached == salt)?;
+    debug!("Using the cached history key");
+    BASE64.decode(key).ok()?.try_into().ok()
+}
+
+/// Marks the key derived for the salt as right, once it decrypted a file or was confirmed,
+/// and puts it in the key cache. Failing to cache it is only a warning, the passphrase is
+/// asked for again next time
+fn remember(salt: [u8; SALT_LEN], key: [u8; KEY_LEN]) -> Result<()> {
+    {
+        let mut derived = lock(&DERIVED)?;
+        match derived.iter_mut().find(|d| d.salt == salt) {
+            Some(d) if d.verified => return Ok(()),
+            Some(d) => d.verified = true,
+            None => derived.push(Derived {
+                salt,
+                key,
+                verified: true,
+            }),
+        }
+    }
+    let Some(path) = key_cache_path() else {
+        return Ok(());
+    };
+    let salt = BASE64.encode(salt);
+    let mut keys = cached_keys();
+    keys.retain(|(cached, _)| *cached != salt);
+    keys.push((salt, BASE64.encode(key)));
+    let contents = keys
+        .iter()
+        .map(|(salt, key)| format!("{} {}\n", salt, key))
+        .collect::<String>();
+    if let Err(e) = write_private(&path, &contents) {
+        warn!(
+            "Unable to cache the history key in {}: {:#}",
+            path.display(),
+            e
+        );
+    }
+    Ok(())
+}
+
+/// Writes the file readable only by the user, in a directory only the user can enter
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    let dir = path.parent().context("No directory")?;
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)?;
+    // NamedTempFile is created readable only by the user
+    write_atomic(path, contents)
+}
+
+/// $AI_HISTORY_PASSPHRASE, otherwise asked for on the terminal
+fn passphrase(new: bool) -> Result<String> {
+    if let Some(passphrase) = lock(&PASSPHRASE)?.clone() {
+        return Ok(passphrase);
+    }
+    let passphrase = match std::env::var(PASSPHRASE_VAR) {
+        Ok(passphrase) if !passphrase.is_empty() => passphrase,
+        _ if interact::available() => {
+            let prompt = Password::new().with_prompt(Message::HistoryPassphrase.to_string());
+            let prompt = if new {
+                prompt.with_confirmation(
+                    Message::RepeatHistoryPassphrase.to_string(),
+                    Message::HistoryPassphraseMismatch.to_string(),
+                )
+            } else {
+                prompt
+            };
+            prompt.interact_on(&Term::stderr())?
+        }
+        _ => anyhow::bail!("{}", Message::NoHistoryPassphrase),
+    };
+    if passphrase.is_empty() {
+        anyhow::bail!("{}", Message::NoHistoryPassphrase);
+    }
+    *lock(&PASSPHRASE)? = Some(passphrase.clone());
+    Ok(passphrase)
+}
+
+/// The key kept in the keyring, created there when `create` and there is none yet
+fn keyring_key(create: bool) -> Result<[u8; KEY_LEN]> {
+    if let Some(key) = *lock(&KEYRING)? {
+        return Ok(key);
+    }
+    if let Some(stored) = credentials::get(KEYRING_ACCOUNT)? {
+        let key = BASE64
+            .decode(stored.trim())
+            .ok()
+            .and_then(|key| <[u8; KEY_LEN]>::try_from(key).ok())
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::NotAKey(KEYRING_ACCOUNT)))?;
+        *lock(&KEYRING)? = Some(key);
+        return Ok(key);
+    }
+    if !create {
+        anyhow::bail!("{}", Message::NoHistoryKey);
+    }
+    let mut key = [0; KEY_LEN];
+    fill_random(&mut key)?;
+    info!("Storing a new history encryption key in the keyring");
+    credentials::set(KEYRING_ACCOUNT, &BASE64.encode(key))?;
+    *lock(&KEYRING)? = Some(key);
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_decrypt_only_with_their_key() {
+        let key = [7; KEY_LEN];
+        let contents = format!(
+            "{} keyring\n{}\n{}\n",
+            MAGIC,
+            record(&key, "first\n").unwrap(),
+            record(&key, "second\n").unwrap()
+        );
+        assert_eq!(decrypt_records(&key, &contents).unwrap(), "first\nsecond\n");
+        assert!(decrypt_records(&[8; KEY_LEN], &contents).is_err());
+    }
+
+    #[test]
+    fn a_changed_record_fails_to_decrypt() {
+        let key = [7; KEY_LEN];
+        let mut data = encrypt(&key, b"secret").unwrap();
+        assert_eq!(decrypt(&key, &data).unwrap(), b"secret");
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(decrypt(&key, &data).is_err());
+        assert!(decrypt(&key, &data[..NONCE_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn headers_name_the_mode_and_salt() {
+        let salt = [3; SALT_LEN];
+        let header =
+            Header::parse(&format!("{} passphrase {}\n", MAGIC, BASE64.encode(salt))).unwrap();
+        assert_eq!(header.mode, Encryption::Passphrase);
+        assert_eq!(header.salt, Some(salt));
+        let header = Header::parse(&format!("{} keyring\nrecord\n", MAGIC)).unwrap();
+        assert_eq!(header.mode, Encryption::Keyring);
+        assert!(Header::parse(&format!("{} passphrase\n", MAGIC)).is_none());
+        assert!(Header::parse("{\"prompt\": \"ls\"}\n").is_none());
+    }
+
+    #[test]
+    fn a_passphrase_encrypted_file_round_trips_and_is_appended_to() {
+        init(Encryption::Passphrase, 0);
+        *lock(&PASSPHRASE).unwrap() = Some("correct horse".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+
+        append(&path, "one\n").unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(written.starts_with(&format!("{} passphrase ", MAGIC)));
+        assert!(!written.contains("one"));
+
+        append(&path, "two\n").unwrap();
+        let appended = std::fs::read_to_string(&path).unwrap();
+        // the first record is left as it was, the second is a line of its own
+        assert!(appended.starts_with(&written));
+        assert_eq!(appended.lines().count(), 3);
+        assert_eq!(read(&path).unwrap(), "one\ntwo\n");
+
+        write(&path, "three\n").unwrap();
+        let rewritten = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(rewritten.lines().next(), written.lines().next());
+        assert_eq!(read(&path).unwrap(), "three\n");
+    }
+
+    #[test]
+    fn plain_files_are_read_as_they_are() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.json");
+        std::fs::write(&path, "{\"messages\": []}\n").unwrap();
+        assert_eq!(read(&path).unwrap(), "{\"messages\": []}\n");
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::io::{IsTerminal, Read};
+
+use anyhow::Result;
+use tracing::info;
+
+use crate::messages::Message;
+
+/// Most bytes read from stdin, a runaway log piped in is cut off there
+const MAX_INPUT_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Most characters of the error sent to the model
+const MAX_ERROR_CHARS: usize = 8000;
+
+/// Characters of that kept from the start, where messages such as a Rust panic's are.
+/// The rest is the end, where a Python traceback's is
+const HEAD_CHARS: usize = 2000;
+
+/// Error output piped into `ai explain-error`
+#[derive(Debug)]
+pub struct ErrorOutput {
+    /// The command that failed, when given
+    pub command: Option<String>,
+    /// The output, its middle left out when it's over `MAX_ERROR_CHARS`
+    pub error: String,
+}
+
+impl ErrorOutput {
+    /// Reads the error from stdin, which has to be piped
+    pub fn read(command: Option<String>) -> Result<Self> {
+        if std::io::stdin().is_terminal() {
+            anyhow::bail!("{}", Message::NoErrorInput);
+        }
+        let mut input = Vec::new();
+        std::io::stdin()
+            .take(MAX_INPUT_BYTES)
+            .read_to_end(&mut input)?;
+        let input = String::from_utf8_lossy(&input);
+        if input.trim().is_empty() {
+            anyhow::bail!("{}", Message::NoErrorInput);
+        }
+        let error = shorten(input.trim(), MAX_ERROR_CHARS);
+        info!(
+            "Explaining {} of {} characters of error output",
+            error.chars().count(),
+            input.chars().count()
+        );
+        Ok(Self { command, error })
+    }
+
+    /// The line the error is summed up by, for the history: the last one naming an error,
+    /// otherwise the last line
+    pub fn summary(&self) -> String {
+        let lines = || {
+            self.error
+                .lines()
+                .rev()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+        };
+        lines()
+            .find(|line| line.to_lowercase().contains("error"))
+            .or_else(|| lines().next())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Variables for the explain-error prompt template: command and error
+    pub fn vars(&self) -> HashMap<String, String> {
+        HashMap::from([
+            (
+                "command".to_string(),
+                self.command
+                    .clone()
+                    .unwrap_or_else(|| "unknown".to_string()),
+            ),
+            ("error".to_string(), self.error.clone()),
+        ])
+    }
+}
+
+/// The start and end of the text when it's over `max` characters, with how many lines
+/// were left out in between
+fn shorten(text: &str, max: usize) -> String {
+    let count = text.chars().count();
+    if count <= max {
+        return text.to_string();
+    }
+    let head: String = text.chars().take(HEAD_CHARS).collect();
+    let tail: String = text.chars().skip(count - (max - HEAD_CHARS)).collect();
+    let omitted = text
+        .chars()
+        .skip(HEAD_CHARS)
+        .take(count - max)
+        .filter(|&c| c == '\n')
+        .count();
+    format!("{}\n... ({} lines omitted)\n{}", head, omitted, tail)
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::io::{IsTerminal, Read};
+
+use anyhow::Result;
+use tracing::warn;
+
+use crate::messages::Message;
+
+/// Most characters of error output sent to the model, the end is kept as that's where errors are
+const MAX_ERROR_OUTPUT: usize = 4000;
+
+/// A command that failed, as passed to `ai fix`
+#[derive(Debug)]
+pub struct FailedCommand {
+    pub command: String,
+    pub status: Option<i32>,
+    /// stdout and stderr of the command, when piped into `ai fix`
+    pub error: String,
+}
+
+impl FailedCommand {
+    /// Uses the given command and status, falling back to the ones recorded by the
+    /// shell integration. Error output is read from stdin when it is piped.
+    pub fn gather(command: Option<String>, status: Option<i32>) -> Result<Self> {
+        let command =
+            last_command(command).ok_or_else(|| anyhow::anyhow!("{}", Message::NoFailedCommand))?;
+        let status = status.or_else(|| {
+            std::env::var("AI_LAST_STATUS")
+                .ok()
+                .and_then(|status| status.parse().ok())
+        });
+        let mut error = String::new();
+        if !std::io::stdin().is_terminal() {
+            std::io::stdin().read_to_string(&mut error)?;
+        }
+        if status == Some(0) && error.trim().is_empty() {
+            warn!("{} exited successfully, asking for a fix anyway", command);
+        }
+        Ok(Self {
+            command,
+            status,
+            error: tail(&error, MAX_ERROR_OUTPUT),
+        })
+    }
+
+    /// Variables for the fix prompt template: command, status and error
+    pub fn vars(&self) -> HashMap<String, String> {
+        HashMap::from([
+            ("command".to_string(), self.command.clone()),
+            (
+                "status".to_string(),
+                self.status
+                    .map(|status| status.to_string())
+                    .unwrap_or_else(|| "unknown".to_string()),
+            ),
+            (
+                "error".to_string(),
+                if self.error.trim().is_empty() {
+                    "(not captured)".to_string()
+                } else {
+                    self.error.trim_end().to_string()
+                },
+            ),
+        ])
+    }
+}
+
+/// The given command, otherwise the last one recorded by the shell integration
+pub fn last_command(command: Option<String>) -> Option<String> {
+    let given = |command: &String| !command.trim().is_empty();
+    command
+        .filter(given)
+        .or_else(|| std::env::var("AI_LAST_COMMAND").ok().filter(given))
+        .map(|command| command.trim().to_string())
+}
+
+/// The last `max` characters of the text
+fn tail(text: &str, max: usize) -> String {
+    let count = text.chars().count();
+    if count <= max {
+        return text.to_string();
+    }
+    text.chars().skip(count - max).collect()
+}
@@ -0,0 +1,680 @@
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use super::Grammar;
+
+/// The parts of a JSON schema that shape what can be generated. Formats, patterns and
+/// ranges aren't enforced while generating, the response is validated afterwards
+#[derive(Debug)]
+enum Schema {
+    Any,
+    Object {
+        properties: Vec<(String, Arc<Schema>)>,
+        required: Vec<String>,
+        /// Schema of keys not in `properties`, None when they aren't allowed
+        additional: Option<Arc<Schema>>,
+    },
+    Array {
+        items: Arc<Schema>,
+        min_items: usize,
+        max_items: Option<usize>,
+    },
+    String,
+    Number {
+        integer: bool,
+    },
+    /// One of these json texts, from `enum`, `const`, booleans and null
+    Literals(Vec<String>),
+    /// Type lists, `anyOf` and `oneOf`, picked by the first character of the value
+    OneOf(Vec<Arc<Schema>>),
+}
+
+impl Schema {
+    fn compile(schema: &Value) -> Arc<Schema> {
+        let Some(object) = schema.as_object() else {
+            return Arc::new(Schema::Any);
+        };
+        if let Some(values) = object.get("enum").and_then(Value::as_array) {
+            return Arc::new(Schema::Literals(
+                values.iter().map(Value::to_string).collect(),
+            ));
+        }
+        if let Some(value) = object.get("const") {
+            return Arc::new(Schema::Literals(vec![value.to_string()]));
+        }
+        if let Some(branches) = object
+            .get("anyOf")
+            .or_else(|| object.get("oneOf"))
+            .and_then(Value::as_array)
+        {
+            return Arc::new(Schema::OneOf(
+                branches.iter().map(Schema::compile).collect(),
+            ));
+        }
+        let types: Vec<&str> = match object.get("type") {
+            Some(Value::String(kind)) => vec![kind.as_str()],
+            Some(Value::Array(kinds)) => kinds.iter().filter_map(Value::as_str).collect(),
+            _ if object.contains_key("properties") => vec!["object"],
+            _ if object.contains_key("items") => vec!["array"],
+            _ => return Arc::new(Schema::Any),
+        };
+        let mut branches: Vec<Arc<Schema>> = types
+            .into_iter()
+            .map(|kind| Arc::new(Schema::of_type(kind, object)))
+            .collect();
+        match branches.len() {
+            1 => branches.remove(0),
+            _ => Arc::new(Schema::OneOf(branches)),
+        }
+    }
+
+    fn of_type(kind: &str, object: &serde_json::Map<String, Value>) -> Schema {
+        match kind {
+            "object" => Schema::Object {
+                properties: object
+                    .get("properties")
+                    .and_then(Value::as_object)
+                    .map(|properties| {
+                        properties
+                            .iter()
+                            .map(|(name, schema)| (name.clone(), Schema::compile(schema)))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                required: object
+                    .get("required")
+                    .and_then(Value::as_array)
+                    .map(|required| {
+                        required
+                            .iter()
+                            .filter_map(Value::as_str)
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                additional: match object.get("additionalProperties") {
+                    Some(Value::Bool(false)) => None,
+                    Some(schema @ Value::Object(_)) => Some(Schema::compile(schema)),
+                    _ => Some(Arc::new(Schema::Any)),
+                },
+            },
+            "array" => Schema::Array {
+                items: object
+                    .get("items")
+                    .map(Schema::compile)
+                    .unwrap_or_else(|| Arc::new(Schema::Any)),
+                min_items: object
+                    .get("minItems")
+                    .and_then(Value::as_u64)
+                    .unwrap_or_default() as usize,
+                max_items: object
+                    .get("maxItems")
+                    .and_then(Value::as_u64)
+                    .map(|max| max as usize),
+            },
+            "string" => Schema::String,
+            "integer" => Schema::Number { integer: true },
+            "number" => Schema::Number { integer: false },
+            "boolean" => Schema::Literals(vec!["true".to_string(), "false".to_string()]),
+            "null" => Schema::Literals(vec!["null".to_string()]),
+            _ => Schema::Any,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ObjectState {
+    /// After {, a key or }
+    KeyOrEnd,
+    /// After a comma, a key
+    Key,
+    /// After a key, the colon
+    Colon,
+    /// After a value, a comma or }
+    AfterValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NumberState {
+    Minus,
+    Zero,
+    Int,
+    Dot,
+    Fraction,
+    Exponent,
+    ExponentSign,
+    ExponentDigits,
+}
+
+impl NumberState {
+    fn next(self, c: char, integer: bool) -> Option<Self> {
+        use NumberState::*;
+        match (self, c) {
+            (Minus, '0') => Some(Zero),
+            (Minus, '1'..='9') => Some(Int),
+            (Int, '0'..='9') => Some(Int),
+            (Zero | Int, '.') if !integer => Some(Dot),
+            (Dot | Fraction, '0'..='9') => Some(Fraction),
+            (Zero | Int | Fraction, 'e' | 'E') if !integer => Some(Exponent),
+            (Exponent, '+' | '-') => Some(ExponentSign),
+            (Exponent | ExponentSign | ExponentDigits, '0'..='9') => Some(ExponentDigits),
+            _ => None,
+        }
+    }
+
+    fn is_complete(self) -> bool {
+        matches!(
+            self,
+            NumberState::Zero
+                | NumberState::Int
+                | NumberState::Fraction
+                | NumberState::ExponentDigits
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Backslash,
+    /// Hex digits of a \u escape still to come
+    Unicode(u8),
+}
+
+#[derive(Debug, Clone)]
+enum Frame {
+    /// Waiting for a value, skipping whitespace
+    Value(Arc<Schema>),
+    Object {
+        schema: Arc<Schema>,
+        seen: Vec<String>,
+        state: ObjectState,
+    },
+    Array {
+        schema: Arc<Schema>,
+        count: usize,
+        /// Nothing but whitespace since the [
+        empty: bool,
+    },
+    /// A string value, or an object key when `key` is set
+    String {
+        key: bool,
+        text: String,
+        escape: Escape,
+    },
+    Number {
+        integer: bool,
+        state: NumberState,
+    },
+    Literal {
+        options: Vec<String>,
+        text: String,
+    },
+}
+
+/// Accepts a single JSON value matching a schema: the right types, only the allowed keys
+/// with every required one, enum values and the array length limits
+#[derive(Debug, Clone)]
+pub struct JsonGrammar {
+    stack: Vec<Frame>,
+    finished: bool,
+}
+
+impl JsonGrammar {
+    pub fn new(schema: &Value) -> Self {
+        Self {
+            stack: vec![Frame::Value(Schema::compile(schema))],
+            finished: false,
+        }
+    }
+
+    /// Starts the value `c` begins, if the schema allows one that does
+    fn start(&mut self, schema: &Arc<Schema>, c: char) -> bool {
+        let frame = match (schema.as_ref(), c) {
+            (Schema::OneOf(branches), _) => {
+                return branches.iter().any(|branch| self.start(branch, c));
+            }
+            (Schema::Any, _) => {
+                let any = match c {
+                    '{' => Schema::Object {
+                        properties: Vec::new(),
+                        required: Vec::new(),
+                        additional: Some(Arc::new(Schema::Any)),
+                    },
+                    '[' => Schema::Array {
+                        items: Arc::new(Schema::Any),
+                        min_items: 0,
+                        max_items: None,
+                    },
+                    '"' => Schema::String,
+                    '-' | '0'..='9' => Schema::Number { integer: false },
+                    _ => Schema::Literals(["true", "false", "null"].map(str::to_string).to_vec()),
+                };
+                return self.start(&Arc::new(any), c);
+            }
+            (Schema::Object { .. }, '{') => Frame::Object {
+                schema: schema.clone(),
+                seen: Vec::new(),
+                state: ObjectState::KeyOrEnd,
+            },
+            (Schema::Array { .. }, '[') => Frame::Array {
+                schema: schema.clone(),
+                count: 0,
+                empty: true,
+            },
+            (Schema::String, '"') => Frame::String {
+                key: false,
+                text: String::new(),
+                escape: Escape::None,
+            },
+            (Schema::Number { integer }, '-' | '0'..='9') => Frame::Number {
+                integer: *integer,
+                state: match c {
+                    '-' => NumberState::Minus,
+                    '0' => NumberState::Zero,
+                    _ => NumberState::Int,
+                },
+            },
+            (Schema::Literals(options), _) => {
+                let options: Vec<String> = options
+                    .iter()
+                    .filter(|option| option.starts_with(c))
+                    .cloned()
+                    .collect();
+                if options.is_empty() {
+                    return false;
+                }
+                Frame::Literal {
+                    options,
+                    text: c.to_string(),
+                }
+            }
+            _ => return false,
+        };
+        self.stack.push(frame);
+        true
+    }
+
+    /// Pops the finished value and moves its parent on
+    fn value_done(&mut self) {
+        self.stack.pop();
+        match self.stack.last_mut() {
+            Some(Frame::Object { state, .. }) => *state = ObjectState::AfterValue,
+            Some(Frame::Array { count, empty, .. }) => {
+                *count += 1;
+                *empty = false;
+            }
+            _ => self.finished = self.stack.is_empty(),
+        }
+    }
+
+    /// The key names still allowed in the object, None when any key is
+    fn allowed_keys(&self) -> Option<Vec<String>> {
+        let Some(Frame::Object { schema, seen, .. }) = self.stack.iter().rev().nth(1) else {
+            return None;
+        };
+        let Schema::Object {
+            properties,
+            additional,
+            ..
+        } = schema.as_ref()
+        else {
+            return None;
+        };
+        if additional.is_some() {
+            return None;
+        }
+        Some(
+            properties
+                .iter()
+                .map(|(name, _)| name)
+                .filter(|name| !seen.contains(name))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Ends a number or literal that can't continue with `c`, so `c` belongs to the parent
+    fn end_open_value(&mut self) -> bool {
+        match self.stack.last() {
+            Some(Frame::Number { state, .. }) if state.is_complete() => {}
+            Some(Frame::Literal { options, text }) if options.contains(text) => {}
+            _ => return false,
+        }
+        self.value_done();
+        true
+    }
+}
+
+impl Grammar for JsonGrammar {
+    fn push(&mut self, c: char) -> bool {
+        if self.finished {
+            return false;
+        }
+        let allowed_keys = match self.stack.last() {
+            Some(Frame::String { key: true, .. }) => self.allowed_keys(),
+            _ => None,
+        };
+        let Some(top) = self.stack.last_mut() else {
+            return false;
+        };
+        match top {
+            Frame::Value(schema) => {
+                if c.is_whitespace() {
+                    return true;
+                }
+                let schema = schema.clone();
+                self.stack.pop();
+                if !self.start(&schema, c) {
+                    return false;
+                }
+                // an empty value position isn't left behind for the parent
+                true
+            }
+            Frame::String { key, text, escape } => {
+                match (*escape, c) {
+                    (Escape::Backslash, '"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't') => {
+                        *escape = Escape::None
+                    }
+                    (Escape::Backslash, 'u') => *escape = Escape::Unicode(4),
+                    (Escape::Backslash, _) => return false,
+                    (Escape::Unicode(left), _) if c.is_ascii_hexdigit() => {
+                        *escape = match left {
+                            1 => Escape::None,
+                            _ => Escape::Unicode(left - 1),
+                        }
+                    }
+                    (Escape::Unicode(_), _) => return false,
+                    // keys limited to the schema's properties are matched literally
+                    (Escape::None, '\\') if allowed_keys.is_some() => return false,
+                    (Escape::None, '\\') => *escape = Escape::Backslash,
+                    (Escape::None, '"') => {
+                        let is_key = *key;
+                        let name = std::mem::take(text);
+                        if let Some(allowed) = &allowed_keys {
+                            if !allowed.contains(&name) {
+                                return false;
+                            }
+                        }
+                        if !is_key {
+                            self.value_done();
+                            return true;
+                        }
+                        self.stack.pop();
+                        let Some(Frame::Object { seen, state, .. }) = self.stack.last_mut() else {
+                            return false;
+                        };
+                        if seen.contains(&name) {
+                            return false;
+                        }
+                        seen.push(name);
+                        *state = ObjectState::Colon;
+                        return true;
+                    }
+                    (Escape::None, c) if c.is_control() => return false,
+                    (Escape::None, c) => {
+                        text.push(c);
+                        if let Some(allowed) = &allowed_keys {
+                            return allowed.iter().any(|name| name.starts_with(text.as_str()));
+                        }
+                    }
+                }
+                true
+            }
+            Frame::Number { integer, state } => match state.next(c, *integer) {
+                Some(next) => {
+                    *state = next;
+                    true
+                }
+                None => self.end_open_value() && self.push(c),
+            },
+            Frame::Literal { options, text } => {
+                let mut extended = text.clone();
+                extended.push(c);
+                if options.iter().any(|option| option.starts_with(&extended)) {
+                    *text = extended;
+                    if options.len() == 1 && options[0] == *text {
+                        self.value_done();
+                    }
+                    true
+                } else {
+                    self.end_open_value() && self.push(c)
+                }
+            }
+            Frame::Object {
+                schema,
+                seen,
+                state,
+            } => {
+                if c.is_whitespace() {
+                    return true;
+                }
+                let Schema::Object {
+                    properties,
+                    required,
+                    additional,
+                } = schema.as_ref()
+                else {
+                    return false;
+                };
+                match (*state, c) {
+                    (ObjectState::KeyOrEnd | ObjectState::Key, '"') => {
+                        self.stack.push(Frame::String {
+                            key: true,
+                            text: String::new(),
+                            escape: Escape::None,
+                        });
+                        true
+                    }
+                    (ObjectState::KeyOrEnd | ObjectState::AfterValue, '}') => {
+                        if !required.iter().all(|name| seen.contains(name)) {
+                            return false;
+                        }
+                        self.value_done();
+                        true
+                    }
+                    (ObjectState::AfterValue, ',') => {
+                        // don't start a key when there is none left to write
+                        let more = additional.is_some()
+                            || properties.iter().any(|(name, _)| !seen.contains(name));
+                        *state = ObjectState::Key;
+                        more
+                    }
+                    (ObjectState::Colon, ':') => {
+                        let Some(key) = seen.last() else {
+                            return false;
+                        };
+                        let value = properties
+                            .iter()
+                            .find(|(name, _)| name == key)
+                            .map(|(_, schema)| schema.clone())
+                            .or_else(|| additional.clone());
+                        let Some(value) = value else {
+                            return false;
+                        };
+                        self.stack.push(Frame::Value(value));
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Frame::Array {
+                schema,
+                count,
+                empty,
+            } => {
+                if c.is_whitespace() {
+                    return true;
+                }
+                let Schema::Array {
+                    items,
+                    min_items,
+                    max_items,
+                } = schema.as_ref()
+                else {
+                    return false;
+                };
+                match c {
+                    ']' if *count >= *min_items => {
+                        self.value_done();
+                        true
+                    }
+                    ',' if !*empty => {
+                        if max_items.is_some_and(|max| *count >= max) {
+                            return false;
+                        }
+                        let items = items.clone();
+                        self.stack.push(Frame::Value(items));
+                        true
+                    }
+                    _ if *empty && max_items.is_none_or(|max| max > 0) => {
+                        let items = items.clone();
+                        *empty = false;
+                        self.start(&items, c)
+                    }
+                    _ => false,
+                }
+            }
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        let mut end = self.clone();
+        end.end_open_value();
+        end.finished
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Grammar> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Whether every character is accepted, and whether the text is then complete
+    fn parse(schema: Value, text: &str) -> (bool, bool) {
+        let mut grammar = JsonGrammar::new(&schema);
+        let accepted = text.chars().all(|c| grammar.push(c));
+        (accepted, accepted && grammar.is_complete())
+    }
+
+    fn complete(schema: Value, text: &str) -> bool {
+        parse(schema, text) == (true, true)
+    }
+
+    fn incomplete(schema: Value, text: &str) -> bool {
+        parse(schema, text) == (true, false)
+    }
+
+    fn rejected(schema: Value, text: &str) -> bool {
+        !parse(schema, text).0
+    }
+
+    fn command() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "command": {"type": "string"},
+                "risk": {"enum": ["low", "high"]},
+            },
+            "required": ["command"],
+            "additionalProperties": false,
+        })
+    }
+
+    #[test]
+    fn objects_need_the_required_keys_and_only_the_allowed_ones() {
+        assert!(complete(command(), r#"{"command": "ls", "risk": "low"}"#));
+        assert!(complete(command(), r#" { "command" : "ls -la" }"#));
+        assert!(incomplete(command(), r#"{"command": "ls""#));
+        assert!(rejected(command(), r#"{"risk": "low"}"#));
+        assert!(rejected(command(), r#"{"other": 1}"#));
+        assert!(rejected(command(), r#"{"command": "ls", "command": "ls"}"#));
+        assert!(rejected(command(), r#"{"command": 1}"#));
+    }
+
+    #[test]
+    fn enum_values_are_matched_whole() {
+        assert!(incomplete(command(), r#"{"command": "ls", "risk": "lo"#));
+        assert!(rejected(
+            command(),
+            r#"{"command": "ls", "risk": "medium"}"#
+        ));
+    }
+
+    #[test]
+    fn integers_have_no_fraction() {
+        let schema = json!({"type": "integer"});
+        assert!(complete(schema.clone(), "-12"));
+        assert!(rejected(schema.clone(), "1.5"));
+        assert!(rejected(schema, "01"));
+        let schema = json!({"type": "number"});
+        assert!(complete(schema.clone(), "1.5e-3"));
+        assert!(incomplete(schema.clone(), "1."));
+        assert!(incomplete(schema, "-"));
+    }
+
+    #[test]
+    fn arrays_keep_to_their_length_limits() {
+        let schema = json!({
+            "type": "array",
+            "items": {"type": "integer"},
+            "minItems": 1,
+            "maxItems": 2,
+        });
+        assert!(complete(schema.clone(), "[1, 2]"));
+        assert!(rejected(schema.clone(), "[]"));
+        assert!(rejected(schema.clone(), "[1, 2, 3]"));
+        assert!(rejected(schema, r#"["1"]"#));
+    }
+
+    #[test]
+    fn strings_take_escapes() {
+        let schema = json!({"type": "string"});
+        assert!(complete(schema.clone(), r#""a \"quoted\" \\ é line\n""#));
+        assert!(incomplete(schema.clone(), r#""\u00e"#));
+        assert!(rejected(schema.clone(), r#""\x""#));
+        assert!(rejected(schema, "\"a\nb\""));
+    }
+
+    #[test]
+    fn type_lists_and_any_of_accept_each_branch() {
+        let schema = json!({"type": ["string", "null"]});
+        assert!(complete(schema.clone(), r#""text""#));
+        assert!(complete(schema.clone(), "null"));
+        assert!(rejected(schema, "1"));
+        let schema = json!({"anyOf": [{"type": "boolean"}, {"type": "integer"}]});
+        assert!(complete(schema.clone(), "true"));
+        assert!(complete(schema.clone(), "42"));
+        assert!(rejected(schema, r#""no""#));
+    }
+
+    #[test]
+    fn any_value_is_accepted_without_a_schema() {
+        assert!(complete(
+            json!({}),
+            r#"{"a": [1, "b", {"c": null}], "d": false}"#
+        ));
+        assert!(rejected(json!({}), r#"{"a": nope}"#));
+    }
+
+    #[test]
+    fn nothing_is_accepted_after_the_value() {
+        let mut grammar = JsonGrammar::new(&command());
+        assert!(r#"{"command": "ls"}"#.chars().all(|c| grammar.push(c)));
+        assert!(grammar.is_finished());
+        assert!(!grammar.push(' '));
+        let mut number = JsonGrammar::new(&json!({"type": "integer"}));
+        assert!("12".chars().all(|c| number.push(c)));
+        assert!(number.is_complete() && !number.is_finished());
+    }
+}
@@ -0,0 +1,225 @@
+pub mod json;
+pub mod shell;
+
+use anyhow::Result;
+use candle_core::Tensor;
+use candle_transformers::generation::LogitsProcessor;
+use tracing::warn;
+
+use crate::messages::Message;
+
+pub use json::JsonGrammar;
+pub use shell::ShellGrammar;
+
+/// Decides character by character whether generated text can still become valid output,
+/// used to constrain the local models' sampling
+pub trait Grammar: Send {
+    /// Feeds the next character, false when the text can no longer become valid
+    fn push(&mut self, c: char) -> bool;
+
+    /// Whether the text so far is valid as it is, so generation may stop here
+    fn is_complete(&self) -> bool;
+
+    /// Whether the output is done and nothing more may follow
+    fn is_finished(&self) -> bool;
+
+    fn boxed_clone(&self) -> Box<dyn Grammar>;
+}
+
+/// The text of every token in a trie, so checking the whole vocabulary against a grammar
+/// feeds each prefix the tokens share once instead of every token's text from the start
+pub struct Vocab {
+    texts: Vec<String>,
+    nodes: Vec<Node>,
+}
+
+#[derive(Default)]
+struct Node {
+    children: Vec<(char, usize)>,
+    /// Tokens whose text ends here
+    tokens: Vec<u32>,
+}
+
+impl Vocab {
+    /// The vocabulary from the text of each token id, in order
+    pub fn new(texts: Vec<String>) -> Self {
+        let mut nodes = vec![Node::default()];
+        for (token, text) in texts.iter().enumerate() {
+            let mut node = 0;
+            for c in text.chars() {
+                node = match nodes[node].children.iter().find(|(child, _)| *child == c) {
+                    Some(&(_, child)) => child,
+                    None => {
+                        nodes.push(Node::default());
+                        let child = nodes.len() - 1;
+                        nodes[node].children.push((c, child));
+                        child
+                    }
+                };
+            }
+            nodes[node].tokens.push(token as u32);
+        }
+        Self { texts, nodes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.texts.is_empty()
+    }
+
+    fn text(&self, token: u32) -> Option<&str> {
+        self.texts.get(token as usize).map(String::as_str)
+    }
+
+    /// Every token whose whole text the grammar accepts next. Tokens without text aren't
+    fn allowed(&self, grammar: &dyn Grammar) -> Vec<u32> {
+        let mut allowed = Vec::new();
+        self.collect(0, grammar, &mut allowed);
+        allowed
+    }
+
+    fn collect(&self, node: usize, grammar: &dyn Grammar, allowed: &mut Vec<u32>) {
+        for &(c, child) in self.nodes[node].children.iter() {
+            let mut grammar = grammar.boxed_clone();
+            if grammar.push(c) {
+                allowed.extend_from_slice(&self.nodes[child].tokens);
+                self.collect(child, grammar.as_ref(), allowed);
+            }
+        }
+    }
+}
+
+/// A grammar applied to one generation, tracking the text generated so far
+pub struct Constrained {
+    grammar: Box<dyn Grammar>,
+    text: String,
+    /// Length of `text` when it last was complete
+    complete_len: usize,
+}
+
+impl Constrained {
+    pub fn new(grammar: Box<dyn Grammar>) -> Self {
+        Self {
+            grammar,
+            text: String::new(),
+            complete_len: 0,
+        }
+    }
+
+    /// Whether the grammar accepts the token next. The stop tokens are only allowed once
+    /// the output is complete, tokens without text never are
+    fn allows(&self, vocab: &Vocab, token: u32, stop_tokens: &[u32]) -> bool {
+        if stop_tokens.contains(&token) {
+            return self.grammar.is_complete();
+        }
+        let Some(text) = vocab.text(token).filter(|text| !text.is_empty()) else {
+            return false;
+        };
+        let mut grammar = self.grammar.boxed_clone();
+        text.chars().all(|c| grammar.push(c))
+    }
+
+    /// Samples a token the grammar accepts. The sampled token is usually fine, only when
+    /// it isn't are the tokens the grammar accepts looked up and the rest masked out
+    pub fn sample(
+        &self,
+        logits_processor: &mut LogitsProcessor,
+        logits: &Tensor,
+        vocab: &Vocab,
+        stop_tokens: &[u32],
+    ) -> Result<u32> {
+        let token = logits_processor.sample(logits)?;
+        if self.allows(vocab, token, stop_tokens) {
+            return Ok(token);
+        }
+        let mut allowed = vocab.allowed(self.grammar.as_ref());
+        // their text may fit, but they end the output
+        allowed.retain(|token| !stop_tokens.contains(token));
+        if self.grammar.is_complete() {
+            allowed.extend_from_slice(stop_tokens);
+        }
+        if allowed.is_empty() {
+            anyhow::bail!("{}", Message::GrammarDeadEnd);
+        }
+        let values = logits.to_vec1::<f32>()?;
+        let mut masked = vec![f32::NEG_INFINITY; values.len()];
+        for token in allowed {
+            if let Some(value) = values.get(token as usize) {
+                masked[token as usize] = *value;
+            }
+        }
+        Ok(logits_processor.sample(&Tensor::new(masked, logits.device())?)?)
+    }
+
+    /// Adds the text a sampled token decoded to, from the token stream so characters
+    /// spanning tokens and the spaces sentencepiece puts in front of words come out right
+    pub fn push(&mut self, text: &str) {
+        for c in text.chars() {
+            self.grammar.push(c);
+        }
+        self.text.push_str(text);
+        if self.grammar.is_complete() {
+            self.complete_len = self.text.len();
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.grammar.is_finished()
+    }
+
+    /// The generated text, cut back to the last point it was complete when generation
+    /// stopped early, e.g. at the token limit inside a quoted string
+    pub fn into_output(mut self) -> String {
+        if self.complete_len < self.text.len() {
+            warn!(
+                "Generation stopped before the output was complete, dropping {:?}",
+                &self.text[self.complete_len..]
+            );
+            self.text.truncate(self.complete_len);
+        }
+        self.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts runs of the letters a and b
+    #[derive(Clone)]
+    struct AB;
+
+    impl Grammar for AB {
+        fn push(&mut self, c: char) -> bool {
+            matches!(c, 'a' | 'b')
+        }
+
+        fn is_complete(&self) -> bool {
+            true
+        }
+
+        fn is_finished(&self) -> bool {
+            false
+        }
+
+        fn boxed_clone(&self) -> Box<dyn Grammar> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn vocab_allows_the_tokens_whose_whole_text_fits() {
+        let texts = ["a", "ab", "abc", "b", "", "ba", "c", "aab"];
+        let vocab = Vocab::new(texts.iter().map(|text| text.to_string()).collect());
+        let mut allowed = vocab.allowed(&AB);
+        allowed.sort();
+        assert_eq!(allowed, vec![0, 1, 3, 5, 7]);
+    }
+
+    #[test]
+    fn constrained_output_is_cut_back_to_the_last_complete_point() {
+        let mut constrained = Constrained::new(Box::new(JsonGrammar::new(&serde_json::json!({}))));
+        constrained.push("12");
+        constrained.push("3.");
+        assert_eq!(constrained.into_output(), "12");
+    }
+}
@@ -0,0 +1,508 @@
+use super::Grammar;
+
+/// What the parser is inside of
+#[derive(Debug, Clone, PartialEq)]
+enum Frame {
+    /// '...'
+    Single,
+    /// "..."
+    Double,
+    /// `...`
+    Backtick,
+    /// ${...}
+    Parameter,
+    /// ( ... ) or $( ... ), holding the enclosing command to return to
+    Subshell(Box<Command>),
+    /// Waiting for the word closing a compound command: fi, done, esac or }
+    Keyword(&'static str),
+}
+
+/// The command being read
+#[derive(Debug, Clone, PartialEq)]
+struct Command {
+    /// No word since the start or the last operator
+    empty: bool,
+    /// The last operator was |, && or || and a command has to follow
+    dangling: bool,
+    /// A redirection is waiting for its target
+    redirect: bool,
+    /// The next word is a command name, so it can be a keyword
+    command_position: bool,
+    /// Reading the words after `for` or `case`, which aren't commands
+    in_header: bool,
+    /// The word being read, to recognize keywords
+    word: String,
+    in_word: bool,
+    /// The word has quotes, escapes or expansions, so it isn't a keyword
+    word_quoted: bool,
+}
+
+impl Command {
+    fn new() -> Self {
+        Self {
+            empty: true,
+            dangling: false,
+            redirect: false,
+            command_position: true,
+            in_header: false,
+            word: String::new(),
+            in_word: false,
+            word_quoted: false,
+        }
+    }
+}
+
+/// Accepts a single POSIX shell command line: quotes, escapes, command substitutions
+/// and parameter expansions are closed, `if`/`for`/`while`/`case`/`{` have their closing
+/// keyword, pipes and `&&`/`||` are followed by a command and redirections have a target.
+/// A newline at the top level ends the command. Here documents and arithmetic aren't
+/// understood beyond their parentheses
+#[derive(Debug, Clone)]
+pub struct ShellGrammar {
+    stack: Vec<Frame>,
+    command: Command,
+    escaped: bool,
+    /// The previous character was a $ that can start $( or ${
+    dollar: bool,
+    /// A |, & or ; that is an operator of its own unless the next character doubles it
+    operator: Option<char>,
+    previous: char,
+    comment: bool,
+    /// Something other than whitespace was read
+    started: bool,
+    finished: bool,
+}
+
+impl Default for ShellGrammar {
+    fn default() -> Self {
+        Self {
+            stack: Vec::new(),
+            command: Command::new(),
+            escaped: false,
+            dollar: false,
+            operator: None,
+            previous: ' ',
+            comment: false,
+            started: false,
+            finished: false,
+        }
+    }
+}
+
+impl ShellGrammar {
+    fn start_word(&mut self, quoted: bool) {
+        self.command.in_word = true;
+        self.command.word_quoted |= quoted;
+    }
+
+    fn open(&mut self, frame: Frame) {
+        self.start_word(true);
+        self.stack.push(frame);
+    }
+
+    /// Enters a subshell or command substitution, with a command of its own
+    fn open_subshell(&mut self) {
+        let outer = std::mem::replace(&mut self.command, Command::new());
+        self.stack.push(Frame::Subshell(Box::new(outer)));
+    }
+
+    fn end_word(&mut self) -> bool {
+        if !self.command.in_word {
+            return true;
+        }
+        let word = std::mem::take(&mut self.command.word);
+        let quoted = std::mem::take(&mut self.command.word_quoted);
+        self.command.in_word = false;
+        if self.command.redirect {
+            self.command.redirect = false;
+            return true;
+        }
+        if self.command.command_position && !quoted {
+            let closer = match word.as_str() {
+                "if" => Some("fi"),
+                "while" | "until" | "for" => Some("done"),
+                "case" => Some("esac"),
+                "{" => Some("}"),
+                _ => None,
+            };
+            if let Some(closer) = closer {
+                self.stack.push(Frame::Keyword(closer));
+                if matches!(word.as_str(), "for" | "case") {
+                    self.command.in_header = true;
+                    self.command.command_position = false;
+                    self.command.empty = false;
+                }
+                return true;
+            }
+            match word.as_str() {
+                "then" | "do" | "else" | "elif" | "!" => return true,
+                "fi" | "done" | "esac" | "}" => {
+                    if !matches!(self.stack.last(), Some(Frame::Keyword(closer)) if *closer == word)
+                    {
+                        return false;
+                    }
+                    self.stack.pop();
+                    self.command.empty = false;
+                    self.command.dangling = false;
+                    self.command.command_position = false;
+                    return true;
+                }
+                _ => {}
+            }
+        }
+        if self.command.in_header
+            && word == "in"
+            && self.stack.last() == Some(&Frame::Keyword("esac"))
+        {
+            self.command.in_header = false;
+            return true;
+        }
+        self.command.empty = false;
+        self.command.dangling = false;
+        self.command.command_position = false;
+        true
+    }
+
+    /// Applies a |, &, ;, ||, && or ;;
+    fn operator(&mut self, operator: char, doubled: bool) -> bool {
+        let command = &mut self.command;
+        if command.redirect {
+            return false;
+        }
+        match (operator, doubled) {
+            ('|', _) | ('&', true) => {
+                if command.empty {
+                    return false;
+                }
+                command.dangling = true;
+            }
+            (';', true) => {
+                // ends an item of a case
+                if self.stack.last() != Some(&Frame::Keyword("esac")) {
+                    return false;
+                }
+            }
+            _ => {
+                if command.empty || command.dangling {
+                    return false;
+                }
+                command.in_header = false;
+            }
+        }
+        command.empty = true;
+        command.command_position = true;
+        true
+    }
+
+    fn in_command(&self) -> bool {
+        matches!(
+            self.stack.last(),
+            None | Some(Frame::Subshell(_)) | Some(Frame::Keyword(_))
+        )
+    }
+
+    fn push_command(&mut self, c: char, dollar: bool) -> bool {
+        if self.comment {
+            if c != '\n' {
+                return true;
+            }
+            self.comment = false;
+        }
+        if let Some(operator) = self.operator.take() {
+            if c == operator {
+                return self.operator(operator, true);
+            }
+            if !self.operator(operator, false) {
+                return false;
+            }
+        }
+        match c {
+            ' ' | '\t' => {
+                if !self.end_word() {
+                    return false;
+                }
+            }
+            '\n' => {
+                if !self.end_word() {
+                    return false;
+                }
+                if self.started && !self.command.dangling {
+                    if self.command.redirect {
+                        return false;
+                    }
+                    if self.stack.is_empty() {
+                        self.finished = true;
+                    } else if !self.command.empty || self.command.in_header {
+                        // a separator like ;
+                        self.command.in_header = false;
+                        self.command.empty = true;
+                        self.command.command_position = true;
+                    }
+                }
+            }
+            '|' | '&' if matches!(self.previous, '>' | '<') => {
+                // part of a redirection such as 2>&1 or >|
+            }
+            '|' | '&' | ';' => {
+                if !self.end_word() {
+                    return false;
+                }
+                self.operator = Some(c);
+            }
+            '<' | '>' => {
+                if !self.end_word() {
+                    return false;
+                }
+                if self.command.redirect && !matches!(self.previous, '<' | '>') {
+                    return false;
+                }
+                self.command.redirect = true;
+            }
+            '(' if dollar => self.open_subshell(),
+            '(' if !self.command.in_word && self.command.command_position => self.open_subshell(),
+            '(' => return false,
+            ')' => {
+                if !self.end_word() {
+                    return false;
+                }
+                match self.stack.last() {
+                    Some(Frame::Subshell(_)) => {
+                        if self.command.dangling || self.command.redirect {
+                            return false;
+                        }
+                        let Some(Frame::Subshell(outer)) = self.stack.pop() else {
+                            unreachable!()
+                        };
+                        self.command = *outer;
+                        // the subshell or substitution is a word of the enclosing command
+                        self.start_word(true);
+                    }
+                    // the end of a case pattern
+                    Some(Frame::Keyword("esac")) if !self.command.in_header => {
+                        self.command.empty = true;
+                        self.command.command_position = true;
+                    }
+                    _ => return false,
+                }
+            }
+            '\'' => self.open(Frame::Single),
+            '"' => self.open(Frame::Double),
+            '`' => self.open(Frame::Backtick),
+            '{' if dollar => self.open(Frame::Parameter),
+            '\\' => {
+                self.escaped = true;
+            }
+            '$' => {
+                self.dollar = true;
+                self.start_word(true);
+            }
+            '#' if !self.command.in_word => self.comment = true,
+            _ => {
+                self.start_word(false);
+                self.command.word.push(c);
+            }
+        }
+        self.previous = c;
+        true
+    }
+}
+
+impl Grammar for ShellGrammar {
+    fn push(&mut self, c: char) -> bool {
+        if self.finished {
+            return false;
+        }
+        let dollar = std::mem::take(&mut self.dollar);
+        if self.escaped {
+            self.escaped = false;
+            // an escaped newline continues the line
+            if c != '\n' {
+                self.start_word(true);
+            }
+            self.started = true;
+            return true;
+        }
+        if !c.is_whitespace() {
+            self.started = true;
+        }
+        match self.stack.last() {
+            Some(Frame::Single) => {
+                if c == '\'' {
+                    self.stack.pop();
+                }
+                true
+            }
+            Some(Frame::Double) => {
+                match c {
+                    '"' => {
+                        self.stack.pop();
+                    }
+                    '\\' => self.escaped = true,
+                    '`' => self.stack.push(Frame::Backtick),
+                    '$' => self.dollar = true,
+                    '(' if dollar => self.open_subshell(),
+                    '{' if dollar => self.stack.push(Frame::Parameter),
+                    _ => {}
+                }
+                true
+            }
+            Some(Frame::Backtick) => {
+                match c {
+                    '`' => {
+                        self.stack.pop();
+                    }
+                    '\\' => self.escaped = true,
+                    _ => {}
+                }
+                true
+            }
+            Some(Frame::Parameter) => {
+                match c {
+                    '}' => {
+                        self.stack.pop();
+                    }
+                    '\'' => self.stack.push(Frame::Single),
+                    '"' => self.stack.push(Frame::Double),
+                    _ => {}
+                }
+                true
+            }
+            _ => {
+                debug_assert!(self.in_command());
+                self.push_command(c, dollar)
+            }
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        let mut end = self.clone();
+        if let Some(operator) = end.operator.take() {
+            if !end.operator(operator, false) {
+                return false;
+            }
+        }
+        if !end.end_word() {
+            return false;
+        }
+        end.started
+            && !end.escaped
+            && end.stack.is_empty()
+            && !end.command.dangling
+            && !end.command.redirect
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Grammar> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether every character is accepted, and whether the text is then complete
+    fn parse(text: &str) -> (bool, bool) {
+        let mut grammar = ShellGrammar::default();
+        let accepted = text.chars().all(|c| grammar.push(c));
+        (accepted, accepted && grammar.is_complete())
+    }
+
+    fn complete(text: &str) -> bool {
+        parse(text) == (true, true)
+    }
+
+    fn incomplete(text: &str) -> bool {
+        parse(text) == (true, false)
+    }
+
+    fn rejected(text: &str) -> bool {
+        !parse(text).0
+    }
+
+    #[test]
+    fn accepts_pipelines_and_lists() {
+        assert!(complete("ls -la | grep foo"));
+        assert!(complete("make && make install || echo failed"));
+        assert!(complete("sleep 1 & wait; echo done"));
+        assert!(incomplete("ls |"));
+        assert!(incomplete("make &&"));
+        assert!(rejected("| ls"));
+        assert!(rejected("ls && && pwd"));
+        assert!(rejected("ls ; ; pwd"));
+    }
+
+    #[test]
+    fn quotes_and_expansions_have_to_close() {
+        assert!(complete(
+            r#"echo 'a | b' "c $(date +%s) ${HOME:-/}" `id -u`"#
+        ));
+        assert!(complete(r#"echo "it's" \"quoted\""#));
+        assert!(incomplete("echo 'unterminated"));
+        assert!(incomplete("echo \"$(date"));
+        assert!(incomplete("echo ${HOME"));
+        assert!(incomplete("echo trailing\\"));
+    }
+
+    #[test]
+    fn compound_commands_need_their_closing_keyword() {
+        assert!(complete("if true; then echo yes; else echo no; fi"));
+        assert!(complete("for f in *.txt; do echo \"$f\"; done"));
+        assert!(complete("while read -r line; do echo $line; done < file"));
+        assert!(complete("case $1 in a) echo a;; *) echo other;; esac"));
+        assert!(complete("{ echo a; echo b; } > out"));
+        assert!(incomplete("if true; then echo yes"));
+        assert!(incomplete("for f in *; do echo $f"));
+        // a keyword is only known once the word ends
+        assert!(incomplete("fi"));
+        assert!(rejected("fi "));
+        assert!(rejected("if true; then echo; done "));
+    }
+
+    #[test]
+    fn keywords_only_count_in_command_position() {
+        assert!(complete("echo if then fi"));
+        assert!(complete("echo 'fi'"));
+    }
+
+    #[test]
+    fn subshells_close_before_the_end() {
+        assert!(complete("(cd /tmp && ls) | wc -l"));
+        assert!(incomplete("(cd /tmp && ls"));
+        assert!(rejected("ls)"));
+        assert!(rejected("echo a(b"));
+    }
+
+    #[test]
+    fn redirections_need_a_target() {
+        assert!(complete("cmd > out.txt 2>&1"));
+        assert!(complete("cmd >> log < in"));
+        assert!(incomplete("cmd >"));
+        assert!(rejected("cmd > | wc"));
+    }
+
+    #[test]
+    fn a_newline_ends_the_command() {
+        let mut grammar = ShellGrammar::default();
+        assert!("ls -l\n".chars().all(|c| grammar.push(c)));
+        assert!(grammar.is_finished());
+        assert!(!grammar.push('x'));
+        // but not after an escape, a dangling operator or inside quotes
+        for text in ["ls \\\n", "ls |\n", "echo 'a\n"] {
+            let mut grammar = ShellGrammar::default();
+            assert!(text.chars().all(|c| grammar.push(c)));
+            assert!(!grammar.is_finished());
+            assert!(grammar.push('x'));
+        }
+    }
+
+    #[test]
+    fn empty_and_comment_only_output() {
+        assert!(incomplete(""));
+        assert!(incomplete("   "));
+        assert!(complete("ls # list the files"));
+    }
+}
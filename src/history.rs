@@ -0,0 +1,388 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::budget;
+use crate::encryption;
+use crate::messages::Message;
+use crate::settings::{config_dir, Settings};
+
+/// Thumbs up/down feedback on a generated command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Feedback {
+    Up,
+    Down,
+}
+
+/// Output formats for `ai history export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// The raw history entries, one json object per line
+    Jsonl,
+    /// Instruction tuning pairs of thumbs-up generations, one {"prompt", "completion"} per line
+    Finetune,
+    /// A spreadsheet of id, time, backend, feedback, prompt and command
+    Csv,
+    /// A markdown table of the same columns, for sharing in docs or chat
+    Markdown,
+}
+
+/// A single generation saved to the history file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: u64,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub backend: String,
+    pub prompt: String,
+    pub response: String,
+    #[serde(default)]
+    pub feedback: Option<Feedback>,
+    /// Settings the generation ran with, missing for entries from older versions
+    #[serde(default)]
+    pub params: Option<GenerationParams>,
+}
+
+/// The settings a generation ran with, so it can be replayed with --seed-from
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenerationParams {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub seed: Option<u64>,
+    pub sample_len: Option<usize>,
+    /// Bedrock model id or the local model's hf repo
+    pub model_id: Option<String>,
+}
+
+impl GenerationParams {
+    /// Captures the settings the given backend uses
+    pub fn from_settings(settings: &Settings, backend: &str) -> Self {
+        match backend {
+            "local" => {
+                let local = &settings.local_model_config;
+                Self {
+                    temperature: local.temperature,
+                    top_p: local.top_p,
+                    seed: Some(local.seed),
+                    sample_len: Some(local.sample_len),
+                    model_id: local.model_id.clone(),
+                }
+            }
+            "bedrock" => {
+                let bedrock = &settings.bedrock_model_config;
+                Self {
+                    temperature: bedrock.temperature,
+                    top_p: bedrock.top_p,
+                    sample_len: bedrock.max_tokens.map(|max| max as usize),
+                    model_id: Some(settings.aws_settings.model_id.clone()),
+                    ..Self::default()
+                }
+            }
+            _ => Self::default(),
+        }
+    }
+
+    /// Overrides the settings with the recorded values
+    pub fn apply(&self, settings: &mut Settings, backend: &str) {
+        match backend {
+            "local" => {
+                let local = &mut settings.local_model_config;
+                if self.temperature.is_some() {
+                    local.temperature = self.temperature;
+                }
+                if self.top_p.is_some() {
+                    local.top_p = self.top_p;
+                }
+                if let Some(seed) = self.seed {
+                    local.seed = seed;
+                }
+                if let Some(sample_len) = self.sample_len {
+                    local.sample_len = sample_len;
+                }
+                if self.model_id.is_some() {
+                    local.model_id = self.model_id.clone();
+                }
+            }
+            "bedrock" => {
+                let bedrock = &mut settings.bedrock_model_config;
+                if self.temperature.is_some() {
+                    bedrock.temperature = self.temperature;
+                }
+                if self.top_p.is_some() {
+                    bedrock.top_p = self.top_p;
+                }
+                if let Some(sample_len) = self.sample_len {
+                    bedrock.max_tokens = Some(sample_len as u32);
+                }
+                if let Some(model_id) = &self.model_id {
+                    settings.aws_settings.model_id = model_id.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Builds the prompt for a replay of `entry`, the new instruction refines the previous request.
+/// Without an instruction the original prompt is replayed as is.
+pub fn follow_up(entry: &HistoryEntry, instruction: &str) -> String {
+    if instruction.trim().is_empty() {
+        return entry.prompt.clone();
+    }
+    format!(
+        "Previous request: {}\nPrevious command: {}\n\nChange the previous command as follows: {}",
+        entry.prompt,
+        entry.response.trim(),
+        instruction
+    )
+}
+
+/// How well `query` fuzzy matches `text`, None when its characters don't all appear in
+/// order. Case is ignored, consecutive characters and ones starting a word score higher
+/// and gaps cost a little, so `gco` ranks `git checkout` above `go doc`
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut text = text.chars().flat_map(char::to_lowercase).peekable();
+    let mut previous: Option<char> = None;
+    let mut consecutive = false;
+    for wanted in query.chars().flat_map(char::to_lowercase) {
+        if wanted.is_whitespace() {
+            continue;
+        }
+        let mut gap = 0;
+        loop {
+            let c = text.next()?;
+            let word_start = previous.is_none_or(|p| !p.is_alphanumeric());
+            previous = Some(c);
+            if c == wanted {
+                score += 1;
+                if consecutive && gap == 0 {
+                    score += 4;
+                }
+                if word_start {
+                    score += 3;
+                }
+                score -= gap.min(3);
+                consecutive = true;
+                break;
+            }
+            gap += 1;
+            consecutive &= gap == 0;
+        }
+    }
+    Some(score)
+}
+
+/// The entries whose prompt or command fuzzy match the query, best first and the newest
+/// first among equally good ones. An empty query lists them all, newest first
+pub fn search<'a>(entries: &'a [HistoryEntry], query: &str) -> Vec<&'a HistoryEntry> {
+    let mut matches: Vec<(i64, &HistoryEntry)> = entries
+        .iter()
+        .rev()
+        .filter_map(|entry| {
+            let prompt = fuzzy_score(query, &entry.prompt);
+            let response = fuzzy_score(query, &entry.response);
+            prompt.max(response).map(|score| (score, entry))
+        })
+        .collect();
+    // stable, so newer entries stay ahead of older ones with the same score
+    matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    matches.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// Columns of the csv and markdown exports
+const EXPORT_COLUMNS: [&str; 6] = ["id", "time", "backend", "feedback", "prompt", "command"];
+
+impl HistoryEntry {
+    /// The entry's values for `EXPORT_COLUMNS`, the time in UTC
+    fn columns(&self) -> [String; 6] {
+        let time = budget::utc_time(self.timestamp);
+        let feedback = match self.feedback {
+            Some(Feedback::Up) => "up",
+            Some(Feedback::Down) => "down",
+            None => "",
+        };
+        [
+            self.id.to_string(),
+            time,
+            self.backend.clone(),
+            feedback.to_string(),
+            self.prompt.clone(),
+            self.response.trim().to_string(),
+        ]
+    }
+}
+
+/// Escapes a table cell: a pipe would end it and a newline the row
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', "<br>")
+}
+
+/// An instruction tuning example produced by the finetune export
+#[derive(Debug, Serialize)]
+struct FinetuneExample<'a> {
+    prompt: &'a str,
+    completion: &'a str,
+}
+
+/// Append only jsonl store of past generations, ~/.config/ai/history.jsonl
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn open() -> Result<Self> {
+        Ok(Self::new(config_dir().join("history.jsonl")))
+    }
+
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Reads all entries, oldest first
+    pub fn entries(&self) -> Result<Vec<HistoryEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let contents = encryption::read(&self.path)
+            .with_context(|| format!("Unable to open history file {:?}", self.path))?;
+        let mut entries = Vec::new();
+        for line in contents.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(serde_json::from_str(line)?);
+        }
+        Ok(entries)
+    }
+
+    /// Looks up an entry by id
+    pub fn get(&self, id: u64) -> Result<HistoryEntry> {
+        self.entries()?
+            .into_iter()
+            .find(|e| e.id == id)
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::NoHistoryEntry(id)))
+    }
+
+    /// Appends a new generation and returns its id
+    pub fn record(
+        &self,
+        backend: &str,
+        prompt: &str,
+        response: &str,
+        params: GenerationParams,
+    ) -> Result<u64> {
+        let entries = self.entries()?;
+        let id = entries.last().map(|e| e.id + 1).unwrap_or(1);
+        let entry = HistoryEntry {
+            id,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            backend: backend.to_string(),
+            prompt: prompt.to_string(),
+            response: response.to_string(),
+            feedback: None,
+            params: Some(params),
+        };
+        encryption::append(&self.path, &format!("{}\n", serde_json::to_string(&entry)?))
+            .with_context(|| format!("Unable to write history file {:?}", self.path))?;
+        Ok(id)
+    }
+
+    /// Sets the feedback on an entry, rewriting the history file
+    pub fn rate(&self, id: u64, feedback: Feedback) -> Result<()> {
+        let mut entries = self.entries()?;
+        let entry = entries
+            .iter_mut()
+            .find(|e| e.id == id)
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::NoHistoryEntry(id)))?;
+        entry.feedback = Some(feedback);
+        self.write_all(&entries)
+    }
+
+    fn write_all(&self, entries: &[HistoryEntry]) -> Result<()> {
+        let mut contents = String::new();
+        for entry in entries {
+            contents.push_str(&serde_json::to_string(entry)?);
+            contents.push('\n');
+        }
+        encryption::write(&self.path, &contents)
+            .with_context(|| format!("Unable to write history file {:?}", self.path))
+    }
+
+    /// Writes the history in the given format
+    pub fn export<W: Write>(&self, format: ExportFormat, out: &mut W) -> Result<()> {
+        let entries = self.entries()?;
+        match format {
+            ExportFormat::Jsonl => {
+                for entry in &entries {
+                    writeln!(out, "{}", serde_json::to_string(entry)?)?
+                }
+            }
+            ExportFormat::Finetune => {
+                for entry in entries.iter().filter(|e| e.feedback == Some(Feedback::Up)) {
+                    let example = FinetuneExample {
+                        prompt: &entry.prompt,
+                        completion: entry.response.trim(),
+                    };
+                    writeln!(out, "{}", serde_json::to_string(&example)?)?
+                }
+            }
+            ExportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(out);
+                writer.write_record(EXPORT_COLUMNS)?;
+                for entry in &entries {
+                    writer.write_record(entry.columns())?;
+                }
+                writer.flush()?;
+            }
+            ExportFormat::Markdown => {
+                writeln!(out, "| {} |", EXPORT_COLUMNS.join(" | "))?;
+                writeln!(out, "|{}", "---|".repeat(EXPORT_COLUMNS.len()))?;
+                for entry in &entries {
+                    let cells: Vec<String> =
+                        entry.columns().iter().map(|c| markdown_cell(c)).collect();
+                    writeln!(out, "| {} |", cells.join(" | "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the entries of a jsonl export, numbered after the existing ones. Entries
+    /// already in the history, with the same time, prompt and command, are skipped so a
+    /// backup imported twice isn't duplicated. Returns how many were imported and skipped
+    pub fn import(&self, path: &Path) -> Result<(usize, usize)> {
+        if !path.exists() {
+            anyhow::bail!("{}", Message::NoHistoryExport(&format!("{:?}", path)));
+        }
+        let incoming = Self::new(path.to_path_buf())
+            .entries()
+            .with_context(|| format!("{:?} isn't a jsonl history export", path))?;
+        let mut entries = self.entries()?;
+        let existing = entries.len();
+        // appended in order, `record` numbers new generations after the last entry
+        let mut id = entries.iter().map(|e| e.id).max().unwrap_or(0);
+        let mut skipped = 0;
+        for mut entry in incoming {
+            let duplicate = entries.iter().any(|e| {
+                e.timestamp == entry.timestamp
+                    && e.prompt == entry.prompt
+                    && e.response == entry.response
+            });
+            if duplicate {
+                skipped += 1;
+                continue;
+            }
+            id += 1;
+            entry.id = id;
+            entries.push(entry);
+        }
+        self.write_all(&entries)?;
+        Ok((entries.len() - existing, skipped))
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::{IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+use crate::interrupt;
+use crate::messages::Message;
+
+/// Reads the prompt from a file, or from stdin when the path is `-`. When the prompt is a
+/// file and something is piped in as well, the piped text follows the prompt as its input,
+/// e.g. `git log | ai -f release-notes.txt`
+pub fn read_prompt_file(path: &Path) -> Result<String> {
+    let mut stdin = String::new();
+    if path == Path::new("-") {
+        std::io::stdin()
+            .read_to_string(&mut stdin)
+            .context("Unable to read the prompt from stdin")?;
+        return Ok(stdin.trim().to_string());
+    }
+    let prompt = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read prompt file {:?}", path))?;
+    if !std::io::stdin().is_terminal() {
+        std::io::stdin().read_to_string(&mut stdin)?;
+    }
+    if stdin.trim().is_empty() {
+        return Ok(prompt.trim().to_string());
+    }
+    info!("Adding {} bytes of piped input to the prompt", stdin.len());
+    Ok(format!(
+        "{}\n\nInput:\n```\n{}\n```",
+        prompt.trim(),
+        stdin.trim_end()
+    ))
+}
+
+/// Joins the prompt given as arguments with the one from a file or the editor, the
+/// arguments first
+pub fn join(prompt: &str, more: &str) -> String {
+    match (prompt.trim().is_empty(), more.trim().is_empty()) {
+        (true, _) => more.trim().to_string(),
+        (false, true) => prompt.trim().to_string(),
+        (false, false) => format!("{}\n\n{}", prompt.trim(), more.trim()),
+    }
+}
+
+/// Opens `text` in $VISUAL or $EDITOR, falling back to vi, and returns the saved text.
+/// The temporary file ends in `name` so the editor picks the right syntax. It's created
+/// with a random name only the user can read, so nobody else can swap in a file of their
+/// own for a command that's then run, and it's removed afterwards whatever happens
+pub fn edit(text: &str, name: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let text = text.trim();
+    let contents = if text.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n", text)
+    };
+    let mut file = tempfile::Builder::new()
+        .prefix("ai-")
+        .suffix(&format!("-{}", name))
+        .tempfile()
+        .context("Unable to create a temporary file to edit")?;
+    file.write_all(contents.as_bytes())
+        .with_context(|| format!("Unable to write {:?}", file.path()))?;
+    // closed, so editors that replace the file rather than writing to it work too
+    let path = file.into_temp_path();
+    // through the shell so editors with arguments such as `code --wait` work
+    let status = interrupt::foreground(|| {
+        Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("sh")
+            .arg(&path)
+            .status()
+    })
+    .with_context(|| format!("Unable to run editor {}", editor))?;
+    let edited = std::fs::read_to_string(&path);
+    if !status.success() {
+        anyhow::bail!("{}", Message::EditorFailed(&editor, &status.to_string()));
+    }
+    Ok(edited?.trim().to_string())
+}
@@ -0,0 +1,116 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tracing::{info, warn};
+
+use crate::constants::DEFAULT_CONFIG_CONTENT;
+use crate::schedule;
+use crate::settings::config_dir;
+
+/// Directory for caches such as quantized models, ~/.cache/ai on linux
+pub fn cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("ai")
+}
+
+/// Creates the config directory, default config file, template directory and cache
+/// directory. Safe to run repeatedly, existing files are left untouched.
+/// Returns the paths that were created.
+pub fn init_system() -> Result<Vec<PathBuf>> {
+    let mut created = Vec::new();
+    for dir in [config_dir(), config_dir().join("templates"), cache_dir()] {
+        if !dir.exists() {
+            std::fs::create_dir_all(&dir).with_context(|| format!("Unable to create {:?}", dir))?;
+            created.push(dir);
+        }
+    }
+    let config_file = config_dir().join("config.toml");
+    if !config_file.exists() {
+        std::fs::write(&config_file, DEFAULT_CONFIG_CONTENT)
+            .with_context(|| format!("Unable to write {:?}", config_file))?;
+        created.push(config_file);
+    }
+    Ok(created)
+}
+
+/// Files and directories ai keeps in the config directory. Only these are purged, the
+/// directory itself is removed once they're gone and nothing else is left in it, as it can
+/// be one shared with other programs through $AI_CONFIG_DIR
+const CONFIG_ENTRIES: &[&str] = &[
+    "config.toml",
+    "history.jsonl",
+    "session.json",
+    "spending.json",
+    "usage.json",
+    "aliases.json",
+    "schedule.log",
+    "templates",
+    "prompts",
+];
+
+/// Whether the file is a backup `migrate` made of config.toml
+fn is_config_backup(name: &str) -> bool {
+    name.starts_with("config.toml.v") && name.ends_with(".bak")
+}
+
+/// What `uninstall` removed
+#[derive(Debug, Default)]
+pub struct Uninstalled {
+    pub scheduled_prompts: usize,
+    pub paths: Vec<PathBuf>,
+}
+
+/// Removes the scheduled prompts from the crontab and, with `purge`, the config
+/// (including templates and history) and caches. Other files in the config directory are
+/// left alone.
+/// Missing files and a missing crontab are not errors so package scripts can always call it.
+pub fn uninstall(purge: bool) -> Result<Uninstalled> {
+    let mut removed = Uninstalled::default();
+    match schedule::remove_all() {
+        Ok(count) => removed.scheduled_prompts = count,
+        Err(e) => warn!("Unable to remove scheduled prompts: {:?}", e),
+    }
+    if purge {
+        purge_config(&config_dir(), &mut removed.paths)?;
+        // the cache directory is ai's own
+        let dir = cache_dir();
+        if dir != Path::new(".") && dir.exists() {
+            remove(&dir, &mut removed.paths)?;
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes what ai created in the config directory, then the directory when it's empty
+fn purge_config(dir: &Path, removed: &mut Vec<PathBuf>) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    let entries = std::fs::read_dir(dir).with_context(|| format!("Unable to read {:?}", dir))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Unable to read {:?}", dir))?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if CONFIG_ENTRIES.contains(&name.as_str()) || is_config_backup(&name) {
+            remove(&entry.path(), removed)?;
+        }
+    }
+    // config_dir falls back to the current directory without a home directory
+    if dir != Path::new(".") && std::fs::remove_dir(dir).is_ok() {
+        info!("Removed {:?}", dir);
+        removed.push(dir.to_path_buf());
+    }
+    Ok(())
+}
+
+fn remove(path: &Path, removed: &mut Vec<PathBuf>) -> Result<()> {
+    info!("Removing {:?}", path);
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+    .with_context(|| format!("Unable to remove {:?}", path))?;
+    removed.push(path.to_path_buf());
+    Ok(())
+}
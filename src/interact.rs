@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use console::{Key, Term};
+use dialoguer::Input;
+use tracing::info;
+
+use crate::audit;
+use crate::context::environment::{self, ShellKind};
+use crate::history::{self, HistoryEntry};
+use crate::input;
+use crate::interrupt;
+use crate::messages::Message;
+use crate::output;
+use crate::template;
+
+/// What to do with a generated command, picked from the action menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Copy,
+    Edit,
+    Run,
+    Regenerate,
+    Quit,
+}
+
+/// Whether the action menu can be shown, it needs a terminal to read keys from
+pub fn available() -> bool {
+    std::io::stdin().is_terminal() && std::io::stderr().is_terminal()
+}
+
+/// The key pressed on the terminal within `wait`, None when there was none. Ctrl-C is
+/// read as a key too, so the terminal is always restored
+#[cfg(unix)]
+pub fn key_within(wait: Duration) -> Result<Option<char>> {
+    use std::os::fd::AsRawFd;
+
+    let fd = std::io::stdin().as_raw_fd();
+    let mut original = std::mem::MaybeUninit::<libc::termios>::uninit();
+    // SAFETY: tcgetattr fills in the termios on success, which is checked
+    if unsafe { libc::tcgetattr(fd, original.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let original = unsafe { original.assume_init() };
+    let mut keys = original;
+    keys.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &keys) };
+    let mut stdin = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let ready = unsafe { libc::poll(&mut stdin, 1, wait.as_millis() as libc::c_int) };
+    let mut byte = 0u8;
+    let key = (ready > 0 && unsafe { libc::read(fd, (&mut byte as *mut u8).cast(), 1) } == 1)
+        .then_some(byte as char);
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    Ok(key)
+}
+
+#[cfg(not(unix))]
+pub fn key_within(_wait: Duration) -> Result<Option<char>> {
+    Ok(None)
+}
+
+/// Shows the action menu on stderr and waits for a key
+pub fn choose_action() -> Result<Action> {
+    let term = Term::stderr();
+    loop {
+        term.write_line(&Message::ActionMenu.to_string())?;
+        let action = match term.read_char()? {
+            'c' => Action::Copy,
+            'e' => Action::Edit,
+            'r' => Action::Run,
+            'x' => Action::Regenerate,
+            'q' | '\n' | '\u{1b}' => Action::Quit,
+            _ => continue,
+        };
+        return Ok(action);
+    }
+}
+
+/// Matches shown at once by the history finder
+const FINDER_ROWS: usize = 10;
+
+/// A fuzzy finder over the history on stderr: typing narrows the matches, the arrow keys
+/// move the selection, enter picks it and escape or Ctrl-C gives up
+pub fn find_history(entries: &[HistoryEntry], query: &str) -> Result<Option<HistoryEntry>> {
+    let term = Term::stderr();
+    let width = term.size().1 as usize;
+    let mut query = query.to_string();
+    let mut selected = 0;
+    let mut drawn = 0;
+    term.hide_cursor()?;
+    let picked = loop {
+        let matches = history::search(entries, &query);
+        let rows = matches.len().min(FINDER_ROWS);
+        selected = selected.min(rows.saturating_sub(1));
+        term.clear_last_lines(drawn)?;
+        term.write_line(&format!("{} {}", Message::SearchHistory, query))?;
+        for (index, entry) in matches.iter().take(rows).enumerate() {
+            // a text marker rather than color, so it works with screen readers too
+            let marker = if index == selected { '>' } else { ' ' };
+            let line = format!(
+                "{} {}: {}  =>  {}",
+                marker,
+                entry.id,
+                one_line(&entry.prompt),
+                one_line(&entry.response)
+            );
+            term.write_line(&console::truncate_str(&line, width, "…"))?;
+        }
+        drawn = rows + 1;
+        let key = match term.read_key() {
+            Ok(key) => key,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => Key::Escape,
+            Err(e) => return Err(e.into()),
+        };
+        match key {
+            Key::Enter => break matches.get(selected).map(|entry| (*entry).clone()),
+            Key::Escape => break None,
+            Key::ArrowUp => selected = selected.saturating_sub(1),
+            Key::ArrowDown => selected = (selected + 1).min(rows.saturating_sub(1)),
+            Key::Backspace => {
+                query.pop();
+                selected = 0;
+            }
+            Key::Char(c) if !c.is_control() => {
+                query.push(c);
+                selected = 0;
+            }
+            _ => {}
+        }
+    };
+    term.clear_last_lines(drawn)?;
+    term.show_cursor()?;
+    Ok(picked)
+}
+
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Asks on stderr for the value of each `{{placeholder}}` the model left in the command for
+/// what the request didn't say, and fills them in
+pub fn fill_placeholders(command: &str, accessible: bool) -> Result<String> {
+    let names = template::placeholders(command);
+    if names.is_empty() {
+        return Ok(command.to_string());
+    }
+    let theme = output::theme(accessible);
+    let mut values = HashMap::new();
+    for name in names {
+        let value: String = Input::with_theme(theme.as_ref())
+            .with_prompt(name.replace('_', " "))
+            .interact_text_on(&Term::stderr())?;
+        values.insert(name, value);
+    }
+    Ok(template::fill(command, &values))
+}
+
+/// Opens the command in $VISUAL or $EDITOR, falling back to vi, and returns the edited command
+pub fn edit(command: &str) -> Result<String> {
+    let file_name = match environment::shell_kind() {
+        ShellKind::Posix => "command.sh",
+        ShellKind::PowerShell => "command.ps1",
+        ShellKind::Cmd => "command.cmd",
+    };
+    input::edit(command, file_name)
+}
+
+/// Runs the command in the user's shell with the terminal attached and returns its exit code,
+/// recording it in the audit log when that's on
+pub fn run(command: &str) -> Result<i32> {
+    let (shell, args): (String, &[&str]) = match environment::shell_kind() {
+        ShellKind::Posix => (
+            std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string()),
+            &["-c"],
+        ),
+        ShellKind::PowerShell => (environment::shell_name(), &["-NoProfile", "-Command"]),
+        ShellKind::Cmd => ("cmd".to_string(), &["/C"]),
+    };
+    // opened first, so a command that can't be recorded isn't run
+    let audit = audit::open()?;
+    info!("Running {} with {}", command, shell);
+    let status = interrupt::foreground(|| Command::new(&shell).args(args).arg(command).status())
+        .with_context(|| format!("Unable to run {}", shell))?;
+    let code = status.code().unwrap_or(-1);
+    if let Some(mut audit) = audit {
+        audit.run(command, code)?;
+    }
+    Ok(code)
+}
+
+/// Copies the command to the clipboard
+#[cfg(feature = "clipboard")]
+pub fn copy(command: &str) -> Result<()> {
+    arboard::Clipboard::new()
+        .with_context(|| Message::ClipboardUnavailable.to_string())?
+        .set_text(command.trim())?;
+    Ok(())
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn copy(_command: &str) -> Result<()> {
+    anyhow::bail!(
+        "{} ({})",
+        Message::ClipboardUnavailable,
+        Message::BuildWithClipboard
+    )
+}
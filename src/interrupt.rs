@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Once;
+
+use tracing::{info, warn};
+
+/// Set while a generation runs, Ctrl-C then stops it instead of exiting
+static GENERATING: AtomicBool = AtomicBool::new(false);
+/// Set by Ctrl-C during a generation, backends check it and return what they have
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Programs given the terminal, such as $EDITOR or a command being run. They get Ctrl-C
+/// themselves and decide what to do with it, so it's ignored here while one runs
+static FOREGROUND: AtomicUsize = AtomicUsize::new(0);
+
+/// Exit code of a process killed by SIGINT
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Listens for Ctrl-C. During a generation the first one stops it and keeps the partial
+/// output, a second one (or one at any other time) exits like the default handler. While a
+/// program has the terminal it's left to that program
+pub fn install() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        std::thread::spawn(listen);
+    });
+}
+
+fn listen() {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            warn!("Unable to listen for Ctrl-C: {:?}", e);
+            return;
+        }
+    };
+    runtime.block_on(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if FOREGROUND.load(Ordering::SeqCst) > 0 {
+                info!("Ctrl-C while a program has the terminal, leaving it to the program");
+                continue;
+            }
+            if GENERATING.load(Ordering::SeqCst) && !INTERRUPTED.swap(true, Ordering::SeqCst) {
+                info!("Interrupted, stopping the generation");
+                continue;
+            }
+            // the spinner hides the cursor
+            let _ = console::Term::stderr().show_cursor();
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+    });
+}
+
+/// Runs a generation, during which Ctrl-C stops it rather than exiting
+pub fn generating<T>(generate: impl FnOnce() -> T) -> T {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    GENERATING.store(true, Ordering::SeqCst);
+    let result = generate();
+    GENERATING.store(false, Ordering::SeqCst);
+    result
+}
+
+/// Runs a program that has the terminal, during which Ctrl-C is left to the program
+pub fn foreground<T>(run: impl FnOnce() -> T) -> T {
+    FOREGROUND.fetch_add(1, Ordering::SeqCst);
+    let result = run();
+    FOREGROUND.fetch_sub(1, Ordering::SeqCst);
+    result
+}
+
+/// Whether Ctrl-C was pressed during the current generation
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Stops the current generation as Ctrl-C would, for the daemon's cancel requests.
+/// Returns whether one was running
+pub fn cancel() -> bool {
+    GENERATING.load(Ordering::SeqCst) && !INTERRUPTED.swap(true, Ordering::SeqCst)
+}
@@ -1,34 +1,133 @@
+//! Turns natural language into shell one-liners, with a local model, Amazon Bedrock or
+//! any program acting as the model.
+//!
+//! The `ai` binary is built on this library. [`generate`] runs the same flow without the
+//! terminal side of it, [`create_backend`] and the [`AiBackend`] trait give direct access
+//! to the backends and [`TextGeneration`] runs the local models token by token.
+//!
+//! Nothing is printed: what the binary tells the user along the way, such as a generation
+//! cut short by its timeout or a prompt redacted, is a `tracing` event with the [`NOTICE`]
+//! target, and failures are returned as errors.
+
 mod ai_backend;
+mod alias;
+mod api;
+mod audit;
+mod aws;
+mod batch;
+mod bench;
+mod budget;
+mod cache;
 mod command;
+mod commit;
+mod compare;
+mod complete;
+mod config_schema;
 mod constants;
+mod context;
+mod credentials;
+mod daemon;
+mod delivery;
+mod doctor;
+mod encryption;
+mod explain;
+mod fix;
+mod grammar;
+mod history;
+mod input;
+mod install;
+mod interact;
+mod interrupt;
+mod logging;
+mod memory;
+mod messages;
+mod migrate;
+mod model_alias;
+mod modes;
+mod network;
+mod output;
+mod pipe;
+mod pipeline;
+mod profile;
+mod prompts;
+mod quantize;
+mod quantized_mixformer;
+mod quota;
+mod rag;
+mod redact;
+mod render;
+mod routing;
+mod schedule;
+mod schema;
+mod session;
 mod settings;
+mod sharded;
+mod shell;
+mod telemetry;
+mod template;
 mod text_generation;
 mod token_output_stream;
+mod tokens;
+mod tui;
 // ... other modules
 
-// This is the only export from the crate. It is marked hidden and
-// is not part of the public API.
 use candle_core::utils::{cuda_is_available, metal_is_available};
 use candle_core::{Device, Result};
+
+pub use ai_backend::common::{Cutoff, Role};
+pub use ai_backend::{create_backend, AiBackend, ChatMessage, Usage};
+pub use api::{generate, GenerateOptions};
+// The command line interface of the binary, not part of the public API
+#[doc(hidden)]
+pub use command::{
+    AiCli, AiCliArgs, AiCliCommands, AliasCommands, AuditCommands, AuthCommands, CacheCommands,
+    ConfigCommands, HistoryCommands, ModelCommands, PipelineCommands, ScheduleCommands,
+    TemplateCommands,
+};
+#[doc(hidden)]
+pub use doctor::doctor;
+pub use logging::NOTICE;
+#[doc(hidden)]
+pub use logging::{open_log_file, LogWriter};
 #[doc(hidden)]
-pub use command::{AiCli, AiCliArgs, AiCliCommands};
+pub use output::{colored_logs, init as init_output};
 #[doc(hidden)]
+pub use profile::{record_phase, SpanTimings};
+pub use quantized_mixformer::{Config as MixFormerConfig, MixFormerSequentialForCausalLM};
 pub use settings::Settings;
+#[doc(hidden)]
+pub use telemetry::{flush as flush_telemetry, OtlpLayer};
+pub use text_generation::{Draft, Model, TextGeneration};
 use tracing::warn;
 
+use crate::messages::Message;
+
 /// Loads the safetensors files for a model from the hub based on a json index file.
 pub fn hub_load_safetensors(
     repo: &hf_hub::api::sync::ApiRepo,
     json_file: &str,
 ) -> Result<Vec<std::path::PathBuf>> {
-    let json_file = repo.get(json_file).map_err(candle_core::Error::wrap)?;
+    load_safetensors(
+        |file| repo.get(file).map_err(candle_core::Error::wrap),
+        json_file,
+    )
+}
+
+/// [`hub_load_safetensors`] with the files fetched by `get`
+fn load_safetensors(
+    get: impl Fn(&str) -> Result<std::path::PathBuf>,
+    json_file: &str,
+) -> Result<Vec<std::path::PathBuf>> {
+    let json_file = get(json_file)?;
     let json_file = std::fs::File::open(json_file)?;
     let json: serde_json::Value =
         serde_json::from_reader(&json_file).map_err(candle_core::Error::wrap)?;
     let weight_map = match json.get("weight_map") {
-        None => candle_core::bail!("no weight map in {json_file:?}"),
+        None => candle_core::bail!("{}", Message::NoWeightMap(&format!("{json_file:?}"))),
         Some(serde_json::Value::Object(map)) => map,
-        Some(_) => candle_core::bail!("weight map in {json_file:?} is not a map"),
+        Some(_) => {
+            candle_core::bail!("{}", Message::WeightMapNotAMap(&format!("{json_file:?}")))
+        }
     };
     let mut safetensors_files = std::collections::HashSet::new();
     for value in weight_map.values() {
@@ -38,11 +137,31 @@ pub fn hub_load_safetensors(
     }
     let safetensors_files = safetensors_files
         .iter()
-        .map(|v| repo.get(v).map_err(candle_core::Error::wrap))
+        .map(|v| get(v))
         .collect::<Result<Vec<_>>>()?;
     Ok(safetensors_files)
 }
 
+/// Parses a device specifier such as `cpu`, `cuda:1` or `metal:0` into a candle device.
+/// The ordinal defaults to 0 when omitted.
+pub fn parse_device(spec: &str) -> Result<Device> {
+    let (kind, ordinal) = match spec.trim().split_once(':') {
+        Some((kind, ordinal)) => {
+            let ordinal = ordinal.parse::<usize>().map_err(|_| {
+                candle_core::Error::Msg(format!("invalid device ordinal in {spec}"))
+            })?;
+            (kind, ordinal)
+        }
+        None => (spec.trim(), 0),
+    };
+    match kind.to_lowercase().as_str() {
+        "cpu" => Ok(Device::Cpu),
+        "cuda" => Device::new_cuda(ordinal),
+        "metal" => Device::new_metal(ordinal),
+        _ => candle_core::bail!("{}", Message::UnknownDevice(spec)),
+    }
+}
+
 pub fn device(cpu: bool) -> Result<Device> {
     if cpu {
         Ok(Device::Cpu)
@@ -0,0 +1,125 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::settings::Settings;
+
+/// Target of the events meant for the user rather than the log, e.g. that a generation was
+/// cut short, a prompt redacted or the config migrated. The library only emits them, the
+/// `ai` binary shows them on stderr whatever the verbosity
+pub const NOTICE: &str = "ai::notice";
+
+/// The `log_file` once it's open, logs go to stdout until then
+static LOG_FILE: OnceLock<File> = OnceLock::new();
+
+/// Directory a relative `log_file` is in, ~/.local/state/ai on linux
+pub fn state_dir() -> PathBuf {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("ai")
+}
+
+/// Sends the logs to the `log_file` setting from here on, rotating it first when it has
+/// grown past `log_max_size_mb`. Returns the file's path, None when the setting is unset
+pub fn open_log_file(settings: &Settings) -> Result<Option<PathBuf>> {
+    let Some(path) = &settings.log_file else {
+        return Ok(None);
+    };
+    let path = state_dir().join(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create the log directory {:?}", parent))?;
+    }
+    let size = std::fs::metadata(&path).map_or(0, |metadata| metadata.len());
+    if size >= settings.log_max_size_mb.saturating_mul(1024 * 1024) {
+        rotate(&path, settings.log_max_files)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Unable to open the log file {:?}", path))?;
+    let _ = LOG_FILE.set(file);
+    Ok(Some(path))
+}
+
+/// Shifts ai.log to ai.log.1, ai.log.1 to ai.log.2 and so on, dropping the oldest so
+/// `keep` old files remain
+fn rotate(path: &Path, keep: usize) -> Result<()> {
+    let numbered = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+    if keep == 0 {
+        return std::fs::remove_file(path).with_context(|| format!("Unable to remove {:?}", path));
+    }
+    let _ = std::fs::remove_file(numbered(keep));
+    for n in (1..keep).rev() {
+        let _ = std::fs::rename(numbered(n), numbered(n + 1));
+    }
+    std::fs::rename(path, numbered(1)).with_context(|| format!("Unable to rotate {:?}", path))
+}
+
+/// Where the log output goes: the log file once one is open, with errors also on stderr,
+/// otherwise stdout, or stderr in quiet mode where stdout is the command's
+pub struct LogWriter {
+    pub quiet: bool,
+}
+
+impl<'a> MakeWriter<'a> for LogWriter {
+    type Writer = Box<dyn Write + 'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        match LOG_FILE.get() {
+            Some(file) => Box::new(Plain(file)),
+            None if self.quiet => Box::new(std::io::stderr()),
+            None => Box::new(std::io::stdout()),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        match LOG_FILE.get() {
+            // an error stops the command, so it's shown as well as logged
+            Some(file) if *meta.level() == Level::ERROR => {
+                Box::new(Tee(Plain(file), std::io::stderr()))
+            }
+            _ => self.make_writer(),
+        }
+    }
+}
+
+/// Writes to the log file without the colors meant for the terminal. The formatter
+/// writes each event in one go, so the escape codes are never split
+struct Plain(&'static File);
+
+impl Write for Plain {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        self.0
+            .write_all(console::strip_ansi_codes(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Writes to both
+struct Tee<A, B>(A, B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
@@ -4,12 +4,48 @@ extern crate intel_mkl_src;
 #[cfg(feature = "accelerate")]
 extern crate accelerate_src;
 
-use ai::{AiCli, AiCliArgs, Settings};
+use ai::{
+    colored_logs, flush_telemetry, init_output, open_log_file, record_phase, AiCli, AiCliArgs,
+    AiCliCommands, LogWriter, OtlpLayer, Settings, SpanTimings, NOTICE,
+};
 use anyhow::Result;
 use clap::Parser;
-use tracing::{error, info};
+use clap_verbosity_flag::Level;
+use tracing::field::{Field, Visit};
+use tracing::{error, info, info_span, warn, Event, Subscriber};
 use tracing_log::AsTrace;
-use tracing_subscriber::FmtSubscriber;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Shows the library's notices on stderr as they come, whatever the verbosity, warnings
+/// prefixed with "Warning:"
+struct NoticeLayer;
+
+impl<S: Subscriber> Layer<S> for NoticeLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if event.metadata().target() != NOTICE {
+            return;
+        }
+        let mut message = String::new();
+        event.record(&mut NoticeMessage(&mut message));
+        if *event.metadata().level() == tracing::Level::WARN {
+            eprintln!("Warning: {}", message);
+        } else {
+            eprintln!("{}", message);
+        }
+    }
+}
+
+/// The message of a notice, its only field
+struct NoticeMessage<'a>(&'a mut String);
+
+impl Visit for NoticeMessage<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            *self.0 = format!("{:?}", value);
+        }
+    }
+}
 
 fn main() -> Result<()> {
     use tracing_chrome::ChromeLayerBuilder;
@@ -18,34 +54,71 @@ fn main() -> Result<()> {
     let start = std::time::Instant::now();
 
     let ai_cli_args = AiCliArgs::parse();
+    // ai profile-startup reports on this invocation, parsing the arguments included
+    let span_timings = matches!(
+        ai_cli_args.command,
+        Some(AiCliCommands::ProfileStartup { .. })
+    )
+    .then(|| {
+        record_phase("arg_parse", start.elapsed());
+        SpanTimings
+    });
+    if let Some(path) = &ai_cli_args.config {
+        Settings::use_config_file(path.clone());
+    }
 
-    let settings = Settings::new()?;
     //convert settings.verbosity String into Levelfilter
     // set filter to ai_cli if present, else, from settings
     let log_level_filter = ai_cli_args.verbose.log_level_filter();
+    let quiet = ai_cli_args.quiet();
+    init_output(ai_cli_args.color, quiet);
 
-    // a builder for `FmtSubscriber`.
-    let subscriber = FmtSubscriber::builder()
-        // all spans/events with a level higher than TRACE (e.g, debug, info, warn, etc.)
-        // will be written to stdout.
-        .with_max_level(log_level_filter.as_trace())
-        // .with_line_number(false)
-        // .pretty()
-        // .with_target(true)
-        // completes the builder.
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
-    info!(
-        "Initialized args, settings, and logging in {:?}",
-        start.elapsed()
-    );
-    let _guard = if ai_cli_args.tracing {
-        let (chrome_layer, guard) = ChromeLayerBuilder::new().build();
-        tracing_subscriber::registry().with(chrome_layer).init();
-        Some(guard)
+    // the log output, all spans/events with a level higher than TRACE (e.g, debug, info,
+    // warn, etc.) will be written to stdout, or to the log_file once the settings are read
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        // stdout is reserved for the command in quiet mode
+        .with_writer(LogWriter { quiet })
+        .with_ansi(colored_logs(quiet))
+        .with_filter(
+            Targets::new()
+                .with_default(log_level_filter.as_trace())
+                .with_target(NOTICE, LevelFilter::OFF),
+        );
+    // the chrome layer sits on the same registry as the log output, whatever the verbosity
+    // it records every span of ours, down to the per-token ones, and the info spans of
+    // the libraries
+    let (chrome_layer, _guard) = if ai_cli_args.tracing {
+        let (chrome_layer, guard) = ChromeLayerBuilder::new().include_args(true).build();
+        let targets = Targets::new()
+            .with_target("ai", tracing::Level::TRACE)
+            .with_default(tracing::Level::INFO);
+        (Some(chrome_layer.with_filter(targets)), Some(guard))
     } else {
-        None
+        (None, None)
+    };
+    // spans for the OpenTelemetry export, dropped unless the telemetry setting is on
+    let otlp_layer = OtlpLayer.with_filter(Targets::new().with_target("ai", tracing::Level::INFO));
+    let subscriber = tracing_subscriber::registry()
+        .with(NoticeLayer.with_filter(Targets::new().with_target(NOTICE, tracing::Level::INFO)))
+        .with(fmt_layer)
+        .with(chrome_layer)
+        .with(otlp_layer)
+        .with(span_timings);
+    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+
+    let settings = match info_span!("config_parse").in_scope(Settings::new) {
+        Ok(settings) => settings,
+        // doctor explains what is wrong with the config rather than failing on it
+        Err(_) if matches!(ai_cli_args.command, Some(AiCliCommands::Doctor)) => {
+            return ai::doctor();
+        }
+        Err(e) => return Err(e.into()),
     };
+    match open_log_file(&settings) {
+        Ok(Some(path)) => info!("Logging to {}", path.display()),
+        Ok(None) => {}
+        Err(e) => warn!("{:#}, logging to stdout", e),
+    }
 
     let concatenated_args = ai_cli_args.other_args.join(" ");
 
@@ -54,14 +127,19 @@ fn main() -> Result<()> {
         settings,
         ai_cli_args,
         Some(start),
-        log_level_filter
-            .to_level()
-            .expect("Unable to load log level configuration."),
+        // -q can turn logging off entirely
+        log_level_filter.to_level().unwrap_or(Level::Error),
         concatenated_args,
     );
 
-    match ai_cli.exec() {
+    let result = info_span!("exec").in_scope(|| ai_cli.exec());
+    flush_telemetry();
+    match result {
         Ok(_) => {}
+        Err(e) if quiet => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
         Err(e) => {
             error!("{:?}", e);
             error!("Exiting due to error");
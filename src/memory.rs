@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use candle_core::{DType, Device, DeviceLocation};
+
+use crate::messages::Message;
+
+const GIB: f64 = 1024. * 1024. * 1024.;
+
+/// Largest safetensors header read, real ones are a few hundred KiB
+const MAX_HEADER_BYTES: u64 = 100 * 1024 * 1024;
+
+/// What the local model takes in memory, estimated before loading it
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    pub weights: u64,
+    /// Keys and values of every layer for the tokens of a generation
+    pub kv_cache: u64,
+}
+
+impl Estimate {
+    pub fn total(&self) -> u64 {
+        self.weights + self.kv_cache
+    }
+}
+
+/// Bytes the weights of safetensors files take once loaded as `dtype`, from the tensor
+/// shapes in their headers, as they're often stored in a smaller dtype than they're run in
+pub fn safetensors_bytes(files: &[PathBuf], dtype: DType) -> Result<u64> {
+    let mut parameters = 0;
+    for file in files {
+        parameters += safetensors_parameters(file)?;
+    }
+    Ok(parameters * dtype.size_in_bytes() as u64)
+}
+
+fn safetensors_parameters(path: &Path) -> Result<u64> {
+    let mut file =
+        File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+    // a little endian u64 length, then the json header
+    let mut length = [0; 8];
+    file.read_exact(&mut length)?;
+    let length = u64::from_le_bytes(length);
+    if length > MAX_HEADER_BYTES {
+        anyhow::bail!("{}", Message::NotSafetensors(&path.display().to_string()));
+    }
+    let mut header = vec![0; length as usize];
+    file.read_exact(&mut header)?;
+    let tensors: HashMap<String, serde_json::Value> = serde_json::from_slice(&header)
+        .with_context(|| format!("Invalid safetensors header in {}", path.display()))?;
+    Ok(tensors
+        .iter()
+        .filter(|(name, _)| *name != "__metadata__")
+        .filter_map(|(_, tensor)| tensor.get("shape")?.as_array())
+        .map(|shape| shape.iter().filter_map(|dim| dim.as_u64()).product::<u64>())
+        .sum())
+}
+
+/// Bytes of the files, a gguf is loaded as it's stored
+pub fn file_bytes(files: &[PathBuf]) -> u64 {
+    files
+        .iter()
+        .filter_map(|file| std::fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// The most the model may take on the devices together: what's free on each of them, VRAM
+/// on a CUDA GPU from nvidia-smi and otherwise RAM, which Metal shares, capped by
+/// `max_memory_gb`. None when neither is known
+pub fn limit(devices: &[Device], max_memory_gb: Option<f64>) -> Option<u64> {
+    let max = max_memory_gb.map(|gb| (gb * GIB) as u64);
+    // each GPU's VRAM, and the RAM the cpu and metal share once
+    let mut gpus = Vec::new();
+    for device in devices {
+        let gpu = match device.location() {
+            DeviceLocation::Cuda { gpu_id } => Some(gpu_id),
+            _ => None,
+        };
+        if !gpus.contains(&gpu) {
+            gpus.push(gpu);
+        }
+    }
+    let available = gpus
+        .into_iter()
+        .map(|gpu| match gpu {
+            Some(gpu) => free_vram(gpu),
+            None => available_ram(),
+        })
+        .sum::<Option<u64>>();
+    match (available, max) {
+        (Some(available), Some(max)) => Some(available.min(max)),
+        (available, max) => available.or(max),
+    }
+}
+
+/// MemAvailable from /proc on linux, what can be allocated without swapping
+pub fn available_ram() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kib = meminfo.lines().find_map(|line| {
+        line.strip_prefix("MemAvailable:")?
+            .trim()
+            .strip_suffix("kB")?
+            .trim()
+            .parse::<u64>()
+            .ok()
+    })?;
+    Some(kib * 1024)
+}
+
+fn free_vram(gpu: usize) -> Option<u64> {
+    let output = Command::new("nvidia-smi")
+        .args(["--query-gpu=memory.free", "--format=csv,noheader,nounits"])
+        .arg(format!("--id={}", gpu))
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let mib = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(mib * 1024 * 1024)
+}
+
+pub fn gib(bytes: u64) -> f64 {
+    bytes as f64 / GIB
+}
@@ -0,0 +1,1148 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Languages with a translated message catalog
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    Spanish,
+    German,
+    French,
+}
+
+static LANGUAGE: OnceLock<Language> = OnceLock::new();
+
+impl Language {
+    /// Parses a language code or locale such as "de", "es_ES.UTF-8" or "fr-CA"
+    pub fn from_locale(locale: &str) -> Option<Self> {
+        let code = locale
+            .split(['_', '-', '.', '@'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match code.as_str() {
+            "en" | "c" | "posix" => Some(Language::English),
+            "es" => Some(Language::Spanish),
+            "de" => Some(Language::German),
+            "fr" => Some(Language::French),
+            _ => None,
+        }
+    }
+
+    /// The `language` setting when given, otherwise the system locale, otherwise English
+    pub fn detect(setting: Option<&str>) -> Self {
+        setting
+            .into_iter()
+            .map(str::to_string)
+            .chain(
+                ["LC_ALL", "LC_MESSAGES", "LANG"]
+                    .iter()
+                    .filter_map(|var| std::env::var(var).ok())
+                    .filter(|value| !value.is_empty()),
+            )
+            .find_map(|locale| Language::from_locale(&locale))
+            .unwrap_or(Language::English)
+    }
+}
+
+/// Selects the language for the rest of the process, only the first call has an effect
+pub fn init(setting: Option<&str>) {
+    let _ = LANGUAGE.set(Language::detect(setting));
+}
+
+fn language() -> Language {
+    *LANGUAGE.get_or_init(|| Language::detect(None))
+}
+
+/// User facing messages, displayed in the selected language.
+/// Log lines stay in English so they can be searched and reported.
+#[derive(Debug, Clone, Copy)]
+pub enum Message<'a> {
+    PromptEmpty,
+    NoOutput,
+    NoCommandSelected,
+    PickCommand,
+    Generating,
+    Thinking,
+    Done,
+    SavedTemplate(&'a str),
+    DeletedTemplate(&'a str),
+    ScheduledPrompt(u64),
+    RemovedScheduledPrompt(u64),
+    UnknownWebhook(&'a str),
+    UsageUnavailable(&'a str),
+    Created(&'a str),
+    Removed(&'a str),
+    RemovedScheduledPrompts(usize),
+    NoFailedCommand,
+    ActionMenu,
+    Copied,
+    ClipboardUnavailable,
+    ExitStatus(i32),
+    NothingStaged,
+    ConfirmCommit,
+    EnterApiKey(&'a str),
+    EmptyApiKey,
+    SavedApiKey(&'a str),
+    RemovedApiKey(&'a str),
+    TimedOut(u64),
+    Interrupted,
+    ClearedCache(usize),
+    CachedResponse,
+    TemplateMissing,
+    WroteScript(&'a str),
+    BatchFinished(usize, usize),
+    TokenCount(usize, usize),
+    ExceedsContext(usize),
+    OverBudget(f64, f64, f64),
+    SearchHistory,
+    SavedAlias(&'a str),
+    DeletedAlias(&'a str),
+    NothingToAlias,
+    NoPipeInput,
+    IndexCleared,
+    Indexed(usize, usize, usize),
+    TuiKeys,
+    PressAnyKey,
+    NotATerminal,
+    RegenerateHint,
+    ModelTooLarge(f64, f64),
+    QuantizedFallback(f64, f64),
+    NoCommandToUndo,
+    NotAGitRepository,
+    NoErrorInput,
+    ImportedHistory(usize, usize),
+    HistoryPassphrase,
+    RepeatHistoryPassphrase,
+    HistoryPassphraseMismatch,
+    NoHistoryPassphrase,
+    NoHistoryKey,
+    Redacted(&'a str, &'a str),
+    LocalOnly(&'a str),
+    RemoteBackendLocalOnly(&'a str),
+    NotCached(&'a str, &'a str),
+    NoAuditLog(&'a str),
+    RequestQuota(&'a str, u64, u64),
+    TokenQuota(&'a str, u64, u64, u64),
+    UsageLine(&'a str, u64, u64),
+    QuotaLine(Option<u64>, Option<u64>),
+    AllBackends,
+    NoUsage,
+    PromptTitle,
+    CacheEntries(usize, usize),
+    CacheHits(u64),
+    CacheSize(f64),
+    CacheTtl(u64),
+    CacheOff,
+    ContextSkipped(&'a str, u128),
+    QuantizedOnOneDevice,
+    ModelArchitecture(&'a str, &'a str, &'a str),
+    UnsupportedArchitecture(&'a str, &'a str),
+    DeliveryFailed(&'a str),
+    EditorFailed(&'a str, &'a str),
+    UnknownEncryption,
+    EncryptFailed,
+    TooShortToDecrypt,
+    InvalidNonce,
+    AuthenticationFailed,
+    InvalidKey,
+    NoRandomBytes,
+    LockPoisoned(&'a str),
+    NotAKey(&'a str),
+    DaemonNotRunning,
+    ModelOnOtherBackend(&'a str, &'a str, &'a str),
+    NoCertificates(&'a str),
+    NoHistoryEntry(u64),
+    NoHistoryExport(&'a str),
+    NewerConfig(&'a str, i64, i64),
+    UnexpectedShape(&'a str, &'a str),
+    MfaNeedsTerminal(&'a str),
+    NoCredentialsProvider,
+    NoStsCredentials(&'a str),
+    SsoExpired(&'a str),
+    NoAwsCredentials(&'a str),
+    StepNeedsPromptOrRun(&'a str),
+    StepFailed(&'a str, i32, &'a str),
+    ExpectedKeyValue(&'a str),
+    #[cfg_attr(feature = "clipboard", allow(dead_code))]
+    BuildWithClipboard,
+    GitCommitFailed(&'a str),
+    GitFailed(&'a str, &'a str),
+    NoWeightMap(&'a str),
+    WeightMapNotAMap(&'a str),
+    UnknownDevice(&'a str),
+    NoKeyringKey(&'a str),
+    NotSafetensors(&'a str),
+    UnknownModel(&'a str, &'a str),
+    InvalidRegex(&'a str, &'a str),
+    InvalidJq(&'a str, &'a str),
+    NotAwk(&'a str),
+    UnclosedQuote(&'a str),
+    BackendPanicked(&'a str),
+    InvalidTemplateName(&'a str),
+    NoTemplate(&'a str),
+    UnclosedTemplateVariable,
+    MissingTemplateValue(&'a str),
+    ExpectedNameValue(&'a str),
+    MissingFlagValue(&'a str),
+    InvalidSchemaFile(&'a str, &'a str),
+    InvalidSchema(&'a str),
+    SchemaMismatch(&'a str, &'a str),
+    InvalidAliasName(&'a str),
+    NoAlias(&'a str),
+    BenchmarkFailed(i32),
+    NoMeasurements,
+    UnknownInterval(&'a str),
+    UnknownSchedule(&'a str),
+    EmptyCron,
+    UnknownCronShorthand(&'a str),
+    CronFields(usize),
+    CronValue(&'a str, &'a str, u32, u32),
+    CronStep(&'a str, &'a str),
+    CronRangeBackwards(&'a str, &'a str),
+    InvalidHour(&'a str),
+    InvalidTime(&'a str),
+    UnknownDay(&'a str),
+    NoStdin(&'a str),
+    CrontabRejected,
+    NoScheduledPrompt(u64),
+    DraftNeedsQuantized,
+    TokenizerCantEncode,
+    NoStopToken,
+    ChecksFailed(usize, usize),
+    NoConversation,
+    UnknownRedactionKind(&'a str, &'a str),
+    InvalidRedactionPattern(&'a str, &'a str),
+    CannotDecode(&'a str),
+    UnsupportedBits(u8),
+    GrammarDeadEnd,
+    NotADirectory(&'a str),
+    DaemonClosed,
+    #[cfg_attr(unix, allow(dead_code))]
+    DaemonNeedsUnix,
+    UnexpectedAnswer(&'a str),
+    NotAGeneration(&'a str),
+    DaemonFailed(&'a str),
+    DaemonStopped,
+    DaemonAlreadyRunning(&'a str),
+    DaemonOtherModel,
+    DaemonShuttingDown,
+    FrameTooLarge(usize),
+    InvalidKeepAlive(&'a str),
+    NoKeyringKeyAdd(&'a str),
+    CommandBackendFailed(&'a str, i32, &'a str),
+    QuantizedPhi3,
+    UnknownDraftModel(&'a str),
+    ToolNeeds(&'a str, &'a str),
+    UnknownTool(&'a str),
+    OutsideWorkingTree(&'a str),
+    SecretFile(&'a str),
+    EmptyCommand,
+    NotReadOnly(&'a str),
+    CommandTimedOut(&'a str),
+    NoMockResponse(&'a str),
+    PluginFailed(&'a str, &'a str),
+    PluginStatus(&'a str, i32, &'a str),
+    NoCommandBackend,
+    AutoNeedsBackend,
+    UnknownBackend(&'a str, &'a str),
+    NoBedrockPrompt,
+    BedrockStartsWithUser,
+    BuildFailed,
+    UnknownStreamError,
+    InvalidToolInput(&'a str),
+    BedrockTimedOut(u64),
+    BedrockInterrupted,
+    SendFailed(&'a str),
+    NoBedrockRegions,
+    TooManyToolRounds(usize),
+    ToolResultsFailed(&'a str),
+    HookFailed(&'a str, &'a str, i32),
+    HookPrintedNothing(&'a str, &'a str),
+    NoDevice,
+    NotText(&'a str),
+    UnknownContextProvider(&'a str, &'a str),
+}
+
+impl fmt::Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match language() {
+            Language::English => self.english(f),
+            Language::Spanish => self.spanish(f),
+            Language::German => self.german(f),
+            Language::French => self.french(f),
+        }
+    }
+}
+
+impl Message<'_> {
+    fn english(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::PromptEmpty => write!(f, "Prompt is empty"),
+            Message::NoOutput => write!(f, "No output was generated"),
+            Message::NoCommandSelected => write!(f, "No command selected"),
+            Message::PickCommand => write!(f, "Pick a command"),
+            Message::Generating => write!(f, "Generating…"),
+            Message::Thinking => write!(f, "Thinking..."),
+            Message::Done => write!(f, "Done"),
+            Message::SavedTemplate(name) => write!(f, "Saved template {}", name),
+            Message::DeletedTemplate(name) => write!(f, "Deleted template {}", name),
+            Message::ScheduledPrompt(id) => write!(f, "Scheduled prompt {}", id),
+            Message::RemovedScheduledPrompt(id) => write!(f, "Removed scheduled prompt {}", id),
+            Message::UnknownWebhook(name) => write!(f, "No webhook named {} in settings", name),
+            Message::UsageUnavailable(backend) => write!(
+                f,
+                "Usage stats are not available for the {} backend",
+                backend
+            ),
+            Message::Created(path) => write!(f, "Created {}", path),
+            Message::Removed(path) => write!(f, "Removed {}", path),
+            Message::RemovedScheduledPrompts(count) => {
+                write!(f, "Removed {} scheduled prompt(s) from the crontab", count)
+            }
+            Message::NoFailedCommand => write!(f, "No failed command to fix, pass --command or set up the shell integration with `eval \"$(ai shell-init bash)\"`"),
+            Message::ActionMenu => write!(f, "[c]opy  [e]dit and run  [r]un  [x] regenerate  [q]uit"),
+            Message::Copied => write!(f, "Copied to the clipboard"),
+            Message::ClipboardUnavailable => write!(f, "The clipboard isn't available"),
+            Message::ExitStatus(code) => write!(f, "Exit status {}", code),
+            Message::NothingStaged => write!(f, "Nothing is staged, add changes with git add first"),
+            Message::ConfirmCommit => write!(f, "Commit with this message?"),
+            Message::EnterApiKey(name) => write!(f, "API key for {}", name),
+            Message::EmptyApiKey => write!(f, "The API key is empty"),
+            Message::SavedApiKey(name) => write!(f, "Stored the {} key in the keyring", name),
+            Message::RemovedApiKey(name) => write!(f, "Removed the {} key from the keyring", name),
+            Message::TimedOut(secs) => write!(f, "Timed out after {}s, the output is partial", secs),
+            Message::Interrupted => write!(f, "Interrupted, the output is partial"),
+            Message::ClearedCache(count) => write!(f, "Removed {} cached responses", count),
+            Message::CachedResponse => write!(f, "Answered from the cache, use --no-cache to generate again"),
+            Message::TemplateMissing => write!(f, "Give the template text as an argument or with --prompt-file"),
+            Message::WroteScript(path) => write!(f, "Wrote the script to {}", path),
+            Message::BatchFinished(total, failed) => write!(f, "Ran {} prompts, {} failed", total, failed),
+            Message::TokenCount(tokens, sample_len) => write!(f, "{} tokens, plus up to {} generated (sample_len)", tokens, sample_len),
+            Message::ExceedsContext(length) => write!(f, "The prompt and sample_len exceed the model's context of {} tokens, shorten the prompt or lower sample_len", length),
+            Message::OverBudget(spent, estimate, budget) => write!(f, "Sending would go over the monthly budget: ${:.4} spent plus about ${:.4} for this prompt is more than ${:.2}. Use --force to send anyway", spent, estimate, budget),
+            Message::SearchHistory => write!(f, "Search history:"),
+            Message::SavedAlias(name) => write!(f, "Saved alias {}", name),
+            Message::DeletedAlias(name) => write!(f, "Deleted alias {}", name),
+            Message::NothingToAlias => write!(f, "No generated command to save yet, generate one first"),
+            Message::NoPipeInput => write!(f, "Nothing to process, pipe the text in, e.g. `cat app.log | ai pipe summarize the errors`"),
+            Message::IndexCleared => write!(f, "Deleted the index"),
+            Message::Indexed(files, embedded, chunks) => write!(f, "Indexed {} files ({} new or changed), {} chunks in the index. Add the relevant ones to a prompt with --context index", files, embedded, chunks),
+            Message::TuiKeys => write!(f, "Enter send  ↑/↓ history  Ctrl-Y copy  Ctrl-R run  Ctrl-G regenerate  Esc quit"),
+            Message::PressAnyKey => write!(f, "Press any key to return"),
+            Message::NotATerminal => write!(f, "ai tui needs an interactive terminal"),
+            Message::RegenerateHint => write!(f, "Press r to regenerate"),
+            Message::ModelTooLarge(needed, limit) => write!(f, "The local model needs about {:.1} GiB but only {:.1} GiB is available, set local_model_config.quantized = true, lower sample_len or raise max_memory_gb", needed, limit),
+            Message::QuantizedFallback(needed, limit) => write!(f, "The local model needs about {:.1} GiB but only {:.1} GiB is available, using the quantized model instead", needed, limit),
+            Message::NoCommandToUndo => write!(f, "No command to undo, pass it as `ai undo '<command>'` or set up the shell integration with `eval \"$(ai shell-init bash)\"`"),
+            Message::NotAGitRepository => write!(f, "Not in a git repository, run `ai git` inside one"),
+            Message::NoErrorInput => write!(f, "No error to explain, pipe it in, e.g. `make 2>&1 | ai explain-error`"),
+            Message::ImportedHistory(imported, skipped) => write!(f, "Imported {} history entries, skipped {} already in the history", imported, skipped),
+            Message::HistoryPassphrase => write!(f, "History passphrase"),
+            Message::RepeatHistoryPassphrase => write!(f, "Repeat the passphrase"),
+            Message::HistoryPassphraseMismatch => write!(f, "The passphrases don't match"),
+            Message::NoHistoryPassphrase => write!(f, "The history is encrypted with a passphrase, set AI_HISTORY_PASSPHRASE or run ai in a terminal to enter it"),
+            Message::NoHistoryKey => write!(f, "The history is encrypted with a key from the keyring, but there is none in this keyring"),
+            Message::Redacted(backend, found) => write!(f, "Redacted before sending to {}: {}. Use --no-redact to send them as is", backend, found),
+            Message::LocalOnly(what) => write!(f, "{} needs the network, which local-only mode (--local-only or local_only = true) forbids", what),
+            Message::RemoteBackendLocalOnly(backend) => write!(f, "The {} backend sends prompts off this machine, which local-only mode (--local-only or local_only = true) forbids. Use -b local", backend),
+            Message::NotCached(file, model) => write!(f, "{} of {} isn't in the model cache and local-only mode can't download it. Run ai once without --local-only on a machine with network access and copy the Hugging Face cache (HF_HOME) over", file, model),
+            Message::NoAuditLog(path) => write!(f, "There is no audit log at {}. Turn it on with enabled = true under [audit] in the config", path),
+            Message::RequestQuota(table, used, max) => write!(f, "The quota of {} requests a day in {} is used up ({} made today). It resets at midnight UTC, or raise max_requests_per_day there", max, table, used),
+            Message::TokenQuota(table, used, estimate, max) => write!(f, "Sending would go over the quota of {} tokens a month in {}: {} used this month plus about {} for this prompt. It resets on the 1st (UTC), or raise max_tokens_per_month there", max, table, used, estimate),
+            Message::UsageLine(name, requests, tokens) => write!(f, "{}: {} requests today, {} tokens this month", name, requests, tokens),
+            Message::QuotaLine(requests, tokens) => write!(f, "quota: {} requests a day, {} tokens a month", requests.map_or("unlimited".to_string(), |max| max.to_string()), tokens.map_or("unlimited".to_string(), |max| max.to_string())),
+            Message::AllBackends => write!(f, "all backends"),
+            Message::NoUsage => write!(f, "No requests this month yet"),
+            Message::PromptTitle => write!(f, " Prompt "),
+            Message::CacheEntries(entries, expired) => write!(f, "Entries: {} ({} expired)", entries, expired),
+            Message::CacheHits(hits) => write!(f, "Hits: {}", hits),
+            Message::CacheSize(kib) => write!(f, "Size: {:.1} KiB", kib),
+            Message::CacheTtl(secs) => write!(f, "TTL: {}s", secs),
+            Message::CacheOff => write!(f, "The cache is off, set cache_ttl_secs to turn it on"),
+            Message::ContextSkipped(name, ms) => write!(f, "Left out the {} context, it took longer than context.timeout_ms ({} ms)", name, ms),
+            Message::QuantizedOnOneDevice => write!(f, "The quantized model runs on the first of the devices, set local_model_config.quantized = false to split the model over all of them"),
+            Message::ModelArchitecture(repo, kind, model) => write!(f, "{} is a {} model, but local_model_config.model = {} loads Phi-{}: set model = 2 for phi repos and 3 for phi3 repos, other architectures aren't supported", repo, kind, model, model),
+            Message::UnsupportedArchitecture(repo, kind) => write!(f, "{} is a {} model, the local backend runs phi and phi3 models", repo, kind),
+            Message::DeliveryFailed(error) => write!(f, "The response wasn't delivered: {}", error),
+            Message::EditorFailed(editor, status) => write!(f, "Editor {} exited with {}", editor, status),
+            Message::UnknownEncryption => write!(f, "Unknown encryption"),
+            Message::EncryptFailed => write!(f, "Unable to encrypt"),
+            Message::TooShortToDecrypt => write!(f, "Too short to be encrypted"),
+            Message::InvalidNonce => write!(f, "Invalid nonce"),
+            Message::AuthenticationFailed => write!(f, "Authentication failed"),
+            Message::InvalidKey => write!(f, "Invalid key"),
+            Message::NoRandomBytes => write!(f, "Unable to get random bytes from the system"),
+            Message::LockPoisoned(lock) => write!(f, "{} lock poisoned", lock),
+            Message::NotAKey(account) => write!(f, "The {} key in the keyring isn't a key", account),
+            Message::DaemonNotRunning => write!(f, "The daemon isn't running"),
+            Message::ModelOnOtherBackend(model, backend, chosen) => write!(f, "--model {} runs on the {} backend, not {}", model, backend, chosen),
+            Message::NoCertificates(path) => write!(f, "No PEM certificates in the ca_bundle {}", path),
+            Message::NoHistoryEntry(id) => write!(f, "No history entry with id {}", id),
+            Message::NoHistoryExport(path) => write!(f, "No history export at {}", path),
+            Message::NewerConfig(path, version, supported) => write!(f, "{} is config version {}, newer than the {} this version of ai understands", path, version, supported),
+            Message::UnexpectedShape(tensor, shape) => write!(f, "Unexpected shape for {} {}", tensor, shape),
+            Message::MfaNeedsTerminal(role) => write!(f, "Assuming {} needs an MFA code, which can only be entered in a terminal", role),
+            Message::NoCredentialsProvider => write!(f, "No AWS credentials provider is configured"),
+            Message::NoStsCredentials(role) => write!(f, "STS returned no credentials for {}", role),
+            Message::SsoExpired(profile) => write!(f, "The AWS SSO session of the {} profile has expired, run `aws sso login --profile {}`", profile, profile),
+            Message::NoAwsCredentials(error) => write!(f, "No AWS credentials: {}", error),
+            Message::StepNeedsPromptOrRun(step) => write!(f, "Step {} needs exactly one of prompt or run", step),
+            Message::StepFailed(step, status, stderr) => write!(f, "Step {} failed with status {}: {}", step, status, stderr),
+            Message::ExpectedKeyValue(arg) => write!(f, "Expected key=value, got '{}'", arg),
+            Message::BuildWithClipboard => write!(f, "build with --features clipboard"),
+            Message::GitCommitFailed(status) => write!(f, "git commit exited with {}", status),
+            Message::GitFailed(args, stderr) => write!(f, "git {} failed: {}", args, stderr),
+            Message::NoWeightMap(file) => write!(f, "No weight map in {}", file),
+            Message::WeightMapNotAMap(file) => write!(f, "The weight map in {} is not a map", file),
+            Message::UnknownDevice(spec) => write!(f, "Unknown device {}, expected cpu, cuda:N or metal:N", spec),
+            Message::NoKeyringKey(name) => write!(f, "No {} key in the keyring", name),
+            Message::NotSafetensors(path) => write!(f, "{} isn't a safetensors file", path),
+            Message::UnknownModel(model, aliases) => write!(f, "Unknown model {}, expected one of the [model_aliases] ({}), hf:<repo> or a Bedrock model id", model, aliases),
+            Message::InvalidRegex(pattern, error) => write!(f, "The response isn't a valid regex: {}\n{}", pattern, error),
+            Message::InvalidJq(filter, error) => write!(f, "The response isn't a valid jq filter: {}\n{}", filter, error),
+            Message::NotAwk(command) => write!(f, "The response isn't an awk command: {}", command),
+            Message::UnclosedQuote(command) => write!(f, "The response isn't a complete command, a quote isn't closed: {}", command),
+            Message::BackendPanicked(backend) => write!(f, "The {} backend panicked", backend),
+            Message::InvalidTemplateName(name) => write!(f, "Invalid template name '{}', use letters, numbers, '-' and '_'", name),
+            Message::NoTemplate(name) => write!(f, "No template named '{}'", name),
+            Message::UnclosedTemplateVariable => write!(f, "Unclosed '{{{{' in template"),
+            Message::MissingTemplateValue(name) => write!(f, "Missing value for template variable '{}', pass --{} <value>", name, name),
+            Message::ExpectedNameValue(arg) => write!(f, "Expected a --name value pair, got '{}'", arg),
+            Message::MissingFlagValue(flag) => write!(f, "Missing value for --{}", flag),
+            Message::InvalidSchemaFile(path, error) => write!(f, "Invalid schema in {}: {}", path, error),
+            Message::InvalidSchema(error) => write!(f, "Invalid schema: {}", error),
+            Message::SchemaMismatch(errors, json) => write!(f, "The response doesn't match the schema:\n{}\n{}", errors, json),
+            Message::InvalidAliasName(name) => write!(f, "Invalid alias name '{}', use letters, numbers, '-' and '_'", name),
+            Message::NoAlias(name) => write!(f, "No alias named '{}'", name),
+            Message::BenchmarkFailed(status) => write!(f, "The benchmark run failed with status {}", status),
+            Message::NoMeasurements => write!(f, "The benchmark run printed no measurements"),
+            Message::UnknownInterval(interval) => write!(f, "Unknown interval '{}', use e.g. 15m or 2h", interval),
+            Message::UnknownSchedule(spec) => write!(f, "Unknown schedule '{}', use hourly, daily <time>, weekdays <time>, weekly <day> <time>, every <n>m|h or a cron expression", spec),
+            Message::EmptyCron => write!(f, "The cron expression is empty"),
+            Message::UnknownCronShorthand(shorthand) => write!(f, "Unknown shorthand '{}'", shorthand),
+            Message::CronFields(found) => write!(f, "Expected 5 fields, found {}", found),
+            Message::CronValue(field, value, min, max) => write!(f, "Invalid {} '{}', expected {}-{}", field, value, min, max),
+            Message::CronStep(step, field) => write!(f, "Invalid step '{}' in the {}", step, field),
+            Message::CronRangeBackwards(field, range) => write!(f, "The {} range '{}' is backwards", field, range),
+            Message::InvalidHour(time) => write!(f, "Invalid hour in '{}'", time),
+            Message::InvalidTime(time) => write!(f, "Invalid time '{}'", time),
+            Message::UnknownDay(day) => write!(f, "Unknown day '{}'", day),
+            Message::NoStdin(program) => write!(f, "Unable to open the stdin of {}", program),
+            Message::CrontabRejected => write!(f, "crontab rejected the updated schedule"),
+            Message::NoScheduledPrompt(id) => write!(f, "No scheduled prompt with id {}", id),
+            Message::DraftNeedsQuantized => write!(f, "Speculative decoding needs the quantized phi-2 model"),
+            Message::TokenizerCantEncode => write!(f, "The tokenizer can't encode text"),
+            Message::NoStopToken => write!(f, "The model has no end of text token to stop at"),
+            Message::ChecksFailed(failed, total) => write!(f, "{} of {} checks failed", failed, total),
+            Message::NoConversation => write!(f, "No previous conversation to continue"),
+            Message::UnknownRedactionKind(kind, kinds) => write!(f, "Unknown redaction kind {}, expected one of {}", kind, kinds),
+            Message::InvalidRedactionPattern(name, error) => write!(f, "Invalid redaction pattern {}: {}", name, error),
+            Message::CannotDecode(error) => write!(f, "Cannot decode: {}", error),
+            Message::UnsupportedBits(bits) => write!(f, "Unsupported bit width {}, expected 4, 5, 6 or 8", bits),
+            Message::GrammarDeadEnd => write!(f, "No token can continue the output under the grammar"),
+            Message::NotADirectory(path) => write!(f, "{} isn't a directory", path),
+            Message::DaemonClosed => write!(f, "The daemon closed the connection"),
+            Message::DaemonNeedsUnix => write!(f, "The daemon needs Unix sockets"),
+            Message::UnexpectedAnswer(answer) => write!(f, "Unexpected answer from the daemon: {}", answer),
+            Message::NotAGeneration(request) => write!(f, "Not a generation: {}", request),
+            Message::DaemonFailed(message) => write!(f, "The daemon failed: {}", message),
+            Message::DaemonStopped => write!(f, "The daemon stopped running"),
+            Message::DaemonAlreadyRunning(path) => write!(f, "The daemon is already running on {}", path),
+            Message::DaemonOtherModel => write!(f, "The daemon runs another model, restart it with the new settings"),
+            Message::DaemonShuttingDown => write!(f, "The daemon is shutting down"),
+            Message::FrameTooLarge(bytes) => write!(f, "Frame of {} bytes is too large", bytes),
+            Message::InvalidKeepAlive(keep_alive) => write!(f, "Invalid daemon.keep_alive {:?}, expected e.g. \"90s\", \"10m\", \"2h\" or \"forever\"", keep_alive),
+            Message::NoKeyringKeyAdd(name) => write!(f, "No {} key in the keyring, add it with `ai auth set {}`", name, name),
+            Message::CommandBackendFailed(command, status, stderr) => write!(f, "Command backend {} failed with status {}: {}", command, status, stderr),
+            Message::QuantizedPhi3 => write!(f, "Quantized Phi-3 isn't supported"),
+            Message::UnknownDraftModel(model) => write!(f, "Unknown draft model architecture {}, use 1.5 or 2", model),
+            Message::ToolNeeds(tool, argument) => write!(f, "{} needs a {}", tool, argument),
+            Message::UnknownTool(tool) => write!(f, "Unknown tool {}", tool),
+            Message::OutsideWorkingTree(path) => write!(f, "{} is outside the current directory", path),
+            Message::SecretFile(path) => write!(f, "{} may hold secrets and isn't shared", path),
+            Message::EmptyCommand => write!(f, "Empty command"),
+            Message::NotReadOnly(command) => write!(f, "{} is not an allowed read only command", command),
+            Message::CommandTimedOut(command) => write!(f, "{} timed out", command),
+            Message::NoMockResponse(prompt) => write!(f, "No mock response matches the prompt: {}", prompt),
+            Message::PluginFailed(plugin, message) => write!(f, "The {} plugin failed: {}", plugin, message),
+            Message::PluginStatus(plugin, status, stderr) => write!(f, "The {} plugin failed with status {}: {}", plugin, status, stderr),
+            Message::NoCommandBackend => write!(f, "The command backend needs a [command_backend] table with cmd set"),
+            Message::AutoNeedsBackend => write!(f, "The auto backend picks one per prompt, choose one for this command with -b"),
+            Message::UnknownBackend(name, prefix) => write!(f, "Unknown backend: {}, not built in and no {}{} plugin on PATH", name, prefix, name),
+            Message::NoBedrockPrompt => write!(f, "There is no prompt to send to Bedrock"),
+            Message::BedrockStartsWithUser => write!(f, "Bedrock conversations have to start with a user message"),
+            Message::BuildFailed => write!(f, "Failed to build the message"),
+            Message::UnknownStreamError => write!(f, "Unable to see stream error message"),
+            Message::InvalidToolInput(error) => write!(f, "The tool input isn't valid json: {}", error),
+            Message::BedrockTimedOut(secs) => write!(f, "Bedrock didn't respond within {}s", secs),
+            Message::BedrockInterrupted => write!(f, "Interrupted before Bedrock responded"),
+            Message::SendFailed(error) => write!(f, "Failed to send message: {}", error),
+            Message::NoBedrockRegions => write!(f, "No regions configured for bedrock"),
+            Message::TooManyToolRounds(rounds) => write!(f, "The model kept calling tools after {} rounds", rounds),
+            Message::ToolResultsFailed(error) => write!(f, "Failed to send tool results: {}", error),
+            Message::HookFailed(hook, path, status) => write!(f, "The {} hook {} failed with status {}", hook, path, status),
+            Message::HookPrintedNothing(hook, path) => write!(f, "The {} hook {} printed nothing", hook, path),
+            Message::NoDevice => write!(f, "No device to load the model on"),
+            Message::NotText(path) => write!(f, "Attached file {} isn't text", path),
+            Message::UnknownContextProvider(name, names) => write!(f, "Unknown context provider {}, expected one of {}", name, names),
+        }
+    }
+
+    fn spanish(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::PromptEmpty => write!(f, "La consulta está vacía"),
+            Message::NoOutput => write!(f, "No se generó ninguna respuesta"),
+            Message::NoCommandSelected => write!(f, "No se seleccionó ningún comando"),
+            Message::PickCommand => write!(f, "Elige un comando"),
+            Message::Generating => write!(f, "Generando…"),
+            Message::Thinking => write!(f, "Pensando..."),
+            Message::Done => write!(f, "Listo"),
+            Message::SavedTemplate(name) => write!(f, "Plantilla {} guardada", name),
+            Message::DeletedTemplate(name) => write!(f, "Plantilla {} eliminada", name),
+            Message::ScheduledPrompt(id) => write!(f, "Consulta {} programada", id),
+            Message::RemovedScheduledPrompt(id) => {
+                write!(f, "Consulta programada {} eliminada", id)
+            }
+            Message::UnknownWebhook(name) => write!(
+                f,
+                "No hay ningún webhook llamado {} en la configuración",
+                name
+            ),
+            Message::UsageUnavailable(backend) => write!(
+                f,
+                "Las estadísticas de uso no están disponibles para el backend {}",
+                backend
+            ),
+            Message::Created(path) => write!(f, "Creado {}", path),
+            Message::Removed(path) => write!(f, "Eliminado {}", path),
+            Message::RemovedScheduledPrompts(count) => write!(
+                f,
+                "{} consulta(s) programada(s) eliminada(s) del crontab",
+                count
+            ),
+            Message::NoFailedCommand => write!(f, "No hay ningún comando fallido que corregir, usa --command o configura la integración con el shell con `eval \"$(ai shell-init bash)\"`"),
+            Message::ActionMenu => write!(f, "[c] copiar  [e] editar y ejecutar  [r] ejecutar  [x] regenerar  [q] salir"),
+            Message::Copied => write!(f, "Copiado al portapapeles"),
+            Message::ClipboardUnavailable => write!(f, "El portapapeles no está disponible"),
+            Message::ExitStatus(code) => write!(f, "Código de salida {}", code),
+            Message::NothingStaged => write!(f, "No hay cambios preparados, añádelos primero con git add"),
+            Message::ConfirmCommit => write!(f, "¿Hacer commit con este mensaje?"),
+            Message::EnterApiKey(name) => write!(f, "Clave de API para {}", name),
+            Message::EmptyApiKey => write!(f, "La clave de API está vacía"),
+            Message::SavedApiKey(name) => write!(f, "Clave {} guardada en el llavero", name),
+            Message::RemovedApiKey(name) => write!(f, "Clave {} eliminada del llavero", name),
+            Message::TimedOut(secs) => write!(f, "Tiempo agotado tras {}s, la salida está incompleta", secs),
+            Message::Interrupted => write!(f, "Interrumpido, la salida está incompleta"),
+            Message::ClearedCache(count) => write!(f, "Eliminadas {} respuestas en caché", count),
+            Message::CachedResponse => write!(f, "Respuesta de la caché, usa --no-cache para generar de nuevo"),
+            Message::TemplateMissing => write!(f, "Indica el texto de la plantilla como argumento o con --prompt-file"),
+            Message::WroteScript(path) => write!(f, "Script guardado en {}", path),
+            Message::BatchFinished(total, failed) => write!(f, "{} prompts ejecutados, {} fallidos", total, failed),
+            Message::TokenCount(tokens, sample_len) => write!(f, "{} tokens, más hasta {} generados (sample_len)", tokens, sample_len),
+            Message::ExceedsContext(length) => write!(f, "El prompt y sample_len superan el contexto del modelo de {} tokens, acorta el prompt o reduce sample_len", length),
+            Message::OverBudget(spent, estimate, budget) => write!(f, "Enviar superaría el presupuesto mensual: ${:.4} gastados más unos ${:.4} por este prompt supera ${:.2}. Usa --force para enviarlo igualmente", spent, estimate, budget),
+            Message::SearchHistory => write!(f, "Buscar en el historial:"),
+            Message::SavedAlias(name) => write!(f, "Alias {} guardado", name),
+            Message::DeletedAlias(name) => write!(f, "Alias {} eliminado", name),
+            Message::NothingToAlias => write!(f, "Aún no hay ningún comando generado que guardar, genera uno primero"),
+            Message::NoPipeInput => write!(f, "Nada que procesar, pasa el texto por una tubería, p. ej. `cat app.log | ai pipe resume los errores`"),
+            Message::IndexCleared => write!(f, "Índice eliminado"),
+            Message::Indexed(files, embedded, chunks) => write!(f, "{} archivos indexados ({} nuevos o modificados), {} fragmentos en el índice. Añade los relevantes a un prompt con --context index", files, embedded, chunks),
+            Message::TuiKeys => write!(f, "Enter enviar  ↑/↓ historial  Ctrl-Y copiar  Ctrl-R ejecutar  Ctrl-G regenerar  Esc salir"),
+            Message::PressAnyKey => write!(f, "Pulsa cualquier tecla para volver"),
+            Message::NotATerminal => write!(f, "ai tui necesita una terminal interactiva"),
+            Message::RegenerateHint => write!(f, "Pulsa r para regenerar"),
+            Message::ModelTooLarge(needed, limit) => write!(f, "El modelo local necesita unos {:.1} GiB pero solo hay {:.1} GiB disponibles, configura local_model_config.quantized = true, reduce sample_len o aumenta max_memory_gb", needed, limit),
+            Message::QuantizedFallback(needed, limit) => write!(f, "El modelo local necesita unos {:.1} GiB pero solo hay {:.1} GiB disponibles, se usa el modelo cuantizado", needed, limit),
+            Message::NoCommandToUndo => write!(f, "No hay ningún comando que deshacer, pásalo como `ai undo '<comando>'` o configura la integración con el shell con `eval \"$(ai shell-init bash)\"`"),
+            Message::NotAGitRepository => write!(f, "No estás en un repositorio git, ejecuta `ai git` dentro de uno"),
+            Message::NoErrorInput => write!(f, "No hay ningún error que explicar, pásalo por una tubería, p. ej. `make 2>&1 | ai explain-error`"),
+            Message::ImportedHistory(imported, skipped) => write!(f, "{} entradas importadas al historial, {} omitidas por estar ya en él", imported, skipped),
+            Message::HistoryPassphrase => write!(f, "Frase de contraseña del historial"),
+            Message::RepeatHistoryPassphrase => write!(f, "Repite la frase de contraseña"),
+            Message::HistoryPassphraseMismatch => write!(f, "Las frases de contraseña no coinciden"),
+            Message::NoHistoryPassphrase => write!(f, "El historial está cifrado con una frase de contraseña, define AI_HISTORY_PASSPHRASE o ejecuta ai en una terminal para introducirla"),
+            Message::NoHistoryKey => write!(f, "El historial está cifrado con una clave del llavero, pero este llavero no la tiene"),
+            Message::Redacted(backend, found) => write!(f, "Ocultado antes de enviar a {}: {}. Usa --no-redact para enviarlos tal cual", backend, found),
+            Message::LocalOnly(what) => write!(f, "{} necesita la red, que el modo solo local (--local-only o local_only = true) prohíbe", what),
+            Message::RemoteBackendLocalOnly(backend) => write!(f, "El backend {} envía los prompts fuera de esta máquina, lo que el modo solo local (--local-only o local_only = true) prohíbe. Usa -b local", backend),
+            Message::NotCached(file, model) => write!(f, "{} de {} no está en la caché de modelos y el modo solo local no puede descargarlo. Ejecuta ai una vez sin --local-only en una máquina con red y copia la caché de Hugging Face (HF_HOME)", file, model),
+            Message::NoAuditLog(path) => write!(f, "No hay registro de auditoría en {}. Actívalo con enabled = true en [audit] en la configuración", path),
+            Message::RequestQuota(table, used, max) => write!(f, "La cuota de {} peticiones al día de {} está agotada ({} hechas hoy). Se reinicia a medianoche UTC, o sube max_requests_per_day ahí", max, table, used),
+            Message::TokenQuota(table, used, estimate, max) => write!(f, "Enviar superaría la cuota de {} tokens al mes de {}: {} usados este mes más unos {} de este prompt. Se reinicia el día 1 (UTC), o sube max_tokens_per_month ahí", max, table, used, estimate),
+            Message::UsageLine(name, requests, tokens) => write!(f, "{}: {} peticiones hoy, {} tokens este mes", name, requests, tokens),
+            Message::QuotaLine(requests, tokens) => write!(f, "cuota: {} peticiones al día, {} tokens al mes", requests.map_or("ilimitadas".to_string(), |max| max.to_string()), tokens.map_or("ilimitados".to_string(), |max| max.to_string())),
+            Message::AllBackends => write!(f, "todos los backends"),
+            Message::NoUsage => write!(f, "Aún no hay peticiones este mes"),
+            Message::PromptTitle => write!(f, " Instrucción "),
+            Message::CacheEntries(entries, expired) => write!(f, "Entradas: {} ({} caducadas)", entries, expired),
+            Message::CacheHits(hits) => write!(f, "Aciertos: {}", hits),
+            Message::CacheSize(kib) => write!(f, "Tamaño: {:.1} KiB", kib),
+            Message::CacheTtl(secs) => write!(f, "Vigencia: {}s", secs),
+            Message::CacheOff => write!(f, "La caché está desactivada, define cache_ttl_secs para activarla"),
+            Message::ContextSkipped(name, ms) => write!(f, "Se omitió el contexto {}, tardó más que context.timeout_ms ({} ms)", name, ms),
+            Message::QuantizedOnOneDevice => write!(f, "El modelo cuantizado se ejecuta en el primero de los dispositivos, configura local_model_config.quantized = false para repartir el modelo entre todos"),
+            Message::ModelArchitecture(repo, kind, model) => write!(f, "{} es un modelo {}, pero local_model_config.model = {} carga Phi-{}: configura model = 2 para repositorios phi y 3 para phi3, otras arquitecturas no son compatibles", repo, kind, model, model),
+            Message::UnsupportedArchitecture(repo, kind) => write!(f, "{} es un modelo {}, el backend local ejecuta modelos phi y phi3", repo, kind),
+            Message::DeliveryFailed(error) => write!(f, "No se entregó la respuesta: {}", error),
+            Message::EditorFailed(editor, status) => write!(f, "El editor {} terminó con {}", editor, status),
+            Message::UnknownEncryption => write!(f, "Cifrado desconocido"),
+            Message::EncryptFailed => write!(f, "No se pudo cifrar"),
+            Message::TooShortToDecrypt => write!(f, "Demasiado corto para estar cifrado"),
+            Message::InvalidNonce => write!(f, "Nonce no válido"),
+            Message::AuthenticationFailed => write!(f, "Falló la autenticación"),
+            Message::InvalidKey => write!(f, "Clave no válida"),
+            Message::NoRandomBytes => write!(f, "No se pudieron obtener bytes aleatorios del sistema"),
+            Message::LockPoisoned(lock) => write!(f, "El bloqueo {} quedó envenenado", lock),
+            Message::NotAKey(account) => write!(f, "La clave {} del llavero no es una clave", account),
+            Message::DaemonNotRunning => write!(f, "El daemon no está en ejecución"),
+            Message::ModelOnOtherBackend(model, backend, chosen) => write!(f, "--model {} se ejecuta en el backend {}, no en {}", model, backend, chosen),
+            Message::NoCertificates(path) => write!(f, "No hay certificados PEM en el ca_bundle {}", path),
+            Message::NoHistoryEntry(id) => write!(f, "No hay ninguna entrada del historial con id {}", id),
+            Message::NoHistoryExport(path) => write!(f, "No hay ninguna exportación del historial en {}", path),
+            Message::NewerConfig(path, version, supported) => write!(f, "{} tiene la versión de configuración {}, más nueva que la {} que entiende esta versión de ai", path, version, supported),
+            Message::UnexpectedShape(tensor, shape) => write!(f, "Forma inesperada para {} {}", tensor, shape),
+            Message::MfaNeedsTerminal(role) => write!(f, "Asumir {} necesita un código MFA, que solo se puede introducir en una terminal", role),
+            Message::NoCredentialsProvider => write!(f, "No hay ningún proveedor de credenciales de AWS configurado"),
+            Message::NoStsCredentials(role) => write!(f, "STS no devolvió credenciales para {}", role),
+            Message::SsoExpired(profile) => write!(f, "La sesión de AWS SSO del perfil {} ha caducado, ejecuta `aws sso login --profile {}`", profile, profile),
+            Message::NoAwsCredentials(error) => write!(f, "No hay credenciales de AWS: {}", error),
+            Message::StepNeedsPromptOrRun(step) => write!(f, "El paso {} necesita exactamente uno de prompt o run", step),
+            Message::StepFailed(step, status, stderr) => write!(f, "El paso {} falló con el estado {}: {}", step, status, stderr),
+            Message::ExpectedKeyValue(arg) => write!(f, "Se esperaba clave=valor, se recibió '{}'", arg),
+            Message::BuildWithClipboard => write!(f, "compila con --features clipboard"),
+            Message::GitCommitFailed(status) => write!(f, "git commit terminó con {}", status),
+            Message::GitFailed(args, stderr) => write!(f, "git {} falló: {}", args, stderr),
+            Message::NoWeightMap(file) => write!(f, "No hay mapa de pesos en {}", file),
+            Message::WeightMapNotAMap(file) => write!(f, "El mapa de pesos de {} no es un mapa", file),
+            Message::UnknownDevice(spec) => write!(f, "Dispositivo desconocido {}, se esperaba cpu, cuda:N o metal:N", spec),
+            Message::NoKeyringKey(name) => write!(f, "No hay ninguna clave {} en el llavero", name),
+            Message::NotSafetensors(path) => write!(f, "{} no es un archivo safetensors", path),
+            Message::UnknownModel(model, aliases) => write!(f, "Modelo desconocido {}, se esperaba uno de los [model_aliases] ({}), hf:<repo> o un id de modelo de Bedrock", model, aliases),
+            Message::InvalidRegex(pattern, error) => write!(f, "La respuesta no es una expresión regular válida: {}\n{}", pattern, error),
+            Message::InvalidJq(filter, error) => write!(f, "La respuesta no es un filtro jq válido: {}\n{}", filter, error),
+            Message::NotAwk(command) => write!(f, "La respuesta no es un comando awk: {}", command),
+            Message::UnclosedQuote(command) => write!(f, "La respuesta no es un comando completo, hay una comilla sin cerrar: {}", command),
+            Message::BackendPanicked(backend) => write!(f, "El backend {} entró en pánico", backend),
+            Message::InvalidTemplateName(name) => write!(f, "Nombre de plantilla no válido '{}', usa letras, números, '-' y '_'", name),
+            Message::NoTemplate(name) => write!(f, "No hay ninguna plantilla llamada '{}'", name),
+            Message::UnclosedTemplateVariable => write!(f, "'{{{{' sin cerrar en la plantilla"),
+            Message::MissingTemplateValue(name) => write!(f, "Falta el valor de la variable de plantilla '{}', pasa --{} <valor>", name, name),
+            Message::ExpectedNameValue(arg) => write!(f, "Se esperaba un par --nombre valor, se recibió '{}'", arg),
+            Message::MissingFlagValue(flag) => write!(f, "Falta el valor de --{}", flag),
+            Message::InvalidSchemaFile(path, error) => write!(f, "Esquema no válido en {}: {}", path, error),
+            Message::InvalidSchema(error) => write!(f, "Esquema no válido: {}", error),
+            Message::SchemaMismatch(errors, json) => write!(f, "La respuesta no coincide con el esquema:\n{}\n{}", errors, json),
+            Message::InvalidAliasName(name) => write!(f, "Nombre de alias no válido '{}', usa letras, números, '-' y '_'", name),
+            Message::NoAlias(name) => write!(f, "No hay ningún alias llamado '{}'", name),
+            Message::BenchmarkFailed(status) => write!(f, "La ejecución de la prueba de rendimiento falló con el estado {}", status),
+            Message::NoMeasurements => write!(f, "La ejecución de la prueba de rendimiento no imprimió mediciones"),
+            Message::UnknownInterval(interval) => write!(f, "Intervalo desconocido '{}', usa p. ej. 15m o 2h", interval),
+            Message::UnknownSchedule(spec) => write!(f, "Programación desconocida '{}', usa hourly, daily <hora>, weekdays <hora>, weekly <día> <hora>, every <n>m|h o una expresión cron", spec),
+            Message::EmptyCron => write!(f, "La expresión cron está vacía"),
+            Message::UnknownCronShorthand(shorthand) => write!(f, "Abreviatura desconocida '{}'", shorthand),
+            Message::CronFields(found) => write!(f, "Se esperaban 5 campos, se encontraron {}", found),
+            Message::CronValue(field, value, min, max) => write!(f, "Valor de {} no válido '{}', se esperaba {}-{}", field, value, min, max),
+            Message::CronStep(step, field) => write!(f, "Paso no válido '{}' en el campo {}", step, field),
+            Message::CronRangeBackwards(field, range) => write!(f, "El rango de {} '{}' está al revés", field, range),
+            Message::InvalidHour(time) => write!(f, "Hora no válida en '{}'", time),
+            Message::InvalidTime(time) => write!(f, "Hora no válida '{}'", time),
+            Message::UnknownDay(day) => write!(f, "Día desconocido '{}'", day),
+            Message::NoStdin(program) => write!(f, "No se pudo abrir la entrada estándar de {}", program),
+            Message::CrontabRejected => write!(f, "crontab rechazó la programación actualizada"),
+            Message::NoScheduledPrompt(id) => write!(f, "No hay ningún prompt programado con id {}", id),
+            Message::DraftNeedsQuantized => write!(f, "La decodificación especulativa necesita el modelo phi-2 cuantizado"),
+            Message::TokenizerCantEncode => write!(f, "El tokenizador no puede codificar texto"),
+            Message::NoStopToken => write!(f, "El modelo no tiene un token de fin de texto en el que detenerse"),
+            Message::ChecksFailed(failed, total) => write!(f, "Fallaron {} de {} comprobaciones", failed, total),
+            Message::NoConversation => write!(f, "No hay ninguna conversación anterior que continuar"),
+            Message::UnknownRedactionKind(kind, kinds) => write!(f, "Tipo de redacción desconocido {}, se esperaba uno de {}", kind, kinds),
+            Message::InvalidRedactionPattern(name, error) => write!(f, "Patrón de redacción no válido {}: {}", name, error),
+            Message::CannotDecode(error) => write!(f, "No se puede decodificar: {}", error),
+            Message::UnsupportedBits(bits) => write!(f, "Ancho de bits no admitido {}, se esperaba 4, 5, 6 u 8", bits),
+            Message::GrammarDeadEnd => write!(f, "Ningún token puede continuar la salida según la gramática"),
+            Message::NotADirectory(path) => write!(f, "{} no es un directorio", path),
+            Message::DaemonClosed => write!(f, "El demonio cerró la conexión"),
+            Message::DaemonNeedsUnix => write!(f, "El demonio necesita sockets Unix"),
+            Message::UnexpectedAnswer(answer) => write!(f, "Respuesta inesperada del demonio: {}", answer),
+            Message::NotAGeneration(request) => write!(f, "No es una generación: {}", request),
+            Message::DaemonFailed(message) => write!(f, "El demonio falló: {}", message),
+            Message::DaemonStopped => write!(f, "El demonio dejó de ejecutarse"),
+            Message::DaemonAlreadyRunning(path) => write!(f, "El demonio ya se está ejecutando en {}", path),
+            Message::DaemonOtherModel => write!(f, "El demonio ejecuta otro modelo, reinícialo con la nueva configuración"),
+            Message::DaemonShuttingDown => write!(f, "El demonio se está apagando"),
+            Message::FrameTooLarge(bytes) => write!(f, "La trama de {} bytes es demasiado grande", bytes),
+            Message::InvalidKeepAlive(keep_alive) => write!(f, "daemon.keep_alive no válido {:?}, se esperaba p. ej. \"90s\", \"10m\", \"2h\" o \"forever\"", keep_alive),
+            Message::NoKeyringKeyAdd(name) => write!(f, "No hay ninguna clave {} en el llavero, añádela con `ai auth set {}`", name, name),
+            Message::CommandBackendFailed(command, status, stderr) => write!(f, "El backend de comando {} falló con el estado {}: {}", command, status, stderr),
+            Message::QuantizedPhi3 => write!(f, "Phi-3 cuantizado no es compatible"),
+            Message::UnknownDraftModel(model) => write!(f, "Arquitectura de modelo borrador desconocida {}, usa 1.5 o 2", model),
+            Message::ToolNeeds(tool, argument) => write!(f, "{} necesita el argumento {}", tool, argument),
+            Message::UnknownTool(tool) => write!(f, "Herramienta desconocida {}", tool),
+            Message::OutsideWorkingTree(path) => write!(f, "{} está fuera del directorio actual", path),
+            Message::SecretFile(path) => write!(f, "{} puede contener secretos y no se comparte", path),
+            Message::EmptyCommand => write!(f, "Comando vacío"),
+            Message::NotReadOnly(command) => write!(f, "{} no es un comando de solo lectura permitido", command),
+            Message::CommandTimedOut(command) => write!(f, "{} agotó el tiempo de espera", command),
+            Message::NoMockResponse(prompt) => write!(f, "Ninguna respuesta simulada coincide con el prompt: {}", prompt),
+            Message::PluginFailed(plugin, message) => write!(f, "El plugin {} falló: {}", plugin, message),
+            Message::PluginStatus(plugin, status, stderr) => write!(f, "El plugin {} falló con el estado {}: {}", plugin, status, stderr),
+            Message::NoCommandBackend => write!(f, "El backend de comando necesita una tabla [command_backend] con cmd definido"),
+            Message::AutoNeedsBackend => write!(f, "El backend auto elige uno por prompt, elige uno para este comando con -b"),
+            Message::UnknownBackend(name, prefix) => write!(f, "Backend desconocido: {}, no está integrado y no hay ningún plugin {}{} en el PATH", name, prefix, name),
+            Message::NoBedrockPrompt => write!(f, "No hay ningún prompt que enviar a Bedrock"),
+            Message::BedrockStartsWithUser => write!(f, "Las conversaciones de Bedrock tienen que empezar con un mensaje del usuario"),
+            Message::BuildFailed => write!(f, "No se pudo construir el mensaje"),
+            Message::UnknownStreamError => write!(f, "No se pudo ver el mensaje de error del flujo"),
+            Message::InvalidToolInput(error) => write!(f, "La entrada de la herramienta no es json válido: {}", error),
+            Message::BedrockTimedOut(secs) => write!(f, "Bedrock no respondió en {}s", secs),
+            Message::BedrockInterrupted => write!(f, "Interrumpido antes de que Bedrock respondiera"),
+            Message::SendFailed(error) => write!(f, "No se pudo enviar el mensaje: {}", error),
+            Message::NoBedrockRegions => write!(f, "No hay regiones configuradas para bedrock"),
+            Message::TooManyToolRounds(rounds) => write!(f, "El modelo siguió llamando herramientas después de {} rondas", rounds),
+            Message::ToolResultsFailed(error) => write!(f, "No se pudieron enviar los resultados de las herramientas: {}", error),
+            Message::HookFailed(hook, path, status) => write!(f, "El hook {} {} falló con el estado {}", hook, path, status),
+            Message::HookPrintedNothing(hook, path) => write!(f, "El hook {} {} no imprimió nada", hook, path),
+            Message::NoDevice => write!(f, "No hay ningún dispositivo en el que cargar el modelo"),
+            Message::NotText(path) => write!(f, "El archivo adjunto {} no es texto", path),
+            Message::UnknownContextProvider(name, names) => write!(f, "Proveedor de contexto desconocido {}, se esperaba uno de {}", name, names),
+        }
+    }
+
+    fn german(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::PromptEmpty => write!(f, "Die Eingabe ist leer"),
+            Message::NoOutput => write!(f, "Es wurde keine Ausgabe erzeugt"),
+            Message::NoCommandSelected => write!(f, "Kein Befehl ausgewählt"),
+            Message::PickCommand => write!(f, "Befehl auswählen"),
+            Message::Generating => write!(f, "Wird erzeugt…"),
+            Message::Thinking => write!(f, "Denke nach..."),
+            Message::Done => write!(f, "Fertig"),
+            Message::SavedTemplate(name) => write!(f, "Vorlage {} gespeichert", name),
+            Message::DeletedTemplate(name) => write!(f, "Vorlage {} gelöscht", name),
+            Message::ScheduledPrompt(id) => write!(f, "Eingabe {} geplant", id),
+            Message::RemovedScheduledPrompt(id) => {
+                write!(f, "Geplante Eingabe {} entfernt", id)
+            }
+            Message::UnknownWebhook(name) => {
+                write!(f, "Kein Webhook namens {} in den Einstellungen", name)
+            }
+            Message::UsageUnavailable(backend) => write!(
+                f,
+                "Für das Backend {} sind keine Nutzungsstatistiken verfügbar",
+                backend
+            ),
+            Message::Created(path) => write!(f, "{} erstellt", path),
+            Message::Removed(path) => write!(f, "{} entfernt", path),
+            Message::RemovedScheduledPrompts(count) => {
+                write!(f, "{} geplante Eingabe(n) aus der Crontab entfernt", count)
+            }
+            Message::NoFailedCommand => write!(f, "Kein fehlgeschlagener Befehl zum Korrigieren, --command angeben oder die Shell-Integration mit `eval \"$(ai shell-init bash)\"` einrichten"),
+            Message::ActionMenu => write!(f, "[c] kopieren  [e] bearbeiten und ausführen  [r] ausführen  [x] neu erzeugen  [q] beenden"),
+            Message::Copied => write!(f, "In die Zwischenablage kopiert"),
+            Message::ClipboardUnavailable => write!(f, "Die Zwischenablage ist nicht verfügbar"),
+            Message::ExitStatus(code) => write!(f, "Exit-Status {}", code),
+            Message::NothingStaged => write!(f, "Nichts vorgemerkt, Änderungen zuerst mit git add hinzufügen"),
+            Message::ConfirmCommit => write!(f, "Mit dieser Nachricht committen?"),
+            Message::EnterApiKey(name) => write!(f, "API-Schlüssel für {}", name),
+            Message::EmptyApiKey => write!(f, "Der API-Schlüssel ist leer"),
+            Message::SavedApiKey(name) => write!(f, "Schlüssel {} im Schlüsselbund gespeichert", name),
+            Message::RemovedApiKey(name) => write!(f, "Schlüssel {} aus dem Schlüsselbund entfernt", name),
+            Message::TimedOut(secs) => write!(f, "Zeitüberschreitung nach {}s, die Ausgabe ist unvollständig", secs),
+            Message::Interrupted => write!(f, "Abgebrochen, die Ausgabe ist unvollständig"),
+            Message::ClearedCache(count) => write!(f, "{} zwischengespeicherte Antworten entfernt", count),
+            Message::CachedResponse => write!(f, "Antwort aus dem Cache, --no-cache erzeugt sie neu"),
+            Message::TemplateMissing => write!(f, "Gib den Vorlagentext als Argument oder mit --prompt-file an"),
+            Message::WroteScript(path) => write!(f, "Skript nach {} geschrieben", path),
+            Message::BatchFinished(total, failed) => write!(f, "{} Prompts ausgeführt, {} fehlgeschlagen", total, failed),
+            Message::TokenCount(tokens, sample_len) => write!(f, "{} Tokens, plus bis zu {} generierte (sample_len)", tokens, sample_len),
+            Message::ExceedsContext(length) => write!(f, "Prompt und sample_len überschreiten den Kontext des Modells von {} Tokens, kürze den Prompt oder senke sample_len", length),
+            Message::OverBudget(spent, estimate, budget) => write!(f, "Das Senden würde das Monatsbudget überschreiten: ${:.4} ausgegeben plus etwa ${:.4} für diesen Prompt ist mehr als ${:.2}. Mit --force trotzdem senden", spent, estimate, budget),
+            Message::SearchHistory => write!(f, "Verlauf durchsuchen:"),
+            Message::SavedAlias(name) => write!(f, "Alias {} gespeichert", name),
+            Message::DeletedAlias(name) => write!(f, "Alias {} gelöscht", name),
+            Message::NothingToAlias => write!(f, "Noch kein generierter Befehl zum Speichern, erst einen generieren"),
+            Message::NoPipeInput => write!(f, "Nichts zu verarbeiten, den Text per Pipe übergeben, z. B. `cat app.log | ai pipe fasse die Fehler zusammen`"),
+            Message::IndexCleared => write!(f, "Index gelöscht"),
+            Message::Indexed(files, embedded, chunks) => write!(f, "{} Dateien indexiert ({} neu oder geändert), {} Abschnitte im Index. Mit --context index kommen die passenden in den Prompt", files, embedded, chunks),
+            Message::TuiKeys => write!(f, "Enter senden  ↑/↓ Verlauf  Ctrl-Y kopieren  Ctrl-R ausführen  Ctrl-G neu erzeugen  Esc beenden"),
+            Message::PressAnyKey => write!(f, "Beliebige Taste drücken, um zurückzukehren"),
+            Message::NotATerminal => write!(f, "ai tui braucht ein interaktives Terminal"),
+            Message::RegenerateHint => write!(f, "r drücken, um neu zu erzeugen"),
+            Message::ModelTooLarge(needed, limit) => write!(f, "Das lokale Modell braucht etwa {:.1} GiB, verfügbar sind nur {:.1} GiB, setze local_model_config.quantized = true, verringere sample_len oder erhöhe max_memory_gb", needed, limit),
+            Message::QuantizedFallback(needed, limit) => write!(f, "Das lokale Modell braucht etwa {:.1} GiB, verfügbar sind nur {:.1} GiB, stattdessen wird das quantisierte Modell verwendet", needed, limit),
+            Message::NoCommandToUndo => write!(f, "Kein Befehl zum Rückgängigmachen, ihn als `ai undo '<Befehl>'` angeben oder die Shell-Integration mit `eval \"$(ai shell-init bash)\"` einrichten"),
+            Message::NotAGitRepository => write!(f, "Nicht in einem Git-Repository, `ai git` darin ausführen"),
+            Message::NoErrorInput => write!(f, "Kein Fehler zum Erklären, ihn per Pipe übergeben, z. B. `make 2>&1 | ai explain-error`"),
+            Message::ImportedHistory(imported, skipped) => write!(f, "{} Einträge in den Verlauf importiert, {} übersprungen, da schon vorhanden", imported, skipped),
+            Message::HistoryPassphrase => write!(f, "Passphrase für den Verlauf"),
+            Message::RepeatHistoryPassphrase => write!(f, "Passphrase wiederholen"),
+            Message::HistoryPassphraseMismatch => write!(f, "Die Passphrasen stimmen nicht überein"),
+            Message::NoHistoryPassphrase => write!(f, "Der Verlauf ist mit einer Passphrase verschlüsselt, AI_HISTORY_PASSPHRASE setzen oder ai in einem Terminal ausführen, um sie einzugeben"),
+            Message::NoHistoryKey => write!(f, "Der Verlauf ist mit einem Schlüssel aus dem Schlüsselbund verschlüsselt, dieser Schlüsselbund hat aber keinen"),
+            Message::Redacted(backend, found) => write!(f, "Vor dem Senden an {} geschwärzt: {}. Mit --no-redact werden sie unverändert gesendet", backend, found),
+            Message::LocalOnly(what) => write!(f, "{} braucht das Netzwerk, das der Nur-lokal-Modus (--local-only oder local_only = true) verbietet", what),
+            Message::RemoteBackendLocalOnly(backend) => write!(f, "Das Backend {} sendet Prompts von diesem Rechner weg, was der Nur-lokal-Modus (--local-only oder local_only = true) verbietet. -b local verwenden", backend),
+            Message::NotCached(file, model) => write!(f, "{} von {} ist nicht im Modell-Cache und der Nur-lokal-Modus kann es nicht herunterladen. ai einmal ohne --local-only auf einem Rechner mit Netzwerk ausführen und den Hugging-Face-Cache (HF_HOME) kopieren", file, model),
+            Message::NoAuditLog(path) => write!(f, "Unter {} gibt es kein Audit-Log. Mit enabled = true unter [audit] in der Konfiguration einschalten", path),
+            Message::RequestQuota(table, used, max) => write!(f, "Das Kontingent von {} Anfragen pro Tag in {} ist aufgebraucht ({} heute). Es wird um Mitternacht UTC zurückgesetzt, oder max_requests_per_day dort erhöhen", max, table, used),
+            Message::TokenQuota(table, used, estimate, max) => write!(f, "Das Senden würde das Kontingent von {} Tokens pro Monat in {} überschreiten: {} in diesem Monat verbraucht plus etwa {} für diesen Prompt. Es wird am 1. (UTC) zurückgesetzt, oder max_tokens_per_month dort erhöhen", max, table, used, estimate),
+            Message::UsageLine(name, requests, tokens) => write!(f, "{}: {} Anfragen heute, {} Tokens diesen Monat", name, requests, tokens),
+            Message::QuotaLine(requests, tokens) => write!(f, "Kontingent: {} Anfragen pro Tag, {} Tokens pro Monat", requests.map_or("unbegrenzt".to_string(), |max| max.to_string()), tokens.map_or("unbegrenzt".to_string(), |max| max.to_string())),
+            Message::AllBackends => write!(f, "alle Backends"),
+            Message::NoUsage => write!(f, "Diesen Monat noch keine Anfragen"),
+            Message::PromptTitle => write!(f, " Eingabe "),
+            Message::CacheEntries(entries, expired) => write!(f, "Einträge: {} ({} abgelaufen)", entries, expired),
+            Message::CacheHits(hits) => write!(f, "Treffer: {}", hits),
+            Message::CacheSize(kib) => write!(f, "Größe: {:.1} KiB", kib),
+            Message::CacheTtl(secs) => write!(f, "Gültigkeit: {}s", secs),
+            Message::CacheOff => write!(f, "Der Cache ist aus, setze cache_ttl_secs, um ihn einzuschalten"),
+            Message::ContextSkipped(name, ms) => write!(f, "Der Kontext {} wurde weggelassen, er brauchte länger als context.timeout_ms ({} ms)", name, ms),
+            Message::QuantizedOnOneDevice => write!(f, "Das quantisierte Modell läuft auf dem ersten der Geräte, setze local_model_config.quantized = false, um das Modell auf alle zu verteilen"),
+            Message::ModelArchitecture(repo, kind, model) => write!(f, "{} ist ein {}-Modell, aber local_model_config.model = {} lädt Phi-{}: setze model = 2 für phi-Repos und 3 für phi3-Repos, andere Architekturen werden nicht unterstützt", repo, kind, model, model),
+            Message::UnsupportedArchitecture(repo, kind) => write!(f, "{} ist ein {}-Modell, das lokale Backend führt phi- und phi3-Modelle aus", repo, kind),
+            Message::DeliveryFailed(error) => write!(f, "Die Antwort wurde nicht zugestellt: {}", error),
+            Message::EditorFailed(editor, status) => write!(f, "Der Editor {} wurde mit {} beendet", editor, status),
+            Message::UnknownEncryption => write!(f, "Unbekannte Verschlüsselung"),
+            Message::EncryptFailed => write!(f, "Verschlüsselung fehlgeschlagen"),
+            Message::TooShortToDecrypt => write!(f, "Zu kurz, um verschlüsselt zu sein"),
+            Message::InvalidNonce => write!(f, "Ungültige Nonce"),
+            Message::AuthenticationFailed => write!(f, "Authentifizierung fehlgeschlagen"),
+            Message::InvalidKey => write!(f, "Ungültiger Schlüssel"),
+            Message::NoRandomBytes => write!(f, "Vom System konnten keine Zufallsbytes bezogen werden"),
+            Message::LockPoisoned(lock) => write!(f, "Die Sperre {} ist vergiftet", lock),
+            Message::NotAKey(account) => write!(f, "Der Schlüssel {} im Schlüsselbund ist kein Schlüssel", account),
+            Message::DaemonNotRunning => write!(f, "Der Daemon läuft nicht"),
+            Message::ModelOnOtherBackend(model, backend, chosen) => write!(f, "--model {} läuft auf dem Backend {}, nicht auf {}", model, backend, chosen),
+            Message::NoCertificates(path) => write!(f, "Keine PEM-Zertifikate im ca_bundle {}", path),
+            Message::NoHistoryEntry(id) => write!(f, "Kein Verlaufseintrag mit der ID {}", id),
+            Message::NoHistoryExport(path) => write!(f, "Kein Verlaufsexport unter {}", path),
+            Message::NewerConfig(path, version, supported) => write!(f, "{} hat die Konfigurationsversion {}, neuer als die {}, die diese Version von ai versteht", path, version, supported),
+            Message::UnexpectedShape(tensor, shape) => write!(f, "Unerwartete Form für {} {}", tensor, shape),
+            Message::MfaNeedsTerminal(role) => write!(f, "Die Übernahme von {} braucht einen MFA-Code, der nur in einem Terminal eingegeben werden kann", role),
+            Message::NoCredentialsProvider => write!(f, "Es ist kein AWS-Anmeldeinformationsanbieter konfiguriert"),
+            Message::NoStsCredentials(role) => write!(f, "STS hat keine Anmeldeinformationen für {} zurückgegeben", role),
+            Message::SsoExpired(profile) => write!(f, "Die AWS-SSO-Sitzung des Profils {} ist abgelaufen, führe `aws sso login --profile {}` aus", profile, profile),
+            Message::NoAwsCredentials(error) => write!(f, "Keine AWS-Anmeldeinformationen: {}", error),
+            Message::StepNeedsPromptOrRun(step) => write!(f, "Schritt {} braucht genau eines von prompt oder run", step),
+            Message::StepFailed(step, status, stderr) => write!(f, "Schritt {} ist mit Status {} fehlgeschlagen: {}", step, status, stderr),
+            Message::ExpectedKeyValue(arg) => write!(f, "Erwartet wurde Schlüssel=Wert, erhalten '{}'", arg),
+            Message::BuildWithClipboard => write!(f, "mit --features clipboard bauen"),
+            Message::GitCommitFailed(status) => write!(f, "git commit wurde mit {} beendet", status),
+            Message::GitFailed(args, stderr) => write!(f, "git {} ist fehlgeschlagen: {}", args, stderr),
+            Message::NoWeightMap(file) => write!(f, "Keine Gewichtszuordnung in {}", file),
+            Message::WeightMapNotAMap(file) => write!(f, "Die Gewichtszuordnung in {} ist keine Zuordnung", file),
+            Message::UnknownDevice(spec) => write!(f, "Unbekanntes Gerät {}, erwartet cpu, cuda:N oder metal:N", spec),
+            Message::NoKeyringKey(name) => write!(f, "Kein Schlüssel {} im Schlüsselbund", name),
+            Message::NotSafetensors(path) => write!(f, "{} ist keine safetensors-Datei", path),
+            Message::UnknownModel(model, aliases) => write!(f, "Unbekanntes Modell {}, erwartet einer der [model_aliases] ({}), hf:<repo> oder eine Bedrock-Modell-ID", model, aliases),
+            Message::InvalidRegex(pattern, error) => write!(f, "Die Antwort ist kein gültiger regulärer Ausdruck: {}\n{}", pattern, error),
+            Message::InvalidJq(filter, error) => write!(f, "Die Antwort ist kein gültiger jq-Filter: {}\n{}", filter, error),
+            Message::NotAwk(command) => write!(f, "Die Antwort ist kein awk-Befehl: {}", command),
+            Message::UnclosedQuote(command) => write!(f, "Die Antwort ist kein vollständiger Befehl, ein Anführungszeichen ist nicht geschlossen: {}", command),
+            Message::BackendPanicked(backend) => write!(f, "Das Backend {} ist abgestürzt", backend),
+            Message::InvalidTemplateName(name) => write!(f, "Ungültiger Vorlagenname '{}', verwende Buchstaben, Ziffern, '-' und '_'", name),
+            Message::NoTemplate(name) => write!(f, "Keine Vorlage namens '{}'", name),
+            Message::UnclosedTemplateVariable => write!(f, "Nicht geschlossenes '{{{{' in der Vorlage"),
+            Message::MissingTemplateValue(name) => write!(f, "Fehlender Wert für die Vorlagenvariable '{}', übergib --{} <Wert>", name, name),
+            Message::ExpectedNameValue(arg) => write!(f, "Erwartet wurde ein Paar --Name Wert, erhalten '{}'", arg),
+            Message::MissingFlagValue(flag) => write!(f, "Fehlender Wert für --{}", flag),
+            Message::InvalidSchemaFile(path, error) => write!(f, "Ungültiges Schema in {}: {}", path, error),
+            Message::InvalidSchema(error) => write!(f, "Ungültiges Schema: {}", error),
+            Message::SchemaMismatch(errors, json) => write!(f, "Die Antwort entspricht nicht dem Schema:\n{}\n{}", errors, json),
+            Message::InvalidAliasName(name) => write!(f, "Ungültiger Aliasname '{}', verwende Buchstaben, Ziffern, '-' und '_'", name),
+            Message::NoAlias(name) => write!(f, "Kein Alias namens '{}'", name),
+            Message::BenchmarkFailed(status) => write!(f, "Der Benchmark-Lauf ist mit Status {} fehlgeschlagen", status),
+            Message::NoMeasurements => write!(f, "Der Benchmark-Lauf hat keine Messwerte ausgegeben"),
+            Message::UnknownInterval(interval) => write!(f, "Unbekanntes Intervall '{}', verwende z. B. 15m oder 2h", interval),
+            Message::UnknownSchedule(spec) => write!(f, "Unbekannter Zeitplan '{}', verwende hourly, daily <Zeit>, weekdays <Zeit>, weekly <Tag> <Zeit>, every <n>m|h oder einen Cron-Ausdruck", spec),
+            Message::EmptyCron => write!(f, "Der Cron-Ausdruck ist leer"),
+            Message::UnknownCronShorthand(shorthand) => write!(f, "Unbekannte Kurzform '{}'", shorthand),
+            Message::CronFields(found) => write!(f, "5 Felder erwartet, {} gefunden", found),
+            Message::CronValue(field, value, min, max) => write!(f, "Ungültiger Wert für {} '{}', erwartet {}-{}", field, value, min, max),
+            Message::CronStep(step, field) => write!(f, "Ungültige Schrittweite '{}' im Feld {}", step, field),
+            Message::CronRangeBackwards(field, range) => write!(f, "Der Bereich für {} '{}' ist rückwärts", field, range),
+            Message::InvalidHour(time) => write!(f, "Ungültige Stunde in '{}'", time),
+            Message::InvalidTime(time) => write!(f, "Ungültige Uhrzeit '{}'", time),
+            Message::UnknownDay(day) => write!(f, "Unbekannter Tag '{}'", day),
+            Message::NoStdin(program) => write!(f, "Die Standardeingabe von {} konnte nicht geöffnet werden", program),
+            Message::CrontabRejected => write!(f, "crontab hat den aktualisierten Zeitplan abgelehnt"),
+            Message::NoScheduledPrompt(id) => write!(f, "Kein geplanter Prompt mit der ID {}", id),
+            Message::DraftNeedsQuantized => write!(f, "Spekulatives Dekodieren braucht das quantisierte phi-2-Modell"),
+            Message::TokenizerCantEncode => write!(f, "Der Tokenizer kann keinen Text kodieren"),
+            Message::NoStopToken => write!(f, "Das Modell hat kein Textende-Token, an dem es anhalten kann"),
+            Message::ChecksFailed(failed, total) => write!(f, "{} von {} Prüfungen sind fehlgeschlagen", failed, total),
+            Message::NoConversation => write!(f, "Kein vorheriges Gespräch zum Fortsetzen"),
+            Message::UnknownRedactionKind(kind, kinds) => write!(f, "Unbekannte Schwärzungsart {}, erwartet eine von {}", kind, kinds),
+            Message::InvalidRedactionPattern(name, error) => write!(f, "Ungültiges Schwärzungsmuster {}: {}", name, error),
+            Message::CannotDecode(error) => write!(f, "Dekodieren nicht möglich: {}", error),
+            Message::UnsupportedBits(bits) => write!(f, "Nicht unterstützte Bitbreite {}, erwartet 4, 5, 6 oder 8", bits),
+            Message::GrammarDeadEnd => write!(f, "Kein Token kann die Ausgabe unter der Grammatik fortsetzen"),
+            Message::NotADirectory(path) => write!(f, "{} ist kein Verzeichnis", path),
+            Message::DaemonClosed => write!(f, "Der Daemon hat die Verbindung geschlossen"),
+            Message::DaemonNeedsUnix => write!(f, "Der Daemon braucht Unix-Sockets"),
+            Message::UnexpectedAnswer(answer) => write!(f, "Unerwartete Antwort vom Daemon: {}", answer),
+            Message::NotAGeneration(request) => write!(f, "Keine Generierung: {}", request),
+            Message::DaemonFailed(message) => write!(f, "Der Daemon ist fehlgeschlagen: {}", message),
+            Message::DaemonStopped => write!(f, "Der Daemon läuft nicht mehr"),
+            Message::DaemonAlreadyRunning(path) => write!(f, "Der Daemon läuft bereits auf {}", path),
+            Message::DaemonOtherModel => write!(f, "Der Daemon führt ein anderes Modell aus, starte ihn mit den neuen Einstellungen neu"),
+            Message::DaemonShuttingDown => write!(f, "Der Daemon wird beendet"),
+            Message::FrameTooLarge(bytes) => write!(f, "Ein Frame von {} Bytes ist zu groß", bytes),
+            Message::InvalidKeepAlive(keep_alive) => write!(f, "Ungültiges daemon.keep_alive {:?}, erwartet z. B. \"90s\", \"10m\", \"2h\" oder \"forever\"", keep_alive),
+            Message::NoKeyringKeyAdd(name) => write!(f, "Kein {}-Schlüssel im Schlüsselbund, füge ihn mit `ai auth set {}` hinzu", name, name),
+            Message::CommandBackendFailed(command, status, stderr) => write!(f, "Das Befehls-Backend {} ist mit Status {} fehlgeschlagen: {}", command, status, stderr),
+            Message::QuantizedPhi3 => write!(f, "Quantisiertes Phi-3 wird nicht unterstützt"),
+            Message::UnknownDraftModel(model) => write!(f, "Unbekannte Entwurfsmodell-Architektur {}, verwende 1.5 oder 2", model),
+            Message::ToolNeeds(tool, argument) => write!(f, "{} braucht das Argument {}", tool, argument),
+            Message::UnknownTool(tool) => write!(f, "Unbekanntes Werkzeug {}", tool),
+            Message::OutsideWorkingTree(path) => write!(f, "{} liegt außerhalb des aktuellen Verzeichnisses", path),
+            Message::SecretFile(path) => write!(f, "{} kann Geheimnisse enthalten und wird nicht geteilt", path),
+            Message::EmptyCommand => write!(f, "Leerer Befehl"),
+            Message::NotReadOnly(command) => write!(f, "{} ist kein erlaubter schreibgeschützter Befehl", command),
+            Message::CommandTimedOut(command) => write!(f, "{} hat das Zeitlimit überschritten", command),
+            Message::NoMockResponse(prompt) => write!(f, "Keine Mock-Antwort passt zum Prompt: {}", prompt),
+            Message::PluginFailed(plugin, message) => write!(f, "Das Plugin {} ist fehlgeschlagen: {}", plugin, message),
+            Message::PluginStatus(plugin, status, stderr) => write!(f, "Das Plugin {} ist mit Status {} fehlgeschlagen: {}", plugin, status, stderr),
+            Message::NoCommandBackend => write!(f, "Das Befehls-Backend braucht eine [command_backend]-Tabelle mit gesetztem cmd"),
+            Message::AutoNeedsBackend => write!(f, "Das auto-Backend wählt eines pro Prompt, wähle eines für diesen Befehl mit -b"),
+            Message::UnknownBackend(name, prefix) => write!(f, "Unbekanntes Backend: {}, nicht eingebaut und kein Plugin {}{} im PATH", name, prefix, name),
+            Message::NoBedrockPrompt => write!(f, "Es gibt keinen Prompt, der an Bedrock gesendet werden kann"),
+            Message::BedrockStartsWithUser => write!(f, "Bedrock-Gespräche müssen mit einer Benutzernachricht beginnen"),
+            Message::BuildFailed => write!(f, "Die Nachricht konnte nicht erstellt werden"),
+            Message::UnknownStreamError => write!(f, "Die Fehlermeldung des Streams ist nicht lesbar"),
+            Message::InvalidToolInput(error) => write!(f, "Die Werkzeugeingabe ist kein gültiges JSON: {}", error),
+            Message::BedrockTimedOut(secs) => write!(f, "Bedrock hat nicht innerhalb von {}s geantwortet", secs),
+            Message::BedrockInterrupted => write!(f, "Unterbrochen, bevor Bedrock geantwortet hat"),
+            Message::SendFailed(error) => write!(f, "Die Nachricht konnte nicht gesendet werden: {}", error),
+            Message::NoBedrockRegions => write!(f, "Keine Regionen für bedrock konfiguriert"),
+            Message::TooManyToolRounds(rounds) => write!(f, "Das Modell hat nach {} Runden weiter Werkzeuge aufgerufen", rounds),
+            Message::ToolResultsFailed(error) => write!(f, "Die Werkzeugergebnisse konnten nicht gesendet werden: {}", error),
+            Message::HookFailed(hook, path, status) => write!(f, "Der {}-Hook {} ist mit Status {} fehlgeschlagen", hook, path, status),
+            Message::HookPrintedNothing(hook, path) => write!(f, "Der {}-Hook {} hat nichts ausgegeben", hook, path),
+            Message::NoDevice => write!(f, "Kein Gerät, auf das das Modell geladen werden kann"),
+            Message::NotText(path) => write!(f, "Die angehängte Datei {} ist kein Text", path),
+            Message::UnknownContextProvider(name, names) => write!(f, "Unbekannter Kontextanbieter {}, erwartet einer von {}", name, names),
+        }
+    }
+
+    fn french(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::PromptEmpty => write!(f, "La requête est vide"),
+            Message::NoOutput => write!(f, "Aucune réponse n'a été générée"),
+            Message::NoCommandSelected => write!(f, "Aucune commande sélectionnée"),
+            Message::PickCommand => write!(f, "Choisissez une commande"),
+            Message::Generating => write!(f, "Génération…"),
+            Message::Thinking => write!(f, "Réflexion..."),
+            Message::Done => write!(f, "Terminé"),
+            Message::SavedTemplate(name) => write!(f, "Modèle {} enregistré", name),
+            Message::DeletedTemplate(name) => write!(f, "Modèle {} supprimé", name),
+            Message::ScheduledPrompt(id) => write!(f, "Requête {} planifiée", id),
+            Message::RemovedScheduledPrompt(id) => {
+                write!(f, "Requête planifiée {} supprimée", id)
+            }
+            Message::UnknownWebhook(name) => {
+                write!(f, "Aucun webhook nommé {} dans la configuration", name)
+            }
+            Message::UsageUnavailable(backend) => write!(
+                f,
+                "Les statistiques d'utilisation ne sont pas disponibles pour le backend {}",
+                backend
+            ),
+            Message::Created(path) => write!(f, "Créé {}", path),
+            Message::Removed(path) => write!(f, "Supprimé {}", path),
+            Message::RemovedScheduledPrompts(count) => write!(
+                f,
+                "{} requête(s) planifiée(s) supprimée(s) de la crontab",
+                count
+            ),
+            Message::NoFailedCommand => write!(f, "Aucune commande échouée à corriger, utilisez --command ou configurez l'intégration du shell avec `eval \"$(ai shell-init bash)\"`"),
+            Message::ActionMenu => write!(f, "[c] copier  [e] modifier et exécuter  [r] exécuter  [x] régénérer  [q] quitter"),
+            Message::Copied => write!(f, "Copié dans le presse-papiers"),
+            Message::ClipboardUnavailable => write!(f, "Le presse-papiers n'est pas disponible"),
+            Message::ExitStatus(code) => write!(f, "Code de sortie {}", code),
+            Message::NothingStaged => write!(f, "Rien n'est indexé, ajoutez d'abord les modifications avec git add"),
+            Message::ConfirmCommit => write!(f, "Committer avec ce message ?"),
+            Message::EnterApiKey(name) => write!(f, "Clé d'API pour {}", name),
+            Message::EmptyApiKey => write!(f, "La clé d'API est vide"),
+            Message::SavedApiKey(name) => write!(f, "Clé {} enregistrée dans le trousseau", name),
+            Message::RemovedApiKey(name) => write!(f, "Clé {} supprimée du trousseau", name),
+            Message::TimedOut(secs) => write!(f, "Délai dépassé après {}s, la sortie est partielle", secs),
+            Message::Interrupted => write!(f, "Interrompu, la sortie est partielle"),
+            Message::ClearedCache(count) => write!(f, "{} réponses en cache supprimées", count),
+            Message::CachedResponse => write!(f, "Réponse tirée du cache, utilisez --no-cache pour régénérer"),
+            Message::TemplateMissing => write!(f, "Indiquez le texte du modèle en argument ou avec --prompt-file"),
+            Message::WroteScript(path) => write!(f, "Script écrit dans {}", path),
+            Message::BatchFinished(total, failed) => write!(f, "{} prompts exécutés, {} en échec", total, failed),
+            Message::TokenCount(tokens, sample_len) => write!(f, "{} tokens, plus jusqu'à {} générés (sample_len)", tokens, sample_len),
+            Message::ExceedsContext(length) => write!(f, "Le prompt et sample_len dépassent le contexte du modèle de {} tokens, raccourcissez le prompt ou baissez sample_len", length),
+            Message::OverBudget(spent, estimate, budget) => write!(f, "L'envoi dépasserait le budget mensuel : ${:.4} dépensés plus environ ${:.4} pour ce prompt dépassent ${:.2}. Utilisez --force pour envoyer quand même", spent, estimate, budget),
+            Message::SearchHistory => write!(f, "Rechercher dans l'historique :"),
+            Message::SavedAlias(name) => write!(f, "Alias {} enregistré", name),
+            Message::DeletedAlias(name) => write!(f, "Alias {} supprimé", name),
+            Message::NothingToAlias => write!(f, "Aucune commande générée à enregistrer, générez-en une d'abord"),
+            Message::NoPipeInput => write!(f, "Rien à traiter, envoyez le texte par un pipe, par ex. `cat app.log | ai pipe résume les erreurs`"),
+            Message::IndexCleared => write!(f, "Index supprimé"),
+            Message::Indexed(files, embedded, chunks) => write!(f, "{} fichiers indexés ({} nouveaux ou modifiés), {} extraits dans l'index. Ajoutez les pertinents à un prompt avec --context index", files, embedded, chunks),
+            Message::TuiKeys => write!(f, "Entrée envoyer  ↑/↓ historique  Ctrl-Y copier  Ctrl-R exécuter  Ctrl-G regénérer  Échap quitter"),
+            Message::PressAnyKey => write!(f, "Appuyez sur une touche pour revenir"),
+            Message::NotATerminal => write!(f, "ai tui a besoin d'un terminal interactif"),
+            Message::RegenerateHint => write!(f, "Appuyez sur r pour regénérer"),
+            Message::ModelTooLarge(needed, limit) => write!(f, "Le modèle local a besoin d'environ {:.1} Gio mais seuls {:.1} Gio sont disponibles, définissez local_model_config.quantized = true, réduisez sample_len ou augmentez max_memory_gb", needed, limit),
+            Message::QuantizedFallback(needed, limit) => write!(f, "Le modèle local a besoin d'environ {:.1} Gio mais seuls {:.1} Gio sont disponibles, utilisation du modèle quantifié", needed, limit),
+            Message::NoCommandToUndo => write!(f, "Aucune commande à annuler, passez-la avec `ai undo '<commande>'` ou configurez l'intégration du shell avec `eval \"$(ai shell-init bash)\"`"),
+            Message::NotAGitRepository => write!(f, "Pas dans un dépôt git, lancez `ai git` dans un dépôt"),
+            Message::NoErrorInput => write!(f, "Aucune erreur à expliquer, envoyez-la par un tube, p. ex. `make 2>&1 | ai explain-error`"),
+            Message::ImportedHistory(imported, skipped) => write!(f, "{} entrées importées dans l'historique, {} ignorées car déjà présentes", imported, skipped),
+            Message::HistoryPassphrase => write!(f, "Phrase secrète de l'historique"),
+            Message::RepeatHistoryPassphrase => write!(f, "Répétez la phrase secrète"),
+            Message::HistoryPassphraseMismatch => write!(f, "Les phrases secrètes ne correspondent pas"),
+            Message::NoHistoryPassphrase => write!(f, "L'historique est chiffré avec une phrase secrète, définissez AI_HISTORY_PASSPHRASE ou lancez ai dans un terminal pour la saisir"),
+            Message::NoHistoryKey => write!(f, "L'historique est chiffré avec une clé du trousseau, mais ce trousseau n'en a pas"),
+            Message::Redacted(backend, found) => write!(f, "Masqué avant l'envoi à {} : {}. Utilisez --no-redact pour les envoyer tels quels", backend, found),
+            Message::LocalOnly(what) => write!(f, "{} a besoin du réseau, interdit en mode local uniquement (--local-only ou local_only = true)", what),
+            Message::RemoteBackendLocalOnly(backend) => write!(f, "Le backend {} envoie les prompts hors de cette machine, ce que le mode local uniquement (--local-only ou local_only = true) interdit. Utilisez -b local", backend),
+            Message::NotCached(file, model) => write!(f, "{} de {} n'est pas dans le cache des modèles et le mode local uniquement ne peut pas le télécharger. Lancez ai une fois sans --local-only sur une machine connectée et copiez le cache Hugging Face (HF_HOME)", file, model),
+            Message::NoAuditLog(path) => write!(f, "Il n'y a pas de journal d'audit à {}. Activez-le avec enabled = true sous [audit] dans la configuration", path),
+            Message::RequestQuota(table, used, max) => write!(f, "Le quota de {} requêtes par jour de {} est épuisé ({} aujourd'hui). Il est remis à zéro à minuit UTC, ou augmentez max_requests_per_day", max, table, used),
+            Message::TokenQuota(table, used, estimate, max) => write!(f, "L'envoi dépasserait le quota de {} tokens par mois de {} : {} utilisés ce mois-ci plus environ {} pour ce prompt. Il est remis à zéro le 1er (UTC), ou augmentez max_tokens_per_month", max, table, used, estimate),
+            Message::UsageLine(name, requests, tokens) => write!(f, "{} : {} requêtes aujourd'hui, {} tokens ce mois-ci", name, requests, tokens),
+            Message::QuotaLine(requests, tokens) => write!(f, "quota : {} requêtes par jour, {} tokens par mois", requests.map_or("illimitées".to_string(), |max| max.to_string()), tokens.map_or("illimités".to_string(), |max| max.to_string())),
+            Message::AllBackends => write!(f, "tous les backends"),
+            Message::NoUsage => write!(f, "Aucune requête ce mois-ci pour l'instant"),
+            Message::PromptTitle => write!(f, " Requête "),
+            Message::CacheEntries(entries, expired) => write!(f, "Entrées : {} ({} expirées)", entries, expired),
+            Message::CacheHits(hits) => write!(f, "Réutilisations : {}", hits),
+            Message::CacheSize(kib) => write!(f, "Taille : {:.1} Kio", kib),
+            Message::CacheTtl(secs) => write!(f, "Durée de vie : {}s", secs),
+            Message::CacheOff => write!(f, "Le cache est désactivé, définissez cache_ttl_secs pour l'activer"),
+            Message::ContextSkipped(name, ms) => write!(f, "Contexte {} omis, il a pris plus de context.timeout_ms ({} ms)", name, ms),
+            Message::QuantizedOnOneDevice => write!(f, "Le modèle quantifié tourne sur le premier des périphériques, définissez local_model_config.quantized = false pour répartir le modèle sur tous"),
+            Message::ModelArchitecture(repo, kind, model) => write!(f, "{} est un modèle {}, mais local_model_config.model = {} charge Phi-{} : définissez model = 2 pour les dépôts phi et 3 pour les dépôts phi3, les autres architectures ne sont pas prises en charge", repo, kind, model, model),
+            Message::UnsupportedArchitecture(repo, kind) => write!(f, "{} est un modèle {}, le backend local exécute les modèles phi et phi3", repo, kind),
+            Message::DeliveryFailed(error) => write!(f, "La réponse n'a pas été livrée : {}", error),
+            Message::EditorFailed(editor, status) => write!(f, "L'éditeur {} s'est terminé avec {}", editor, status),
+            Message::UnknownEncryption => write!(f, "Chiffrement inconnu"),
+            Message::EncryptFailed => write!(f, "Impossible de chiffrer"),
+            Message::TooShortToDecrypt => write!(f, "Trop court pour être chiffré"),
+            Message::InvalidNonce => write!(f, "Nonce invalide"),
+            Message::AuthenticationFailed => write!(f, "Échec de l'authentification"),
+            Message::InvalidKey => write!(f, "Clé invalide"),
+            Message::NoRandomBytes => write!(f, "Impossible d'obtenir des octets aléatoires du système"),
+            Message::LockPoisoned(lock) => write!(f, "Le verrou {} est empoisonné", lock),
+            Message::NotAKey(account) => write!(f, "La clé {} du trousseau n'est pas une clé", account),
+            Message::DaemonNotRunning => write!(f, "Le démon n'est pas lancé"),
+            Message::ModelOnOtherBackend(model, backend, chosen) => write!(f, "--model {} tourne sur le backend {}, pas sur {}", model, backend, chosen),
+            Message::NoCertificates(path) => write!(f, "Aucun certificat PEM dans le ca_bundle {}", path),
+            Message::NoHistoryEntry(id) => write!(f, "Aucune entrée d'historique avec l'id {}", id),
+            Message::NoHistoryExport(path) => write!(f, "Aucun export d'historique à {}", path),
+            Message::NewerConfig(path, version, supported) => write!(f, "{} est en version de configuration {}, plus récente que la {} que comprend cette version d'ai", path, version, supported),
+            Message::UnexpectedShape(tensor, shape) => write!(f, "Forme inattendue pour {} {}", tensor, shape),
+            Message::MfaNeedsTerminal(role) => write!(f, "Endosser {} demande un code MFA, qui ne peut être saisi que dans un terminal", role),
+            Message::NoCredentialsProvider => write!(f, "Aucun fournisseur d'identifiants AWS n'est configuré"),
+            Message::NoStsCredentials(role) => write!(f, "STS n'a renvoyé aucun identifiant pour {}", role),
+            Message::SsoExpired(profile) => write!(f, "La session AWS SSO du profil {} a expiré, lancez `aws sso login --profile {}`", profile, profile),
+            Message::NoAwsCredentials(error) => write!(f, "Aucun identifiant AWS : {}", error),
+            Message::StepNeedsPromptOrRun(step) => write!(f, "L'étape {} doit avoir exactement un prompt ou un run", step),
+            Message::StepFailed(step, status, stderr) => write!(f, "L'étape {} a échoué avec le statut {} : {}", step, status, stderr),
+            Message::ExpectedKeyValue(arg) => write!(f, "clé=valeur attendu, reçu '{}'", arg),
+            Message::BuildWithClipboard => write!(f, "compilez avec --features clipboard"),
+            Message::GitCommitFailed(status) => write!(f, "git commit s'est terminé avec {}", status),
+            Message::GitFailed(args, stderr) => write!(f, "git {} a échoué : {}", args, stderr),
+            Message::NoWeightMap(file) => write!(f, "Aucune table des poids dans {}", file),
+            Message::WeightMapNotAMap(file) => write!(f, "La table des poids de {} n'est pas une table", file),
+            Message::UnknownDevice(spec) => write!(f, "Périphérique inconnu {}, attendu cpu, cuda:N ou metal:N", spec),
+            Message::NoKeyringKey(name) => write!(f, "Aucune clé {} dans le trousseau", name),
+            Message::NotSafetensors(path) => write!(f, "{} n'est pas un fichier safetensors", path),
+            Message::UnknownModel(model, aliases) => write!(f, "Modèle inconnu {}, attendu un des [model_aliases] ({}), hf:<repo> ou un id de modèle Bedrock", model, aliases),
+            Message::InvalidRegex(pattern, error) => write!(f, "La réponse n'est pas une expression régulière valide : {}\n{}", pattern, error),
+            Message::InvalidJq(filter, error) => write!(f, "La réponse n'est pas un filtre jq valide : {}\n{}", filter, error),
+            Message::NotAwk(command) => write!(f, "La réponse n'est pas une commande awk : {}", command),
+            Message::UnclosedQuote(command) => write!(f, "La réponse n'est pas une commande complète, un guillemet n'est pas fermé : {}", command),
+            Message::BackendPanicked(backend) => write!(f, "Le backend {} a paniqué", backend),
+            Message::InvalidTemplateName(name) => write!(f, "Nom de modèle invalide '{}', utilisez des lettres, des chiffres, '-' et '_'", name),
+            Message::NoTemplate(name) => write!(f, "Aucun modèle nommé '{}'", name),
+            Message::UnclosedTemplateVariable => write!(f, "'{{{{' non fermé dans le modèle"),
+            Message::MissingTemplateValue(name) => write!(f, "Valeur manquante pour la variable de modèle '{}', passez --{} <valeur>", name, name),
+            Message::ExpectedNameValue(arg) => write!(f, "Paire --nom valeur attendue, reçu '{}'", arg),
+            Message::MissingFlagValue(flag) => write!(f, "Valeur manquante pour --{}", flag),
+            Message::InvalidSchemaFile(path, error) => write!(f, "Schéma invalide dans {} : {}", path, error),
+            Message::InvalidSchema(error) => write!(f, "Schéma invalide : {}", error),
+            Message::SchemaMismatch(errors, json) => write!(f, "La réponse ne correspond pas au schéma :\n{}\n{}", errors, json),
+            Message::InvalidAliasName(name) => write!(f, "Nom d'alias invalide '{}', utilisez des lettres, des chiffres, '-' et '_'", name),
+            Message::NoAlias(name) => write!(f, "Aucun alias nommé '{}'", name),
+            Message::BenchmarkFailed(status) => write!(f, "L'exécution du benchmark a échoué avec le statut {}", status),
+            Message::NoMeasurements => write!(f, "L'exécution du benchmark n'a affiché aucune mesure"),
+            Message::UnknownInterval(interval) => write!(f, "Intervalle inconnu '{}', utilisez par ex. 15m ou 2h", interval),
+            Message::UnknownSchedule(spec) => write!(f, "Planification inconnue '{}', utilisez hourly, daily <heure>, weekdays <heure>, weekly <jour> <heure>, every <n>m|h ou une expression cron", spec),
+            Message::EmptyCron => write!(f, "L'expression cron est vide"),
+            Message::UnknownCronShorthand(shorthand) => write!(f, "Raccourci inconnu '{}'", shorthand),
+            Message::CronFields(found) => write!(f, "5 champs attendus, {} trouvés", found),
+            Message::CronValue(field, value, min, max) => write!(f, "Valeur de {} invalide '{}', attendu {}-{}", field, value, min, max),
+            Message::CronStep(step, field) => write!(f, "Pas invalide '{}' dans le champ {}", step, field),
+            Message::CronRangeBackwards(field, range) => write!(f, "La plage de {} '{}' est inversée", field, range),
+            Message::InvalidHour(time) => write!(f, "Heure invalide dans '{}'", time),
+            Message::InvalidTime(time) => write!(f, "Heure invalide '{}'", time),
+            Message::UnknownDay(day) => write!(f, "Jour inconnu '{}'", day),
+            Message::NoStdin(program) => write!(f, "Impossible d'ouvrir l'entrée standard de {}", program),
+            Message::CrontabRejected => write!(f, "crontab a refusé la planification mise à jour"),
+            Message::NoScheduledPrompt(id) => write!(f, "Aucun prompt planifié avec l'id {}", id),
+            Message::DraftNeedsQuantized => write!(f, "Le décodage spéculatif nécessite le modèle phi-2 quantifié"),
+            Message::TokenizerCantEncode => write!(f, "Le tokenizer ne peut pas encoder de texte"),
+            Message::NoStopToken => write!(f, "Le modèle n'a pas de jeton de fin de texte où s'arrêter"),
+            Message::ChecksFailed(failed, total) => write!(f, "{} vérifications sur {} ont échoué", failed, total),
+            Message::NoConversation => write!(f, "Aucune conversation précédente à poursuivre"),
+            Message::UnknownRedactionKind(kind, kinds) => write!(f, "Type de masquage inconnu {}, attendu un parmi {}", kind, kinds),
+            Message::InvalidRedactionPattern(name, error) => write!(f, "Motif de masquage invalide {} : {}", name, error),
+            Message::CannotDecode(error) => write!(f, "Impossible de décoder : {}", error),
+            Message::UnsupportedBits(bits) => write!(f, "Largeur de bits non prise en charge {}, attendu 4, 5, 6 ou 8", bits),
+            Message::GrammarDeadEnd => write!(f, "Aucun jeton ne peut poursuivre la sortie selon la grammaire"),
+            Message::NotADirectory(path) => write!(f, "{} n'est pas un répertoire", path),
+            Message::DaemonClosed => write!(f, "Le démon a fermé la connexion"),
+            Message::DaemonNeedsUnix => write!(f, "Le démon a besoin de sockets Unix"),
+            Message::UnexpectedAnswer(answer) => write!(f, "Réponse inattendue du démon : {}", answer),
+            Message::NotAGeneration(request) => write!(f, "Pas une génération : {}", request),
+            Message::DaemonFailed(message) => write!(f, "Le démon a échoué : {}", message),
+            Message::DaemonStopped => write!(f, "Le démon s'est arrêté"),
+            Message::DaemonAlreadyRunning(path) => write!(f, "Le démon tourne déjà sur {}", path),
+            Message::DaemonOtherModel => write!(f, "Le démon exécute un autre modèle, redémarrez-le avec les nouveaux paramètres"),
+            Message::DaemonShuttingDown => write!(f, "Le démon est en cours d'arrêt"),
+            Message::FrameTooLarge(bytes) => write!(f, "Une trame de {} octets est trop grande", bytes),
+            Message::InvalidKeepAlive(keep_alive) => write!(f, "daemon.keep_alive invalide {:?}, attendu par ex. \"90s\", \"10m\", \"2h\" ou \"forever\"", keep_alive),
+            Message::NoKeyringKeyAdd(name) => write!(f, "Aucune clé {} dans le trousseau, ajoutez-la avec `ai auth set {}`", name, name),
+            Message::CommandBackendFailed(command, status, stderr) => write!(f, "Le backend de commande {} a échoué avec le statut {} : {}", command, status, stderr),
+            Message::QuantizedPhi3 => write!(f, "Phi-3 quantifié n'est pas pris en charge"),
+            Message::UnknownDraftModel(model) => write!(f, "Architecture de modèle brouillon inconnue {}, utilisez 1.5 ou 2", model),
+            Message::ToolNeeds(tool, argument) => write!(f, "{} a besoin de l'argument {}", tool, argument),
+            Message::UnknownTool(tool) => write!(f, "Outil inconnu {}", tool),
+            Message::OutsideWorkingTree(path) => write!(f, "{} est en dehors du répertoire courant", path),
+            Message::SecretFile(path) => write!(f, "{} peut contenir des secrets et n'est pas partagé", path),
+            Message::EmptyCommand => write!(f, "Commande vide"),
+            Message::NotReadOnly(command) => write!(f, "{} n'est pas une commande en lecture seule autorisée", command),
+            Message::CommandTimedOut(command) => write!(f, "{} a dépassé le délai", command),
+            Message::NoMockResponse(prompt) => write!(f, "Aucune réponse simulée ne correspond au prompt : {}", prompt),
+            Message::PluginFailed(plugin, message) => write!(f, "Le plugin {} a échoué : {}", plugin, message),
+            Message::PluginStatus(plugin, status, stderr) => write!(f, "Le plugin {} a échoué avec le statut {} : {}", plugin, status, stderr),
+            Message::NoCommandBackend => write!(f, "Le backend de commande a besoin d'une table [command_backend] avec cmd défini"),
+            Message::AutoNeedsBackend => write!(f, "Le backend auto en choisit un par prompt, choisissez-en un pour cette commande avec -b"),
+            Message::UnknownBackend(name, prefix) => write!(f, "Backend inconnu : {}, non intégré et aucun plugin {}{} dans le PATH", name, prefix, name),
+            Message::NoBedrockPrompt => write!(f, "Il n'y a aucun prompt à envoyer à Bedrock"),
+            Message::BedrockStartsWithUser => write!(f, "Les conversations Bedrock doivent commencer par un message de l'utilisateur"),
+            Message::BuildFailed => write!(f, "Impossible de construire le message"),
+            Message::UnknownStreamError => write!(f, "Impossible de lire le message d'erreur du flux"),
+            Message::InvalidToolInput(error) => write!(f, "L'entrée de l'outil n'est pas du json valide : {}", error),
+            Message::BedrockTimedOut(secs) => write!(f, "Bedrock n'a pas répondu en {}s", secs),
+            Message::BedrockInterrupted => write!(f, "Interrompu avant que Bedrock ne réponde"),
+            Message::SendFailed(error) => write!(f, "Impossible d'envoyer le message : {}", error),
+            Message::NoBedrockRegions => write!(f, "Aucune région configurée pour bedrock"),
+            Message::TooManyToolRounds(rounds) => write!(f, "Le modèle a continué d'appeler des outils après {} tours", rounds),
+            Message::ToolResultsFailed(error) => write!(f, "Impossible d'envoyer les résultats des outils : {}", error),
+            Message::HookFailed(hook, path, status) => write!(f, "Le hook {} {} a échoué avec le statut {}", hook, path, status),
+            Message::HookPrintedNothing(hook, path) => write!(f, "Le hook {} {} n'a rien affiché", hook, path),
+            Message::NoDevice => write!(f, "Aucun appareil sur lequel charger le modèle"),
+            Message::NotText(path) => write!(f, "Le fichier joint {} n'est pas du texte", path),
+            Message::UnknownContextProvider(name, names) => write!(f, "Fournisseur de contexte inconnu {}, attendu un parmi {}", name, names),
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use config::{Config, File, FileFormat};
+
+use crate::messages::Message;
+
+/// Format version of the config file this build writes, `config_version` in the file
+pub const CONFIG_VERSION: i64 = 1;
+
+/// Rewrites the text of a config file from the version before `to` into version `to`
+struct Migration {
+    to: i64,
+    description: &'static str,
+    apply: fn(&str) -> String,
+}
+
+/// Every migration in order, a file goes through the ones newer than its version
+const MIGRATIONS: &[Migration] = &[Migration {
+    to: 1,
+    description: "[model_config] is now [local_model_config]",
+    apply: rename_model_config,
+}];
+
+/// A config file brought up to date
+#[derive(Debug)]
+pub struct Migrated {
+    pub from: i64,
+    /// What each migration changed
+    pub changes: Vec<&'static str>,
+    /// Copy of the file as it was
+    pub backup: PathBuf,
+}
+
+/// The file's version with its text migrated to CONFIG_VERSION and what each migration
+/// changed, nothing when none of them did
+fn plan(path: &Path, contents: &str) -> Result<(i64, String, Vec<&'static str>)> {
+    let from = version(contents)?;
+    if from > CONFIG_VERSION {
+        anyhow::bail!(
+            "{}",
+            Message::NewerConfig(&format!("{:?}", path), from, CONFIG_VERSION)
+        );
+    }
+    let mut migrated = contents.to_string();
+    let mut changes = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|migration| migration.to > from) {
+        let applied = (migration.apply)(&migrated);
+        if applied != migrated {
+            changes.push(migration.description);
+        }
+        migrated = applied;
+    }
+    Ok((from, set_version(&migrated, CONFIG_VERSION), changes))
+}
+
+/// What migrating the file would change, without touching it. For a file given with
+/// --config, which may be shared, e.g. in a dotfiles repo
+pub fn pending(path: &Path) -> Result<Vec<&'static str>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read config {:?}", path))?;
+    Ok(plan(path, &contents)?.2)
+}
+
+/// Migrates the config file to CONFIG_VERSION when a migration changes it, keeping a copy
+/// of the original next to it as config.toml.v<version>.bak. Files without a version are
+/// version 0. None when no migration changed anything, the file is left as it is then
+pub fn migrate(path: &Path) -> Result<Option<Migrated>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read config {:?}", path))?;
+    let (from, migrated, changes) = plan(path, &contents)?;
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    // never replace a working file with one that doesn't parse
+    Config::builder()
+        .add_source(File::from_str(&migrated, FileFormat::Toml))
+        .build()
+        .context("The migrated config isn't valid, leaving the file as it is")?;
+    // an earlier backup of the same version is kept too
+    let backup = (0..)
+        .map(|n| match n {
+            0 => path.with_extension(format!("toml.v{}.bak", from)),
+            n => path.with_extension(format!("toml.v{}.{}.bak", from, n)),
+        })
+        .find(|backup| !backup.exists())
+        .unwrap_or_default();
+    std::fs::write(&backup, &contents)
+        .with_context(|| format!("Unable to back up the config to {:?}", backup))?;
+    std::fs::write(path, migrated)
+        .with_context(|| format!("Unable to write the migrated config {:?}", path))?;
+    Ok(Some(Migrated {
+        from,
+        changes,
+        backup,
+    }))
+}
+
+/// The file's `config_version`, 0 when it has none
+fn version(contents: &str) -> Result<i64> {
+    let config = Config::builder()
+        .add_source(File::from_str(contents, FileFormat::Toml))
+        .build()?;
+    match config.get_int("config_version") {
+        Ok(version) => Ok(version),
+        Err(config::ConfigError::NotFound(_)) => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replaces the `config_version` line, or adds one at the top where it can't end up
+/// inside a table
+fn set_version(contents: &str, version: i64) -> String {
+    let line = format!("config_version = {}", version);
+    if contents
+        .lines()
+        .any(|l| l.trim_start().starts_with("config_version"))
+    {
+        return contents
+            .lines()
+            .map(|l| {
+                if l.trim_start().starts_with("config_version") {
+                    line.as_str()
+                } else {
+                    l
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+            + "\n";
+    }
+    format!(
+        "# Format version of this file, ai migrates older files and keeps a backup\n{}\n\n{}",
+        line, contents
+    )
+}
+
+/// Renames the [model_config] table, which the settings never read, to [local_model_config].
+/// When the file has both, the old section is commented out to be merged by hand
+fn rename_model_config(contents: &str) -> String {
+    let is_header = |line: &str, name: &str| {
+        let line = line.trim();
+        line == format!("[{}]", name) || line.starts_with(&format!("[{}.", name))
+    };
+    if !contents.lines().any(|line| is_header(line, "model_config")) {
+        return contents.to_string();
+    }
+    let has_new = contents
+        .lines()
+        .any(|line| is_header(line, "local_model_config"));
+    let mut in_old = false;
+    let mut lines = Vec::new();
+    for line in contents.lines() {
+        if line.trim_start().starts_with('[') {
+            in_old = is_header(line, "model_config");
+        }
+        if !in_old {
+            lines.push(line.to_string());
+        } else if has_new {
+            if is_header(line, "model_config") {
+                lines.push(
+                    "# Moved from [model_config], merge these into [local_model_config]"
+                        .to_string(),
+                );
+            }
+            lines.push(format!("# {}", line));
+        } else if is_header(line, "model_config") {
+            lines.push(line.replacen("model_config", "local_model_config", 1));
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_file_without_anything_to_migrate_is_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let contents = "ai_backend = \"mock\"\n";
+        std::fs::write(&path, contents).unwrap();
+        assert!(migrate(&path).unwrap().is_none());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), contents);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn an_old_file_is_migrated_with_a_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let contents = "[model_config]\ncpu = true\n";
+        std::fs::write(&path, contents).unwrap();
+        assert_eq!(pending(&path).unwrap().len(), 1);
+        let migrated = migrate(&path).unwrap().unwrap();
+        assert_eq!(migrated.from, 0);
+        assert_eq!(std::fs::read_to_string(&migrated.backup).unwrap(), contents);
+        let updated = std::fs::read_to_string(&path).unwrap();
+        assert!(updated.contains("config_version = 1"));
+        assert!(updated.contains("[local_model_config]"));
+        assert!(pending(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn model_config_and_its_subtables_are_renamed() {
+        let migrated =
+            rename_model_config("[model_config]\ncpu = true\n[model_config.draft]\ntokens = 4\n");
+        assert_eq!(
+            migrated,
+            "[local_model_config]\ncpu = true\n[local_model_config.draft]\ntokens = 4\n"
+        );
+    }
+
+    #[test]
+    fn model_config_is_commented_out_next_to_local_model_config() {
+        let migrated = rename_model_config(
+            "[local_model_config]\ncpu = false\n[model_config]\ncpu = true\n[aws_settings]\nregion = \"eu-west-1\"\n",
+        );
+        assert_eq!(
+            migrated,
+            "[local_model_config]\ncpu = false\n\
+             # Moved from [model_config], merge these into [local_model_config]\n\
+             # [model_config]\n# cpu = true\n\
+             [aws_settings]\nregion = \"eu-west-1\"\n"
+        );
+    }
+
+    #[test]
+    fn other_tables_are_not_renamed() {
+        let contents = "[local_model_config]\nmodel_config = 1\n[my_model_config]\n";
+        assert_eq!(rename_model_config(contents), contents);
+    }
+
+    #[test]
+    fn the_version_is_added_at_the_top_or_replaced() {
+        let added = set_version("[context]\nenvironment = true\n", 1);
+        assert!(added.starts_with("# Format version"), "{}", added);
+        assert!(
+            added.contains("\nconfig_version = 1\n\n[context]\n"),
+            "{}",
+            added
+        );
+        assert_eq!(version(&added).unwrap(), 1);
+
+        let replaced = set_version("config_version = 0\nhistory = true\n", 2);
+        assert_eq!(replaced, "config_version = 2\nhistory = true\n");
+        assert_eq!(version("history = true\n").unwrap(), 0);
+    }
+}
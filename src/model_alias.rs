@@ -0,0 +1,65 @@
+use anyhow::Result;
+use tracing::info;
+
+use crate::ai_backend::local::{model_type, WhichModel};
+use crate::messages::Message;
+use crate::network::HubRepo;
+use crate::settings::Settings;
+
+/// Prefix of models the local backend runs, followed by the Hugging Face repo
+const LOCAL_PREFIX: &str = "hf:";
+/// Optional prefix of Bedrock model ids, plain ids work too
+const BEDROCK_PREFIX: &str = "bedrock:";
+
+/// Points the settings at the model given with --model and returns the backend that runs
+/// it. `model` is a name from `[model_aliases]` or a model itself: `hf:<repo>` for the
+/// local backend, whose Phi version is read from the repo's config.json, otherwise a
+/// Bedrock model id, inference profile or ARN
+pub fn apply(settings: &mut Settings, model: &str) -> Result<&'static str> {
+    let spec = match settings.model_aliases.get(model) {
+        Some(spec) => {
+            info!("Model alias {} is {}", model, spec);
+            spec.clone()
+        }
+        None if is_model(model) => model.to_string(),
+        None => {
+            let mut names = settings.model_aliases.keys().cloned().collect::<Vec<_>>();
+            names.sort();
+            anyhow::bail!("{}", Message::UnknownModel(model, &names.join(", ")));
+        }
+    };
+    if let Some(repo) = spec.strip_prefix(LOCAL_PREFIX) {
+        settings.local_model_config.model_id = Some(repo.to_string());
+        // quantized repos hold gguf files without a config.json
+        if !settings.local_model_config.quantized {
+            settings.local_model_config.model = architecture(settings, repo)?;
+        }
+        return Ok("local");
+    }
+    let model_id = spec.strip_prefix(BEDROCK_PREFIX).unwrap_or(&spec);
+    settings.aws_settings.model_id = model_id.to_string();
+    Ok("bedrock")
+}
+
+/// The Phi version of the repo, from its config.json in the hub cache or downloaded
+fn architecture(settings: &Settings, repo: &str) -> Result<WhichModel> {
+    let revision = settings
+        .local_model_config
+        .revision
+        .clone()
+        .unwrap_or_else(|| "main".to_string());
+    let kind = model_type(&HubRepo::model(repo.to_string(), revision)?)?.unwrap_or_default();
+    let model = WhichModel::from_model_type(&kind)
+        .ok_or_else(|| anyhow::anyhow!("{}", Message::UnsupportedArchitecture(repo, &kind)))?;
+    info!("{} is a {} model, loading it as {:?}", repo, kind, model);
+    Ok(model)
+}
+
+/// Whether the name is a model rather than a mistyped alias, Bedrock ids have a provider
+/// prefix such as `anthropic.` and ARNs have colons
+fn is_model(name: &str) -> bool {
+    name.starts_with(LOCAL_PREFIX)
+        || name.starts_with(BEDROCK_PREFIX)
+        || name.contains('.')
+        || name.contains(':')
+}
@@ -0,0 +1,76 @@
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use tracing::{debug, info};
+
+use crate::grammar::{Grammar, ShellGrammar};
+use crate::messages::Message;
+use crate::prompts::Task;
+use crate::render;
+use crate::schedule;
+
+/// The response of `ai regex`, `ai jq`, `ai awk` or `ai cron`, checked before it's printed.
+/// A code fence or backticks around it are dropped. Other tasks' responses are kept as is
+pub fn check(task: Task, response: &str) -> Result<String> {
+    let output = render::strip_code_fence(response);
+    let output = match output.strip_prefix('`').and_then(|o| o.strip_suffix('`')) {
+        Some(unquoted) if !unquoted.contains('`') => unquoted.to_string(),
+        _ => output,
+    };
+    match task {
+        Task::Regex => check_regex(&output)?,
+        Task::Jq => check_jq(&output)?,
+        Task::Awk => check_awk(&output)?,
+        Task::Cron => schedule::check_cron(&output)
+            .with_context(|| format!("The response isn't a valid cron expression: {}", output))?,
+        _ => return Ok(response.to_string()),
+    }
+    Ok(output)
+}
+
+/// Compiles the pattern with Rust's regex syntax, which leaves out lookaround and
+/// backreferences like most engines' common subset
+fn check_regex(pattern: &str) -> Result<()> {
+    regex::Regex::new(pattern)
+        .map(|_| ())
+        .map_err(|e| anyhow::anyhow!("{}", Message::InvalidRegex(pattern, &e.to_string())))
+}
+
+/// Compiles the filter with jq without running it. Not checked when jq isn't installed
+fn check_jq(filter: &str) -> Result<()> {
+    // the filter is only compiled, `if false` never runs it
+    let output = match Command::new("jq")
+        .args([
+            "-n",
+            &format!("if false then ({}\n) else empty end", filter),
+        ])
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => {
+            info!("Not checking the jq filter, jq isn't available: {}", e);
+            return Ok(());
+        }
+    };
+    if !output.status.success() {
+        anyhow::bail!(
+            "{}",
+            Message::InvalidJq(filter, String::from_utf8_lossy(&output.stderr).trim())
+        );
+    }
+    debug!("jq compiled {}", filter);
+    Ok(())
+}
+
+/// The awk command has to run awk and be a complete shell command line, quotes closed
+fn check_awk(command: &str) -> Result<()> {
+    let program = command.split_whitespace().next().unwrap_or_default();
+    if !["awk", "gawk", "mawk", "nawk"].contains(&program) {
+        anyhow::bail!("{}", Message::NotAwk(command));
+    }
+    let mut grammar = ShellGrammar::default();
+    if !command.chars().all(|c| grammar.push(c)) || !grammar.is_complete() {
+        anyhow::bail!("{}", Message::UnclosedQuote(command));
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use anyhow::{Context, Result};
+use aws_sdk_sts::config::SharedHttpClient;
+use aws_smithy_http_client::proxy::ProxyConfig;
+use aws_smithy_http_client::tls::{self, TlsContext, TrustStore};
+use aws_smithy_http_client::{Builder, ConnectorBuilder};
+use hf_hub::api::sync::{Api, ApiRepo};
+use hf_hub::{Cache, CacheRepo, Repo, RepoType};
+use tracing::{info, warn};
+
+use crate::messages::Message;
+use crate::settings::Settings;
+
+/// The certificates of the `ca_bundle` setting, once read
+static CA_BUNDLE: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Set by the `local_only` setting or --local-only, nothing may leave the machine
+static LOCAL_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Turns on local-only mode when the settings ask for it and reads the `ca_bundle`
+/// setting's certificates, which every client built here trusts in addition to the
+/// system's. Fails when the file can't be read or holds no certificate
+pub fn init(settings: &Settings) -> Result<()> {
+    if settings.local_only {
+        info!("Local only, no network access");
+        LOCAL_ONLY.store(true, Ordering::SeqCst);
+    }
+    let Some(path) = &settings.ca_bundle else {
+        return Ok(());
+    };
+    let certificates = read_bundle(path)?;
+    info!(
+        "Trusting {} certificates from {}",
+        certificates.len(),
+        path.display()
+    );
+    let _ = CA_BUNDLE.set(certificates);
+    Ok(())
+}
+
+/// Whether network access is forbidden
+pub fn local_only() -> bool {
+    LOCAL_ONLY.load(Ordering::SeqCst)
+}
+
+/// Fails in local-only mode, before `what` connects to anything
+pub fn ensure_online(what: &str) -> Result<()> {
+    if local_only() {
+        anyhow::bail!("{}", Message::LocalOnly(what));
+    }
+    Ok(())
+}
+
+/// A Hugging Face model repo, downloading the files that aren't in the cache. Local-only
+/// only the cached files can be used
+pub enum HubRepo {
+    Online(ApiRepo),
+    Cached { repo: CacheRepo, model_id: String },
+}
+
+impl HubRepo {
+    /// The model's repo at the revision, a branch such as "main"
+    pub fn model(model_id: String, revision: String) -> Result<Self> {
+        let repo = Repo::with_revision(model_id.clone(), RepoType::Model, revision);
+        if local_only() {
+            return Ok(HubRepo::Cached {
+                repo: Cache::default().repo(repo),
+                model_id,
+            });
+        }
+        Ok(HubRepo::Online(Api::new()?.repo(repo)))
+    }
+
+    /// The file's path in the cache, downloaded first unless local-only
+    pub fn get(&self, filename: &str) -> Result<PathBuf> {
+        match self {
+            HubRepo::Online(repo) => Ok(repo.get(filename)?),
+            HubRepo::Cached { repo, model_id } => repo
+                .get(filename)
+                .ok_or_else(|| anyhow::anyhow!("{}", Message::NotCached(filename, model_id))),
+        }
+    }
+
+    /// The safetensors files listed in a json index file, see [`crate::hub_load_safetensors`]
+    pub fn load_safetensors(&self, json_file: &str) -> Result<Vec<PathBuf>> {
+        Ok(crate::load_safetensors(
+            |file| {
+                self.get(file)
+                    .map_err(|e| candle_core::Error::Msg(format!("{:#}", e)))
+            },
+            json_file,
+        )?)
+    }
+}
+
+/// The PEM certificates in the file, checked to parse
+pub fn read_bundle(path: &Path) -> Result<Vec<String>> {
+    let pem = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read the ca_bundle {}", path.display()))?;
+    let certificates = pem
+        .split_inclusive("-----END CERTIFICATE-----")
+        .filter_map(|block| {
+            let start = block.find("-----BEGIN CERTIFICATE-----")?;
+            Some(block[start..].to_string())
+        })
+        .collect::<Vec<_>>();
+    for certificate in certificates.iter() {
+        native_tls::Certificate::from_pem(certificate.as_bytes())
+            .with_context(|| format!("Invalid certificate in {}", path.display()))?;
+    }
+    if certificates.is_empty() {
+        anyhow::bail!("{}", Message::NoCertificates(&path.display().to_string()));
+    }
+    Ok(certificates)
+}
+
+/// An agent for requests to `url`, through the proxy that HTTPS_PROXY, HTTP_PROXY or
+/// ALL_PROXY name unless NO_PROXY excludes the host, and trusting the `ca_bundle`
+pub fn agent(url: &str) -> ureq::AgentBuilder {
+    let mut builder = ureq::AgentBuilder::new().try_proxy_from_env(false);
+    if let Some(proxy) = proxy_for(url) {
+        match ureq::Proxy::new(&proxy) {
+            Ok(proxy) => builder = builder.proxy(proxy),
+            Err(e) => warn!("Ignoring the proxy {}: {}", proxy, e),
+        }
+    }
+    if let Some(certificates) = CA_BUNDLE.get() {
+        let mut connector = native_tls::TlsConnector::builder();
+        for certificate in certificates {
+            // checked by read_bundle
+            if let Ok(certificate) = native_tls::Certificate::from_pem(certificate.as_bytes()) {
+                connector.add_root_certificate(certificate);
+            }
+        }
+        match connector.build() {
+            Ok(connector) => builder = builder.tls_connector(Arc::new(connector)),
+            Err(e) => warn!("Unable to use the ca_bundle: {}", e),
+        }
+    }
+    builder
+}
+
+/// The HTTP client for the AWS SDK, using the proxy variables and trusting the `ca_bundle`
+pub fn aws_http_client() -> SharedHttpClient {
+    let mut trust_store = TrustStore::default();
+    for certificate in CA_BUNDLE.get().into_iter().flatten() {
+        trust_store = trust_store.with_pem_certificate(certificate.as_bytes());
+    }
+    let tls_context = TlsContext::builder()
+        .with_trust_store(trust_store)
+        .build()
+        .unwrap_or_else(|e| {
+            warn!("Unable to use the ca_bundle for AWS: {}", e);
+            TlsContext::default()
+        });
+    Builder::new().build_with_connector_fn(move |settings, runtime_components| {
+        let mut connector = ConnectorBuilder::default()
+            .tls_provider(tls::Provider::Rustls(
+                tls::rustls_provider::CryptoMode::AwsLc,
+            ))
+            .tls_context(tls_context.clone())
+            .proxy_config(ProxyConfig::from_env());
+        connector.set_connector_settings(settings.cloned());
+        if let Some(components) = runtime_components {
+            connector.set_sleep_impl(components.sleep_impl());
+        }
+        connector.build()
+    })
+}
+
+/// The proxy for a url from the environment, None for a direct connection
+pub fn proxy_for(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next()?,
+        None => host.split(':').next()?,
+    };
+    if bypasses_proxy(host) {
+        return None;
+    }
+    let names: &[&str] = match scheme {
+        "https" => &["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"],
+        _ => &["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"],
+    };
+    names
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|proxy| !proxy.is_empty())
+}
+
+/// Whether NO_PROXY lists the host: `*` for every host, a domain for it and its
+/// subdomains, with or without a leading dot
+fn bypasses_proxy(host: &str) -> bool {
+    let no_proxy = std::env::var("NO_PROXY")
+        .or_else(|_| std::env::var("no_proxy"))
+        .unwrap_or_default();
+    let host = host.to_lowercase();
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            let domain = entry.trim_start_matches('.');
+            // a port on the entry is ignored, the proxy is per host
+            let domain = match domain.rsplit_once(':') {
+                Some((domain, port)) if !domain.contains(':') && port.parse::<u16>().is_ok() => {
+                    domain
+                }
+                _ => domain,
+            };
+            entry == "*" || host == domain || host.ends_with(&format!(".{}", domain))
+        })
+}
@@ -0,0 +1,85 @@
+use std::io::IsTerminal;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use console::Term;
+use dialoguer::theme::{ColorfulTheme, SimpleTheme, Theme};
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// When to color the output, from --color
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color what goes to a terminal, unless NO_COLOR is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Decides for stdout and stderr whether they're colored. Everything styled with
+/// `console`, the markdown rendering, spinner, menus, stats, doctor and `ai tui`, follows
+/// this, as do the logs. `plain` is quiet or accessibility mode, which never use color
+pub fn init(choice: ColorChoice, plain: bool) {
+    let enabled = |term: Term| match choice {
+        _ if plain => false,
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => !no_color() && term.features().colors_supported(),
+    };
+    console::set_colors_enabled(enabled(Term::stdout()));
+    console::set_colors_enabled_stderr(enabled(Term::stderr()));
+}
+
+/// https://no-color.org: set to anything but empty to turn colors off
+fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// Whether the log lines get colors, they go to stderr in quiet mode and stdout otherwise
+pub fn colored_logs(quiet: bool) -> bool {
+    if quiet {
+        console::colors_enabled_stderr()
+    } else {
+        console::colors_enabled()
+    }
+}
+
+/// Whether responses are rendered as markdown rather than printed as they are
+pub fn styled_stdout() -> bool {
+    console::colors_enabled()
+}
+
+/// Spinner shown on stderr while generating, None when stderr isn't a terminal
+pub fn spinner(message: String) -> Option<ProgressBar> {
+    if !std::io::stderr().is_terminal() {
+        return None;
+    }
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::with_template("{spinner:.green} {msg}")
+            .unwrap()
+            .tick_strings(&[
+                "⣷", "⣯", "⣟", "⡿", "⢿", "⣻", "⣽", "⣾", // full block
+                "⣿", // "▹▹▹▹▹",
+                     //                 "▸▹▹▹▹",
+                     //                 "▹▸▹▹▹",
+                     //                 "▹▹▸▹▹",
+                     //                 "▹▹▹▸▹",
+                     //                 "▹▹▹▹▸",
+                     //                 "▪▪▪▪▪",
+            ]),
+    );
+    spinner.tick();
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner.set_message(message);
+    Some(spinner)
+}
+
+/// Colorful prompts, or plain text markers in accessibility mode and without colors
+pub fn theme(accessible: bool) -> Box<dyn Theme> {
+    if accessible || !console::colors_enabled_stderr() {
+        Box::new(SimpleTheme)
+    } else {
+        Box::new(ColorfulTheme::default())
+    }
+}
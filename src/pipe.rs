@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::io::{IsTerminal, Read};
+
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::ai_backend::{AiBackend, ChatMessage};
+use crate::budget;
+use crate::constants::PIPE_CHUNK_TOKENS;
+use crate::interrupt;
+use crate::messages::Message;
+use crate::prompts::Prompt;
+use crate::settings::Settings;
+
+/// Tokens kept free in a local model's context for the instruction and the template
+const PROMPT_RESERVE_TOKENS: usize = 256;
+
+/// All of stdin, which has to be piped
+pub fn read_input() -> Result<String> {
+    if std::io::stdin().is_terminal() {
+        anyhow::bail!("{}", Message::NoPipeInput);
+    }
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    if input.trim().is_empty() {
+        anyhow::bail!("{}", Message::NoPipeInput);
+    }
+    Ok(input)
+}
+
+/// Approximate tokens of input sent per request: the `pipe_chunk_tokens` setting, or what
+/// fits in the local model's context next to the generated tokens
+pub fn chunk_tokens(settings: &Settings, backend: &str) -> usize {
+    if let Some(tokens) = settings.pipe_chunk_tokens {
+        return tokens.max(1);
+    }
+    match backend {
+        "local" => {
+            let config = &settings.local_model_config;
+            config
+                .model
+                .context_length()
+                .saturating_sub(config.sample_len + PROMPT_RESERVE_TOKENS)
+                .max(PROMPT_RESERVE_TOKENS)
+        }
+        _ => PIPE_CHUNK_TOKENS,
+    }
+}
+
+/// Splits the input into chunks of about `max_tokens`, at line ends where possible
+pub fn split(input: &str, max_tokens: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    for line in input.split_inclusive('\n') {
+        if !chunk.is_empty()
+            && budget::estimate_tokens(&chunk) + budget::estimate_tokens(line) > max_tokens
+        {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+        // a line longer than a whole chunk is cut at character boundaries
+        let mut rest = line;
+        while budget::estimate_tokens(rest) > max_tokens {
+            let (head, tail) = rest.split_at(
+                rest.char_indices()
+                    .nth(max_tokens * 4)
+                    .map(|(i, _)| i)
+                    .unwrap_or(rest.len()),
+            );
+            chunks.push(head.to_string());
+            rest = tail;
+        }
+        chunk.push_str(rest);
+    }
+    if !chunk.trim().is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Applies the instruction to every chunk of the input, then merges the partial results
+/// with the same instruction until one is left. Ctrl-C stops after the current request,
+/// returning the results so far
+pub fn run(
+    backend: &dyn AiBackend,
+    template: &Prompt,
+    instruction: &str,
+    input: &str,
+    max_tokens: usize,
+) -> Result<String> {
+    interrupt::install();
+    let mut chunks = split(input, max_tokens);
+    let mut results = map(backend, template, instruction, &chunks, false)?;
+    while results.len() > 1 && !interrupt::interrupted() {
+        let merged = results.join("\n\n");
+        let parts = split(&merged, max_tokens);
+        if parts.len() >= chunks.len() {
+            warn!("The partial results don't get shorter, printing them as they are");
+            return Ok(merged);
+        }
+        chunks = parts;
+        results = map(backend, template, instruction, &chunks, true)?;
+    }
+    if interrupt::interrupted() {
+        eprintln!("{}", Message::Interrupted);
+    }
+    Ok(results.join("\n\n"))
+}
+
+/// One response per chunk, or fewer when Ctrl-C is pressed. `merging` tells the model
+/// the chunks are earlier responses
+fn map(
+    backend: &dyn AiBackend,
+    template: &Prompt,
+    instruction: &str,
+    chunks: &[String],
+    merging: bool,
+) -> Result<Vec<String>> {
+    let mut results = Vec::with_capacity(chunks.len());
+    for (index, chunk) in chunks.iter().enumerate() {
+        let part = match (merging, chunks.len()) {
+            (true, _) => "The input below is your answers for consecutive parts of a longer input. Combine them into a single answer.".to_string(),
+            (false, 1) => String::new(),
+            (false, total) => format!("The input below is part {} of {} of a longer input.", index + 1, total),
+        };
+        info!("Sending chunk {} of {}", index + 1, chunks.len());
+        let vars = HashMap::from([
+            ("prompt".to_string(), instruction.to_string()),
+            ("part".to_string(), part),
+            ("input".to_string(), chunk.trim_end().to_string()),
+        ]);
+        let rendered = template.render(&vars)?;
+        let response = interrupt::generating(|| {
+            backend.invoke_conversation(&[ChatMessage::user(rendered)], 1)
+        })?
+        .pop()
+        .ok_or_else(|| anyhow::anyhow!("{}", Message::NoOutput))?;
+        results.push(response.trim().to_string());
+        if interrupt::interrupted() {
+            break;
+        }
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_at_line_ends() {
+        // 8 characters, 2 tokens, per line
+        let input = "aaaaaaa\nbbbbbbb\nccccccc\n";
+        assert_eq!(split(input, 4), ["aaaaaaa\nbbbbbbb\n", "ccccccc\n"]);
+        assert_eq!(split(input, 100), [input]);
+    }
+
+    #[test]
+    fn cuts_a_line_longer_than_a_chunk_at_character_boundaries() {
+        let input = "é".repeat(10);
+        let chunks = split(&input, 1);
+        assert_eq!(chunks, ["éééé", "éééé", "éé"]);
+        assert_eq!(chunks.concat(), input);
+    }
+
+    #[test]
+    fn nothing_but_whitespace_gives_no_chunks() {
+        assert!(split("", 10).is_empty());
+        assert!(split("\n\n", 10).is_empty());
+    }
+}
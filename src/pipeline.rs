@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::ai_backend::AiBackend;
+use crate::audit;
+use crate::messages::Message;
+use crate::render::strip_code_fence;
+use crate::schedule::shell_quote;
+use crate::template;
+
+/// A pipeline file, yaml, toml or json picked by extension
+///
+/// ```yaml
+/// vars:
+///   namespace: prod
+/// steps:
+///   - name: cmd
+///     prompt: "list the pods in the {{namespace}} namespace"
+///   - name: pods
+///     run: "sh -c {{cmd}}"
+///   - name: summary
+///     prompt: "summarize the unhealthy pods in {{pods}}"
+///     when:
+///       var: pods_status
+///       equals: "0"
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct Pipeline {
+    /// Default variables, overridden by --var
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    pub steps: Vec<Step>,
+}
+
+/// A single step, either a prompt sent to the backend or a shell command.
+/// Its output is available to later steps as {{name}}, shell steps also set {{name_status}}.
+/// A prompt's output is the response without its code fence, and variables are put in a
+/// shell command as single quoted words, so a response runs only with e.g. `sh -c {{cmd}}`
+#[derive(Debug, Deserialize)]
+pub struct Step {
+    pub name: String,
+    /// Prompt template sent to the backend
+    pub prompt: Option<String>,
+    /// Shell command template run with `sh -c`
+    pub run: Option<String>,
+    /// Only run the step when the condition holds
+    pub when: Option<Condition>,
+    /// Keep going when a shell step exits non zero
+    #[serde(default)]
+    pub continue_on_error: bool,
+}
+
+/// Checks a variable, by default that it is set and not empty
+#[derive(Debug, Deserialize)]
+pub struct Condition {
+    pub var: String,
+    pub equals: Option<String>,
+    pub contains: Option<String>,
+}
+
+impl Condition {
+    fn holds(&self, vars: &HashMap<String, String>) -> bool {
+        let Some(value) = vars.get(&self.var).map(|v| v.trim()) else {
+            return false;
+        };
+        match (&self.equals, &self.contains) {
+            (Some(expected), _) => value == expected,
+            (_, Some(needle)) => value.contains(needle.as_str()),
+            _ => !value.is_empty(),
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn load(path: &Path) -> Result<Self> {
+        let pipeline: Pipeline = config::Config::builder()
+            .add_source(config::File::from(path))
+            .build()
+            .with_context(|| format!("Unable to read pipeline {:?}", path))?
+            .try_deserialize()
+            .with_context(|| format!("Invalid pipeline {:?}", path))?;
+        for step in pipeline.steps.iter() {
+            if step.prompt.is_some() == step.run.is_some() {
+                anyhow::bail!("{}", Message::StepNeedsPromptOrRun(&step.name));
+            }
+        }
+        Ok(pipeline)
+    }
+
+    /// Runs the steps in order, printing each step's output, and returns the final variables
+    pub fn run(
+        &self,
+        backend: &dyn AiBackend,
+        overrides: HashMap<String, String>,
+    ) -> Result<HashMap<String, String>> {
+        let mut vars = self.vars.clone();
+        vars.extend(overrides);
+        for step in self.steps.iter() {
+            if let Some(condition) = &step.when {
+                if !condition.holds(&vars) {
+                    info!("Skipping step {}", step.name);
+                    continue;
+                }
+            }
+            eprintln!("==> {}", step.name);
+            let output = if let Some(prompt) = &step.prompt {
+                strip_code_fence(&backend.invoke(template::render(prompt, &vars)?)?)
+            } else if let Some(run) = &step.run {
+                let quoted = vars
+                    .iter()
+                    .map(|(name, value)| (name.clone(), shell_quote(value)))
+                    .collect();
+                let command = template::render(run, &quoted)?;
+                // opened first, so a command that can't be recorded isn't run
+                let audit = audit::open()?;
+                info!("Running {}", command);
+                let output = Command::new("sh")
+                    .arg("-c")
+                    .arg(&command)
+                    .output()
+                    .with_context(|| format!("Unable to run step {}", step.name))?;
+                let status = output.status.code().unwrap_or(-1);
+                if let Some(mut audit) = audit {
+                    audit.run(&command, status)?;
+                }
+                vars.insert(format!("{}_status", step.name), status.to_string());
+                if !output.status.success() && !step.continue_on_error {
+                    anyhow::bail!(
+                        "{}",
+                        Message::StepFailed(
+                            &step.name,
+                            status,
+                            String::from_utf8_lossy(&output.stderr).trim()
+                        )
+                    );
+                }
+                String::from_utf8_lossy(&output.stdout).to_string()
+            } else {
+                unreachable!("steps are validated on load")
+            };
+            println!("{}", output.trim_end());
+            vars.insert(step.name.clone(), output.trim().to_string());
+        }
+        Ok(vars)
+    }
+}
+
+/// Parses --var key=value arguments
+pub fn parse_vars(args: &[String]) -> Result<HashMap<String, String>> {
+    args.iter()
+        .map(|arg| {
+            arg.split_once('=')
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .ok_or_else(|| anyhow::anyhow!("{}", Message::ExpectedKeyValue(arg)))
+        })
+        .collect()
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use clap_verbosity_flag::Level;
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::messages::Message;
+use crate::{AiCli, AiCliArgs, Settings};
+
+/// Phases of an invocation, in the order they run. Each is a tracing span
+const PHASES: &[&str] = &[
+    "config_parse",
+    "arg_parse",
+    "context",
+    "backend_init",
+    "model_load",
+    "network",
+    "generation",
+    "post_processing",
+];
+
+/// Prompt profiled when none is given
+pub const DEFAULT_PROFILE_PROMPT: &str = "list the files in the current directory";
+
+/// How long each span was open, summed by span name, while SpanTimings is installed
+static TIMINGS: Mutex<BTreeMap<&'static str, Duration>> = Mutex::new(BTreeMap::new());
+
+/// Records how long each span was open in TIMINGS. main installs it for
+/// `ai profile-startup`, so the phases of the invocation itself are measured
+pub struct SpanTimings;
+
+/// Adds time spent before there was a subscriber to record spans, parsing the arguments
+pub fn record_phase(phase: &'static str, duration: Duration) {
+    if let Ok(mut timings) = TIMINGS.lock() {
+        *timings.entry(phase).or_default() += duration;
+    }
+}
+
+struct Opened(Instant);
+
+impl<S> Layer<S> for SpanTimings
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Opened(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(opened) = span.extensions().get::<Opened>().map(|opened| opened.0) else {
+            return;
+        };
+        record_phase(span.name(), opened.elapsed());
+    }
+}
+
+/// Carries on this invocation with the prompt, the same way `ai <prompt>` would, and prints
+/// how long each phase took since `start`, argument and config parsing included, along
+/// with hints on where the time could be saved. History and the clipboard are left untouched.
+pub fn profile_startup(
+    mut settings: Settings,
+    mut args: AiCliArgs,
+    start: Instant,
+    log_level: Level,
+    prompt: String,
+) -> Result<()> {
+    args.command = None;
+    args.plain = true;
+    args.no_clipboard = true;
+    args.interactive = false;
+    settings.history = false;
+    settings.interactive = false;
+    let backend = args
+        .ai_backend
+        .clone()
+        .unwrap_or_else(|| settings.ai_backend.clone());
+    AiCli::new(settings, args, Some(start), log_level, prompt).exec()?;
+    let total = start.elapsed();
+    let timings = TIMINGS
+        .lock()
+        .map_err(|_| anyhow::anyhow!("{}", Message::LockPoisoned("timings")))?
+        .clone();
+
+    eprintln!();
+    eprintln!(
+        "Startup profile for the {} backend, total {:.2?}",
+        backend, total
+    );
+    let mut accounted = Duration::ZERO;
+    for phase in PHASES {
+        let Some(duration) = timings.get(phase) else {
+            continue;
+        };
+        accounted += *duration;
+        eprintln!(
+            "  {:<16} {:>10.2?} {:>5.1}%",
+            phase,
+            duration,
+            percent(*duration, total)
+        );
+    }
+    let other = total.saturating_sub(accounted);
+    eprintln!(
+        "  {:<16} {:>10.2?} {:>5.1}%",
+        "other",
+        other,
+        percent(other, total)
+    );
+    for hint in hints(&timings, total) {
+        eprintln!("hint: {}", hint);
+    }
+    Ok(())
+}
+
+fn percent(duration: Duration, total: Duration) -> f64 {
+    if total.is_zero() {
+        0.
+    } else {
+        duration.as_secs_f64() / total.as_secs_f64() * 100.
+    }
+}
+
+/// Suggestions for the phases that dominate the run
+fn hints(timings: &BTreeMap<&'static str, Duration>, total: Duration) -> Vec<&'static str> {
+    let share = |phase: &str| percent(timings.get(phase).copied().unwrap_or_default(), total);
+    let mut hints = Vec::new();
+    if share("model_load") > 40. {
+        hints.push("loading the model dominates, use a quantized model (`quantized = true`, `ai models quantize`) or the bedrock backend");
+    }
+    if share("generation") > 40. && timings.contains_key("model_load") {
+        hints.push("local generation is slow, lower `sample_len` or set `devices` to use a GPU");
+    }
+    if share("network") > 30. {
+        hints.push("connecting to bedrock is slow, try a closer `region` or check proxy settings");
+    }
+    if share("context") > 20. {
+        hints.push("gathering context is slow, lower `context.timeout_ms` or disable providers");
+    }
+    if share("config_parse") + share("arg_parse") > 20. {
+        hints.push("reading the config is slow, check that ~/.config/ai is on a local disk");
+    }
+    hints
+}
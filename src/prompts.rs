@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::context;
+use crate::context::environment::{self, EnvironmentContext, ShellKind};
+use crate::settings::{config_dir, Settings};
+use crate::template;
+
+/// Tasks with their own prompts. The built-in prompts can be overridden per task
+/// in ~/.config/ai/prompts/<task>.toml
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    /// Turning a request into a command, `ai <prompt>`
+    Generate,
+    /// Correcting a failed command, `ai fix`
+    Fix,
+    /// Reversing what a command did, `ai undo`
+    Undo,
+    /// Diagnosing error output piped in, `ai explain-error`
+    ExplainError,
+    /// A git command for the repository's state, `ai git`
+    Git,
+    /// Writing a commit message for the staged changes, `ai commit`
+    Commit,
+    /// Changing the last answer, `ai refine`. Sent as the next turn of the last conversation
+    Refine,
+    /// Finishing a partly typed command, `ai complete`
+    Complete,
+    /// Applying an instruction to text piped in, `ai pipe`
+    Pipe,
+    /// Summarizing text piped in, `ai summarize`
+    Summarize,
+    /// Writing a regular expression, `ai regex`
+    Regex,
+    /// Writing a jq filter, `ai jq`
+    Jq,
+    /// Writing an awk command, `ai awk`
+    Awk,
+    /// Writing a cron expression, `ai cron`
+    Cron,
+}
+
+impl Task {
+    pub fn name(self) -> &'static str {
+        match self {
+            Task::Generate => "generate",
+            Task::Fix => "fix",
+            Task::Undo => "undo",
+            Task::ExplainError => "explain-error",
+            Task::Git => "git",
+            Task::Commit => "commit",
+            Task::Refine => "refine",
+            Task::Complete => "complete",
+            Task::Pipe => "pipe",
+            Task::Summarize => "summarize",
+            Task::Regex => "regex",
+            Task::Jq => "jq",
+            Task::Awk => "awk",
+            Task::Cron => "cron",
+        }
+    }
+
+    /// Whether the response is a shell command, rather than e.g. a regex or commit message
+    pub fn is_command(self) -> bool {
+        !matches!(
+            self,
+            Task::ExplainError
+                | Task::Commit
+                | Task::Pipe
+                | Task::Summarize
+                | Task::Regex
+                | Task::Jq
+                | Task::Cron
+        )
+    }
+
+    fn builtin(self) -> Prompt {
+        let (system, template) = match self {
+            Task::Generate => (generate_system(), GENERATE_TEMPLATE),
+            Task::Fix => (generate_system(), FIX_TEMPLATE),
+            Task::Undo => (generate_system(), UNDO_TEMPLATE),
+            Task::ExplainError => (EXPLAIN_ERROR_SYSTEM, EXPLAIN_ERROR_TEMPLATE),
+            Task::Git => (GIT_SYSTEM, GENERATE_TEMPLATE),
+            Task::Commit => (COMMIT_SYSTEM, COMMIT_TEMPLATE),
+            Task::Refine => (generate_system(), REFINE_TEMPLATE),
+            Task::Complete => (generate_system(), COMPLETE_TEMPLATE),
+            Task::Pipe => (PIPE_SYSTEM, PIPE_TEMPLATE),
+            Task::Summarize => (SUMMARIZE_SYSTEM, PIPE_TEMPLATE),
+            Task::Regex => (REGEX_SYSTEM, GENERATE_TEMPLATE),
+            Task::Jq => (JQ_SYSTEM, GENERATE_TEMPLATE),
+            Task::Awk => (AWK_SYSTEM, GENERATE_TEMPLATE),
+            Task::Cron => (CRON_SYSTEM, GENERATE_TEMPLATE),
+        };
+        Prompt {
+            system: system.to_string(),
+            template: template.to_string(),
+        }
+    }
+}
+
+/// The system prompt and user prompt template of a task
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    /// Default system prompt, the `system_prompt` setting and --system replace it
+    pub system: String,
+    /// Template the user's request is sent as, `{{prompt}}` is the request.
+    /// Tasks add their own variables, e.g. `{{command}}` for fix
+    pub template: String,
+}
+
+/// The fields of a prompts/<task>.toml file, unset ones keep the built-in prompt
+#[derive(Debug, Default, Deserialize)]
+struct PromptOverride {
+    system: Option<String>,
+    template: Option<String>,
+}
+
+/// The system prompt to send: `custom`, or the `system_prompt`/`system_prompt_file` setting,
+/// with its placeholders expanded, otherwise `default`, followed by `system_prompt_append`.
+/// With `environment` a description of the OS, shell and installed tools is appended, left
+/// out when gathering it takes longer than the context time budget
+pub fn system_prompt(
+    custom: Option<&str>,
+    settings: &Settings,
+    default: &str,
+    environment: bool,
+) -> Result<String> {
+    let custom = match (custom, settings.system_prompt.as_deref()) {
+        (Some(system), _) | (None, Some(system)) => Some(system.to_string()),
+        (None, None) => match &settings.system_prompt_file {
+            Some(path) => Some(
+                std::fs::read_to_string(path)
+                    .with_context(|| format!("Unable to read system prompt file {:?}", path))?,
+            ),
+            None => None,
+        },
+    };
+    let system_prompt = match custom {
+        Some(custom) => template::render(&custom, &environment::placeholders(default))
+            .context("Invalid system prompt, placeholders are {{default}}, {{shell}} and {{os}}")?,
+        None => default.to_string(),
+    };
+    let system_prompt = match &settings.system_prompt_append {
+        Some(append) => format!("{}\n\n{}", system_prompt, append.trim()),
+        None => system_prompt,
+    };
+    if !environment {
+        return Ok(system_prompt);
+    }
+    let budget = Duration::from_millis(settings.context.timeout_ms);
+    let block = context::gather(vec![Box::new(EnvironmentContext)], budget)
+        .pop()
+        .flatten()
+        .unwrap_or_default();
+    Ok(format!("{}{}", system_prompt, block))
+}
+
+/// ~/.config/ai/prompts
+pub fn prompts_dir() -> PathBuf {
+    config_dir().join("prompts")
+}
+
+impl Prompt {
+    /// The task's built-in prompts with the ones set in prompts/<task>.toml replacing them
+    pub fn load(task: Task) -> Result<Self> {
+        let mut prompt = task.builtin();
+        let path = prompts_dir().join(task.name()).with_extension("toml");
+        if !path.exists() {
+            return Ok(prompt);
+        }
+        let overrides: PromptOverride = config::Config::builder()
+            .add_source(config::File::from(path.as_path()))
+            .build()
+            .with_context(|| format!("Unable to read prompts {:?}", path))?
+            .try_deserialize()
+            .with_context(|| format!("Invalid prompts {:?}", path))?;
+        if let Some(system) = overrides.system {
+            prompt.system = system;
+        }
+        if let Some(template) = overrides.template {
+            prompt.template = template;
+        }
+        Ok(prompt)
+    }
+
+    /// Fills in the template
+    pub fn render(&self, vars: &HashMap<String, String>) -> Result<String> {
+        template::render(&self.template, vars).context("Invalid prompt template")
+    }
+}
+
+/// The system prompt for commands in the syntax of the user's shell
+fn generate_system() -> &'static str {
+    match environment::shell_kind() {
+        ShellKind::Posix => GENERATE_SYSTEM,
+        ShellKind::PowerShell => POWERSHELL_SYSTEM,
+        ShellKind::Cmd => CMD_SYSTEM,
+    }
+}
+
+const GENERATE_SYSTEM: &str = "You are a command-line interface expert focused on generating bash one-liners. Your role is to create concise, efficient, and safe bash commands that solve the user's specified task in a single line.
+
+Key responsibilities:
+1. Generate ONLY the bash command, without explanation unless asked
+2. Always use proper shell escaping and quoting
+3. Prefer portable POSIX-compliant solutions when possible
+4. Use common Unix tools (grep, sed, awk, find, etc.) appropriately
+5. Consider error handling and edge cases
+6. Never include dangerous operations (rm -rf, etc.) without warning
+7. Add comments only if they fit in the one-liner using #
+8. When the request leaves out a value the command needs, such as a path, host or name, write a placeholder like {{target_dir}} instead of guessing
+
+Guidelines for command generation:
+- Parse the user's intent carefully
+- Choose the most efficient approach for the task
+- Use pipes (|) to chain commands when needed
+- Leverage command substitution $() where appropriate
+- Consider environment variables if relevant
+- Use appropriate file globbing patterns when needed
+
+Security and safety:
+- Always escape special characters in filenames
+- Use quotes around variables and paths
+- Avoid commands that could cause data loss
+- Include error checking where critical
+- Never generate commands that could harm the system
+
+Example format:
+Human: Find all PDF files modified in the last 24 hours
+Assistant: find . -type f -name \"*.pdf\" -mtime -1";
+
+const POWERSHELL_SYSTEM: &str = "You are a command-line interface expert focused on generating PowerShell one-liners for Windows. Your role is to create concise, efficient, and safe PowerShell commands that solve the user's specified task in a single line.
+
+Key responsibilities:
+1. Generate ONLY the PowerShell command, without explanation unless asked
+2. Use full cmdlet names (Get-ChildItem, not ls or gci) so the command reads clearly
+3. Quote paths with single quotes, or double quotes when they contain variables
+4. Pipe objects between cmdlets (Where-Object, Sort-Object, Select-Object) rather than parsing text
+5. Never use bash syntax or Unix tools such as grep, sed, awk or find
+6. Never include destructive operations (Remove-Item -Recurse -Force, etc.) without -WhatIf or a warning
+7. Add comments only if they fit in the one-liner using #
+8. When the request leaves out a value the command needs, such as a path, host or name, write a placeholder like {{target_dir}} instead of guessing
+
+Example format:
+Human: Find all PDF files modified in the last 24 hours
+Assistant: Get-ChildItem -Recurse -Filter *.pdf | Where-Object { $_.LastWriteTime -gt (Get-Date).AddDays(-1) }";
+
+const CMD_SYSTEM: &str = "You are a command-line interface expert focused on generating Windows command prompt (cmd.exe) one-liners. Your role is to create concise, efficient, and safe cmd commands that solve the user's specified task in a single line.
+
+Key responsibilities:
+1. Generate ONLY the cmd command, without explanation unless asked
+2. Use built-in commands (dir, copy, move, del, findstr, for, where) and the tools shipped with Windows
+3. Quote paths with double quotes and use %VAR% for environment variables
+4. Chain commands with &, && and || and use a single % in for loops as they are typed at the prompt
+5. Never use bash or PowerShell syntax or Unix tools such as grep, sed, awk or find
+6. Never include destructive operations (del /s /q, rd /s /q, etc.) without a warning
+7. When the request leaves out a value the command needs, such as a path, host or name, write a placeholder like {{target_dir}} instead of guessing
+
+Example format:
+Human: Find all PDF files under the current directory
+Assistant: dir /s /b *.pdf";
+
+/// The user's request as is
+const GENERATE_TEMPLATE: &str = "{{prompt}}";
+
+/// Prompt template for `ai fix`, filled in with the failed command, its exit status and output
+const FIX_TEMPLATE: &str =
+    "This command failed. Reply with a corrected command that does what it was meant to do.
+
+Command: {{command}}
+Exit status: {{status}}
+Output:
+{{error}}";
+
+/// Prompt template for `ai undo`, filled in with the command to reverse
+const UNDO_TEMPLATE: &str = "Reply with a command that reverses what this command did, e.g. moving a file back with `mv` or `git revert` for a commit. If it can't be undone, e.g. deleted or overwritten files, reply with a # comment saying why.
+
+Command: {{command}}";
+
+/// Prompt template for `ai explain-error`, filled in with the error output and the command
+/// that printed it, when given
+const EXPLAIN_ERROR_TEMPLATE: &str = "Explain what caused this error and how to fix it.
+
+Command: {{command}}
+Error output:
+{{error}}";
+
+/// Prompt template for `ai refine`, the previous request and answer come before it in the
+/// conversation
+const REFINE_TEMPLATE: &str = "Change your previous command as follows: {{prompt}}
+
+Reply with ONLY the updated command.";
+
+/// Prompt template for `ai complete`, the fragment is kept verbatim so only what follows
+/// it needs to be inserted on the command line
+const COMPLETE_TEMPLATE: &str = "Finish this partly typed command. Keep what is typed exactly as it is, including a partly typed word at the end, and fill in the rest.
+
+Typed so far: {{prompt}}
+
+Reply with ONLY the whole command, starting with the typed text.";
+
+const PIPE_SYSTEM: &str = "You are a text processing tool in a shell pipeline. You are given an instruction and the text it applies to, such as logs, data or documents.
+
+Rules:
+- Follow the instruction using only the given text
+- Reply with ONLY the result, no introduction, explanation or code fences unless asked for
+- Keep the format of the input when the instruction transforms it, e.g. one line in for one line out
+- When the text is part of a longer input, answer for this part alone";
+
+/// Prompt template for `ai pipe`, filled in with the instruction, which part of the input
+/// this is when it was split and the piped text
+const PIPE_TEMPLATE: &str = "{{prompt}}
+
+{{part}}
+<input>
+{{input}}
+</input>";
+
+const EXPLAIN_ERROR_SYSTEM: &str = "You are an experienced developer and system administrator diagnosing errors from compilers, package managers, scripts and services.
+
+Reply in this format and keep it short:
+Cause: one or two sentences on what went wrong and why, naming the file, line, package or setting involved when the output shows it
+Fix: the command that fixes it in a ```sh code block, or the change to make when it isn't a command
+
+Rules:
+- Don't restate the error output
+- When the output is cut off or the cause can't be known from it, say what to check and the command that shows it";
+
+const GIT_SYSTEM: &str = "You are a git expert. Write the git command, or commands joined with &&, doing what the user asks in their repository.
+
+Rules:
+- Reply with ONLY the command on one line, no explanation
+- Base it on the branch, recent commits and status given with the request: use the real branch names, commit hashes and counts
+- Prefer the safe form: --force-with-lease over --force, and git switch and git restore over git checkout
+- When the request needs history rewriting on a pushed branch, still answer but add a # comment warning about it
+
+Example format:
+Human: squash my last 3 commits
+Assistant: git reset --soft HEAD~3 && git commit";
+
+const REGEX_SYSTEM: &str = "You are a regular expression expert. Write a regular expression matching what the user describes.
+
+Rules:
+- Reply with ONLY the pattern on one line, no delimiters, quotes, flags or explanation
+- Stick to syntax most engines share (grep -E, Python, JavaScript, Rust): no lookahead, lookbehind or backreferences
+- Anchor with ^ and $ only when the whole line or string has to match
+
+Example format:
+Human: match ISO dates
+Assistant: [0-9]{4}-[0-9]{2}-[0-9]{2}";
+
+const JQ_SYSTEM: &str =
+    "You are a jq expert. Write a jq filter doing what the user describes to JSON input.
+
+Rules:
+- Reply with ONLY the filter on one line, without `jq`, shell quotes or explanation
+
+Example format:
+Human: names of the items older than 30
+Assistant: .items[] | select(.age > 30) | .name";
+
+const AWK_SYSTEM: &str =
+    "You are an awk expert. Write an awk command doing what the user describes to text input.
+
+Rules:
+- Reply with ONLY the command on one line, starting with awk, the program in single quotes
+- Use POSIX awk, no gawk extensions unless asked
+- Set the field separator with -F when the input isn't whitespace separated
+
+Example format:
+Human: sum the third column of a csv
+Assistant: awk -F, '{ sum += $3 } END { print sum }'";
+
+const CRON_SYSTEM: &str =
+    "You are a cron expert. Write the cron expression for the schedule the user describes.
+
+Rules:
+- Reply with ONLY the 5 fields: minute, hour, day of month, month and day of week
+- Follow them with the command when the user gives one, as a crontab line
+- No explanation
+
+Example format:
+Human: every weekday at 9am
+Assistant: 0 9 * * 1-5";
+
+const SUMMARIZE_SYSTEM: &str = "You summarize logs and other long text piped in from a shell, such as service logs, build output or command results.
+
+Rules:
+- Lead with what matters most: errors, failures and warnings, how often each happened and when
+- Group repeated lines into one point with a count instead of listing them
+- Keep names exact: hosts, services, paths, error codes and ids
+- Reply with at most 10 short bullet points, no introduction
+- When the text is part of a longer input, summarize that part alone
+- When combining summaries of consecutive parts, merge them into one, adding up the counts, without mentioning the parts";
+
+const COMMIT_SYSTEM: &str = "You are an experienced software engineer writing git commit messages. Follow the Conventional Commits format:
+
+<type>(<optional scope>): <summary>
+
+<optional body>
+
+Rules:
+- type is one of feat, fix, docs, style, refactor, perf, test, build, ci, chore or revert
+- The summary is in the imperative mood, lower case, without a trailing period and under 72 characters
+- Add a body only when the why isn't obvious from the summary, wrapped at 72 characters
+- Describe what changed and why, not how the diff looks
+- Reply with ONLY the commit message, no code fences or explanation";
+
+/// Prompt template for `ai commit`, filled in with the staged diffstat and diff
+const COMMIT_TEMPLATE: &str = "Write a commit message for these staged changes.
+
+{{stat}}
+
+{{diff}}";
@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::Device;
+use tracing::{debug, info};
+
+use crate::install;
+use crate::messages::Message;
+use crate::network::HubRepo;
+
+/// Maps the requested bit width onto a ggml quantization type
+fn dtype_for_bits(bits: u8) -> Result<GgmlDType> {
+    match bits {
+        4 => Ok(GgmlDType::Q4K),
+        5 => Ok(GgmlDType::Q5K),
+        6 => Ok(GgmlDType::Q6K),
+        8 => Ok(GgmlDType::Q8_0),
+        _ => anyhow::bail!("{}", Message::UnsupportedBits(bits)),
+    }
+}
+
+/// Directory quantized artifacts are written to, ~/.cache/ai/models on linux
+pub fn quantized_cache_dir() -> PathBuf {
+    install::cache_dir().join("models")
+}
+
+/// Downloads the safetensors weights for `model_id` and writes a quantized gguf file to the cache.
+/// Only matrices whose row length is a multiple of the block size are quantized, everything
+/// else (norms, biases, embeddings with odd shapes) is kept as f32.
+/// Returns the path of the written file.
+pub fn quantize_model(model_id: &str, revision: Option<&str>, bits: u8) -> Result<PathBuf> {
+    let dtype = dtype_for_bits(bits)?;
+    let revision = revision.unwrap_or("main");
+    info!("Downloading {model_id} revision {revision} for quantization");
+    let repo = HubRepo::model(model_id.to_string(), revision.to_string())?;
+    let filenames = match repo.get("model.safetensors.index.json") {
+        Ok(_) => repo.load_safetensors("model.safetensors.index.json")?,
+        Err(_) => vec![repo.get("model.safetensors")?],
+    };
+
+    let mut tensors = Vec::new();
+    for filename in filenames.iter() {
+        info!("Quantizing {:?}", filename);
+        for (name, tensor) in candle_core::safetensors::load(filename, &Device::Cpu)? {
+            let quantize =
+                tensor.rank() >= 2 && tensor.dim(candle_core::D::Minus1)? % dtype.block_size() == 0;
+            let target = if quantize { dtype } else { GgmlDType::F32 };
+            debug!("{name} {:?} -> {:?}", tensor.shape(), target);
+            let qtensor = QTensor::quantize(&tensor, target)
+                .with_context(|| format!("Unable to quantize tensor {name}"))?;
+            tensors.push((name, qtensor));
+        }
+    }
+    tensors.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let out_dir = quantized_cache_dir();
+    std::fs::create_dir_all(&out_dir)?;
+    let out_path = out_dir.join(format!(
+        "models--{}--{}-{}.gguf",
+        model_id.replace('/', "--"),
+        revision,
+        format!("{:?}", dtype).to_lowercase()
+    ));
+
+    let name = gguf_file::Value::String(model_id.to_string());
+    let file_type = gguf_file::Value::String(format!("{:?}", dtype));
+    let metadata = [("general.name", &name), ("general.file_type", &file_type)];
+    let tensors = tensors
+        .iter()
+        .map(|(name, tensor)| (name.as_str(), tensor))
+        .collect::<Vec<_>>();
+    let mut out_file = std::fs::File::create(&out_path)
+        .with_context(|| format!("Unable to create {:?}", out_path))?;
+    gguf_file::write(&mut out_file, &metadata, &tensors)?;
+    info!("Wrote {} tensors to {:?}", tensors.len(), out_path);
+    Ok(out_path)
+}
@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+use crate::budget;
+use crate::messages::Message;
+use crate::settings::{config_dir, Quota, QuotaSettings};
+
+/// Requests sent to a backend and the tokens they used
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+pub struct Consumption {
+    pub requests: u64,
+    pub tokens: u64,
+}
+
+impl Consumption {
+    fn add(&mut self, other: Consumption) {
+        self.requests += other.requests;
+        self.tokens += other.tokens;
+    }
+}
+
+/// Consumption by backend, by day as "YYYY-MM-DD"
+type Days = BTreeMap<String, BTreeMap<String, Consumption>>;
+
+/// What was used today and this month in UTC, by backend
+#[derive(Debug, Default)]
+pub struct CurrentUsage {
+    pub today: BTreeMap<String, Consumption>,
+    pub month: BTreeMap<String, Consumption>,
+}
+
+impl CurrentUsage {
+    /// Today's and this month's consumption of the backend, of every backend when None
+    fn of(&self, backend: Option<&str>) -> (Consumption, Consumption) {
+        let sum = |by_backend: &BTreeMap<String, Consumption>| {
+            let mut total = Consumption::default();
+            by_backend
+                .iter()
+                .filter(|(name, _)| backend.is_none_or(|backend| backend == name.as_str()))
+                .for_each(|(_, consumption)| total.add(*consumption));
+            total
+        };
+        (sum(&self.today), sum(&self.month))
+    }
+}
+
+/// Requests and tokens by day and backend, ~/.config/ai/usage.json
+pub struct UsageLog {
+    path: PathBuf,
+}
+
+impl UsageLog {
+    pub fn open() -> Self {
+        Self {
+            path: config_dir().join("usage.json"),
+        }
+    }
+
+    fn days(&self) -> Result<Days> {
+        if !self.path.exists() {
+            return Ok(Days::new());
+        }
+        let contents = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Unable to read {:?}", self.path))?;
+        serde_json::from_str(&contents).with_context(|| format!("Invalid {:?}", self.path))
+    }
+
+    pub fn current(&self) -> Result<CurrentUsage> {
+        let today = budget::current_day();
+        let month = budget::current_month();
+        let mut usage = CurrentUsage::default();
+        for (day, by_backend) in self.days()? {
+            if !day.starts_with(&month) {
+                continue;
+            }
+            for (backend, consumption) in by_backend {
+                if day == today {
+                    usage
+                        .today
+                        .entry(backend.clone())
+                        .or_default()
+                        .add(consumption);
+                }
+                usage.month.entry(backend).or_default().add(consumption);
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Adds a request and its tokens to today's consumption of the backend
+    pub fn record(&self, backend: &str, tokens: u64) -> Result<()> {
+        let mut days = self.days()?;
+        days.entry(budget::current_day())
+            .or_default()
+            .entry(backend.to_string())
+            .or_default()
+            .add(Consumption {
+                requests: 1,
+                tokens,
+            });
+        std::fs::write(&self.path, serde_json::to_string_pretty(&days)?)
+            .with_context(|| format!("Unable to write {:?}", self.path))
+    }
+}
+
+/// Refuses a request once today's requests reached `max_requests_per_day`, or when the
+/// prompt's estimated tokens would take this month's over `max_tokens_per_month`, checking
+/// the quota of every backend together and then the backend's own
+pub fn check(quotas: &QuotaSettings, backend: &str, prompt: &str) -> Result<()> {
+    let own = quotas.backends.get(backend);
+    if quotas.total().is_unlimited() && own.is_none_or(Quota::is_unlimited) {
+        return Ok(());
+    }
+    let usage = UsageLog::open().current()?;
+    let estimate = budget::estimate_tokens(prompt) as u64;
+    check_quota(&quotas.total(), "[quotas]", usage.of(None), estimate)?;
+    if let Some(quota) = own {
+        let table = format!("[quotas.backends.{}]", backend);
+        check_quota(quota, &table, usage.of(Some(backend)), estimate)?;
+    }
+    Ok(())
+}
+
+fn check_quota(
+    quota: &Quota,
+    table: &str,
+    (today, month): (Consumption, Consumption),
+    estimate: u64,
+) -> Result<()> {
+    if let Some(max) = quota.max_requests_per_day {
+        info!(
+            "{} of {} requests made today for {}",
+            today.requests, max, table
+        );
+        if today.requests >= max {
+            anyhow::bail!("{}", Message::RequestQuota(table, today.requests, max));
+        }
+    }
+    if let Some(max) = quota.max_tokens_per_month {
+        info!(
+            "{} of {} tokens used this month for {}",
+            month.tokens, max, table
+        );
+        if month.tokens + estimate > max {
+            anyhow::bail!(
+                "{}",
+                Message::TokenQuota(table, month.tokens, estimate, max)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Prints today's requests and this month's tokens by backend, then of every backend, with
+/// the quotas that apply
+pub fn show(quotas: &QuotaSettings) -> Result<()> {
+    let usage = UsageLog::open().current()?;
+    if usage.month.is_empty() {
+        println!("{}", Message::NoUsage);
+    }
+    for backend in usage.month.keys() {
+        let (today, month) = usage.of(Some(backend));
+        println!(
+            "{}",
+            Message::UsageLine(backend, today.requests, month.tokens)
+        );
+        if let Some(quota) = quotas.backends.get(backend.as_str()) {
+            print_quota(quota);
+        }
+    }
+    let (today, month) = usage.of(None);
+    println!(
+        "{}",
+        Message::UsageLine(
+            &Message::AllBackends.to_string(),
+            today.requests,
+            month.tokens
+        )
+    );
+    print_quota(&quotas.total());
+    Ok(())
+}
+
+fn print_quota(quota: &Quota) {
+    if !quota.is_unlimited() {
+        println!(
+            "    {}",
+            Message::QuotaLine(quota.max_requests_per_day, quota.max_tokens_per_month)
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Error as E, Result};
+use candle_core::{Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
+use tracing::info;
+
+use crate::messages::Message;
+use crate::network::HubRepo;
+
+/// Default sentence embedding model, a 384 dimension BERT small enough for the CPU
+pub const DEFAULT_EMBEDDING_MODEL: &str = "sentence-transformers/all-MiniLM-L6-v2";
+
+/// Texts embedded per forward pass
+const BATCH_SIZE: usize = 16;
+
+/// The embedder loaded last with its model id, kept for the searches that follow in the
+/// same process
+static LOADED: Mutex<Option<(String, Arc<Embedder>)>> = Mutex::new(None);
+
+/// A BERT sentence embedding model from the hub, always on the CPU as it's small and
+/// loading it on a GPU would cost more than it saves
+pub struct Embedder {
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+}
+
+impl Embedder {
+    /// Downloads the model on first use, e.g. `sentence-transformers/all-MiniLM-L6-v2`
+    /// or `thenlper/gte-small`, any BERT with safetensors weights works
+    pub fn load(model_id: &str) -> Result<Self> {
+        info!("Loading embedding model {}", model_id);
+        let repo = HubRepo::model(model_id.to_string(), "main".to_string())?;
+        let config: Config =
+            serde_json::from_str(&std::fs::read_to_string(repo.get("config.json")?)?)?;
+        let mut tokenizer = Tokenizer::from_file(repo.get("tokenizer.json")?).map_err(E::msg)?;
+        tokenizer.with_padding(Some(PaddingParams {
+            strategy: PaddingStrategy::BatchLongest,
+            ..Default::default()
+        }));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_position_embeddings,
+                ..Default::default()
+            }))
+            .map_err(E::msg)?;
+        let device = Device::Cpu;
+        let weights = repo.get("model.safetensors")?;
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DTYPE, &device)? };
+        let model = BertModel::load(vb, &config)?;
+        Ok(Self {
+            model,
+            tokenizer,
+            device,
+        })
+    }
+
+    /// The embedder for `model_id`, loaded on the first call and kept for the following
+    /// ones: interactive sessions and the daemon search the index for every prompt
+    pub fn shared(model_id: &str) -> Result<Arc<Self>> {
+        let mut loaded = LOADED
+            .lock()
+            .map_err(|_| anyhow::anyhow!("{}", Message::LockPoisoned("embedder")))?;
+        if let Some((loaded_id, embedder)) = loaded.as_ref() {
+            if loaded_id == model_id {
+                return Ok(embedder.clone());
+            }
+        }
+        let embedder = Arc::new(Self::load(model_id)?);
+        *loaded = Some((model_id.to_string(), embedder.clone()));
+        Ok(embedder)
+    }
+
+    /// One unit length vector per text, the mean of its token embeddings
+    pub fn embed(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(BATCH_SIZE) {
+            vectors.extend(self.embed_batch(batch)?);
+        }
+        Ok(vectors)
+    }
+
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(E::msg)?;
+        let ids = encodings
+            .iter()
+            .map(|e| Tensor::new(e.get_ids(), &self.device))
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        let masks = encodings
+            .iter()
+            .map(|e| Tensor::new(e.get_attention_mask(), &self.device))
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        let ids = Tensor::stack(&ids, 0)?;
+        let mask = Tensor::stack(&masks, 0)?;
+        let token_type_ids = ids.zeros_like()?;
+        let output = self.model.forward(&ids, &token_type_ids, Some(&mask))?;
+        // padding doesn't count towards the mean
+        let mask = mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+        let pooled = output
+            .broadcast_mul(&mask)?
+            .sum(1)?
+            .broadcast_div(&mask.sum(1)?)?;
+        let norms = pooled.sqr()?.sum_keepdim(1)?.sqrt()?;
+        Ok(pooled.broadcast_div(&norms)?.to_vec2::<f32>()?)
+    }
+}
+
+/// Cosine similarity of two unit length vectors
+pub fn similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
@@ -0,0 +1,306 @@
+pub mod embeddings;
+pub mod store;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use tracing::{info, warn};
+
+pub use embeddings::{Embedder, DEFAULT_EMBEDDING_MODEL};
+pub use store::Index;
+
+use crate::context::display_path;
+use crate::messages::Message;
+use crate::settings::PROJECT_CONFIG;
+use store::{Chunk, IndexedFile};
+
+/// Directories that hold dependencies or build output rather than notes
+const SKIPPED_DIRS: &[&str] = &[
+    ".git",
+    ".hg",
+    "node_modules",
+    "target",
+    "__pycache__",
+    ".venv",
+    ".cache",
+];
+
+/// Files or directories marking the top directory of a project
+const PROJECT_MARKERS: &[&str] = &[".git", ".hg", PROJECT_CONFIG];
+
+/// Larger files are skipped, they are rarely notes or configuration
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Characters per chunk, a few hundred tokens so a chunk stays within the model's input
+const CHUNK_CHARS: usize = 1000;
+
+/// A chunk of an indexed file that is relevant to a query
+#[derive(Debug)]
+pub struct Hit {
+    pub path: String,
+    pub line: usize,
+    pub text: String,
+    pub score: f32,
+}
+
+/// What `ai index` did
+#[derive(Debug)]
+pub struct Indexed {
+    /// Files found in the directories
+    pub files: usize,
+    /// Files that were new or changed and were embedded
+    pub embedded: usize,
+    /// Chunks in the whole index
+    pub chunks: usize,
+}
+
+/// Embeds the text files under `dirs` and adds them to the index. Files that haven't
+/// changed since they were last indexed are kept as they are and ones that were deleted
+/// are dropped, so running it again is cheap
+pub fn index(model_id: &str, dirs: &[PathBuf]) -> Result<Indexed> {
+    let mut index = Index::load()?;
+    if index.model != model_id {
+        if !index.files.is_empty() {
+            warn!(
+                "The index was built with {}, rebuilding it with {} from the given directories",
+                index.model, model_id
+            );
+        }
+        index = Index {
+            model: model_id.to_string(),
+            ..Default::default()
+        };
+    }
+    let mut found = Vec::new();
+    let mut roots = Vec::new();
+    for dir in dirs {
+        let dir = dir
+            .canonicalize()
+            .with_context(|| format!("Unable to index {:?}", dir))?;
+        if !dir.is_dir() {
+            anyhow::bail!("{}", Message::NotADirectory(&format!("{:?}", dir)));
+        }
+        walk(&dir, &mut found);
+        roots.push(display_path(&dir));
+    }
+    let found_keys: HashSet<String> = found.iter().map(|path| display_path(path)).collect();
+    index.files.retain(|key, _| {
+        found_keys.contains(key)
+            || !roots
+                .iter()
+                .any(|root| Path::new(key).starts_with(Path::new(root)))
+    });
+
+    let mut embedder: Option<Embedder> = None;
+    let mut embedded = 0;
+    let bar = ProgressBar::new(found.len() as u64);
+    bar.set_style(ProgressStyle::with_template("{bar:30} {pos}/{len} {msg}")?);
+    for path in found.iter() {
+        bar.inc(1);
+        let key = display_path(path);
+        let modified = modified_secs(path);
+        if index
+            .files
+            .get(&key)
+            .is_some_and(|file| file.modified == modified)
+        {
+            continue;
+        }
+        // binary files aren't notes
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            index.files.remove(&key);
+            continue;
+        };
+        let chunks = chunk(&contents);
+        if chunks.is_empty() {
+            index.files.remove(&key);
+            continue;
+        }
+        bar.set_message(key.clone());
+        let embedder = match &mut embedder {
+            Some(embedder) => embedder,
+            None => embedder.insert(Embedder::load(model_id)?),
+        };
+        let texts = chunks
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect::<Vec<_>>();
+        let vectors = embedder.embed(&texts)?;
+        let chunks = chunks
+            .into_iter()
+            .zip(vectors)
+            .map(|((line, text), vector)| Chunk { line, text, vector })
+            .collect();
+        index.files.insert(key, IndexedFile { modified, chunks });
+        embedded += 1;
+    }
+    bar.finish_and_clear();
+    index.save()?;
+    info!("Saved the index to {:?}", Index::path());
+    Ok(Indexed {
+        files: found.len(),
+        embedded,
+        chunks: index.chunk_count(),
+    })
+}
+
+/// The project `dir` is in: the closest directory with a .git, .hg or .ai.toml, `dir`
+/// itself outside of one
+pub fn project_root(dir: &Path) -> PathBuf {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    dir.ancestors()
+        .find(|ancestor| {
+            PROJECT_MARKERS
+                .iter()
+                .any(|marker| ancestor.join(marker).exists())
+        })
+        .unwrap_or(&dir)
+        .to_path_buf()
+}
+
+/// The `k` chunks of the files indexed under `scope` closest to the query, none when
+/// nothing there is indexed
+pub fn search(query: &str, k: usize, scope: &Path) -> Result<Vec<Hit>> {
+    let index = Index::load()?;
+    let files = in_scope(&index, scope);
+    if files.is_empty() {
+        info!("Nothing under {} is indexed", display_path(scope));
+        return Ok(Vec::new());
+    }
+    let query = Embedder::shared(&index.model)?
+        .embed(&[query])?
+        .pop()
+        .unwrap_or_default();
+    let mut hits = files
+        .into_iter()
+        .flat_map(|(path, file)| {
+            file.chunks.iter().map(|chunk| Hit {
+                path: path.clone(),
+                line: chunk.line,
+                text: chunk.text.clone(),
+                score: embeddings::similarity(&query, &chunk.vector),
+            })
+        })
+        .collect::<Vec<_>>();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(k);
+    Ok(hits)
+}
+
+/// The indexed files under `scope`, so one project's search doesn't return another's files
+fn in_scope<'a>(index: &'a Index, scope: &Path) -> Vec<(&'a String, &'a IndexedFile)> {
+    index
+        .files
+        .iter()
+        .filter(|(path, _)| Path::new(path.as_str()).starts_with(scope))
+        .collect()
+}
+
+/// Files under `dir`, skipping dependency and build directories and large files
+fn walk(dir: &Path, found: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Unable to list {:?}: {}", dir, e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            let name = entry.file_name();
+            if !SKIPPED_DIRS.iter().any(|skipped| name == *skipped) {
+                walk(&path, found);
+            }
+        } else if file_type.is_file()
+            && entry
+                .metadata()
+                .is_ok_and(|metadata| metadata.len() <= MAX_FILE_BYTES)
+        {
+            found.push(path);
+        }
+    }
+}
+
+fn modified_secs(path: &Path) -> u64 {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Splits text into chunks of whole lines of about CHUNK_CHARS, with the line each
+/// starts on. Blank chunks are dropped and overly long lines cut
+fn chunk(text: &str) -> Vec<(usize, String)> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut start = 1;
+    for (index, line) in text.lines().enumerate() {
+        let line = match line.char_indices().nth(CHUNK_CHARS) {
+            Some((end, _)) => &line[..end],
+            None => line,
+        };
+        if !current.is_empty() && current.len() + line.len() > CHUNK_CHARS {
+            if !current.trim().is_empty() {
+                chunks.push((start, std::mem::take(&mut current)));
+            }
+            current.clear();
+            start = index + 1;
+        }
+        current.push_str(line);
+        current.push('\n');
+    }
+    if !current.trim().is_empty() {
+        chunks.push((start, current));
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_project_is_the_closest_directory_with_a_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let nested = root.join("src").join("bin");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert_eq!(project_root(&nested), nested);
+        std::fs::create_dir(root.join(".git")).unwrap();
+        assert_eq!(project_root(&nested), root);
+        std::fs::write(root.join("src").join(PROJECT_CONFIG), "").unwrap();
+        assert_eq!(project_root(&nested), root.join("src"));
+    }
+
+    #[test]
+    fn only_files_under_the_scope_are_searched() {
+        let file = || IndexedFile {
+            modified: 0,
+            chunks: Vec::new(),
+        };
+        let index = Index {
+            model: DEFAULT_EMBEDDING_MODEL.to_string(),
+            files: [
+                ("/home/me/project/README.md".to_string(), file()),
+                ("/home/me/project-two/notes.md".to_string(), file()),
+                ("/home/me/notes/todo.md".to_string(), file()),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let paths = in_scope(&index, Path::new("/home/me/project"))
+            .into_iter()
+            .map(|(path, _)| path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["/home/me/project/README.md"]);
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::install::cache_dir;
+
+/// Embedded chunks of the indexed files, ~/.cache/ai/index.json
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+    /// Embedding model the vectors come from, queries are embedded with it too
+    pub model: String,
+    /// Indexed files by absolute path
+    pub files: BTreeMap<String, IndexedFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexedFile {
+    /// Modification time in seconds since the epoch, unchanged files aren't embedded again
+    pub modified: u64,
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Chunk {
+    /// Line the chunk starts on, from 1
+    pub line: usize,
+    pub text: String,
+    pub vector: Vec<f32>,
+}
+
+impl Index {
+    pub fn path() -> PathBuf {
+        cache_dir().join("index.json")
+    }
+
+    /// The saved index, empty when nothing was indexed yet
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Unable to read index {:?}", path))?;
+        serde_json::from_str(&contents).with_context(|| format!("Invalid index {:?}", path))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Unable to create {:?}", dir))?;
+        }
+        std::fs::write(&path, serde_json::to_string(self)?)
+            .with_context(|| format!("Unable to write index {:?}", path))
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.files.values().map(|file| file.chunks.len()).sum()
+    }
+}
@@ -0,0 +1,174 @@
+use console::Style;
+
+/// Renders a markdown-ish model response for the terminal.
+/// Fenced code blocks get shell syntax highlighting, headings are bold and
+/// `inline code` / **bold** spans are styled. Styling is dropped automatically
+/// by `console` when colors are disabled.
+pub fn render_markdown(text: &str) -> String {
+    let fence = Style::new().dim();
+    let heading = Style::new().bold().underlined();
+    let mut in_code = false;
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            in_code = !in_code;
+            lines.push(fence.apply_to(line).to_string());
+        } else if in_code {
+            lines.push(highlight_shell(line));
+        } else if trimmed.starts_with('#') {
+            lines.push(
+                heading
+                    .apply_to(trimmed.trim_start_matches('#').trim())
+                    .to_string(),
+            );
+        } else {
+            lines.push(render_inline(line));
+        }
+    }
+    let mut rendered = lines.join("\n");
+    if text.ends_with('\n') {
+        rendered.push('\n');
+    }
+    rendered
+}
+
+/// Styles `inline code` and **bold** spans in a line of prose
+fn render_inline(line: &str) -> String {
+    let code = Style::new().cyan();
+    let bold = Style::new().bold();
+    let mut output = String::with_capacity(line.len());
+    let mut rest = line;
+    loop {
+        let next_code = rest.find('`');
+        let next_bold = rest.find("**");
+        match (next_code, next_bold) {
+            (Some(start), b) if b.is_none_or(|b| start < b) => match rest[start + 1..].find('`') {
+                Some(len) => {
+                    output.push_str(&rest[..start]);
+                    let span = &rest[start + 1..start + 1 + len];
+                    output.push_str(&code.apply_to(span).to_string());
+                    rest = &rest[start + len + 2..];
+                }
+                None => break,
+            },
+            (_, Some(start)) => match rest[start + 2..].find("**") {
+                Some(len) => {
+                    output.push_str(&rest[..start]);
+                    let span = &rest[start + 2..start + 2 + len];
+                    output.push_str(&bold.apply_to(span).to_string());
+                    rest = &rest[start + len + 4..];
+                }
+                None => break,
+            },
+            _ => break,
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Lightweight shell highlighting: commands, flags, strings, operators and comments
+fn highlight_shell(line: &str) -> String {
+    let command = Style::new().green().bold();
+    let flag = Style::new().cyan();
+    let string = Style::new().yellow();
+    let operator = Style::new().magenta();
+    let comment = Style::new().dim();
+
+    let mut output = String::with_capacity(line.len());
+    let mut chars = line.char_indices().peekable();
+    // the next word starts a new command, e.g. after a pipe
+    let mut expect_command = true;
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '#' if line[..start].ends_with(char::is_whitespace) || start == 0 => {
+                output.push_str(&comment.apply_to(&line[start..]).to_string());
+                break;
+            }
+            '\'' | '"' => {
+                let mut end = line.len();
+                let mut escaped = false;
+                for (i, ch) in chars.by_ref() {
+                    if ch == c && !escaped {
+                        end = i + ch.len_utf8();
+                        break;
+                    }
+                    escaped = ch == '\\' && !escaped && c == '"';
+                }
+                output.push_str(&string.apply_to(&line[start..end]).to_string());
+                expect_command = false;
+            }
+            '|' | '&' | ';' | '>' | '<' => {
+                let mut end = start + 1;
+                while let Some(&(i, next)) = chars.peek() {
+                    if matches!(next, '|' | '&' | '>' | '<') {
+                        end = i + 1;
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let op = &line[start..end];
+                output.push_str(&operator.apply_to(op).to_string());
+                // redirections are followed by a file name rather than a command
+                expect_command = !op.contains(['>', '<']);
+            }
+            c if c.is_whitespace() => output.push(c),
+            _ => {
+                let mut end = line.len();
+                while let Some(&(i, next)) = chars.peek() {
+                    if next.is_whitespace()
+                        || matches!(next, '|' | '&' | ';' | '>' | '<' | '\'' | '"')
+                    {
+                        end = i;
+                        break;
+                    }
+                    chars.next();
+                }
+                let word = &line[start..end];
+                if expect_command {
+                    output.push_str(&command.apply_to(word).to_string());
+                    expect_command = false;
+                } else if word.starts_with('-') {
+                    output.push_str(&flag.apply_to(word).to_string());
+                } else {
+                    output.push_str(word);
+                }
+            }
+        }
+    }
+    output
+}
+
+/// Removes the code fence models sometimes wrap a whole response in, e.g. a commit
+/// message or a script
+pub fn strip_code_fence(message: &str) -> String {
+    let message = message.trim();
+    let message = match message.strip_prefix("```") {
+        Some(fenced) => fenced
+            .split_once('\n')
+            .map(|(_, body)| body)
+            .unwrap_or_default()
+            .trim_end()
+            .trim_end_matches("```"),
+        None => message,
+    };
+    message.trim().to_string()
+}
+
+/// Breaks the text into lines of at most `width` characters
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let chars = line.chars().collect::<Vec<_>>();
+        if chars.is_empty() {
+            lines.push(String::new());
+        }
+        for chunk in chars.chunks(width) {
+            lines.push(chunk.iter().collect());
+        }
+    }
+    lines
+}
@@ -0,0 +1,33 @@
+use tracing::info;
+
+use crate::budget;
+use crate::settings::RoutingSettings;
+
+/// Backend name that picks the local or the remote backend for each prompt
+pub const AUTO: &str = "auto";
+
+/// The backend for a prompt with `ai_backend = "auto"`: the local one for short prompts,
+/// the remote one once the prompt, context included, or the attached files go over the
+/// `[routing]` thresholds
+pub fn route<'a>(settings: &'a RoutingSettings, prompt: &str, files: usize) -> &'a str {
+    let tokens = budget::estimate_tokens(prompt);
+    if tokens > settings.max_local_tokens {
+        info!(
+            "Prompt of about {} tokens is over {}, routing to {}",
+            tokens, settings.max_local_tokens, settings.remote_backend
+        );
+        &settings.remote_backend
+    } else if files > settings.max_local_files {
+        info!(
+            "{} attached files are over {}, routing to {}",
+            files, settings.max_local_files, settings.remote_backend
+        );
+        &settings.remote_backend
+    } else {
+        info!(
+            "Prompt of about {} tokens, routing to {}",
+            tokens, settings.local_backend
+        );
+        &settings.local_backend
+    }
+}
@@ -0,0 +1,286 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+
+use crate::messages::Message;
+use crate::settings::config_dir;
+
+/// Marker appended to crontab lines managed by `ai schedule`
+const CRON_MARKER: &str = "# ai-schedule:";
+
+/// Where the output of a scheduled prompt goes
+#[derive(Debug, Clone)]
+pub enum Delivery {
+    /// Append to a file
+    File(String),
+    /// Pipe into a shell command, e.g. `mail -s report me@example.com`
+    Command(String),
+    /// Show a desktop notification
+    Notify,
+    /// Post to a named webhook from the settings, stdout is appended to the schedule log
+    Webhook(String),
+}
+
+/// A prompt registered in the crontab
+#[derive(Debug, Clone)]
+pub struct ScheduledPrompt {
+    pub id: u64,
+    pub line: String,
+}
+
+/// Converts a friendly schedule such as "daily 9am", "hourly", "every 15m",
+/// "weekly mon 8:30pm" or a raw 5 field cron expression into cron syntax
+pub fn parse_schedule(spec: &str) -> Result<String> {
+    let words: Vec<String> = spec.split_whitespace().map(|w| w.to_lowercase()).collect();
+    if words.len() == 5 {
+        check_cron(spec)?;
+        return Ok(words.join(" "));
+    }
+    let words: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+    match words.as_slice() {
+        ["hourly"] => Ok("0 * * * *".to_string()),
+        ["daily"] => Ok("0 9 * * *".to_string()),
+        ["daily", time] => {
+            let (hour, minute) = parse_time(time)?;
+            Ok(format!("{minute} {hour} * * *"))
+        }
+        ["weekdays", time] => {
+            let (hour, minute) = parse_time(time)?;
+            Ok(format!("{minute} {hour} * * 1-5"))
+        }
+        ["weekly", day, time] => {
+            let (hour, minute) = parse_time(time)?;
+            Ok(format!("{minute} {hour} * * {}", parse_day(day)?))
+        }
+        ["every", interval] => {
+            if let Some(minutes) = interval.strip_suffix('m') {
+                Ok(format!("*/{} * * * *", minutes.parse::<u32>()?))
+            } else if let Some(hours) = interval.strip_suffix('h') {
+                Ok(format!("0 */{} * * *", hours.parse::<u32>()?))
+            } else {
+                anyhow::bail!("{}", Message::UnknownInterval(interval))
+            }
+        }
+        _ => anyhow::bail!("{}", Message::UnknownSchedule(spec)),
+    }
+}
+
+/// Checks a cron expression: an @ shorthand such as @daily, or 5 fields of values, ranges,
+/// steps and lists within the minute, hour, day, month and weekday ranges. Months and
+/// weekdays may be names. A crontab command may follow
+pub fn check_cron(expression: &str) -> Result<()> {
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    let Some(first) = fields.first() else {
+        anyhow::bail!("{}", Message::EmptyCron);
+    };
+    if let Some(shorthand) = first.strip_prefix('@') {
+        let shorthands = [
+            "reboot", "yearly", "annually", "monthly", "weekly", "daily", "midnight", "hourly",
+        ];
+        if !shorthands.contains(&shorthand) {
+            anyhow::bail!("{}", Message::UnknownCronShorthand(first));
+        }
+        return Ok(());
+    }
+    if fields.len() < 5 {
+        anyhow::bail!("{}", Message::CronFields(fields.len()));
+    }
+    let months = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let days = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+    let ranges: [(&str, u32, u32, &[&str]); 5] = [
+        ("minute", 0, 59, &[]),
+        ("hour", 0, 23, &[]),
+        ("day of month", 1, 31, &[]),
+        ("month", 1, 12, &months),
+        ("day of week", 0, 7, &days),
+    ];
+    for (field, (name, min, max, names)) in fields.iter().zip(ranges) {
+        let value = |value: &str| -> Result<u32> {
+            let named = names
+                .iter()
+                .position(|n| value.eq_ignore_ascii_case(n))
+                .map(|i| i as u32 + min);
+            match named.or_else(|| value.parse().ok()) {
+                Some(value) if (min..=max).contains(&value) => Ok(value),
+                _ => anyhow::bail!("{}", Message::CronValue(name, value, min, max)),
+            }
+        };
+        for item in field.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => (range, Some(step)),
+                None => (item, None),
+            };
+            if let Some(step) = step {
+                if !step.parse::<u32>().is_ok_and(|step| step > 0) {
+                    anyhow::bail!("{}", Message::CronStep(step, name));
+                }
+            }
+            match range.split_once('-') {
+                _ if range == "*" => {}
+                Some((start, end)) => {
+                    if value(start)? > value(end)? {
+                        anyhow::bail!("{}", Message::CronRangeBackwards(name, range));
+                    }
+                }
+                None => {
+                    value(range)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses 9am, 9:30pm or 21:30 into (hour, minute)
+fn parse_time(time: &str) -> Result<(u32, u32)> {
+    let (time, offset) = if let Some(t) = time.strip_suffix("am") {
+        (t, Some(0))
+    } else if let Some(t) = time.strip_suffix("pm") {
+        (t, Some(12))
+    } else {
+        (time, None)
+    };
+    let (hour, minute) = match time.split_once(':') {
+        Some((h, m)) => (h.parse::<u32>()?, m.parse::<u32>()?),
+        None => (time.parse::<u32>()?, 0),
+    };
+    let hour = match offset {
+        Some(offset) if (1..=12).contains(&hour) => hour % 12 + offset,
+        Some(_) => anyhow::bail!("{}", Message::InvalidHour(time)),
+        None => hour,
+    };
+    if hour > 23 || minute > 59 {
+        anyhow::bail!("{}", Message::InvalidTime(time));
+    }
+    Ok((hour, minute))
+}
+
+fn parse_day(day: &str) -> Result<u32> {
+    let days = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+    days.iter()
+        .position(|d| day.starts_with(d))
+        .map(|d| d as u32)
+        .ok_or_else(|| anyhow::anyhow!("{}", Message::UnknownDay(day)))
+}
+
+/// Quotes a string for safe use as a single shell word
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+fn read_crontab() -> Result<String> {
+    let output = Command::new("crontab")
+        .arg("-l")
+        .output()
+        .context("Unable to run crontab, is cron installed?")?;
+    // crontab -l exits non zero when the user has no crontab yet
+    if output.status.success() {
+        Ok(String::from_utf8(output.stdout)?)
+    } else {
+        Ok(String::new())
+    }
+}
+
+fn write_crontab(contents: &str) -> Result<()> {
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Unable to run crontab, is cron installed?")?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("{}", Message::NoStdin("crontab")))?
+        .write_all(contents.as_bytes())?;
+    if !child.wait()?.success() {
+        anyhow::bail!("{}", Message::CrontabRejected);
+    }
+    Ok(())
+}
+
+/// Lists the prompts registered by `ai schedule`
+pub fn list() -> Result<Vec<ScheduledPrompt>> {
+    Ok(read_crontab()?
+        .lines()
+        .filter_map(|line| {
+            let (_, id) = line.rsplit_once(CRON_MARKER)?;
+            Some(ScheduledPrompt {
+                id: id.trim().parse().ok()?,
+                line: line.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Registers a prompt to run on the given schedule and returns its id
+pub fn add(schedule: &str, prompt: &str, delivery: Option<Delivery>) -> Result<u64> {
+    let cron = parse_schedule(schedule)?;
+    let id = list()?.iter().map(|s| s.id).max().unwrap_or(0) + 1;
+    let exe = std::env::current_exe()?;
+    let deliver = match &delivery {
+        Some(Delivery::Webhook(name)) => format!(" --deliver {}", shell_quote(name)),
+        _ => String::new(),
+    };
+    let run = format!(
+        "{} --plain --no-clipboard{deliver} generate {}",
+        shell_quote(&exe.to_string_lossy()),
+        shell_quote(prompt)
+    );
+    let log = config_dir()
+        .join("schedule.log")
+        .to_string_lossy()
+        .to_string();
+    let command = match delivery.unwrap_or(Delivery::File(log.clone())) {
+        Delivery::File(path) => format!("{run} >> {} 2>&1", shell_quote(&path)),
+        Delivery::Webhook(_) => format!("{run} >> {} 2>&1", shell_quote(&log)),
+        Delivery::Command(cmd) => format!("{run} 2>&1 | {cmd}"),
+        Delivery::Notify if cfg!(target_os = "macos") => format!(
+            "osascript -e 'on run argv' -e 'display notification (item 1 of argv) with title \"ai\"' -e 'end run' \"$({run} 2>&1)\""
+        ),
+        Delivery::Notify => format!("notify-send 'ai' \"$({run} 2>&1)\""),
+    };
+    // % is a newline in crontab commands
+    let line = format!("{cron} {} {CRON_MARKER}{id}", command.replace('%', r"\%"));
+
+    let mut crontab = read_crontab()?;
+    if !crontab.is_empty() && !crontab.ends_with('\n') {
+        crontab.push('\n');
+    }
+    crontab.push_str(&line);
+    crontab.push('\n');
+    write_crontab(&crontab)?;
+    Ok(id)
+}
+
+/// Removes a scheduled prompt from the crontab
+pub fn remove(id: u64) -> Result<()> {
+    let crontab = read_crontab()?;
+    let marker = format!("{CRON_MARKER}{id}");
+    if !crontab.lines().any(|l| l.trim_end().ends_with(&marker)) {
+        anyhow::bail!("{}", Message::NoScheduledPrompt(id));
+    }
+    let mut updated = crontab
+        .lines()
+        .filter(|l| !l.trim_end().ends_with(&marker))
+        .collect::<Vec<_>>()
+        .join("\n");
+    updated.push('\n');
+    write_crontab(&updated)
+}
+
+/// Removes every prompt registered by `ai schedule` and returns how many were removed
+pub fn remove_all() -> Result<usize> {
+    let crontab = read_crontab()?;
+    let (scheduled, kept): (Vec<&str>, Vec<&str>) =
+        crontab.lines().partition(|l| l.contains(CRON_MARKER));
+    if scheduled.is_empty() {
+        return Ok(0);
+    }
+    let mut updated = kept.join("\n");
+    updated.push('\n');
+    write_crontab(&updated)?;
+    Ok(scheduled.len())
+}
@@ -0,0 +1,43 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::messages::Message;
+use crate::render;
+
+/// Reads a JSON schema for --schema, making sure it is one before anything is generated
+pub fn load(path: &Path) -> Result<Value> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read schema file {:?}", path))?;
+    let schema: Value = serde_json::from_str(&contents)
+        .with_context(|| format!("Schema file {:?} isn't valid json", path))?;
+    jsonschema::validator_for(&schema).map_err(|e| {
+        anyhow::anyhow!(
+            "{}",
+            Message::InvalidSchemaFile(&format!("{:?}", path), &e.to_string())
+        )
+    })?;
+    Ok(schema)
+}
+
+/// The json in the response, checked against the schema. Backends that can't be
+/// constrained may wrap it in a code fence, which is dropped
+pub fn check(schema: &Value, response: &str) -> Result<String> {
+    let json = render::strip_code_fence(response);
+    let instance: Value = serde_json::from_str(&json)
+        .with_context(|| format!("The response isn't valid json: {}", json))?;
+    let validator = jsonschema::validator_for(schema)
+        .map_err(|e| anyhow::anyhow!("{}", Message::InvalidSchema(&e.to_string())))?;
+    let errors: Vec<String> = validator
+        .iter_errors(&instance)
+        .map(|e| format!("{}: {}", e.instance_path, e))
+        .collect();
+    if !errors.is_empty() {
+        anyhow::bail!(
+            "{}",
+            Message::SchemaMismatch(&errors.join("\n"), &json.to_string())
+        );
+    }
+    Ok(json)
+}
@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::ai_backend::ChatMessage;
+use crate::encryption;
+use crate::history::GenerationParams;
+use crate::messages::Message;
+use crate::settings::config_dir;
+
+/// The last conversation, continued with `ai --continue`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub backend: String,
+    /// Model and sampling settings, reapplied when continuing
+    pub params: GenerationParams,
+    pub messages: Vec<ChatMessage>,
+}
+
+impl Session {
+    pub fn new(backend: &str, params: GenerationParams) -> Self {
+        Self {
+            backend: backend.to_string(),
+            params,
+            messages: Vec::new(),
+        }
+    }
+
+    /// ~/.config/ai/session.json
+    fn path() -> PathBuf {
+        config_dir().join("session.json")
+    }
+
+    /// Loads the last conversation
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            anyhow::bail!("{}", Message::NoConversation);
+        }
+        let contents = encryption::read(&path)
+            .with_context(|| format!("Unable to read session {:?}", path))?;
+        serde_json::from_str(&contents).with_context(|| format!("Invalid session {:?}", path))
+    }
+
+    /// Replaces the saved conversation with this one
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        encryption::write(&path, &serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Unable to write session {:?}", path))
+    }
+}
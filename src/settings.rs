@@ -1,35 +1,375 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Once, OnceLock};
 
 use clap_verbosity_flag::LogLevel;
+use config::builder::{ConfigBuilder, DefaultState};
 use config::Config;
+use tracing::{info, warn};
 
-use crate::{ai_backend::local::WhichModel, constants::DEFAULT_CONFIG_CONTENT};
+use crate::ai_backend::chat_template::ChatTemplate;
+use crate::ai_backend::local::WhichModel;
+use crate::audit::AuditContent;
+use crate::config_schema;
+use crate::constants::{BEDROCK_MODEL_ID, DEFAULT_CONFIG_CONTENT};
+use crate::delivery::Webhook;
+use crate::encryption::Encryption;
+use crate::logging::NOTICE;
+use crate::migrate;
+use crate::rag::DEFAULT_EMBEDDING_MODEL;
+use crate::redact::{redact_command, Secret, REDACTION_KINDS};
 
 /// Top Level settings object
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Deserialize)]
 pub struct Settings {
-    /// Verbosity setting, CLI arg takes precident 
+    /// Format version of the config file, older files are migrated when loaded
+    pub config_version: Option<i64>,
+    /// Verbosity setting, CLI arg takes precident
     pub verbosity: Option<String>,
-    // Which AI backend to use by default, bedrock or local
+    /// File the logs go to instead of stdout, relative to ~/.local/state/ai
+    pub log_file: Option<PathBuf>,
+    /// Size in MiB past which the log file is rotated when `ai` starts
+    pub log_max_size_mb: u64,
+    /// Rotated log files kept, ai.log.1 being the newest
+    pub log_max_files: usize,
+    // Which AI backend to use by default, bedrock, local, command or mock
     pub ai_backend: String,
     /// The local model configuration
     pub local_model_config: LocalModelConfig,
     /// Various AWS setting such as profile (not respected yet) and region
     pub aws_settings: AwsSettings,
+    /// Sampling settings sent with Bedrock requests, the model's defaults when unset
+    #[serde(default)]
+    pub bedrock_model_config: BedrockModelConfig,
+    /// When to copy the generated command to the clipboard, needs the clipboard feature
+    pub clipboard: ClipboardMode,
+    /// Whether to save generations to the history file
+    pub history: bool,
+    /// Whether the history and the last conversation are encrypted on disk, and with what key
+    pub history_encryption: Encryption,
+    /// Seconds a key derived from the history passphrase is kept in the runtime directory so
+    /// the next runs don't ask for the passphrase, 0 to ask every run
+    pub history_passphrase_cache_secs: u64,
+    /// Forbid any network access: remote backends, model downloads, webhooks and telemetry
+    pub local_only: bool,
+    /// Seconds a request may take before it's cancelled and the partial output is used, 0 for no limit
+    pub request_timeout_secs: u64,
+    /// PEM file of certificates to trust besides the system's, for proxies that intercept TLS
+    pub ca_bundle: Option<PathBuf>,
+    /// Seconds a generated response is reused for the same request, 0 turns the cache off
+    pub cache_ttl_secs: u64,
+    /// Offer to copy, edit and run, run or regenerate the command after generating
+    pub interactive: bool,
+    /// Without the menu, wait a moment after printing the command for `r` to regenerate it
+    pub quick_regenerate: bool,
+    /// Screen reader friendly output: no spinner or colors, plain progress messages
+    pub accessibility: bool,
+    /// Language for CLI messages such as "de" or "es", defaults to the system locale
+    pub language: Option<String>,
+    /// Replaces the built-in system prompt, `{{default}}` expands to the built-in one
+    /// and `{{shell}}`/`{{os}}` to the environment
+    pub system_prompt: Option<String>,
+    /// File to read the system prompt from when `system_prompt` isn't set
+    pub system_prompt_file: Option<PathBuf>,
+    /// Added to the end of the system prompt, e.g. a repo's conventions from its .ai.toml
+    pub system_prompt_append: Option<String>,
+    /// Hugging Face BERT model `ai index` embeds files with
+    pub embedding_model: String,
+    /// Approximate tokens of input `ai pipe` sends per request, by default what fits the model
+    pub pipe_chunk_tokens: Option<usize>,
+    /// Which context providers add to the prompt and how
+    pub context: ContextSettings,
+    /// Where prompts go with `ai_backend = "auto"`
+    pub routing: RoutingSettings,
+    /// Export of traces and metrics to an OpenTelemetry collector
+    pub telemetry: TelemetrySettings,
+    /// `ai daemon`, which keeps the local model loaded between commands
+    pub daemon: DaemonSettings,
+    /// What is redacted from prompts sent to a backend other than local or mock
+    pub redaction: RedactionSettings,
+    /// Append-only record of generations and the commands run
+    pub audit: AuditSettings,
+    /// Programs every prompt and response are piped through
+    #[serde(default)]
+    pub hooks: HooksSettings,
+    /// Named webhooks generated output can be delivered to with --deliver
+    #[serde(default)]
+    pub webhooks: HashMap<String, Webhook>,
+    /// External program used as the model by the command backend
+    pub command_backend: Option<CommandBackendSettings>,
+    /// Canned responses for the mock backend
+    pub mock_backend: Option<MockBackendSettings>,
+    /// Plugin backends that aren't on PATH as `ai-backend-<name>`, or override one that is
+    #[serde(default)]
+    pub plugins: HashMap<String, PluginSettings>,
+    /// Most to spend on Bedrock per calendar month in USD, unlimited when unset
+    pub monthly_budget_usd: Option<f64>,
+    /// Prices of Bedrock models by exact model id, added to the built-in table
+    #[serde(default)]
+    pub prices: HashMap<String, ModelPrice>,
+    /// Limits on requests and tokens, of every backend together and per backend
+    #[serde(default)]
+    pub quotas: QuotaSettings,
+    /// Short names for models, used with --model: `hf:<repo>` or a Bedrock model id
+    #[serde(default)]
+    pub model_aliases: HashMap<String, String>,
+    /// JSON schema the response has to match, from --schema
+    #[serde(skip)]
+    pub response_schema: Option<serde_json::Value>,
+    /// Send even when the monthly budget would be exceeded, from --force
+    #[serde(skip)]
+    pub ignore_budget: bool,
+    /// Keys of the config files nothing reads, shown by `ai doctor`
+    #[serde(skip)]
+    pub unknown_keys: Vec<String>,
+    /// The .ai.toml merged over the user config, if one was found
+    #[serde(skip)]
+    pub project_config: Option<PathBuf>,
+}
+
+/// What a model costs in USD per 1000 tokens
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub struct ModelPrice {
+    pub input_per_1k: f64,
+    pub output_per_1k: f64,
+}
+
+/// Requests a day and tokens a month, unlimited when unset
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+pub struct Quota {
+    pub max_requests_per_day: Option<u64>,
+    pub max_tokens_per_month: Option<u64>,
+}
+
+impl Quota {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_requests_per_day.is_none() && self.max_tokens_per_month.is_none()
+    }
+}
+
+/// Usage quotas, days and months in UTC
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct QuotaSettings {
+    /// Of every backend together
+    pub max_requests_per_day: Option<u64>,
+    pub max_tokens_per_month: Option<u64>,
+    /// Of a backend by name, on top of the overall quota
+    #[serde(default)]
+    pub backends: HashMap<String, Quota>,
+}
+
+impl QuotaSettings {
+    /// The quota of every backend together
+    pub fn total(&self) -> Quota {
+        Quota {
+            max_requests_per_day: self.max_requests_per_day,
+            max_tokens_per_month: self.max_tokens_per_month,
+        }
+    }
+}
+
+/// Settings for the command backend
+#[derive(Clone, serde::Deserialize)]
+pub struct CommandBackendSettings {
+    /// Shell command to run, gets `{"system", "prompt", "messages"}` json on stdin and prints the completion
+    pub cmd: String,
+    /// Name of an API key stored with `ai auth set`, passed to the command as $AI_API_KEY
+    pub credential: Option<String>,
+}
+
+// the command line can carry an API key, keep it out of `ai config` and the logs
+impl std::fmt::Debug for CommandBackendSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandBackendSettings")
+            .field("cmd", &redact_command(&self.cmd))
+            .field("credential", &self.credential)
+            .finish()
+    }
+}
+
+/// A plugin backend registered by name
+#[derive(Clone, serde::Deserialize)]
+pub struct PluginSettings {
+    /// The plugin executable
+    pub path: PathBuf,
+    /// Arguments it's run with
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Name of an API key stored with `ai auth set`, passed to the plugin as $AI_API_KEY
+    pub credential: Option<String>,
+}
+
+// the arguments can carry an API key the same way
+impl std::fmt::Debug for PluginSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PluginSettings")
+            .field("path", &self.path)
+            .field("args", &redact_command(&self.args.join(" ")))
+            .field("credential", &self.credential)
+            .finish()
+    }
+}
+
+/// Settings for the mock backend
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct MockBackendSettings {
+    /// Json array of `{"prompt": "...", "response": "..."}`, the first entry whose prompt is
+    /// part of the request is used. Without a file every request gets a fixed command
+    pub fixture: Option<PathBuf>,
+    /// Delay per streamed word in milliseconds, to exercise the spinner and timeouts
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+/// Thresholds of `ai_backend = "auto"`, prompts within them go to the local backend
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct RoutingSettings {
+    /// Most estimated tokens, context included, a prompt sent to the local backend has
+    pub max_local_tokens: usize,
+    /// Most files attached with --file a prompt sent to the local backend has
+    pub max_local_files: usize,
+    /// Backend for the prompts within the thresholds
+    pub local_backend: String,
+    /// Backend for the rest
+    pub remote_backend: String,
+}
+
+/// OpenTelemetry export over OTLP/HTTP, off unless enabled
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TelemetrySettings {
+    pub enabled: bool,
+    /// Collector url, traces go to `<endpoint>/v1/traces` and metrics to `<endpoint>/v1/metrics`
+    pub endpoint: String,
+    /// `service.name` resource attribute the collector groups the data under
+    pub service_name: String,
+    /// Headers sent with every export, e.g. the collector's API key
+    #[serde(default)]
+    pub headers: HashMap<String, Secret>,
+    /// Seconds between exports while ai runs, what's left is sent when it finishes
+    pub export_interval_secs: u64,
+}
+
+/// Credentials and personal data swapped for placeholders before a prompt leaves the machine
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct RedactionSettings {
+    /// Turned off for a run with --no-redact
+    pub enabled: bool,
+    /// Built-in kinds redacted: aws_key, token, email and ip
+    pub kinds: Vec<String>,
+    /// Extra regexes by name, what they match is redacted as REDACTED_<NAME>_1 and so on.
+    /// Only the first group is redacted when the pattern has one
+    #[serde(default)]
+    pub patterns: HashMap<String, String>,
+}
+
+/// Audit log of who generated and ran which commands, where and when
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct AuditSettings {
+    pub enabled: bool,
+    /// Whether prompts, responses and commands are logged as SHA-256 hashes or in full
+    pub content: AuditContent,
+    /// JSONL file written to, relative paths are in the state directory
+    pub path: Option<PathBuf>,
+}
+
+/// Executables the prompt and the response are piped through, stdin to stdout
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct HooksSettings {
+    /// Gets every prompt, context included, before it's sent to the backend
+    pub pre_prompt: Option<PathBuf>,
+    /// Gets every response before it's shown
+    pub post_response: Option<PathBuf>,
+}
+
+/// Settings of `ai daemon`
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct DaemonSettings {
+    /// How long the model stays loaded after the last request, e.g. "90s", "10m", "2h" or
+    /// "forever". The next request loads it again
+    pub keep_alive: String,
+}
+
+/// Prompt context settings
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ContextSettings {
+    /// Total time budget in milliseconds for gathering context, slower providers are skipped
+    pub timeout_ms: u64,
+    /// Append the OS, shell, coreutils flavor and installed tools to the system prompt
+    pub environment: bool,
+    /// Per provider settings keyed by provider name (system, git, directory, tools, docs, files, index)
+    #[serde(default)]
+    pub providers: HashMap<String, ProviderSettings>,
+}
+
+/// Overrides for a single context provider, unset values use the provider's defaults
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct ProviderSettings {
+    /// Add this provider's context to every prompt (default: false)
+    pub enabled: Option<bool>,
+    /// Higher priorities are placed first in the prompt
+    pub priority: Option<i32>,
+    /// Approximate token budget, longer context is truncated
+    pub max_tokens: Option<usize>,
+}
+
+/// Clipboard copy behavior
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardMode {
+    /// Copy only when stdout is a terminal, so `ai ... | sh` leaves the clipboard alone
+    Auto,
+    /// Always copy
+    Always,
+    /// Never copy
+    Never,
 }
 
 /// AWS related settings
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Deserialize)]
 pub struct AwsSettings {
     pub profile: Option<String>,
+    /// Role to assume with the profile's credentials
+    pub role_arn: Option<String>,
+    /// MFA device the role requires, its code is asked for when the role is assumed
+    pub mfa_serial: Option<String>,
     pub region: String,
+    /// Sends Bedrock requests here rather than to the region's endpoint, for VPC interface
+    /// endpoints and emulators
+    pub endpoint_url: Option<String>,
+    /// Bedrock model id, cross-region inference profile id (us.anthropic...) or profile ARN
+    pub model_id: String,
+    /// Regions to retry in, in order, when the primary region is out of capacity
+    #[serde(default)]
+    pub fallback_regions: Vec<String>,
+    /// Let the model call read only tools (read files, list directories, check versions) before answering
+    pub tools: bool,
+    /// Mark the system prompt and earlier turns as cacheable for Anthropic models
+    pub prompt_caching: bool,
+}
+
+/// The inference config of Bedrock's Converse requests
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct BedrockModelConfig {
+    /// Most tokens to generate
+    pub max_tokens: Option<u32>,
+    /// Randomness of outputs (0.0-1.0)
+    pub temperature: Option<f64>,
+    /// Top-p sampling threshold (0.0-1.0)
+    pub top_p: Option<f64>,
+    /// Texts that end the response when the model generates them
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
 }
 
 /// Config options for the local LLM setting
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Deserialize)]
 pub struct LocalModelConfig {
     /// Run on CPU rather than on GPU.
     pub cpu: bool,
+    /// Devices to place the model on, e.g. ["cuda:0", "cuda:1"], its layers are split over
+    /// them when there are several. Overrides `cpu`
+    #[serde(default)]
+    pub devices: Vec<String>,
     /// Which local model to pull (2, 3)
     pub model: WhichModel,
     /// whether to use the quantized version of the model, 2 only supported
@@ -58,39 +398,290 @@ pub struct LocalModelConfig {
     pub repeat_last_n: usize,
     /// Data type for model weights (e.g. "f32", "f16")
     pub dtype: Option<String>,
+    /// Most GiB the model may take, on top of what the RAM or VRAM has available
+    pub max_memory_gb: Option<f64>,
+    /// Strings the model is never allowed to generate, e.g. "sudo" or "rm -rf /"
+    #[serde(default)]
+    pub banned_strings: Vec<String>,
+    /// How the conversation is laid out in the prompt, detected from the model when unset
+    pub chat_template: Option<ChatTemplate>,
+    /// End the output at the end of the command line, e.g. rather than explaining it after.
+    /// Turned off for commit messages, `ai pipe` and `ai explain-error`
+    pub stop_at_newline: bool,
+    /// Only sample tokens that keep the output valid under this grammar, e.g. "shell"
+    pub grammar: Option<OutputGrammar>,
+    /// Small model proposing tokens for speculative decoding, off unless set
+    pub draft: Option<DraftModelConfig>,
+}
+
+/// Grammars the local models' output can be constrained to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputGrammar {
+    /// A single POSIX shell command line
+    Shell,
+}
+
+/// The draft model for speculative decoding, a quantized phi gguf sharing phi-2's tokenizer
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct DraftModelConfig {
+    /// HF model repo holding the weights (default: lmz/candle-quantized-phi)
+    pub model_id: Option<String>,
+    /// Model revision/version - HF git branch
+    pub revision: Option<String>,
+    /// gguf file in the repo or a local path (default: model-q4k.gguf, phi-1.5)
+    pub weight_file: Option<String>,
+    /// Architecture of the weights, "1.5" or "2" (default: "1.5")
+    #[serde(default = "default_draft_architecture")]
+    pub architecture: String,
+    /// Tokens proposed per round (default: 4)
+    #[serde(default = "default_draft_tokens")]
+    pub tokens: usize,
+}
+
+fn default_draft_architecture() -> String {
+    "1.5".to_string()
+}
+
+fn default_draft_tokens() -> usize {
+    4
+}
+
+/// Directory holding the config file and other user data such as templates. The first of:
+/// - `$AI_CONFIG_DIR`
+/// - `%APPDATA%\ai` on Windows
+/// - `$XDG_CONFIG_HOME/ai`, unless only `~/.config/ai` exists so configs from before
+///   $XDG_CONFIG_HOME was read keep working
+/// - `~/Library/Application Support/ai` on macOS, when that directory exists
+/// - `~/.config/ai`
+///
+/// falling back to the current directory without a home directory
+pub fn config_dir() -> PathBuf {
+    if let Some(dir) = env_dir("AI_CONFIG_DIR") {
+        return dir;
+    }
+    if cfg!(windows) {
+        return dirs::config_dir()
+            .map(|path| path.join("ai"))
+            .unwrap_or_else(|| PathBuf::from("."));
+    }
+    let home_dir = dirs::home_dir().map(|path| path.join(".config").join("ai"));
+    if let Some(dir) = env_dir("XDG_CONFIG_HOME").map(|path| path.join("ai")) {
+        match home_dir {
+            Some(home_dir) if !dir.exists() && home_dir.is_dir() => return home_dir,
+            _ => return dir,
+        }
+    }
+    // ~/.config on mac too, Application Support is opted into by creating the directory
+    if cfg!(target_os = "macos") {
+        if let Some(dir) = dirs::config_dir().map(|path| path.join("ai")) {
+            if dir.is_dir() {
+                return dir;
+            }
+        }
+    }
+    home_dir.unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// A directory from the environment, ignored when empty or relative as the XDG spec says
+fn env_dir(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+/// Name of the per-project config looked for from the current directory upwards
+pub const PROJECT_CONFIG: &str = ".ai.toml";
+
+/// Keys a project config may set, as dotted paths. The others can run programs, send
+/// prompts or output elsewhere, pick the endpoint that gets the prompt and its context,
+/// or read files outside the repo, which a cloned repo shouldn't get to decide
+const PROJECT_KEYS: &[&str] = &[
+    "config_version",
+    "ai_backend",
+    "system_prompt",
+    "system_prompt_append",
+    "pipe_chunk_tokens",
+    "aws_settings.model_id",
+    "bedrock_model_config.max_tokens",
+    "bedrock_model_config.temperature",
+    "bedrock_model_config.top_p",
+    "bedrock_model_config.stop_sequences",
+    "local_model_config.model",
+    "local_model_config.temperature",
+    "local_model_config.top_p",
+    "local_model_config.sample_len",
+    "local_model_config.repeat_penalty",
+    "local_model_config.repeat_last_n",
+    "local_model_config.stop_at_newline",
+];
+
+/// Keys a project may set that change where prompts go or what the model is told, so the
+/// user is warned that the project set them
+const PROJECT_KEYS_WARNED: &[&str] = &["ai_backend", "system_prompt"];
+
+/// The closest .ai.toml in the current directory or one of its parents
+pub fn project_config_path() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG))
+        .find(|path| path.is_file())
+}
+
+/// Loads a project config, leaving out the keys it isn't allowed to set with a warning
+fn project_config(
+    source: impl config::Source + Send + Sync + 'static,
+    name: &str,
+    warnings: &mut Vec<String>,
+) -> Result<Config, config::ConfigError> {
+    let mut project = Config::builder().add_source(source).build()?;
+    let mut table = project.cache.clone().into_table()?;
+    let origin = name.to_string();
+    retain_project_keys(&mut table, "", &origin, warnings);
+    project.cache = config::Value::new(Some(&origin), table);
+    Ok(project)
+}
+
+/// Drops the keys of the table under `prefix` that aren't in `PROJECT_KEYS`, keeping the
+/// tables that hold allowed keys
+fn retain_project_keys(
+    table: &mut config::Map<String, config::Value>,
+    prefix: &str,
+    origin: &String,
+    warnings: &mut Vec<String>,
+) {
+    table.retain(|key, value| {
+        let path = match prefix {
+            "" => key.clone(),
+            prefix => format!("{}.{}", prefix, key),
+        };
+        if PROJECT_KEYS.contains(&path.as_str()) {
+            if PROJECT_KEYS_WARNED.contains(&path.as_str()) {
+                warnings.push(format!("{} is set by the project config {}", path, origin));
+            }
+            return true;
+        }
+        let holds_allowed = PROJECT_KEYS
+            .iter()
+            .any(|allowed| allowed.starts_with(&format!("{}.", path)));
+        if holds_allowed {
+            if let Ok(mut inner) = value.clone().into_table() {
+                retain_project_keys(&mut inner, &path, origin, warnings);
+                *value = config::Value::new(Some(origin), inner);
+                return true;
+            }
+        }
+        warnings.push(format!(
+            "{} in {} is ignored, a project config can only set {}",
+            path,
+            origin,
+            PROJECT_KEYS.join(", ")
+        ));
+        false
+    });
+}
+
+/// Config file given with --config, read instead of the user and project configs
+static CONFIG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// The config file in use, the --config one or config.toml in [`config_dir`]
+pub fn config_file() -> PathBuf {
+    CONFIG_FILE
+        .get()
+        .cloned()
+        .unwrap_or_else(|| config_dir().join("config.toml"))
 }
 
 impl Settings {
+    /// Reads `path` instead of the user and project configs from now on, for --config.
+    /// The file isn't created or looked for elsewhere. Only the first call counts
+    pub fn use_config_file(path: PathBuf) {
+        // absolute so `ai config path` shows where it is
+        let path = match std::env::current_dir() {
+            Ok(dir) if path.is_relative() => dir.join(path),
+            _ => path,
+        };
+        let _ = CONFIG_FILE.set(path);
+    }
+
+    /// Loads ~/.config/ai/config.toml, writing the default one on first run, with the
+    /// closest .ai.toml and a config.toml in the current directory overriding the keys a
+    /// project may set. Values of
+    /// the wrong type or out of range are errors naming the file and key, unknown keys are
+    /// warned about
     pub fn new() -> Result<Self, config::ConfigError> {
-        // I personally like my config files in .config on mac
-        let config_path = dirs::home_dir() // Gets the config directory cross-platform
-            .map(|mut path| {
-                path.push(".config");
-                path.push("ai");
-                path.push("config");
-                path
-            })
-            .unwrap_or_else(|| PathBuf::from("config")); // Fallback to local config
-
-        // create ~/.config/ai if it doesn't exist
-        let config_parent_dir = config_path.parent().unwrap();
-        if !config_parent_dir.exists() {
-            // info! doesnn't work here as this get's run before we set up the log subscriber
-            println!("Creating config directory: {:?}", &config_parent_dir);
-            std::fs::create_dir_all(config_parent_dir).unwrap();
-        }
+        let explicit = CONFIG_FILE.get().is_some();
+        let config_file = config_file();
 
-        // Check if config file exists, if not create it with defaults
-        let config_file = config_path.with_extension("toml");
-        if !config_file.exists() {
-            println!("Creating config file: {:?}", &config_file);
-            std::fs::write(&config_file, DEFAULT_CONFIG_CONTENT)
-                .expect("Failed to write config file");
+        if !explicit {
+            // create ~/.config/ai if it doesn't exist
+            let config_parent_dir = config_file.parent().unwrap_or(Path::new("."));
+            if !config_parent_dir.exists() {
+                info!(target: NOTICE, "Creating config directory: {:?}", &config_parent_dir);
+                std::fs::create_dir_all(config_parent_dir).map_err(|e| {
+                    config::ConfigError::Message(format!(
+                        "Unable to create {:?}: {}",
+                        config_parent_dir, e
+                    ))
+                })?;
+            }
+
+            // Check if config file exists, if not create it with defaults
+            if !config_file.exists() {
+                info!(target: NOTICE, "Creating config file: {:?}", &config_file);
+                std::fs::write(&config_file, DEFAULT_CONFIG_CONTENT).map_err(|e| {
+                    config::ConfigError::Message(format!(
+                        "Unable to write {:?}: {}",
+                        config_file, e
+                    ))
+                })?;
+            }
+        } else if !config_file.is_file() {
+            return Err(config::ConfigError::Message(format!(
+                "Config file {:?} not found",
+                config_file
+            )));
         }
 
-        let settings = Config::builder()
-            .add_source(config::File::with_name(config_path.to_str().unwrap()).required(false))
-            .add_source(config::File::with_name("config").required(false))
+        // once, the settings are loaded more than once per run
+        static MIGRATE: Once = Once::new();
+        MIGRATE.call_once(|| {
+            // a file given with --config may be shared, e.g. in a dotfiles repo, so it's
+            // never rewritten
+            let result = if explicit {
+                migrate::pending(&config_file).map(|changes| {
+                    if !changes.is_empty() {
+                        warn!(
+                            target: NOTICE,
+                            "{:?} is from an older version of ai and is left as it is: {}",
+                            config_file,
+                            changes.join(", ")
+                        );
+                    }
+                })
+            } else {
+                migrate::migrate(&config_file).map(|migrated| {
+                    if let Some(migrated) = migrated {
+                        info!(
+                            target: NOTICE,
+                            "Updated {:?} from config version {} to {}: {}. The original is in {:?}",
+                            config_file,
+                            migrated.from,
+                            migrate::CONFIG_VERSION,
+                            migrated.changes.join(", "),
+                            migrated.backup
+                        );
+                    }
+                })
+            };
+            if let Err(e) = result {
+                warn!(target: NOTICE, "{:#}", e);
+            }
+        });
+
+        let mut warnings = Vec::new();
+        let (builder, project_path) = sources(explicit, &config_file, &mut warnings)?;
+        let settings = builder
             .set_default("local_model_config.cpu", false)?
             .set_default("local_model_config.model", "V2")?
             .set_default("local_model_config.quantized", true)?
@@ -102,12 +693,95 @@ impl Settings {
             .set_default("local_model_config.repeat_penalty", 1.1)?
             .set_default("local_model_config.repeat_last_n", 64)?
             .set_default("local_model_config.dtype", "f32")?
+            .set_default("local_model_config.stop_at_newline", true)?
             .set_default("aws_settings.region", "us-east-1")?
+            .set_default("aws_settings.model_id", BEDROCK_MODEL_ID)?
+            .set_default("aws_settings.tools", false)?
+            .set_default("aws_settings.prompt_caching", true)?
             .set_default("ai_backend", "local")?
+            .set_default("clipboard", "auto")?
+            .set_default("log_max_size_mb", 10)?
+            .set_default("log_max_files", 3)?
+            .set_default("history", true)?
+            .set_default("history_encryption", "off")?
+            .set_default("history_passphrase_cache_secs", 900)?
+            .set_default("local_only", false)?
+            .set_default("request_timeout_secs", 120)?
+            .set_default("cache_ttl_secs", 0)?
+            .set_default("embedding_model", DEFAULT_EMBEDDING_MODEL)?
+            .set_default("interactive", false)?
+            .set_default("quick_regenerate", true)?
+            .set_default("accessibility", false)?
+            .set_default("routing.max_local_tokens", 400)?
+            .set_default("routing.max_local_files", 1)?
+            .set_default("routing.local_backend", "local")?
+            .set_default("routing.remote_backend", "bedrock")?
+            .set_default("telemetry.enabled", false)?
+            .set_default("telemetry.endpoint", "http://localhost:4318")?
+            .set_default("telemetry.service_name", "ai")?
+            .set_default("telemetry.export_interval_secs", 30)?
+            .set_default("daemon.keep_alive", "10m")?
+            .set_default("redaction.enabled", true)?
+            .set_default("redaction.kinds", REDACTION_KINDS.to_vec())?
+            .set_default("audit.enabled", false)?
+            .set_default("audit.content", "hash")?
+            .set_default("context.timeout_ms", 300)?
+            .set_default("context.environment", true)?
             .build()?;
 
-        settings.try_deserialize()
+        let report = config_schema::validate(&settings.cache);
+        warnings.extend(report.warnings.iter().cloned());
+        // settings are loaded more than once per run, the warnings only need showing once
+        static WARN: Once = Once::new();
+        WARN.call_once(|| {
+            for warning in warnings.iter() {
+                warn!(target: NOTICE, "{}", warning);
+            }
+        });
+        if !report.errors.is_empty() {
+            return Err(config::ConfigError::Message(format!(
+                "Invalid config:\n{}",
+                report.errors.join("\n")
+            )));
+        }
+        let mut settings: Self = settings.try_deserialize()?;
+        settings.unknown_keys = warnings;
+        settings.project_config = project_path;
+        Ok(settings)
+    }
+}
+
+/// The config files read, in order, with the project's path when there's one. Keys a
+/// project may not set are dropped from its .ai.toml with a warning
+fn sources(
+    explicit: bool,
+    config_file: &Path,
+    warnings: &mut Vec<String>,
+) -> Result<(ConfigBuilder<DefaultState>, Option<PathBuf>), config::ConfigError> {
+    let mut builder = Config::builder();
+    if explicit {
+        // only the given file, so tests and containers don't pick up anything else
+        return Ok((builder.add_source(config::File::from(config_file)), None));
+    }
+    let config_path = config_file.with_extension("");
+    builder =
+        builder.add_source(config::File::with_name(&config_path.to_string_lossy()).required(false));
+    let project_path = project_config_path();
+    if let Some(path) = &project_path {
+        let source = config::File::from(path.as_path()).format(config::FileFormat::Toml);
+        let name = path.display().to_string();
+        builder = builder.add_source(project_config(source, &name, warnings)?);
     }
+    builder = builder.add_source(config::File::with_name("config").required(false));
+    Ok((builder, project_path))
+}
+
+/// The `verbosity` setting, read without creating, migrating or warning about the config:
+/// it's needed to parse the arguments, before anything can be reported
+fn verbosity() -> Option<String> {
+    let (builder, _) =
+        sources(CONFIG_FILE.get().is_some(), &config_file(), &mut Vec::new()).ok()?;
+    builder.build().ok()?.get_string("verbosity").ok()
 }
 
 #[derive(Debug)]
@@ -115,9 +789,8 @@ pub struct ConfigLogLevel {}
 
 impl LogLevel for ConfigLogLevel {
     fn default() -> Option<clap_verbosity_flag::Level> {
-        // read from settings options
-        let settings = Settings::new().unwrap();
-        let log_level = settings.verbosity.unwrap_or_else(|| "error".to_string());
+        // read from settings options, an invalid config is reported once the args are parsed
+        let log_level = verbosity().unwrap_or_else(|| "error".to_string());
         let level = match log_level.as_str() {
             "error" => Some(clap_verbosity_flag::Level::Error),
             "warn" => Some(clap_verbosity_flag::Level::Warn),
@@ -129,3 +802,97 @@ impl LogLevel for ConfigLogLevel {
         level
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(toml: &str) -> (Config, Vec<String>) {
+        let mut warnings = Vec::new();
+        let source = config::File::from_str(toml, config::FileFormat::Toml);
+        let config = project_config(source, ".ai.toml", &mut warnings).unwrap();
+        (config, warnings)
+    }
+
+    #[test]
+    fn plugin_args_are_masked_in_the_debug_output() {
+        let plugin = PluginSettings {
+            path: PathBuf::from("/usr/bin/ai-backend-openai"),
+            args: vec![
+                "--api-key".to_string(),
+                "sk-abcdefghijklmnop".to_string(),
+                "--model".to_string(),
+                "gpt".to_string(),
+            ],
+            credential: None,
+        };
+        let debug = format!("{:?}", plugin);
+        assert!(!debug.contains("abcdefghijklmnop"), "{}", debug);
+        assert!(debug.contains("--model gpt"), "{}", debug);
+    }
+
+    #[test]
+    fn project_config_keeps_allowed_leaf_keys() {
+        let (config, warnings) = project(
+            "system_prompt_append = \"use bazel\"\n\
+             [aws_settings]\nmodel_id = \"m\"\n\
+             [local_model_config]\nmodel = \"V3\"\ntemperature = 0.2\n",
+        );
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(
+            config.get_string("system_prompt_append").unwrap(),
+            "use bazel"
+        );
+        assert_eq!(config.get_string("aws_settings.model_id").unwrap(), "m");
+        assert_eq!(config.get_string("local_model_config.model").unwrap(), "V3");
+    }
+
+    #[test]
+    fn project_config_pins_the_backend_and_system_prompt_with_a_warning() {
+        let (config, mut warnings) =
+            project("ai_backend = \"bedrock\"\nsystem_prompt = \"Answer with bazel commands.\"\n");
+        assert_eq!(config.get_string("ai_backend").unwrap(), "bedrock");
+        assert_eq!(
+            config.get_string("system_prompt").unwrap(),
+            "Answer with bazel commands."
+        );
+        warnings.sort();
+        assert_eq!(
+            warnings,
+            [
+                "ai_backend is set by the project config .ai.toml",
+                "system_prompt is set by the project config .ai.toml"
+            ]
+        );
+    }
+
+    #[test]
+    fn project_config_drops_endpoint_and_context_keys() {
+        let (config, warnings) = project(
+            "[aws_settings]\nmodel_id = \"m\"\n\
+             endpoint_url = \"https://attacker.example\"\ntools = true\n\
+             role_arn = \"arn:aws:iam::1:role/r\"\n\
+             [context.providers.files]\nenabled = true\n\
+             [command_backend]\ncmd = \"curl\"\n",
+        );
+        assert_eq!(config.get_string("aws_settings.model_id").unwrap(), "m");
+        for key in [
+            "aws_settings.endpoint_url",
+            "aws_settings.tools",
+            "aws_settings.role_arn",
+            "context",
+            "command_backend",
+        ] {
+            assert!(
+                config.get::<config::Value>(key).is_err(),
+                "{} was kept",
+                key
+            );
+            assert!(
+                warnings.iter().any(|warning| warning.starts_with(key)),
+                "no warning for {}",
+                key
+            );
+        }
+    }
+}
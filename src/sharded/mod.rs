@@ -0,0 +1,55 @@
+//! Phi-2 and Phi-3 from candle-transformers with their decoder layers spread over several
+//! devices, so a model too large for one GPU runs on a few. The token embedding is on the
+//! first device, each device takes an even run of consecutive layers and the final norm and
+//! lm head are on the last one. The hidden states move to the next device where its layers
+//! start and the logits come back to the first device, where the input tokens are.
+
+pub mod phi;
+pub mod phi3;
+
+use std::path::PathBuf;
+
+use candle_core::{DType, Device, Result};
+use candle_nn::VarBuilder;
+
+/// The index of the device each of `layers` layers is placed on, the first devices taking
+/// one more layer when they don't divide evenly
+pub fn placement(devices: usize, layers: usize) -> Vec<usize> {
+    (0..layers)
+        .map(|layer| layer * devices.max(1) / layers)
+        .collect()
+}
+
+/// A var builder over the safetensors files for each device, a tensor is read onto the
+/// device of the builder it's taken from
+pub fn var_builders(
+    filenames: &[PathBuf],
+    dtype: DType,
+    devices: &[Device],
+) -> Result<Vec<VarBuilder<'static>>> {
+    devices
+        .iter()
+        .map(|device| unsafe { VarBuilder::from_mmaped_safetensors(filenames, dtype, device) })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_are_split_into_even_runs() {
+        assert_eq!(placement(1, 4), vec![0, 0, 0, 0]);
+        assert_eq!(placement(2, 4), vec![0, 0, 1, 1]);
+        let three = placement(3, 32);
+        assert_eq!(three.iter().filter(|&&device| device == 0).count(), 11);
+        assert_eq!(three.iter().filter(|&&device| device == 1).count(), 11);
+        assert_eq!(three.iter().filter(|&&device| device == 2).count(), 10);
+        assert!(three.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn more_devices_than_layers_leaves_some_empty() {
+        assert_eq!(placement(4, 2), vec![0, 2]);
+    }
+}
@@ -0,0 +1,375 @@
+//! Phi-2, candle-transformers' phi model with its layers on several devices
+
+use candle_core::{DType, Device, IndexOp, Module, Result, Tensor, D};
+use candle_nn::{Activation, VarBuilder};
+use candle_transformers::models::with_tracing::{layer_norm, linear, Embedding, LayerNorm, Linear};
+use candle_transformers::utils::repeat_kv;
+use serde::Deserialize;
+
+use super::placement;
+
+use crate::messages::Message;
+
+/// The model's config.json, candle's keeps its fields private
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Config {
+    vocab_size: usize,
+    hidden_size: usize,
+    intermediate_size: usize,
+    num_hidden_layers: usize,
+    num_attention_heads: usize,
+    num_key_value_heads: Option<usize>,
+    hidden_act: Activation,
+    max_position_embeddings: usize,
+    layer_norm_eps: f64,
+    rope_theta: f32,
+    partial_rotary_factor: f64,
+    qk_layernorm: bool,
+}
+
+impl Config {
+    fn num_key_value_heads(&self) -> usize {
+        self.num_key_value_heads.unwrap_or(self.num_attention_heads)
+    }
+
+    fn head_dim(&self) -> usize {
+        self.hidden_size / self.num_attention_heads
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RotaryEmbedding {
+    dim: usize,
+    sin: Tensor,
+    cos: Tensor,
+}
+
+impl RotaryEmbedding {
+    fn new(cfg: &Config, dev: &Device) -> Result<Self> {
+        let dim = (cfg.partial_rotary_factor * cfg.head_dim() as f64) as usize;
+        let inv_freq: Vec<_> = (0..dim)
+            .step_by(2)
+            .map(|i| 1f32 / cfg.rope_theta.powf(i as f32 / dim as f32))
+            .collect();
+        let inv_freq_len = inv_freq.len();
+        let inv_freq = Tensor::from_vec(inv_freq, (1, inv_freq_len), dev)?;
+        let t = Tensor::arange(0u32, cfg.max_position_embeddings as u32, dev)?
+            .to_dtype(DType::F32)?
+            .reshape((cfg.max_position_embeddings, 1))?;
+        let freqs = t.matmul(&inv_freq)?;
+        Ok(Self {
+            dim,
+            sin: freqs.sin()?,
+            cos: freqs.cos()?,
+        })
+    }
+
+    fn apply_rotary_emb(&self, xs: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        let (_b_size, _num_heads, seq_len, _headdim) = xs.dims4()?;
+        let xs_rot = xs.i((.., .., .., ..self.dim))?.contiguous()?;
+        let xs_pass = xs.i((.., .., .., self.dim..))?;
+        let c = self.cos.narrow(0, seqlen_offset, seq_len)?;
+        let s = self.sin.narrow(0, seqlen_offset, seq_len)?;
+        let xs_rot = candle_nn::rotary_emb::rope(&xs_rot, &c, &s)?;
+        Tensor::cat(&[&xs_rot, &xs_pass], D::Minus1)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Mlp {
+    fc1: Linear,
+    fc2: Linear,
+    act: Activation,
+}
+
+impl Mlp {
+    fn new(cfg: &Config, vb: VarBuilder) -> Result<Self> {
+        Ok(Self {
+            fc1: linear(cfg.hidden_size, cfg.intermediate_size, vb.pp("fc1"))?,
+            fc2: linear(cfg.intermediate_size, cfg.hidden_size, vb.pp("fc2"))?,
+            act: cfg.hidden_act,
+        })
+    }
+}
+
+impl Module for Mlp {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        xs.apply(&self.fc1)?.apply(&self.act)?.apply(&self.fc2)
+    }
+}
+
+#[derive(Clone)]
+struct Attention {
+    q_proj: Linear,
+    k_proj: Linear,
+    v_proj: Linear,
+    dense: Linear,
+    kv_cache: Option<(Tensor, Tensor)>,
+    q_layernorm: Option<LayerNorm>,
+    k_layernorm: Option<LayerNorm>,
+    rotary_emb: RotaryEmbedding,
+    softmax_scale: f64,
+    num_heads: usize,
+    num_kv_heads: usize,
+    head_dim: usize,
+}
+
+fn get_mask(size: usize, device: &Device) -> Result<Tensor> {
+    let mask: Vec<_> = (0..size)
+        .flat_map(|i| (0..size).map(move |j| u8::from(j > i)))
+        .collect();
+    Tensor::from_slice(&mask, (size, size), device)
+}
+
+fn masked_fill(on_false: &Tensor, mask: &Tensor, on_true: f32) -> Result<Tensor> {
+    let shape = mask.shape();
+    let on_true = Tensor::new(on_true, on_false.device())?.broadcast_as(shape.dims())?;
+    mask.where_cond(&on_true, on_false)
+}
+
+impl Attention {
+    fn new(cfg: &Config, vb: VarBuilder) -> Result<Self> {
+        let num_heads = cfg.num_attention_heads;
+        let num_kv_heads = cfg.num_key_value_heads();
+        let head_dim = cfg.head_dim();
+        let (q_layernorm, k_layernorm) = if cfg.qk_layernorm {
+            let q_layernorm = layer_norm(head_dim, cfg.layer_norm_eps, vb.pp("q_layernorm"))?;
+            let k_layernorm = layer_norm(head_dim, cfg.layer_norm_eps, vb.pp("k_layernorm"))?;
+            (Some(q_layernorm), Some(k_layernorm))
+        } else {
+            (None, None)
+        };
+        Ok(Self {
+            q_proj: linear(cfg.hidden_size, num_heads * head_dim, vb.pp("q_proj"))?,
+            k_proj: linear(cfg.hidden_size, num_kv_heads * head_dim, vb.pp("k_proj"))?,
+            v_proj: linear(cfg.hidden_size, num_kv_heads * head_dim, vb.pp("v_proj"))?,
+            dense: linear(num_heads * head_dim, cfg.hidden_size, vb.pp("dense"))?,
+            kv_cache: None,
+            q_layernorm,
+            k_layernorm,
+            rotary_emb: RotaryEmbedding::new(cfg, vb.device())?,
+            softmax_scale: 1f64 / (head_dim as f64).sqrt(),
+            num_heads,
+            num_kv_heads,
+            head_dim,
+        })
+    }
+
+    fn forward(&mut self, xs: &Tensor, mask: Option<&Tensor>) -> Result<Tensor> {
+        let (b_size, seq_len, _n_embd) = xs.dims3()?;
+        let query_states = self.q_proj.forward(xs)?;
+        let key_states = self.k_proj.forward(xs)?;
+        let value_states = self.v_proj.forward(xs)?;
+
+        let query_states = match &self.q_layernorm {
+            None => query_states,
+            Some(ln) => query_states.apply(ln)?,
+        };
+        let key_states = match &self.k_layernorm {
+            None => key_states,
+            Some(ln) => key_states.apply(ln)?,
+        };
+
+        let query_states = query_states
+            .reshape((b_size, seq_len, self.num_heads, self.head_dim))?
+            .transpose(1, 2)?;
+        let key_states = key_states
+            .reshape((b_size, seq_len, self.num_kv_heads, self.head_dim))?
+            .transpose(1, 2)?;
+        let value_states = value_states
+            .reshape((b_size, seq_len, self.num_kv_heads, self.head_dim))?
+            .transpose(1, 2)?;
+
+        let seqlen_offset = match &self.kv_cache {
+            None => 0,
+            Some((prev_k, _)) => prev_k.dim(2)?,
+        };
+        let query_states = self
+            .rotary_emb
+            .apply_rotary_emb(&query_states, seqlen_offset)?;
+        let key_states = self
+            .rotary_emb
+            .apply_rotary_emb(&key_states, seqlen_offset)?;
+
+        let (key_states, value_states) = match &self.kv_cache {
+            None => (key_states, value_states),
+            Some((prev_k, prev_v)) => (
+                Tensor::cat(&[prev_k, &key_states], 2)?,
+                Tensor::cat(&[prev_v, &value_states], 2)?,
+            ),
+        };
+        self.kv_cache = Some((key_states.clone(), value_states.clone()));
+
+        let groups = self.num_heads / self.num_kv_heads;
+        let key_states = repeat_kv(key_states, groups)?.contiguous()?;
+        let value_states = repeat_kv(value_states, groups)?.contiguous()?;
+
+        let attn_weights = (query_states
+            .to_dtype(DType::F32)?
+            .contiguous()?
+            .matmul(&key_states.to_dtype(DType::F32)?.t()?)?
+            * self.softmax_scale)?;
+        let attn_weights = match mask {
+            None => attn_weights,
+            Some(mask) => masked_fill(
+                &attn_weights,
+                &mask.broadcast_left((b_size, self.num_heads))?,
+                f32::NEG_INFINITY,
+            )?,
+        };
+        let attn_weights =
+            candle_nn::ops::softmax_last_dim(&attn_weights)?.to_dtype(value_states.dtype())?;
+        attn_weights
+            .matmul(&value_states)?
+            .transpose(1, 2)?
+            .reshape((b_size, seq_len, ()))?
+            .apply(&self.dense)
+    }
+}
+
+#[derive(Clone)]
+struct DecoderLayer {
+    self_attn: Attention,
+    mlp: Mlp,
+    input_layernorm: LayerNorm,
+}
+
+impl DecoderLayer {
+    fn new(cfg: &Config, vb: VarBuilder) -> Result<Self> {
+        Ok(Self {
+            self_attn: Attention::new(cfg, vb.pp("self_attn"))?,
+            mlp: Mlp::new(cfg, vb.pp("mlp"))?,
+            input_layernorm: layer_norm(
+                cfg.hidden_size,
+                cfg.layer_norm_eps,
+                vb.pp("input_layernorm"),
+            )?,
+        })
+    }
+
+    fn forward(&mut self, xs: &Tensor, mask: Option<&Tensor>) -> Result<Tensor> {
+        let residual = xs;
+        let xs = xs.apply(&self.input_layernorm)?;
+        let attn_outputs = self.self_attn.forward(&xs, mask)?;
+        let feed_forward_hidden_states = self.mlp.forward(&xs)?;
+        attn_outputs + feed_forward_hidden_states + residual
+    }
+}
+
+#[derive(Clone)]
+pub struct Model {
+    embed_tokens: Embedding,
+    layers: Vec<DecoderLayer>,
+    /// The device each layer is on
+    devices: Vec<Device>,
+    final_layernorm: LayerNorm,
+    lm_head: Linear,
+    /// Where the input tokens are and the logits are returned
+    first: Device,
+    /// Where the final norm and lm head are
+    last: Device,
+}
+
+impl Model {
+    /// The model with its weights read through `vbs`, one var builder for each device
+    pub fn new(cfg: &Config, vbs: &[VarBuilder]) -> Result<Self> {
+        let (Some(first), Some(last)) = (vbs.first(), vbs.last()) else {
+            candle_core::bail!("{}", Message::NoDevice);
+        };
+        let embed_tokens = Embedding::new(
+            cfg.vocab_size,
+            cfg.hidden_size,
+            first.pp("model.embed_tokens"),
+        )?;
+        let mut layers = Vec::with_capacity(cfg.num_hidden_layers);
+        let mut devices = Vec::with_capacity(cfg.num_hidden_layers);
+        for (layer_idx, device) in placement(vbs.len(), cfg.num_hidden_layers)
+            .into_iter()
+            .enumerate()
+        {
+            let vb = &vbs[device];
+            layers.push(DecoderLayer::new(cfg, vb.pp("model.layers").pp(layer_idx))?);
+            devices.push(vb.device().clone());
+        }
+        let final_layernorm = layer_norm(
+            cfg.hidden_size,
+            cfg.layer_norm_eps,
+            last.pp("model.final_layernorm"),
+        )?;
+        let lm_head = linear(cfg.hidden_size, cfg.vocab_size, last.pp("lm_head"))?;
+        Ok(Self {
+            embed_tokens,
+            layers,
+            devices,
+            final_layernorm,
+            lm_head,
+            first: first.device().clone(),
+            last: last.device().clone(),
+        })
+    }
+
+    /// The logits of the last position, on the first device
+    pub fn forward(&mut self, xs: &Tensor) -> Result<Tensor> {
+        let (_b_size, seq_len) = xs.dims2()?;
+        let mut xs = xs.apply(&self.embed_tokens)?;
+        let mut mask = if seq_len <= 1 {
+            None
+        } else {
+            Some(get_mask(seq_len, xs.device())?)
+        };
+        for (layer, device) in self.layers.iter_mut().zip(&self.devices) {
+            // moving to the device the tensor is already on is a no-op
+            xs = xs.to_device(device)?;
+            mask = mask.map(|mask| mask.to_device(device)).transpose()?;
+            xs = layer.forward(&xs, mask.as_ref())?;
+        }
+        xs.to_device(&self.last)?
+            .apply(&self.final_layernorm)?
+            .narrow(1, seq_len - 1, 1)?
+            .apply(&self.lm_head)?
+            .squeeze(1)?
+            .to_device(&self.first)
+    }
+
+    pub fn clear_kv_cache(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.self_attn.kv_cache = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_nn::VarMap;
+    use candle_transformers::models::phi::{Config as PhiConfig, Model as Phi};
+
+    use super::*;
+
+    const CONFIG: &str = r#"{
+        "vocab_size": 32, "hidden_size": 16, "intermediate_size": 32,
+        "num_hidden_layers": 3, "num_attention_heads": 4, "num_key_value_heads": null,
+        "hidden_act": "gelu_new", "max_position_embeddings": 64, "layer_norm_eps": 1e-5,
+        "tie_word_embeddings": false, "rope_theta": 10000.0, "partial_rotary_factor": 0.5,
+        "qk_layernorm": false
+    }"#;
+
+    #[test]
+    fn split_over_devices_it_computes_what_the_whole_model_does() -> Result<()> {
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        let config: PhiConfig = serde_json::from_str(CONFIG).unwrap();
+        let mut whole = Phi::new(&config, vb.clone())?;
+        let config: Config = serde_json::from_str(CONFIG).unwrap();
+        let mut split = Model::new(&config, &[vb.clone(), vb])?;
+        for input in [&[1u32, 5, 7, 2][..], &[9], &[3]] {
+            let input = Tensor::new(input, &Device::Cpu)?.unsqueeze(0)?;
+            let expected = whole.forward(&input)?.flatten_all()?.to_vec1::<f32>()?;
+            let actual = split.forward(&input)?.flatten_all()?.to_vec1::<f32>()?;
+            assert_eq!(expected.len(), actual.len());
+            for (expected, actual) in expected.iter().zip(&actual) {
+                assert!((expected - actual).abs() < 1e-4, "{expected} != {actual}");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,444 @@
+//! Phi-3, candle-transformers' phi3 model with its layers on several devices
+
+use std::sync::Arc;
+
+use candle_core::{DType, Device, IndexOp, Module, Result, Tensor, D};
+use candle_nn::VarBuilder;
+use candle_transformers::models::with_tracing::{linear_no_bias as linear, Linear, RmsNorm};
+use candle_transformers::utils::repeat_kv;
+use serde::Deserialize;
+
+use super::placement;
+
+use crate::messages::Message;
+
+#[derive(Debug, Clone, Deserialize)]
+struct RopeScaling {
+    short_factor: Vec<f32>,
+    long_factor: Vec<f32>,
+}
+
+/// The model's config.json
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    vocab_size: usize,
+    hidden_act: candle_nn::Activation,
+    hidden_size: usize,
+    intermediate_size: usize,
+    num_hidden_layers: usize,
+    num_attention_heads: usize,
+    num_key_value_heads: usize,
+    rms_norm_eps: f64,
+    rope_theta: f64,
+    rope_scaling: Option<RopeScaling>,
+    max_position_embeddings: usize,
+    original_max_position_embeddings: Option<usize>,
+    partial_rotary_factor: Option<f64>,
+    #[serde(default)]
+    tie_word_embeddings: bool,
+}
+
+impl Config {
+    fn head_dim(&self) -> usize {
+        self.hidden_size / self.num_attention_heads
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RotaryEmbedding {
+    partial_dim: Option<usize>,
+    sin: Tensor,
+    cos: Tensor,
+}
+
+impl RotaryEmbedding {
+    fn new(dtype: DType, cfg: &Config, dev: &Device) -> Result<Self> {
+        let partial_dim = cfg
+            .partial_rotary_factor
+            .map(|factor| (factor * cfg.head_dim() as f64) as usize);
+        let dim = partial_dim.unwrap_or(cfg.head_dim());
+        let inv_freq = |factors: Option<&[f32]>| -> Result<Tensor> {
+            let inv_freq: Vec<_> = (0..dim)
+                .step_by(2)
+                .enumerate()
+                .map(|(n, i)| {
+                    let factor = factors.map_or(1., |factors| factors[n]);
+                    factor / cfg.rope_theta.powf(i as f64 / dim as f64) as f32
+                })
+                .collect();
+            Tensor::from_vec(inv_freq, (1, ()), dev)?.to_dtype(dtype)
+        };
+        let positions = |from: usize, to: usize| -> Result<Tensor> {
+            Tensor::arange(from as u32, to as u32, dev)?
+                .to_dtype(dtype)?
+                .reshape(((), 1))
+        };
+        let max_seq_len = cfg.max_position_embeddings;
+        let freqs = match (&cfg.rope_scaling, cfg.original_max_position_embeddings) {
+            (None, _) => positions(0, max_seq_len)?.matmul(&inv_freq(None)?)?,
+            (Some(scaling), None) => {
+                positions(0, max_seq_len)?.matmul(&inv_freq(Some(&scaling.short_factor))?)?
+            }
+            (Some(scaling), Some(original_max_seq_len)) => {
+                let short = positions(0, original_max_seq_len)?
+                    .matmul(&inv_freq(Some(&scaling.short_factor))?)?;
+                let long = positions(original_max_seq_len, max_seq_len)?
+                    .matmul(&inv_freq(Some(&scaling.long_factor))?)?;
+                Tensor::cat(&[&short, &long], 0)?
+            }
+        };
+        Ok(Self {
+            partial_dim,
+            sin: freqs.sin()?,
+            cos: freqs.cos()?,
+        })
+    }
+
+    fn rope(&self, xs: &Tensor, cos: &Tensor, sin: &Tensor) -> Result<Tensor> {
+        match self.partial_dim {
+            None => candle_nn::rotary_emb::rope(&xs.contiguous()?, cos, sin),
+            Some(dim) => {
+                let xs_rot = xs.i((.., .., .., ..dim))?.contiguous()?;
+                let xs_pass = xs.i((.., .., .., dim..))?;
+                let xs_rot = candle_nn::rotary_emb::rope(&xs_rot, cos, sin)?;
+                Tensor::cat(&[&xs_rot, &xs_pass], D::Minus1)?.contiguous()
+            }
+        }
+    }
+
+    fn apply_rotary_emb_qkv(
+        &self,
+        q: &Tensor,
+        k: &Tensor,
+        seqlen_offset: usize,
+    ) -> Result<(Tensor, Tensor)> {
+        let (_b_sz, _h, seq_len, _n_embd) = q.dims4()?;
+        let cos = self.cos.narrow(0, seqlen_offset, seq_len)?;
+        let sin = self.sin.narrow(0, seqlen_offset, seq_len)?;
+        let q_embed = self.rope(&q.contiguous()?, &cos, &sin)?;
+        let k_embed = self.rope(&k.contiguous()?, &cos, &sin)?;
+        Ok((q_embed, k_embed))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Attention {
+    qkv_proj: Linear,
+    o_proj: Linear,
+    num_heads: usize,
+    num_kv_heads: usize,
+    num_kv_groups: usize,
+    head_dim: usize,
+    rotary_emb: Arc<RotaryEmbedding>,
+    kv_cache: Option<(Tensor, Tensor)>,
+}
+
+impl Attention {
+    fn new(rotary_emb: Arc<RotaryEmbedding>, cfg: &Config, vb: VarBuilder) -> Result<Self> {
+        let num_heads = cfg.num_attention_heads;
+        let num_kv_heads = cfg.num_key_value_heads;
+        let head_dim = cfg.head_dim();
+        let op_size = num_heads * head_dim + 2 * num_kv_heads * head_dim;
+        Ok(Self {
+            qkv_proj: linear(cfg.hidden_size, op_size, vb.pp("qkv_proj"))?,
+            o_proj: linear(num_heads * head_dim, cfg.hidden_size, vb.pp("o_proj"))?,
+            rotary_emb,
+            kv_cache: None,
+            num_heads,
+            num_kv_heads,
+            num_kv_groups: num_heads / num_kv_heads,
+            head_dim,
+        })
+    }
+
+    fn forward(
+        &mut self,
+        xs: &Tensor,
+        attention_mask: Option<&Tensor>,
+        seqlen_offset: usize,
+    ) -> Result<Tensor> {
+        let (b_sz, q_len, _) = xs.dims3()?;
+
+        let qkv = self.qkv_proj.forward(xs)?;
+        let query_pos = self.num_heads * self.head_dim;
+        let kv_size = self.num_kv_heads * self.head_dim;
+        let query_states = qkv.narrow(D::Minus1, 0, query_pos)?;
+        let key_states = qkv.narrow(D::Minus1, query_pos, kv_size)?;
+        let value_states = qkv.narrow(D::Minus1, query_pos + kv_size, kv_size)?;
+
+        let query_states = query_states
+            .reshape((b_sz, q_len, self.num_heads, self.head_dim))?
+            .transpose(1, 2)?;
+        let key_states = key_states
+            .reshape((b_sz, q_len, self.num_kv_heads, self.head_dim))?
+            .transpose(1, 2)?;
+        let value_states = value_states
+            .reshape((b_sz, q_len, self.num_kv_heads, self.head_dim))?
+            .transpose(1, 2)?;
+
+        let (query_states, key_states) =
+            self.rotary_emb
+                .apply_rotary_emb_qkv(&query_states, &key_states, seqlen_offset)?;
+
+        let (key_states, value_states) = match &self.kv_cache {
+            None => (key_states, value_states),
+            Some((prev_k, prev_v)) => (
+                Tensor::cat(&[prev_k, &key_states], 2)?,
+                Tensor::cat(&[prev_v, &value_states], 2)?,
+            ),
+        };
+        self.kv_cache = Some((key_states.clone(), value_states.clone()));
+
+        let key_states = repeat_kv(key_states, self.num_kv_groups)?.contiguous()?;
+        let value_states = repeat_kv(value_states, self.num_kv_groups)?.contiguous()?;
+
+        let scale = 1f64 / f64::sqrt(self.head_dim as f64);
+        let attn_weights = (query_states.matmul(&key_states.transpose(2, 3)?)? * scale)?;
+        let attn_weights = match attention_mask {
+            None => attn_weights,
+            Some(mask) => attn_weights.broadcast_add(mask)?,
+        };
+        candle_nn::ops::softmax_last_dim(&attn_weights)?
+            .matmul(&value_states)?
+            .transpose(1, 2)?
+            .reshape((b_sz, q_len, ()))?
+            .apply(&self.o_proj)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Mlp {
+    gate_up_proj: Linear,
+    down_proj: Linear,
+    act_fn: candle_nn::Activation,
+    i_size: usize,
+}
+
+impl Mlp {
+    fn new(cfg: &Config, vb: VarBuilder) -> Result<Self> {
+        let i_size = cfg.intermediate_size;
+        Ok(Self {
+            gate_up_proj: linear(cfg.hidden_size, 2 * i_size, vb.pp("gate_up_proj"))?,
+            down_proj: linear(i_size, cfg.hidden_size, vb.pp("down_proj"))?,
+            act_fn: cfg.hidden_act,
+            i_size,
+        })
+    }
+}
+
+impl Module for Mlp {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let up_states = xs.apply(&self.gate_up_proj)?;
+        let gate = up_states.narrow(D::Minus1, 0, self.i_size)?;
+        let up_states = up_states.narrow(D::Minus1, self.i_size, self.i_size)?;
+        (up_states * gate.apply(&self.act_fn))?.apply(&self.down_proj)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DecoderLayer {
+    self_attn: Attention,
+    mlp: Mlp,
+    input_layernorm: RmsNorm,
+    post_attention_layernorm: RmsNorm,
+}
+
+impl DecoderLayer {
+    fn new(rotary_emb: Arc<RotaryEmbedding>, cfg: &Config, vb: VarBuilder) -> Result<Self> {
+        Ok(Self {
+            self_attn: Attention::new(rotary_emb, cfg, vb.pp("self_attn"))?,
+            mlp: Mlp::new(cfg, vb.pp("mlp"))?,
+            input_layernorm: RmsNorm::new(
+                cfg.hidden_size,
+                cfg.rms_norm_eps,
+                vb.pp("input_layernorm"),
+            )?,
+            post_attention_layernorm: RmsNorm::new(
+                cfg.hidden_size,
+                cfg.rms_norm_eps,
+                vb.pp("post_attention_layernorm"),
+            )?,
+        })
+    }
+
+    fn forward(
+        &mut self,
+        xs: &Tensor,
+        attention_mask: Option<&Tensor>,
+        seqlen_offset: usize,
+    ) -> Result<Tensor> {
+        let residual = xs;
+        let xs = self.input_layernorm.forward(xs)?;
+        let xs = self.self_attn.forward(&xs, attention_mask, seqlen_offset)?;
+        let xs = (xs + residual)?;
+        let residual = &xs;
+        let xs = xs.apply(&self.post_attention_layernorm)?.apply(&self.mlp)?;
+        residual + xs
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Model {
+    embed_tokens: candle_nn::Embedding,
+    layers: Vec<DecoderLayer>,
+    /// The device each layer is on
+    devices: Vec<Device>,
+    norm: RmsNorm,
+    lm_head: Linear,
+    /// Where the input tokens are and the logits are returned
+    first: Device,
+    /// Where the final norm and lm head are
+    last: Device,
+    dtype: DType,
+}
+
+impl Model {
+    /// The model with its weights read through `vbs`, one var builder for each device
+    pub fn new(cfg: &Config, vbs: &[VarBuilder]) -> Result<Self> {
+        let (Some(first), Some(last)) = (vbs.first(), vbs.last()) else {
+            candle_core::bail!("{}", Message::NoDevice);
+        };
+        let embed_tokens = candle_nn::embedding(
+            cfg.vocab_size,
+            cfg.hidden_size,
+            first.pp("model.embed_tokens"),
+        )?;
+        // the sin and cos tables on every device, shared by its layers
+        let rotary_embs = vbs
+            .iter()
+            .map(|vb| {
+                Ok(Arc::new(RotaryEmbedding::new(
+                    vb.dtype(),
+                    cfg,
+                    vb.device(),
+                )?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut layers = Vec::with_capacity(cfg.num_hidden_layers);
+        let mut devices = Vec::with_capacity(cfg.num_hidden_layers);
+        for (layer_idx, device) in placement(vbs.len(), cfg.num_hidden_layers)
+            .into_iter()
+            .enumerate()
+        {
+            let vb = &vbs[device];
+            layers.push(DecoderLayer::new(
+                rotary_embs[device].clone(),
+                cfg,
+                vb.pp("model.layers").pp(layer_idx),
+            )?);
+            devices.push(vb.device().clone());
+        }
+        let norm = RmsNorm::new(cfg.hidden_size, cfg.rms_norm_eps, last.pp("model.norm"))?;
+        let lm_head = if cfg.tie_word_embeddings {
+            let weights = embed_tokens.embeddings().to_device(last.device())?;
+            Linear::from_weights(weights, None)
+        } else {
+            linear(cfg.hidden_size, cfg.vocab_size, last.pp("lm_head"))?
+        };
+        Ok(Self {
+            embed_tokens,
+            layers,
+            devices,
+            norm,
+            lm_head,
+            first: first.device().clone(),
+            last: last.device().clone(),
+            dtype: first.dtype(),
+        })
+    }
+
+    fn prepare_decoder_attention_mask(
+        &self,
+        b_size: usize,
+        tgt_len: usize,
+        seqlen_offset: usize,
+    ) -> Result<Tensor> {
+        let mask: Vec<_> = (0..tgt_len)
+            .flat_map(|i| (0..tgt_len).map(move |j| if i < j { f32::NEG_INFINITY } else { 0. }))
+            .collect();
+        let mask = Tensor::from_slice(&mask, (tgt_len, tgt_len), &self.first)?;
+        let mask = if seqlen_offset > 0 {
+            let mask0 = Tensor::zeros((tgt_len, seqlen_offset), DType::F32, &self.first)?;
+            Tensor::cat(&[&mask0, &mask], D::Minus1)?
+        } else {
+            mask
+        };
+        mask.expand((b_size, 1, tgt_len, tgt_len + seqlen_offset))?
+            .to_dtype(self.dtype)
+    }
+
+    /// The logits of the last position, on the first device
+    pub fn forward(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        let (b_size, seq_len) = input_ids.dims2()?;
+        let mut attention_mask = if seq_len <= 1 {
+            None
+        } else {
+            Some(self.prepare_decoder_attention_mask(b_size, seq_len, seqlen_offset)?)
+        };
+        let mut xs = self.embed_tokens.forward(input_ids)?;
+        for (layer, device) in self.layers.iter_mut().zip(&self.devices) {
+            // moving to the device the tensor is already on is a no-op
+            xs = xs.to_device(device)?;
+            attention_mask = attention_mask
+                .map(|mask| mask.to_device(device))
+                .transpose()?;
+            xs = layer.forward(&xs, attention_mask.as_ref(), seqlen_offset)?;
+        }
+        xs.to_device(&self.last)?
+            .narrow(1, seq_len - 1, 1)?
+            .apply(&self.norm)?
+            .apply(&self.lm_head)?
+            .to_device(&self.first)
+    }
+
+    pub fn clear_kv_cache(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.self_attn.kv_cache = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_nn::VarMap;
+    use candle_transformers::models::phi3::{Config as Phi3Config, Model as Phi3};
+
+    use super::*;
+
+    const CONFIG: &str = r#"{
+        "vocab_size": 32, "hidden_act": "silu", "hidden_size": 16, "intermediate_size": 32,
+        "num_hidden_layers": 3, "num_attention_heads": 4, "num_key_value_heads": 2,
+        "rms_norm_eps": 1e-5, "rope_theta": 10000.0, "bos_token_id": 1, "eos_token_id": 2,
+        "rope_scaling": null, "max_position_embeddings": 64,
+        "original_max_position_embeddings": null, "partial_rotary_factor": null,
+        "tie_word_embeddings": false
+    }"#;
+
+    #[test]
+    fn split_over_devices_it_computes_what_the_whole_model_does() -> Result<()> {
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        let config: Phi3Config = serde_json::from_str(CONFIG).unwrap();
+        let mut whole = Phi3::new(&config, vb.clone())?;
+        let config: Config = serde_json::from_str(CONFIG).unwrap();
+        let mut split = Model::new(&config, &[vb.clone(), vb.clone(), vb])?;
+        let mut pos = 0;
+        for input in [&[1u32, 5, 7, 2][..], &[9], &[3]] {
+            let tensor = Tensor::new(input, &Device::Cpu)?.unsqueeze(0)?;
+            let expected = whole
+                .forward(&tensor, pos)?
+                .flatten_all()?
+                .to_vec1::<f32>()?;
+            let actual = split
+                .forward(&tensor, pos)?
+                .flatten_all()?
+                .to_vec1::<f32>()?;
+            assert_eq!(expected.len(), actual.len());
+            for (expected, actual) in expected.iter().zip(&actual) {
+                assert!((expected - actual).abs() < 1e-4, "{expected} != {actual}");
+            }
+            pos += input.len();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use clap::ValueEnum;
+
+/// Shells `ai shell-init` and `ai init` emit integration snippets for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Records the last command and its exit status for `ai fix`.
+/// Prepended to PROMPT_COMMAND so $? is still the command's status
+const BASH_INIT: &str = r#"# ai shell integration, add to ~/.bashrc: eval "$(ai shell-init bash)"
+__ai_record_last() {
+    local status=$?
+    export AI_LAST_STATUS=$status
+    export AI_LAST_COMMAND="$(HISTTIMEFORMAT= history 1 | sed 's/^ *[0-9]* *//')"
+    return $status
+}
+if [[ ";${PROMPT_COMMAND[*]};" != *";__ai_record_last;"* ]]; then
+    PROMPT_COMMAND="__ai_record_last${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+"#;
+
+const ZSH_INIT: &str = r#"# ai shell integration, add to ~/.zshrc: eval "$(ai shell-init zsh)"
+__ai_record_last() {
+    export AI_LAST_STATUS=$?
+    export AI_LAST_COMMAND="$(fc -ln -1)"
+}
+autoload -Uz add-zsh-hook
+add-zsh-hook precmd __ai_record_last
+"#;
+
+const FISH_INIT: &str = r#"# ai shell integration, add to ~/.config/fish/config.fish: ai shell-init fish | source
+function __ai_record_last --on-event fish_postexec
+    set -gx AI_LAST_STATUS $status
+    set -gx AI_LAST_COMMAND $argv[1]
+end
+"#;
+
+/// The snippet to eval in the shell's startup file
+pub fn init_snippet(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH_INIT,
+        Shell::Zsh => ZSH_INIT,
+        Shell::Fish => FISH_INIT,
+    }
+}
+
+/// Ctrl-G replaces the command line with the command generated from it, Alt-G appends
+/// the rest of a partly typed command. `--` keeps a buffer starting with a subcommand
+/// name or a dash a prompt
+const BASH_WIDGET: &str = r#"# ai widget, add to ~/.bashrc: eval "$(ai init bash)"
+__ai_widget() {
+    [[ -z "${READLINE_LINE// }" ]] && return
+    local result
+    result="$(ai -q -- "$READLINE_LINE" </dev/null)" || return
+    READLINE_LINE="$result"
+    READLINE_POINT=${#READLINE_LINE}
+}
+__ai_complete_widget() {
+    [[ -z "${READLINE_LINE// }" ]] && return
+    local rest
+    rest="$(ai -q complete -- "$READLINE_LINE" </dev/null)" || return
+    READLINE_LINE="$READLINE_LINE$rest"
+    READLINE_POINT=${#READLINE_LINE}
+}
+bind -x '"\C-g": __ai_widget'
+bind -x '"\eg": __ai_complete_widget'
+"#;
+
+const ZSH_WIDGET: &str = r#"# ai widget, add to ~/.zshrc: eval "$(ai init zsh)"
+__ai_widget() {
+    [[ -z "${BUFFER// }" ]] && return
+    local result
+    result="$(ai -q -- "$BUFFER" </dev/null)"
+    if [[ $? -eq 0 && -n "$result" ]]; then
+        BUFFER="$result"
+        CURSOR=${#BUFFER}
+    fi
+    zle reset-prompt
+}
+__ai_complete_widget() {
+    [[ -z "${BUFFER// }" ]] && return
+    local rest
+    rest="$(ai -q complete -- "$BUFFER" </dev/null)"
+    if [[ $? -eq 0 ]]; then
+        BUFFER="$BUFFER$rest"
+        CURSOR=${#BUFFER}
+    fi
+    zle reset-prompt
+}
+zle -N __ai_widget
+zle -N __ai_complete_widget
+bindkey '^G' __ai_widget
+bindkey '^[g' __ai_complete_widget
+"#;
+
+const FISH_WIDGET: &str = r#"# ai widget, add to ~/.config/fish/config.fish: ai init fish | source
+function __ai_widget
+    set -l buffer (commandline)
+    if string trim -- "$buffer" | string length -q
+        set -l result (ai -q -- "$buffer" </dev/null | string collect)
+        and test -n "$result"
+        and commandline -r -- $result
+    end
+    commandline -f repaint
+end
+function __ai_complete_widget
+    set -l buffer (commandline)
+    if string trim -- "$buffer" | string length -q
+        set -l rest (ai -q complete -- "$buffer" </dev/null | string collect)
+        and commandline -r -- "$buffer$rest"
+    end
+    commandline -f repaint
+end
+bind \cg __ai_widget
+bind \eg __ai_complete_widget
+"#;
+
+/// The widget bindings, followed by the `ai shell-init` snippet so `ai fix` works too
+pub fn widget_snippet(shell: Shell) -> String {
+    let widget = match shell {
+        Shell::Bash => BASH_WIDGET,
+        Shell::Zsh => ZSH_WIDGET,
+        Shell::Fish => FISH_WIDGET,
+    };
+    format!("{}\n{}", widget, init_snippet(shell))
+}
@@ -0,0 +1,419 @@
+use std::sync::{Mutex, Once};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context as _, Result};
+use serde_json::{json, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{info, warn, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::ai_backend::Usage;
+use crate::network;
+use crate::settings::TelemetrySettings;
+
+/// Longest an export may take, the command's output is already printed by then
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Most spans, and most generations, waiting to be exported. Newer ones are dropped once
+/// it's reached, so a collector that can't be reached doesn't grow the buffer without bound
+const MAX_BUFFERED: usize = 2048;
+
+/// Upper bounds in seconds of the generation latency histogram's buckets
+const LATENCY_BOUNDS: &[f64] = &[0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// What is waiting to be exported. Spans are buffered from the start, before the settings
+/// are read, and dropped once they turn out to have telemetry off
+struct Buffer {
+    collecting: bool,
+    settings: Option<TelemetrySettings>,
+    spans: Vec<Value>,
+    metrics: Vec<Generation>,
+    /// Spans and generations dropped for the cap since the last export
+    dropped: usize,
+}
+
+impl Buffer {
+    fn push<T>(items: &mut Vec<T>, dropped: &mut usize, item: T) {
+        if items.len() < MAX_BUFFERED {
+            items.push(item);
+        } else {
+            *dropped += 1;
+        }
+    }
+}
+
+static BUFFER: Mutex<Buffer> = Mutex::new(Buffer {
+    collecting: true,
+    settings: None,
+    spans: Vec::new(),
+    metrics: Vec::new(),
+    dropped: 0,
+});
+
+/// Held for the whole of an export, so the last one at exit waits for a periodic one
+/// still sending rather than the process ending under it
+static EXPORTING: Mutex<()> = Mutex::new(());
+
+/// One generation's measurements
+struct Generation {
+    time: u64,
+    backend: String,
+    model: Option<String>,
+    latency: Duration,
+    usage: Option<Usage>,
+}
+
+/// Turns the export on or off from the settings, the spans buffered so far are kept only
+/// when it's on. When on, a thread exports what's buffered every `export_interval_secs`
+pub fn init(settings: &TelemetrySettings) {
+    {
+        let Ok(mut buffer) = BUFFER.lock() else {
+            return;
+        };
+        buffer.collecting = settings.enabled;
+        if !settings.enabled {
+            buffer.spans.clear();
+            return;
+        }
+        info!("Exporting telemetry to {}", settings.endpoint);
+        buffer.settings = Some(settings.clone());
+    }
+    static EXPORTER: Once = Once::new();
+    let interval = Duration::from_secs(settings.export_interval_secs.max(1));
+    EXPORTER.call_once(|| {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            flush();
+        });
+    });
+}
+
+/// Records the latency and token counts of a generation, a metric data point per run
+pub fn record_generation(
+    backend: &str,
+    model: Option<&str>,
+    latency: Duration,
+    usage: Option<Usage>,
+) {
+    let Ok(mut buffer) = BUFFER.lock() else {
+        return;
+    };
+    if buffer.collecting {
+        let buffer = &mut *buffer;
+        Buffer::push(
+            &mut buffer.metrics,
+            &mut buffer.dropped,
+            Generation {
+                time: unix_nanos(SystemTime::now()),
+                backend: backend.to_string(),
+                model: model.map(str::to_string),
+                latency,
+                usage,
+            },
+        );
+    }
+}
+
+/// Sends the buffered spans and metrics to the collector, emptying the buffer. Failures
+/// are only logged, telemetry never fails a command
+pub fn flush() {
+    let Ok(_exporting) = EXPORTING.lock() else {
+        return;
+    };
+    let (settings, spans, metrics) = {
+        let Ok(mut buffer) = BUFFER.lock() else {
+            return;
+        };
+        let Some(settings) = buffer.settings.clone() else {
+            return;
+        };
+        if buffer.dropped > 0 {
+            warn!(
+                "Dropped {} spans and generations, more than {} were waiting to be exported",
+                buffer.dropped, MAX_BUFFERED
+            );
+            buffer.dropped = 0;
+        }
+        (
+            settings,
+            std::mem::take(&mut buffer.spans),
+            std::mem::take(&mut buffer.metrics),
+        )
+    };
+    let resource = json!({
+        "attributes": [
+            attribute("service.name", json!({ "stringValue": settings.service_name })),
+            attribute("service.version", json!({ "stringValue": env!("CARGO_PKG_VERSION") })),
+        ],
+    });
+    let scope = json!({ "name": "ai", "version": env!("CARGO_PKG_VERSION") });
+    if !spans.is_empty() {
+        let body = json!({
+            "resourceSpans": [{
+                "resource": resource,
+                "scopeSpans": [{ "scope": scope, "spans": spans }],
+            }],
+        });
+        if let Err(e) = export(&settings, "v1/traces", &body) {
+            warn!("{:#}", e);
+        }
+    }
+    if !metrics.is_empty() {
+        let body = json!({
+            "resourceMetrics": [{
+                "resource": resource,
+                "scopeMetrics": [{ "scope": scope, "metrics": metric_data(&metrics) }],
+            }],
+        });
+        if let Err(e) = export(&settings, "v1/metrics", &body) {
+            warn!("{:#}", e);
+        }
+    }
+}
+
+fn export(settings: &TelemetrySettings, path: &str, body: &Value) -> Result<()> {
+    network::ensure_online("Exporting telemetry")?;
+    let url = format!("{}/{}", settings.endpoint.trim_end_matches('/'), path);
+    let agent = network::agent(&url).timeout(EXPORT_TIMEOUT).build();
+    let mut request = agent.post(&url).set("Content-Type", "application/json");
+    for (name, value) in settings.headers.iter() {
+        request = request.set(name, value.expose());
+    }
+    request
+        .send_string(&body.to_string())
+        .with_context(|| format!("Unable to export telemetry to {}", url))?;
+    info!("Exported telemetry to {}", url);
+    Ok(())
+}
+
+/// The generations as delta metrics: a latency histogram and token and request counts,
+/// each data point labeled with the backend and model
+fn metric_data(generations: &[Generation]) -> Vec<Value> {
+    let labels = |generation: &Generation| {
+        let mut labels = vec![attribute(
+            "backend",
+            json!({ "stringValue": generation.backend }),
+        )];
+        if let Some(model) = &generation.model {
+            labels.push(attribute("model", json!({ "stringValue": model })));
+        }
+        labels
+    };
+    let counter = |name: &str, unit: &str, value: fn(&Generation) -> usize| {
+        let points = generations
+            .iter()
+            .map(|generation| {
+                json!({
+                    "attributes": labels(generation),
+                    "timeUnixNano": generation.time.to_string(),
+                    "asInt": value(generation).to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "name": name,
+            "unit": unit,
+            // delta temporality, every run reports only its own counts
+            "sum": { "dataPoints": points, "aggregationTemporality": 1, "isMonotonic": true },
+        })
+    };
+    let latency = generations
+        .iter()
+        .map(|generation| {
+            let seconds = generation.latency.as_secs_f64();
+            let bucket = LATENCY_BOUNDS
+                .iter()
+                .position(|bound| seconds <= *bound)
+                .unwrap_or(LATENCY_BOUNDS.len());
+            let mut counts = vec!["0".to_string(); LATENCY_BOUNDS.len() + 1];
+            counts[bucket] = "1".to_string();
+            json!({
+                "attributes": labels(generation),
+                "timeUnixNano": generation.time.to_string(),
+                "count": "1",
+                "sum": seconds,
+                "bucketCounts": counts,
+                "explicitBounds": LATENCY_BOUNDS,
+            })
+        })
+        .collect::<Vec<_>>();
+    vec![
+        json!({
+            "name": "ai.generation.duration",
+            "unit": "s",
+            "histogram": { "dataPoints": latency, "aggregationTemporality": 1 },
+        }),
+        counter("ai.generation.requests", "1", |_| 1),
+        counter("ai.generation.prompt_tokens", "{token}", |generation| {
+            generation
+                .usage
+                .as_ref()
+                .map_or(0, |usage| usage.prompt_tokens)
+        }),
+        counter("ai.generation.completion_tokens", "{token}", |generation| {
+            generation
+                .usage
+                .as_ref()
+                .map_or(0, |usage| usage.completion_tokens)
+        }),
+    ]
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64)
+}
+
+/// Hex id of `bytes` random bytes, OTLP/JSON writes trace and span ids in hex
+fn random_id(bytes: usize) -> String {
+    (0..bytes)
+        .map(|_| format!("{:02x}", rand::random::<u8>()))
+        .collect()
+}
+
+/// A span that hasn't closed yet
+struct OpenSpan {
+    trace_id: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+    start: u64,
+    attributes: Vec<Value>,
+}
+
+/// Collects closed spans in the OTLP/JSON format. Every root span starts a trace, so a
+/// long running process sends one trace per request
+pub struct OtlpLayer;
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !BUFFER.lock().is_ok_and(|buffer| buffer.collecting) {
+            return;
+        }
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<OpenSpan>()
+                .map(|open| (open.trace_id.clone(), open.span_id.clone()))
+        });
+        let (trace_id, parent_span_id) = match parent {
+            Some((trace_id, span_id)) => (trace_id, Some(span_id)),
+            None => (random_id(16), None),
+        };
+        let mut open = OpenSpan {
+            trace_id,
+            span_id: random_id(8),
+            parent_span_id,
+            start: unix_nanos(SystemTime::now()),
+            attributes: Vec::new(),
+        };
+        attrs.record(&mut SpanFields(&mut open.attributes));
+        span.extensions_mut().insert(open);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(open) = span.extensions_mut().get_mut::<OpenSpan>() {
+                values.record(&mut SpanFields(&mut open.attributes));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(open) = span.extensions_mut().remove::<OpenSpan>() else {
+            return;
+        };
+        let mut exported = json!({
+            "traceId": open.trace_id,
+            "spanId": open.span_id,
+            "name": span.name(),
+            // internal, the spans are phases of this process
+            "kind": 1,
+            "startTimeUnixNano": open.start.to_string(),
+            "endTimeUnixNano": unix_nanos(SystemTime::now()).to_string(),
+            "attributes": open.attributes,
+        });
+        if let Some(parent) = open.parent_span_id {
+            exported["parentSpanId"] = json!(parent);
+        }
+        if let Ok(mut buffer) = BUFFER.lock() {
+            if buffer.collecting {
+                let buffer = &mut *buffer;
+                Buffer::push(&mut buffer.spans, &mut buffer.dropped, exported);
+            }
+        }
+    }
+}
+
+/// Records span fields as OTLP attributes
+struct SpanFields<'a>(&'a mut Vec<Value>);
+
+impl Visit for SpanFields<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push(attribute(
+            field.name(),
+            json!({ "intValue": value.to_string() }),
+        ));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push(attribute(
+            field.name(),
+            json!({ "intValue": value.to_string() }),
+        ));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0
+            .push(attribute(field.name(), json!({ "doubleValue": value })));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0
+            .push(attribute(field.name(), json!({ "boolValue": value })));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0
+            .push(attribute(field.name(), json!({ "stringValue": value })));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.push(attribute(
+            field.name(),
+            json!({ "stringValue": format!("{:?}", value) }),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_buffer_drops_what_is_over_the_cap() {
+        let mut items = Vec::new();
+        let mut dropped = 0;
+        for i in 0..MAX_BUFFERED + 3 {
+            Buffer::push(&mut items, &mut dropped, i);
+        }
+        assert_eq!(items.len(), MAX_BUFFERED);
+        assert_eq!(items.last(), Some(&(MAX_BUFFERED - 1)));
+        assert_eq!(dropped, 3);
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::messages::Message;
+use crate::settings::config_dir;
+
+/// File extension used for stored templates
+const TEMPLATE_EXTENSION: &str = "txt";
+
+/// A directory of named prompt templates, one file per template.
+/// Templates reference variables with `{{name}}` which are filled in at run time.
+pub struct TemplateStore {
+    dir: PathBuf,
+}
+
+impl TemplateStore {
+    /// Opens the template store under the config directory, ~/.config/ai/templates
+    pub fn open() -> Result<Self> {
+        Self::new(config_dir().join("templates"))
+    }
+
+    /// Opens a template store in the given directory, creating it if needed
+    pub fn new(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Unable to create template directory {:?}", dir))?;
+        Ok(Self { dir })
+    }
+
+    fn path_for(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!("{}", Message::InvalidTemplateName(name));
+        }
+        Ok(self.dir.join(name).with_extension(TEMPLATE_EXTENSION))
+    }
+
+    /// Saves a template, replacing any existing template with the same name
+    pub fn add(&self, name: &str, template: &str) -> Result<()> {
+        let path = self.path_for(name)?;
+        std::fs::write(&path, template)
+            .with_context(|| format!("Unable to write template {:?}", path))
+    }
+
+    /// Reads the raw template body
+    pub fn get(&self, name: &str) -> Result<String> {
+        let path = self.path_for(name)?;
+        if !path.exists() {
+            anyhow::bail!("{}", Message::NoTemplate(name));
+        }
+        Ok(std::fs::read_to_string(path)?)
+    }
+
+    /// Lists all templates as (name, body) pairs sorted by name
+    pub fn list(&self) -> Result<Vec<(String, String)>> {
+        let mut templates = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(TEMPLATE_EXTENSION) {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                templates.push((name.to_string(), std::fs::read_to_string(&path)?));
+            }
+        }
+        templates.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(templates)
+    }
+
+    /// Deletes a template
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let path = self.path_for(name)?;
+        if !path.exists() {
+            anyhow::bail!("{}", Message::NoTemplate(name));
+        }
+        Ok(std::fs::remove_file(path)?)
+    }
+}
+
+/// Substitutes every `{{name}}` in the template with its value.
+/// Errors if the template references a variable that wasn't provided.
+pub fn render(template: &str, vars: &HashMap<String, String>) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| anyhow::anyhow!("{}", Message::UnclosedTemplateVariable))?;
+        let name = after[..end].trim();
+        match vars.get(name) {
+            Some(value) => output.push_str(value),
+            None => anyhow::bail!("{}", Message::MissingTemplateValue(name)),
+        }
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// The `{{name}}` placeholders in a generated command, each once, in order. Only plain
+/// names count, so a Go template such as docker's `{{.Names}}` isn't taken for one
+pub fn placeholders(command: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut rest = command;
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start + 2..];
+        let Some(end) = rest.find("}}") else {
+            break;
+        };
+        let name = &rest[..end];
+        if is_placeholder(name) && !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// Replaces the placeholders that have a value, leaving everything else as it is
+pub fn fill(command: &str, values: &HashMap<String, String>) -> String {
+    let mut output = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after
+            .find("}}")
+            .and_then(|end| Some((values.get(&after[..end])?, end)));
+        match value {
+            Some((value, end)) => {
+                output.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                output.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+fn is_placeholder(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parses `--name value` and `--name=value` pairs into a variable map
+pub fn parse_vars(args: &[String]) -> Result<HashMap<String, String>> {
+    let mut vars = HashMap::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let Some(key) = arg.strip_prefix("--") else {
+            anyhow::bail!("{}", Message::ExpectedNameValue(arg));
+        };
+        match key.split_once('=') {
+            Some((key, value)) => {
+                vars.insert(key.to_string(), value.to_string());
+            }
+            None => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("{}", Message::MissingFlagValue(key)))?;
+                vars.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+    Ok(vars)
+}
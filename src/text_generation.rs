@@ -1,32 +1,82 @@
+use crate::ai_backend::common::{cutoff, Cutoff};
+use crate::ai_backend::Usage;
+use crate::grammar::{Constrained, Grammar, Vocab};
+use crate::logging::NOTICE;
+use crate::messages::Message;
+use crate::quantized_mixformer::MixFormerSequentialForCausalLM as QMixFormer;
+use crate::sharded;
 use crate::token_output_stream;
 
+use std::time::Instant;
+
 use anyhow::{Error as E, Result};
-use candle_core::{DType, Device, IndexOp, Tensor};
+use candle_core::{DType, Device, IndexOp, Tensor, D};
 use candle_transformers::generation::LogitsProcessor;
 // use candle_transformers::models::mixformer::MixFormerSequentialForCausalLM as MixFormer;
 use candle_transformers::models::phi::Model as Phi;
 use candle_transformers::models::phi3::Model as Phi3;
-use candle_transformers::models::quantized_mixformer::MixFormerSequentialForCausalLM as QMixFormer;
 use token_output_stream::TokenOutputStream;
 use tokenizers::Tokenizer;
 use tokio::io::AsyncWriteExt;
-use tracing::{debug, info};
+use tracing::{debug, info, info_span, trace_span, warn};
 
+/// A loaded local model
 pub enum Model {
     // MixFormer(MixFormer),
     Phi(Phi),
     Phi3(Phi3),
     Quantized(QMixFormer),
+    /// Phi-2 or Phi-3 with its layers on several devices
+    ShardedPhi(sharded::phi::Model),
+    ShardedPhi3(sharded::phi3::Model),
+}
+
+/// A small model that proposes the next few tokens for the main model to check in a single
+/// pass (speculative decoding). It has to use the same tokenizer as the main model
+pub struct Draft {
+    model: QMixFormer,
+    /// How many tokens to propose at a time
+    tokens: usize,
+}
+
+impl Draft {
+    pub fn new(model: QMixFormer, tokens: usize) -> Self {
+        Self {
+            model,
+            tokens: tokens.max(1),
+        }
+    }
 }
 
+/// Samples text from a local model token by token
 pub struct TextGeneration {
     model: Model,
     device: Device,
     tokenizer: TokenOutputStream,
     logits_processor: LogitsProcessor,
+    temp: Option<f64>,
+    top_p: Option<f64>,
     repeat_penalty: f32,
     repeat_last_n: usize,
     verbose_prompt: bool,
+    /// Writes the prompt in front of the generated text, see `set_echo_prompt`
+    echo_prompt: bool,
+    /// Bytes at the start of the prompt left out of the echo, see `set_echo_skip`
+    echo_skip: usize,
+    /// Tokens that end the output, see `set_stop_tokens`
+    stop_tokens: Vec<u32>,
+    /// Ends the output at the end of the command line, see `set_stop_at_newline`
+    stop_at_newline: bool,
+    /// Generation stops at this point or on Ctrl-C, keeping the tokens produced so far
+    deadline: Option<Instant>,
+    /// Proposes tokens for the model to verify, only used with the quantized model
+    draft: Option<Draft>,
+    /// Token sequences that are never completed, see `set_banned_strings`
+    banned: Vec<Vec<u32>>,
+    /// The output has to be valid under this grammar
+    grammar: Option<Box<dyn Grammar>>,
+    /// Text of every token, decoded once for checking tokens against the grammar
+    vocab: Vocab,
 }
 
 impl TextGeneration {
@@ -47,11 +97,129 @@ impl TextGeneration {
             model,
             tokenizer: TokenOutputStream::new(tokenizer),
             logits_processor,
+            temp,
+            top_p,
             repeat_penalty,
             repeat_last_n,
             verbose_prompt,
             device: device.clone(),
+            echo_prompt: true,
+            echo_skip: 0,
+            stop_tokens: Vec::new(),
+            stop_at_newline: false,
+            deadline: None,
+            draft: None,
+            banned: Vec::new(),
+            grammar: None,
+            vocab: Vocab::new(Vec::new()),
+        }
+    }
+
+    /// Whether the output starts with the prompt, which phi-2's completions continue. The
+    /// prompt is never echoed in front of constrained output
+    pub fn set_echo_prompt(&mut self, echo_prompt: bool) {
+        self.echo_prompt = echo_prompt;
+    }
+
+    /// Leaves the first `skip` bytes of the prompt out of the echo, the system prompt in
+    /// front of a plain prompt isn't part of the answer
+    pub fn set_echo_skip(&mut self, skip: usize) {
+        self.echo_skip = skip;
+    }
+
+    /// Ends the output at any of these tokens, the model's end of text and end of turn
+    pub fn set_stop_tokens(&mut self, stop_tokens: Vec<u32>) {
+        self.stop_tokens = stop_tokens;
+    }
+
+    /// Ends unconstrained output at the first newline not escaped by a backslash once the
+    /// command has started, so a one-liner isn't followed by an explanation
+    pub fn set_stop_at_newline(&mut self, stop_at_newline: bool) {
+        self.stop_at_newline = stop_at_newline;
+    }
+
+    /// Stops generation at `deadline`, the output so far is kept
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Generates with speculative decoding, the draft proposes tokens that the model
+    /// verifies in one forward pass. The output is the same as without a draft
+    pub fn set_draft(&mut self, draft: Option<Draft>) -> Result<()> {
+        if draft.is_some() && !matches!(self.model, Model::Quantized(_)) {
+            anyhow::bail!("{}", Message::DraftNeedsQuantized);
         }
+        self.draft = draft;
+        Ok(())
+    }
+
+    /// Never generates these strings: the last token of each is suppressed whenever the
+    /// tokens before it were just generated. Strings are matched as the tokenizer spells
+    /// them on their own and after a space, other spellings aren't caught
+    pub fn set_banned_strings(&mut self, banned: &[String]) -> Result<()> {
+        self.banned.clear();
+        for string in banned.iter().filter(|string| !string.is_empty()) {
+            for spelling in [string.clone(), format!(" {string}")] {
+                let ids = self
+                    .tokenizer
+                    .tokenizer()
+                    .encode(spelling, false)
+                    .map_err(E::msg)?
+                    .get_ids()
+                    .to_vec();
+                if !ids.is_empty() && !self.banned.contains(&ids) {
+                    debug!("Banning {string:?} as tokens {ids:?}");
+                    self.banned.push(ids);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Only generates output the grammar accepts. The output is held back until the end
+    /// and cut back to where it was last complete if generation stops early, the prompt
+    /// isn't echoed in front of it
+    pub fn set_grammar(&mut self, grammar: Option<Box<dyn Grammar>>) -> Result<()> {
+        if grammar.is_some() && self.vocab.is_empty() {
+            let tokenizer = self.tokenizer.tokenizer();
+            // decoded after another token, on its own sentencepiece drops a word's leading space
+            let anchor = *tokenizer
+                .encode("a", false)
+                .map_err(E::msg)?
+                .get_ids()
+                .last()
+                .ok_or_else(|| E::msg(Message::TokenizerCantEncode.to_string()))?;
+            let anchor_text = tokenizer.decode(&[anchor], false).map_err(E::msg)?;
+            let texts = (0..tokenizer.get_vocab_size(true) as u32)
+                .map(|id| {
+                    let text = tokenizer.decode(&[anchor, id], false).map_err(E::msg)?;
+                    match text.strip_prefix(&anchor_text) {
+                        Some(text) => Ok(text.to_string()),
+                        None => tokenizer.decode(&[id], false).map_err(E::msg),
+                    }
+                })
+                .collect::<Result<_>>()?;
+            self.vocab = Vocab::new(texts);
+        }
+        self.grammar = grammar;
+        Ok(())
+    }
+
+    /// Clears the kv cache and token stream and reseeds the sampler so the
+    /// pipeline can generate a fresh response without reloading the model
+    pub fn reset(&mut self, seed: u64) {
+        match &mut self.model {
+            Model::Phi(m) => m.clear_kv_cache(),
+            Model::Phi3(m) => m.clear_kv_cache(),
+            Model::Quantized(m) => m.clear_kv_cache(),
+            Model::ShardedPhi(m) => m.clear_kv_cache(),
+            Model::ShardedPhi3(m) => m.clear_kv_cache(),
+        }
+        if let Some(draft) = &mut self.draft {
+            draft.model.clear_kv_cache();
+        }
+        self.tokenizer.clear();
+        self.logits_processor = LogitsProcessor::new(seed, self.temp, self.top_p);
     }
 
     /// Async runs the text generation model on the given prompt for a specified number of tokens
@@ -60,7 +228,14 @@ impl TextGeneration {
     /// * `prompt` - The input text prompt to generate from
     /// * `sample_len` - Maximum number of tokens to generate
     /// * `stream` - An async channel or stream to send generated tokens
-    pub async fn run<S>(&mut self, prompt: &str, sample_len: usize, stream: &mut S) -> Result<()>
+    ///
+    /// Returns the token counts and generation time, and why it stopped early if it did
+    pub async fn run<S>(
+        &mut self,
+        prompt: &str,
+        sample_len: usize,
+        stream: &mut S,
+    ) -> Result<(Usage, Option<Cutoff>)>
     where
         S: tokio::io::AsyncWrite + Unpin,
     {
@@ -73,39 +248,69 @@ impl TextGeneration {
         debug!("Encoded tokens: {tokens:?}");
         // Check for empty prompts which are not supported
         if tokens.is_empty() {
-            anyhow::bail!("Empty prompts are not supported in the phi model.")
+            anyhow::bail!("{}", Message::PromptEmpty)
         }
 
         // Print verbose token information if enabled
         if self.verbose_prompt {
             for (token, id) in tokens.get_tokens().iter().zip(tokens.get_ids().iter()) {
                 let token = token.replace('▁', " ").replace("<0x0A>", "\n");
-                println!("{id:7} -> '{token}'");
+                info!(target: NOTICE, "{id:7} -> '{token}'");
             }
         }
 
         // Initialize token tracking
         let mut tokens = tokens.get_ids().to_vec();
+        let prompt_tokens = tokens.len();
         let mut generated_tokens = 0usize;
 
-        // Get the end of text token
-        let eos_token = match self.tokenizer.get_token("<|endoftext|>") {
-            Some(token) => token,
-            None => anyhow::bail!("cannot find the endoftext token"),
-        };
+        if self.stop_tokens.is_empty() {
+            anyhow::bail!("{}", Message::NoStopToken)
+        }
+        let stop_tokens = self.stop_tokens.clone();
 
-        // Write initial prompt to stream
-        stream.write_all(prompt.as_bytes()).await?;
+        // Write initial prompt to stream, constrained output is the generated text alone
+        if self.grammar.is_none() && self.echo_prompt {
+            let echoed = prompt.get(self.echo_skip..).unwrap_or(prompt);
+            stream.write_all(echoed.as_bytes()).await?;
+        }
+
+        if self.draft.is_some() {
+            return self
+                .run_speculative(tokens, &stop_tokens, sample_len, stream)
+                .await;
+        }
+
+        let mut constrained = self
+            .grammar
+            .as_ref()
+            .map(|grammar| Constrained::new(grammar.boxed_clone()));
+        let mut end_of_line = self.stop_at_newline.then(EndOfLine::default);
 
         // Track generation time and position
         let start_gen = std::time::Instant::now();
         let mut pos = 0;
+        let mut stopped = None;
+        let mut time_to_first_token = None;
 
         // Main generation loop
         for index in 0..sample_len {
+            if let Some(stop) = cutoff(self.deadline) {
+                warn!("Stopping generation ({stop:?}) after {generated_tokens} tokens");
+                write_rest(&self.tokenizer, &mut constrained, stream).await?;
+                stopped = Some(stop);
+                break;
+            }
             // Get context size - full context for first iteration, single token after
             let context_size = if index > 0 { 1 } else { tokens.len() };
             let ctxt = &tokens[tokens.len().saturating_sub(context_size)..];
+            // the first pass runs the whole prompt through the model, then one per token
+            let step = if index == 0 {
+                info_span!("prefill", tokens = context_size)
+            } else {
+                trace_span!("token", index)
+            };
+            let step = step.enter();
 
             // Prepare input tensor
             let input = Tensor::new(ctxt, &self.device)?.unsqueeze(0)?;
@@ -116,42 +321,61 @@ impl TextGeneration {
                 Model::Phi(m) => m.forward(&input)?,
                 Model::Quantized(m) => m.forward(&input)?,
                 Model::Phi3(m) => m.forward(&input, pos)?.i((.., 0, ..))?,
+                Model::ShardedPhi(m) => m.forward(&input)?,
+                Model::ShardedPhi3(m) => m.forward(&input, pos)?.i((.., 0, ..))?,
             };
 
             // Process logits
             let logits = logits.squeeze(0)?.to_dtype(DType::F32)?;
 
             // Apply repeat penalty if configured
-            let logits = if self.repeat_penalty == 1. {
-                logits
-            } else {
-                let start_at = tokens.len().saturating_sub(self.repeat_last_n);
-                candle_transformers::utils::apply_repeat_penalty(
-                    &logits,
-                    self.repeat_penalty,
-                    &tokens[start_at..],
-                )?
-            };
+            let logits = repeat_penalty(logits, self.repeat_penalty, self.repeat_last_n, &tokens)?;
+            let logits = suppress_banned(logits, &self.banned, &tokens)?;
 
             // Sample next token
-            let next_token = self.logits_processor.sample(&logits)?;
+            let next_token = match &constrained {
+                Some(constrained) => constrained.sample(
+                    &mut self.logits_processor,
+                    &logits,
+                    &self.vocab,
+                    &stop_tokens,
+                )?,
+                None => self.logits_processor.sample(&logits)?,
+            };
+            drop(step);
             tokens.push(next_token);
             generated_tokens += 1;
+            time_to_first_token = time_to_first_token.or_else(|| Some(start_gen.elapsed()));
 
             // Check for end of text
-            if next_token == eos_token {
-                if let Some(t) = self.tokenizer.decode_rest()? {
-                    stream.write_all(t.as_bytes()).await?;
-                }
+            if stop_tokens.contains(&next_token) {
+                write_rest(&self.tokenizer, &mut constrained, stream).await?;
                 break;
             }
 
             // Write generated token to stream
-            if let Some(t) = self.tokenizer.next_token(next_token)? {
-                stream.write_all(t.as_bytes()).await?;
+            if let Some(constrained) = &mut constrained {
+                constrained.push(&self.tokenizer.next_text(next_token)?);
+                if constrained.is_finished() {
+                    break;
+                }
+            } else if let Some(t) = self.tokenizer.next_token(next_token)? {
+                let end = end_of_line.as_mut().and_then(|line| line.find(&t));
+                stream
+                    .write_all(&t.as_bytes()[..end.unwrap_or(t.len())])
+                    .await?;
+                if end.is_some() {
+                    debug!("Stopping at the end of the command line");
+                    break;
+                }
             }
             pos += context_size;
         }
+        if let Some(constrained) = constrained {
+            stream
+                .write_all(constrained.into_output().as_bytes())
+                .await?;
+        }
 
         // Flush the stream to ensure all data is written
         stream.flush().await?;
@@ -162,6 +386,239 @@ impl TextGeneration {
             "\n{generated_tokens} tokens generated ({:.2} token/s)",
             generated_tokens as f64 / dt.as_secs_f64(),
         );
-        Ok(())
+        Ok((
+            Usage {
+                prompt_tokens,
+                completion_tokens: generated_tokens,
+                duration: dt,
+                cost: None,
+                time_to_first_token,
+                ..Default::default()
+            },
+            stopped,
+        ))
+    }
+
+    /// The generation loop with a draft model: the draft greedily proposes a few tokens, the
+    /// model scores them all at once and samples its own token at each position. Proposals
+    /// are kept up to the first one the model disagrees with, whose place the model's token
+    /// takes, so every round yields at least one token and the output matches plain sampling
+    async fn run_speculative<S>(
+        &mut self,
+        mut tokens: Vec<u32>,
+        stop_tokens: &[u32],
+        sample_len: usize,
+        stream: &mut S,
+    ) -> Result<(Usage, Option<Cutoff>)>
+    where
+        S: tokio::io::AsyncWrite + Unpin,
+    {
+        let (Model::Quantized(model), Some(draft)) = (&mut self.model, &mut self.draft) else {
+            anyhow::bail!("{}", Message::DraftNeedsQuantized);
+        };
+        let mut constrained = self
+            .grammar
+            .as_ref()
+            .map(|grammar| Constrained::new(grammar.boxed_clone()));
+        let mut end_of_line = self.stop_at_newline.then(EndOfLine::default);
+        let prompt_tokens = tokens.len();
+        let mut generated_tokens = 0usize;
+        let mut proposed_tokens = 0usize;
+        let mut accepted_tokens = 0usize;
+        let start_gen = std::time::Instant::now();
+        let mut stopped = None;
+        let mut time_to_first_token = None;
+
+        'generation: while generated_tokens < sample_len {
+            if let Some(stop) = cutoff(self.deadline) {
+                warn!("Stopping generation ({stop:?}) after {generated_tokens} tokens");
+                write_rest(&self.tokenizer, &mut constrained, stream).await?;
+                stopped = Some(stop);
+                break;
+            }
+            let proposing = trace_span!("draft", generated = generated_tokens).entered();
+            let mut proposals = Vec::with_capacity(draft.tokens);
+            while proposals.len() < draft.tokens.min(sample_len - generated_tokens) {
+                let context = [&tokens[..], &proposals[..]].concat();
+                let input =
+                    Tensor::new(&context[draft.model.kv_len()..], &self.device)?.unsqueeze(0)?;
+                let logits = draft.model.forward(&input)?.squeeze(0)?;
+                let proposal = logits.argmax(D::Minus1)?.to_scalar::<u32>()?;
+                proposals.push(proposal);
+                if stop_tokens.contains(&proposal) {
+                    break;
+                }
+            }
+            proposing.exit();
+            proposed_tokens += proposals.len();
+
+            // Row i of the logits predicts the token following input[i]
+            let cached = model.kv_len();
+            let input = [&tokens[cached..], &proposals[..]].concat();
+            let verify = if cached == 0 {
+                info_span!("prefill", tokens = input.len())
+            } else {
+                trace_span!("verify", proposals = proposals.len())
+            };
+            let logits = verify.in_scope(|| -> Result<Tensor> {
+                Ok(model
+                    .forward_all(&Tensor::new(input.as_slice(), &self.device)?.unsqueeze(0)?)?
+                    .squeeze(0)?
+                    .to_dtype(DType::F32)?)
+            })?;
+            let first = tokens.len() - cached - 1;
+            for (i, proposal) in proposals.iter().map(Some).chain([None]).enumerate() {
+                let logits = repeat_penalty(
+                    logits.get(first + i)?,
+                    self.repeat_penalty,
+                    self.repeat_last_n,
+                    &tokens,
+                )?;
+                let logits = suppress_banned(logits, &self.banned, &tokens)?;
+                let next_token = match &constrained {
+                    Some(constrained) => constrained.sample(
+                        &mut self.logits_processor,
+                        &logits,
+                        &self.vocab,
+                        stop_tokens,
+                    )?,
+                    None => self.logits_processor.sample(&logits)?,
+                };
+                tokens.push(next_token);
+                generated_tokens += 1;
+                time_to_first_token = time_to_first_token.or_else(|| Some(start_gen.elapsed()));
+                if stop_tokens.contains(&next_token) {
+                    write_rest(&self.tokenizer, &mut constrained, stream).await?;
+                    break 'generation;
+                }
+                if let Some(constrained) = &mut constrained {
+                    constrained.push(&self.tokenizer.next_text(next_token)?);
+                    if constrained.is_finished() {
+                        break 'generation;
+                    }
+                } else if let Some(t) = self.tokenizer.next_token(next_token)? {
+                    let end = end_of_line.as_mut().and_then(|line| line.find(&t));
+                    stream
+                        .write_all(&t.as_bytes()[..end.unwrap_or(t.len())])
+                        .await?;
+                    if end.is_some() {
+                        debug!("Stopping at the end of the command line");
+                        break 'generation;
+                    }
+                }
+                if proposal != Some(&next_token) || generated_tokens >= sample_len {
+                    break;
+                }
+                accepted_tokens += 1;
+            }
+            // Forget the rejected proposals, the last token is fed in the next round
+            model.truncate_kv_cache(tokens.len() - 1)?;
+            draft.model.truncate_kv_cache(tokens.len() - 1)?;
+        }
+        if let Some(constrained) = constrained {
+            stream
+                .write_all(constrained.into_output().as_bytes())
+                .await?;
+        }
+
+        stream.flush().await?;
+
+        let dt = start_gen.elapsed();
+        info!(
+            "\n{generated_tokens} tokens generated ({:.2} token/s), {accepted_tokens} of {proposed_tokens} draft tokens accepted",
+            generated_tokens as f64 / dt.as_secs_f64(),
+        );
+        Ok((
+            Usage {
+                prompt_tokens,
+                completion_tokens: generated_tokens,
+                duration: dt,
+                cost: None,
+                time_to_first_token,
+                ..Default::default()
+            },
+            stopped,
+        ))
+    }
+}
+
+/// Finds where a one-liner ends in the output as it's generated: the first newline once the
+/// command has started, unless a backslash continues the line. Blank lines and the opening
+/// of a code fence before the command don't end it
+#[derive(Default)]
+struct EndOfLine {
+    /// The line generated so far
+    line: String,
+}
+
+impl EndOfLine {
+    /// Where in the next piece of output the command line ends, if it does
+    fn find(&mut self, text: &str) -> Option<usize> {
+        for (i, c) in text.char_indices() {
+            if c != '\n' {
+                self.line.push(c);
+                continue;
+            }
+            let line = self.line.trim();
+            let command = !line.is_empty() && !line.starts_with("```");
+            if command && !line.ends_with('\\') {
+                return Some(i);
+            }
+            self.line.clear();
+        }
+        None
+    }
+}
+
+/// Writes the text the token stream still holds back, to the constrained output when there
+/// is one since that's written once generation ends
+async fn write_rest<S>(
+    tokenizer: &TokenOutputStream,
+    constrained: &mut Option<Constrained>,
+    stream: &mut S,
+) -> Result<()>
+where
+    S: tokio::io::AsyncWrite + Unpin,
+{
+    if let Some(text) = tokenizer.decode_rest()? {
+        match constrained {
+            Some(constrained) => constrained.push(&text),
+            None => stream.write_all(text.as_bytes()).await?,
+        }
+    }
+    Ok(())
+}
+
+/// Penalizes the logits of tokens among the last `last_n`, unless the penalty is 1
+fn repeat_penalty(logits: Tensor, penalty: f32, last_n: usize, tokens: &[u32]) -> Result<Tensor> {
+    if penalty == 1. {
+        return Ok(logits);
+    }
+    let start_at = tokens.len().saturating_sub(last_n);
+    Ok(candle_transformers::utils::apply_repeat_penalty(
+        &logits,
+        penalty,
+        &tokens[start_at..],
+    )?)
+}
+
+/// Sets the logits of tokens that would complete a banned sequence to -inf
+fn suppress_banned(logits: Tensor, banned: &[Vec<u32>], tokens: &[u32]) -> Result<Tensor> {
+    let suppressed: Vec<u32> = banned
+        .iter()
+        .filter_map(|sequence| {
+            let (last, prefix) = sequence.split_last()?;
+            tokens.ends_with(prefix).then_some(*last)
+        })
+        .collect();
+    if suppressed.is_empty() {
+        return Ok(logits);
+    }
+    let mut values = logits.to_vec1::<f32>()?;
+    for token in suppressed {
+        if let Some(value) = values.get_mut(token as usize) {
+            *value = f32::NEG_INFINITY;
+        }
     }
+    Ok(Tensor::new(values, logits.device())?)
 }
@@ -1,5 +1,7 @@
 use candle_core::Result;
 
+use crate::messages::Message;
+
 /// This is a wrapper around a tokenizer to ensure that tokens can be returned to the user in a
 /// streaming way rather than having to wait for the full decoding.
 /// The struct maintains state about the current tokenization process including:
@@ -34,7 +36,7 @@ impl TokenOutputStream {
     fn decode(&self, tokens: &[u32]) -> Result<String> {
         match self.tokenizer.decode(tokens, true) {
             Ok(str) => Ok(str),
-            Err(err) => candle_core::bail!("cannot decode: {err}"),
+            Err(err) => candle_core::bail!("{}", Message::CannotDecode(&err.to_string())),
         }
     }
 
@@ -63,6 +65,28 @@ impl TokenOutputStream {
         }
     }
 
+    /// Processes the next token like `next_token`, but returns all the text it adds right
+    /// away rather than waiting for a word to end. Only text ending in part of a character,
+    /// decoded as U+FFFD, is held back until the rest of the character arrives
+    pub fn next_text(&mut self, token: u32) -> Result<String> {
+        let prev_text = if self.tokens.is_empty() {
+            String::new()
+        } else {
+            let tokens = &self.tokens[self.prev_index..self.current_index];
+            self.decode(tokens)?
+        };
+        self.tokens.push(token);
+        let text = self.decode(&self.tokens[self.prev_index..])?;
+        if text.len() > prev_text.len() && !text.ends_with('\u{FFFD}') {
+            let text = text.split_at(prev_text.len());
+            self.prev_index = self.current_index;
+            self.current_index = self.tokens.len();
+            Ok(text.1.to_string())
+        } else {
+            Ok(String::new())
+        }
+    }
+
     /// Decodes any remaining tokens that haven't formed complete words yet
     pub fn decode_rest(&self) -> Result<Option<String>> {
         let prev_text = if self.tokens.is_empty() {
@@ -85,20 +109,20 @@ impl TokenOutputStream {
     //     self.decode(&self.tokens)
     // }
 
-    /// Looks up the token ID for a given string in the vocabulary
-    pub fn get_token(&self, token_s: &str) -> Option<u32> {
-        self.tokenizer.get_vocab(true).get(token_s).copied()
-    }
+    // /// Looks up the token ID for a given string in the vocabulary
+    // pub fn get_token(&self, token_s: &str) -> Option<u32> {
+    //     self.tokenizer.get_vocab(true).get(token_s).copied()
+    // }
 
     /// Returns a reference to the underlying tokenizer
     pub fn tokenizer(&self) -> &tokenizers::Tokenizer {
         &self.tokenizer
     }
 
-    // /// Resets the stream state by clearing tokens and indices
-    // pub fn clear(&mut self) {
-    //     self.tokens.clear();
-    //     self.prev_index = 0;
-    //     self.current_index = 0;
-    // }
+    /// Resets the stream state by clearing tokens and indices
+    pub fn clear(&mut self) {
+        self.tokens.clear();
+        self.prev_index = 0;
+        self.current_index = 0;
+    }
 }
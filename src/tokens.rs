@@ -0,0 +1,34 @@
+use std::time::Instant;
+
+use anyhow::{Error as E, Result};
+
+use crate::ai_backend::{ChatMessage, LocalAiBackend};
+use crate::messages::Message;
+use crate::settings::Settings;
+
+/// Counts the prompt's tokens with the local model's tokenizer, without loading the
+/// weights, and warns when they and `sample_len` don't fit in the model's context.
+/// With `breakdown` every token is listed first, as `verbose_prompt` does
+pub fn count(settings: Settings, prompt: &str, breakdown: bool) -> Result<()> {
+    let sample_len = settings.local_model_config.sample_len;
+    let context_length = settings.local_model_config.model.context_length();
+    let backend = LocalAiBackend::new(settings, String::new(), Instant::now());
+    let tokenizer = backend.load_tokenizer()?;
+    // as the model sees it, in its chat template
+    let prompt = backend
+        .chat_template()
+        .format("", &[ChatMessage::user(prompt)]);
+    let encoding = tokenizer.encode(prompt, true).map_err(E::msg)?;
+    if breakdown {
+        for (token, id) in encoding.get_tokens().iter().zip(encoding.get_ids()) {
+            let token = token.replace('▁', " ").replace("<0x0A>", "\n");
+            println!("{id:7} -> '{token}'");
+        }
+    }
+    let tokens = encoding.get_ids().len();
+    println!("{}", Message::TokenCount(tokens, sample_len));
+    if tokens + sample_len > context_length {
+        eprintln!("{}", Message::ExceedsContext(context_length));
+    }
+    Ok(())
+}
@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Text};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use tracing::{info, warn};
+
+use crate::ai_backend::{AiBackend, ChatMessage};
+use crate::context::PromptBuilder;
+use crate::history::{GenerationParams, History, HistoryEntry};
+use crate::interact;
+use crate::interrupt;
+use crate::messages::Message;
+use crate::prompts::Prompt;
+use crate::render;
+
+/// Most columns the history sidebar takes, it gets a third of narrower terminals
+const SIDEBAR_WIDTH: u16 = 32;
+
+/// Lines PageUp and PageDown scroll the response by
+const PAGE: usize = 10;
+
+/// How often the keys are read while a response is generated
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What a key pressed in the main loop does
+enum Action {
+    Quit,
+    Send,
+    Regenerate,
+    Copy,
+    Run,
+    None,
+}
+
+/// Full-screen mode of `ai tui`: a prompt input box, the response above it, streamed as
+/// it's generated, and the history on the side. The backend is created once, so the local
+/// model is only loaded for the first prompt
+pub struct Tui<'a> {
+    backend: &'a dyn AiBackend,
+    backend_name: String,
+    prompts: Prompt,
+    builder: PromptBuilder,
+    params: GenerationParams,
+    save_history: bool,
+    /// Newest first
+    entries: Vec<HistoryEntry>,
+    /// Index into `entries` of the entry shown, None for the current prompt
+    selected: Option<usize>,
+    input: String,
+    /// What was last asked, for regenerating
+    prompt: String,
+    response: String,
+    /// First line of the response shown, the pane follows the end while streaming
+    scroll: usize,
+    streaming: bool,
+    status: Option<String>,
+}
+
+impl<'a> Tui<'a> {
+    pub fn new(
+        backend: &'a dyn AiBackend,
+        backend_name: String,
+        prompts: Prompt,
+        builder: PromptBuilder,
+        params: GenerationParams,
+        save_history: bool,
+    ) -> Self {
+        Self {
+            backend,
+            backend_name,
+            prompts,
+            builder,
+            params,
+            save_history,
+            entries: Vec::new(),
+            selected: None,
+            input: String::new(),
+            prompt: String::new(),
+            response: String::new(),
+            scroll: 0,
+            streaming: false,
+            status: None,
+        }
+    }
+
+    /// Runs until Esc, restoring the screen whatever happens
+    pub fn run(mut self) -> Result<()> {
+        if !std::io::stdout().is_terminal() {
+            anyhow::bail!("{}", Message::NotATerminal);
+        }
+        self.load_history();
+        // Ctrl-C is a key in raw mode, it's checked for between tokens and stops the
+        // generation through the same flag
+        interrupt::install();
+        let mut terminal = ratatui::try_init()?;
+        let result = self.event_loop(&mut terminal);
+        ratatui::try_restore()?;
+        result
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            match self.key(key) {
+                Action::Quit => return Ok(()),
+                Action::Send => {
+                    let prompt = self.input.trim().to_string();
+                    if !prompt.is_empty() {
+                        self.input.clear();
+                        self.prompt = prompt;
+                        self.generate(terminal)?;
+                    }
+                }
+                Action::Regenerate if !self.prompt.is_empty() => self.generate(terminal)?,
+                Action::Copy if !self.response.is_empty() => {
+                    self.status = Some(match interact::copy(&self.command()) {
+                        Ok(()) => Message::Copied.to_string(),
+                        Err(e) => e.to_string(),
+                    });
+                }
+                Action::Run if !self.response.is_empty() => self.run_command(terminal)?,
+                _ => {}
+            }
+        }
+    }
+
+    /// Edits the input line, browses the history and scrolls for the key, returning what's
+    /// left for the main loop to do
+    fn key(&mut self, key: KeyEvent) -> Action {
+        if key.kind != KeyEventKind::Press {
+            return Action::None;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('c') if ctrl => return Action::Quit,
+            KeyCode::Enter => return Action::Send,
+            KeyCode::Char('g') if ctrl => return Action::Regenerate,
+            KeyCode::Char('y') if ctrl => return Action::Copy,
+            KeyCode::Char('r') if ctrl => return Action::Run,
+            KeyCode::Up => self.select(self.selected.map_or(0, |i| i + 1)),
+            KeyCode::Down => match self.selected {
+                Some(0) | None => self.selected = None,
+                Some(i) => self.select(i - 1),
+            },
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(PAGE),
+            KeyCode::PageDown => self.scroll += PAGE,
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Char(c) if !ctrl => self.input.push(c),
+            _ => {}
+        }
+        Action::None
+    }
+
+    /// Shows a history entry, its prompt can be regenerated
+    fn select(&mut self, index: usize) {
+        if let Some(entry) = self.entries.get(index) {
+            self.selected = Some(index);
+            self.prompt = entry.prompt.clone();
+            self.response = entry.response.clone();
+            self.scroll = 0;
+            self.status = None;
+        }
+    }
+
+    fn load_history(&mut self) {
+        match History::open().and_then(|history| history.entries()) {
+            Ok(entries) => self.entries = entries.into_iter().rev().collect(),
+            Err(e) => warn!("Unable to read the history: {:?}", e),
+        }
+    }
+
+    /// Sends the current prompt and streams the response into its pane, showing errors in
+    /// the status line. Esc or Ctrl-C stop the generation and keep what it wrote
+    fn generate(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        self.selected = None;
+        self.response.clear();
+        self.scroll = 0;
+        self.status = Some(Message::Generating.to_string());
+        terminal.draw(|frame| self.draw(frame))?;
+        let vars = HashMap::from([("prompt".to_string(), self.prompt.clone())]);
+        let full_prompt = self
+            .prompts
+            .render(&vars)
+            .and_then(|asked| self.builder.build(&asked));
+        let backend = self.backend;
+        self.streaming = true;
+        let (keys, typed) = mpsc::channel();
+        let response = full_prompt.and_then(|full_prompt| {
+            // keys are read on a thread, so they work while a backend that doesn't stream
+            // is waited on
+            let done = AtomicBool::new(false);
+            std::thread::scope(|scope| {
+                scope.spawn(|| keys_while_generating(&done, keys));
+                let response = interrupt::generating(|| {
+                    backend.stream_conversation(&[ChatMessage::user(full_prompt)], &mut |token| {
+                        self.response.push_str(token);
+                        self.type_ahead(&typed);
+                        if let Err(e) = terminal.draw(|frame| self.draw(frame)) {
+                            warn!("Unable to draw the response: {:?}", e);
+                        }
+                    })
+                });
+                done.store(true, Ordering::Relaxed);
+                response
+            })
+        });
+        self.type_ahead(&typed);
+        self.streaming = false;
+        // the backends may have printed over the screen, e.g. the model download
+        terminal.clear()?;
+        match response {
+            Ok(response) => {
+                self.response = response;
+                self.status = None;
+                if self.save_history {
+                    self.record();
+                }
+            }
+            Err(e) => self.status = Some(format!("{:#}", e)),
+        }
+        Ok(())
+    }
+
+    /// Types the keys pressed during the generation so far into the input line
+    fn type_ahead(&mut self, typed: &mpsc::Receiver<KeyEvent>) {
+        for key in typed.try_iter() {
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Backspace => {
+                    self.input.pop();
+                }
+                KeyCode::Char(c) if !ctrl => self.input.push(c),
+                _ => {}
+            }
+        }
+    }
+
+    fn record(&mut self) {
+        let mut params = self.params.clone();
+        if let Some(seed) = self.backend.usage().and_then(|usage| usage.seed) {
+            params.seed = Some(seed);
+        }
+        let recorded = History::open().and_then(|history| {
+            history.record(&self.backend_name, &self.prompt, &self.response, params)
+        });
+        match recorded {
+            Ok(id) => info!("Saved to history as {}", id),
+            Err(e) => warn!("Unable to save history: {:?}", e),
+        }
+        self.load_history();
+    }
+
+    /// The response without its markdown code fence
+    fn command(&self) -> String {
+        render::strip_code_fence(&self.response)
+    }
+
+    /// Runs the command on the normal screen, coming back once a key is pressed
+    fn run_command(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        let command = self.command();
+        execute!(std::io::stdout(), LeaveAlternateScreen)?;
+        disable_raw_mode()?;
+        eprintln!("{}", command);
+        let status = match interact::run(&command) {
+            Ok(status) => Message::ExitStatus(status).to_string(),
+            Err(e) => format!("{:#}", e),
+        };
+        eprintln!("{}", status);
+        eprintln!("{}", Message::PressAnyKey);
+        enable_raw_mode()?;
+        while !matches!(event::read()?, Event::Key(key) if key.kind == KeyEventKind::Press) {}
+        execute!(std::io::stdout(), EnterAlternateScreen)?;
+        terminal.clear()?;
+        self.status = Some(status);
+        Ok(())
+    }
+
+    /// Draws the history and response side by side, then the input box and the status line
+    fn draw(&mut self, frame: &mut Frame) {
+        let [body, input, status] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [sidebar, response] = Layout::horizontal([
+            Constraint::Length(SIDEBAR_WIDTH.min(body.width / 3)),
+            Constraint::Min(1),
+        ])
+        .areas(body);
+
+        self.draw_history(frame, sidebar);
+        self.draw_response(frame, response);
+
+        let prompt = Block::default()
+            .borders(Borders::ALL)
+            .border_style(dim())
+            .title(Message::PromptTitle.to_string());
+        let inner = prompt.inner(input);
+        // the end of the input stays in view when it's wider than the box
+        let width = self.input.chars().count();
+        let skip = width.saturating_sub(inner.width.saturating_sub(1) as usize);
+        let shown = self.input.chars().skip(skip).collect::<String>();
+        frame.render_widget(Paragraph::new(shown).block(prompt), input);
+        frame.set_cursor_position((inner.x + (width - skip) as u16, inner.y));
+
+        let line = match &self.status {
+            Some(status) => Line::styled(status.clone(), accent()),
+            None => Line::styled(Message::TuiKeys.to_string(), dim()),
+        };
+        frame.render_widget(Paragraph::new(line), status);
+    }
+
+    fn draw_history(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::default().borders(Borders::RIGHT).border_style(dim());
+        let items = self
+            .entries
+            .iter()
+            .map(|entry| {
+                ListItem::new(format!("#{} {}", entry.id, entry.prompt.replace('\n', " ")))
+            })
+            .collect::<Vec<_>>();
+        let list = List::new(items)
+            .block(block)
+            .style(dim())
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(self.selected);
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn draw_response(&mut self, frame: &mut Frame, area: Rect) {
+        let lines = render::wrap(&self.response, area.width as usize);
+        let last = lines.len().saturating_sub(area.height as usize);
+        self.scroll = if self.streaming {
+            last
+        } else {
+            self.scroll.min(last)
+        };
+        let text = lines
+            .into_iter()
+            .skip(self.scroll)
+            .take(area.height as usize)
+            .map(Line::from)
+            .collect::<Text>();
+        frame.render_widget(Paragraph::new(text), area);
+    }
+}
+
+/// Reads the keys pressed until the generation is done: Esc and Ctrl-C stop it, which raw
+/// mode doesn't turn into SIGINT, the others are sent on to be typed ahead
+fn keys_while_generating(done: &AtomicBool, keys: mpsc::Sender<KeyEvent>) {
+    while !done.load(Ordering::Relaxed) {
+        if !event::poll(KEY_POLL_INTERVAL).unwrap_or(false) {
+            continue;
+        }
+        let Ok(Event::Key(key)) = event::read() else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => {
+                interrupt::cancel();
+            }
+            KeyCode::Char('c') if ctrl => {
+                interrupt::cancel();
+            }
+            _ => {
+                let _ = keys.send(key);
+            }
+        }
+    }
+}
+
+fn dim() -> Style {
+    Style::default().add_modifier(Modifier::DIM)
+}
+
+/// The status line's style, yellow unless colors are off
+fn accent() -> Style {
+    if console::colors_enabled() {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default().add_modifier(Modifier::BOLD)
+    }
+}
@@ -0,0 +1,375 @@
+//! Runs the ai binary against the mock backend, in a config directory of its own
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use tempfile::TempDir;
+
+/// A home, config, cache and state directory for one test, with a mock backend answering
+/// from a fixture file, and a project directory to run in
+struct Sandbox {
+    dir: TempDir,
+}
+
+impl Sandbox {
+    fn new(fixture: &str) -> Self {
+        let sandbox = Self {
+            dir: tempfile::tempdir().unwrap(),
+        };
+        for dir in ["home", "config", "project"] {
+            std::fs::create_dir_all(sandbox.path(dir)).unwrap();
+        }
+        std::fs::write(sandbox.path("fixture.json"), fixture).unwrap();
+        sandbox.config("");
+        sandbox
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// Writes the config, the mock backend settings with `extra` before them
+    fn config(&self, extra: &str) {
+        let config = format!(
+            "ai_backend = \"mock\"\n{}\n[mock_backend]\nfixture = {:?}\n",
+            extra,
+            self.path("fixture.json")
+        );
+        std::fs::write(self.path("config").join("config.toml"), config).unwrap();
+    }
+
+    fn ai(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_ai"));
+        command
+            .args(["--no-clipboard"])
+            .args(args)
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", self.path("home"))
+            .env("AI_CONFIG_DIR", self.path("config"))
+            .env("XDG_CACHE_HOME", self.path("cache"))
+            .env("XDG_STATE_HOME", self.path("state"))
+            .env("NO_COLOR", "1")
+            .current_dir(self.path("project"))
+            .stdin(Stdio::null());
+        command
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.ai(args).output().unwrap()
+    }
+
+    fn run_with_input(&self, args: &[&str], input: &str) -> Output {
+        let mut child = self
+            .ai(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+const FIXTURE: &str = r#"[
+    {"prompt": "list files", "response": "ls -la"},
+    {"prompt": "disk", "response": "```sh\ndf -h\n```"}
+]"#;
+
+#[test]
+fn prints_the_response_matching_the_prompt() {
+    let sandbox = Sandbox::new(FIXTURE);
+    let output = sandbox.run(&["--plain", "list", "files"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "ls -la");
+}
+
+#[test]
+fn plain_output_keeps_the_code_fence_and_quiet_output_strips_it() {
+    let sandbox = Sandbox::new(FIXTURE);
+    let plain = sandbox.run(&["--plain", "disk", "usage"]);
+    assert_eq!(stdout(&plain).trim(), "```sh\ndf -h\n```");
+    let quiet = sandbox.run(&["-q", "disk", "usage"]);
+    assert!(quiet.status.success(), "{}", stderr(&quiet));
+    assert_eq!(stdout(&quiet).trim(), "df -h");
+}
+
+#[test]
+fn quiet_mode_fails_with_the_error_on_stderr() {
+    let sandbox = Sandbox::new(FIXTURE);
+    let output = sandbox.run(&["-q", "something", "unknown"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).is_empty());
+    assert!(stderr(&output).contains("No mock response matches the prompt"));
+}
+
+#[test]
+fn piped_input_is_sent_with_the_instruction() {
+    let sandbox = Sandbox::new(r#"[{"prompt": "b\nc", "response": "3 lines"}]"#);
+    let output = sandbox.run_with_input(&["-q", "pipe", "count", "the", "lines"], "a\nb\nc\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "3 lines");
+}
+
+#[test]
+fn the_audit_log_records_generations_from_every_command() {
+    let sandbox = Sandbox::new(r#"[{"prompt": "lines", "response": "3 lines"}]"#);
+    sandbox.config("[audit]\nenabled = true\ncontent = \"full\"");
+    assert!(sandbox.run(&["-q", "count", "lines"]).status.success());
+    let piped = sandbox.run_with_input(&["-q", "pipe", "count", "the", "lines"], "a\nb\n");
+    assert!(piped.status.success(), "{}", stderr(&piped));
+    let log =
+        std::fs::read_to_string(sandbox.path("state").join("ai").join("audit.jsonl")).unwrap();
+    let entries = log.lines().collect::<Vec<_>>();
+    assert_eq!(entries.len(), 2, "{}", log);
+    assert!(entries
+        .iter()
+        .all(|entry| entry.contains(r#""event":"generate""#)
+            && entry.contains(r#""response":"3 lines""#)));
+}
+
+#[test]
+fn a_failed_webhook_is_a_warning_and_the_script_is_still_written() {
+    let sandbox = Sandbox::new(FIXTURE);
+    sandbox.config("[webhooks.down]\nurl = \"http://127.0.0.1:1/hook\"");
+    let script = sandbox.path("project").join("files.sh");
+    let output = sandbox.run(&[
+        "--plain",
+        "--deliver",
+        "down",
+        "--out",
+        script.to_str().unwrap(),
+        "list",
+        "files",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("wasn't delivered: Unable to deliver response to webhook"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(std::fs::read_to_string(&script).unwrap().contains("ls -la"));
+}
+
+#[test]
+fn history_records_generations() {
+    let sandbox = Sandbox::new(FIXTURE);
+    sandbox.config("history = true");
+    assert!(sandbox.run(&["-q", "list", "files"]).status.success());
+    let output = sandbox.run(&["history", "export", "--format", "jsonl"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let entries = stdout(&output);
+    let entry: serde_json::Value = serde_json::from_str(entries.lines().next().unwrap()).unwrap();
+    assert_eq!(entry["prompt"], "list files");
+    assert_eq!(entry["response"], "ls -la");
+}
+
+#[test]
+fn history_is_not_kept_when_off() {
+    let sandbox = Sandbox::new(FIXTURE);
+    sandbox.config("history = false");
+    assert!(sandbox.run(&["-q", "list", "files"]).status.success());
+    assert!(!sandbox.path("config").join("history.jsonl").exists());
+}
+
+#[test]
+fn config_path_is_in_the_config_directory() {
+    let sandbox = Sandbox::new(FIXTURE);
+    let output = sandbox.run(&["config", "path"]);
+    assert_eq!(
+        Path::new(stdout(&output).trim()),
+        sandbox.path("config").join("config.toml")
+    );
+}
+
+#[test]
+fn a_project_config_pins_the_backend_but_not_its_command() {
+    let sandbox = Sandbox::new(FIXTURE);
+    std::fs::write(
+        sandbox.path("config").join("config.toml"),
+        format!(
+            "ai_backend = \"command\"\n[command_backend]\ncmd = \"false\"\n[mock_backend]\nfixture = {:?}\n",
+            sandbox.path("fixture.json")
+        ),
+    )
+    .unwrap();
+    let project_config = sandbox.path("project").join(".ai.toml");
+    std::fs::write(
+        &project_config,
+        "ai_backend = \"mock\"\nsystem_prompt_append = \"Prefer GNU tools.\"\n[command_backend]\ncmd = \"touch pwned\"\n",
+    )
+    .unwrap();
+    let output = sandbox.run(&["-q", "list", "files"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "ls -la");
+    assert!(stderr(&output).contains(&format!(
+        "ai_backend is set by the project config {}",
+        project_config.display()
+    )));
+    assert!(!sandbox.path("project").join("pwned").exists());
+}
+
+#[test]
+fn an_explicit_config_file_is_used_instead() {
+    let sandbox = Sandbox::new(FIXTURE);
+    let other = sandbox.path("other.toml");
+    std::fs::write(
+        &other,
+        format!(
+            "ai_backend = \"mock\"\n[mock_backend]\nfixture = {:?}\n",
+            sandbox.path("other.json")
+        ),
+    )
+    .unwrap();
+    std::fs::write(
+        sandbox.path("other.json"),
+        r#"[{"response": "echo other"}]"#,
+    )
+    .unwrap();
+    let output = sandbox.run(&["-q", "--config", other.to_str().unwrap(), "list", "files"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "echo other");
+}
+
+#[test]
+fn an_unknown_context_provider_is_an_error() {
+    let sandbox = Sandbox::new(FIXTURE);
+    let output = sandbox.run(&["-q", "--context", "nonsense", "list", "files"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("Unknown context provider nonsense"));
+}
+
+#[test]
+fn config_warnings_go_to_stderr_in_quiet_mode() {
+    let sandbox = Sandbox::new(FIXTURE);
+    sandbox.config("no_such_setting = 1");
+    let output = sandbox.run(&["-q", "list", "files"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "ls -la");
+    assert!(stderr(&output).contains("Warning: "));
+    assert!(stderr(&output).contains("no_such_setting"));
+}
+
+#[test]
+fn an_explicit_config_file_is_never_rewritten() {
+    let sandbox = Sandbox::new(FIXTURE);
+    let other = sandbox.path("other.toml");
+    let contents = format!(
+        "ai_backend = \"mock\"\n[mock_backend]\nfixture = {:?}\n[model_config]\ncpu = true\n",
+        sandbox.path("fixture.json")
+    );
+    std::fs::write(&other, &contents).unwrap();
+    let output = sandbox.run(&["-q", "--config", other.to_str().unwrap(), "list", "files"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("left as it is"));
+    assert_eq!(std::fs::read_to_string(&other).unwrap(), contents);
+}
+
+#[test]
+fn doctor_skips_aws_when_bedrock_is_not_used() {
+    let sandbox = Sandbox::new(FIXTURE);
+    let output = sandbox.run(&["doctor"]);
+    assert!(!stdout(&output).contains("aws:"), "{}", stdout(&output));
+}
+
+#[test]
+fn the_cache_is_off_by_default() {
+    let sandbox = Sandbox::new(FIXTURE);
+    assert!(sandbox.run(&["-q", "list", "files"]).status.success());
+    let output = sandbox.run(&["cache", "stats"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Entries: 0"));
+    assert!(stdout(&output).contains("The cache is off"));
+}
+
+#[cfg(unix)]
+#[test]
+fn a_plugin_writing_before_it_reads_a_long_request_does_not_deadlock() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let sandbox = Sandbox::new(FIXTURE);
+    let plugin = sandbox.path("ai-backend-chatty");
+    // fills the stderr pipe before reading a request that doesn't fit in the stdin pipe
+    std::fs::write(
+        &plugin,
+        "#!/bin/sh\nhead -c 200000 /dev/zero | tr '\\0' x >&2\ncat >/dev/null\necho '{\"type\": \"token\", \"text\": \"echo done\"}'\necho '{\"type\": \"done\"}'\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&plugin, std::fs::Permissions::from_mode(0o755)).unwrap();
+    sandbox.config(&format!("[plugins.chatty]\npath = {:?}", plugin));
+    let prompt = "word ".repeat(20000);
+    let output = sandbox.run(&["-q", "-b", "chatty", "--no-context", &prompt]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "echo done");
+}
+
+#[test]
+fn bench_measures_each_run_in_a_process_of_its_own() {
+    let sandbox = Sandbox::new(FIXTURE);
+    let output = sandbox.run(&["bench", "-n", "2", "list", "files"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let report = stdout(&output);
+    assert!(
+        report.contains("Benchmark of the mock backend, 2 runs"),
+        "{}",
+        report
+    );
+    let runs = report
+        .lines()
+        .filter(|line| line.trim_start().starts_with(['1', '2']))
+        .collect::<Vec<_>>();
+    assert_eq!(runs.len(), 2, "{}", report);
+    if cfg!(target_os = "linux") {
+        assert!(runs.iter().all(|run| run.ends_with("MiB")), "{}", report);
+    }
+}
+
+#[test]
+fn pipeline_steps_get_responses_unfenced_and_variables_quoted() {
+    let sandbox = Sandbox::new(
+        r#"[{"prompt": "greet", "response": "```sh\necho \"$(echo hello)\" 'world'\n```"}]"#,
+    );
+    let pipeline = sandbox.path("pipeline.yaml");
+    std::fs::write(
+        &pipeline,
+        "steps:\n  - name: cmd\n    prompt: \"greet {{who}}\"\n  - name: text\n    run: \"printf %s {{cmd}}\"\n  - name: ran\n    run: \"sh -c {{cmd}}\"\n  - name: who_text\n    run: \"printf %s {{who}}\"\n",
+    )
+    .unwrap();
+    let output = sandbox.run(&[
+        "pipeline",
+        "run",
+        pipeline.to_str().unwrap(),
+        "--var",
+        "who=me; touch pwned",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let lines = stdout(&output)
+        .lines()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        [
+            "echo \"$(echo hello)\" 'world'",
+            "echo \"$(echo hello)\" 'world'",
+            "hello world",
+            "me; touch pwned"
+        ]
+    );
+    assert!(!sandbox.path("project").join("pwned").exists());
+}